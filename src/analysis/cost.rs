@@ -0,0 +1,507 @@
+//! Worst-case instruction counts of the bodies the runtime runs, from their
+//! IR. Every instruction and terminator counts one, and a call counts what
+//! its function may run on top. A `for` over the packet of a handler whose
+//! guard bounds it, or over a record, runs once per item; `while` loops,
+//! other `for` loops and recursive calls have no bound.
+//!
+//! A run counts the code on both sides of its `@` delays.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+use super::memory::{label, packet_length};
+use crate::diagnostic::Diagnostic;
+use crate::ir::{
+    BinaryOperator, BlockId, Body, BodyId, BodyKind, Callee, Instruction, Operand, Program, Temp,
+    Terminator, Variable,
+};
+
+#[derive(Debug)]
+pub struct Report {
+    pub costs: Vec<Cost>,
+    /// The most instructions a run may take, from `--cost-limit`.
+    pub limit: Option<u64>,
+}
+
+/// The worst case of a `when` handler, a task or a function started as a
+/// task.
+#[derive(Debug)]
+pub struct Cost {
+    pub body: String,
+    /// Instructions, or `None` when there is no bound.
+    pub instructions: Option<u64>,
+    /// Why there is no bound.
+    pub unbounded: Vec<String>,
+}
+
+impl Report {
+    /// An error for every run that may go over the limit.
+    pub fn check(&self) -> Vec<Diagnostic> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return vec![],
+        };
+        self.costs
+            .iter()
+            .filter_map(|cost| match cost.instructions {
+                Some(instructions) if instructions <= limit => None,
+                Some(instructions) => Some(Diagnostic::error(format!(
+                    "{} may run {} instructions, over the limit of {}",
+                    cost.body, instructions, limit
+                ))),
+                None => Some(Diagnostic::error(format!(
+                    "{} has no bound to fit the limit of {} instructions: {}",
+                    cost.body,
+                    limit,
+                    cost.unbounded.join(", ")
+                ))),
+            })
+            .collect()
+    }
+}
+
+/// Bounds the instructions of every body `program` runs on its own, against
+/// `limit` when there is one.
+pub fn analyze(program: &Program, limit: Option<u64>) -> Report {
+    let mut analyzer = Analyzer {
+        program,
+        memo: HashMap::new(),
+        path: vec![],
+    };
+    let costs = program
+        .run_bodies()
+        .into_iter()
+        .map(|id| {
+            let (instructions, unbounded) = analyzer.body(id);
+            Cost {
+                body: label(&program.bodies[id]),
+                instructions,
+                unbounded,
+            }
+        })
+        .collect();
+    Report { costs, limit }
+}
+
+struct Analyzer<'a> {
+    program: &'a Program,
+    memo: HashMap<BodyId, (Option<u64>, Vec<String>)>,
+    /// The bodies being analyzed, callers first.
+    path: Vec<BodyId>,
+}
+
+impl Analyzer<'_> {
+    /// The worst case of body `id` with the calls it makes, and why it has
+    /// no bound.
+    fn body(&mut self, id: BodyId) -> (Option<u64>, Vec<String>) {
+        if let Some(cost) = self.memo.get(&id) {
+            return cost.clone();
+        }
+        let body = &self.program.bodies[id];
+        if self.path.contains(&id) {
+            return (None, vec![format!("recursive call to {}", body.name())]);
+        }
+
+        self.path.push(id);
+        let mut unbounded = vec![];
+        let mut costs = vec![];
+        for block in body.blocks.iter() {
+            let mut cost = Some(block.instructions.len() as u64 + 1);
+            for instruction in block.instructions.iter() {
+                if let Instruction::Call {
+                    callee: Callee::Function(callee),
+                    ..
+                } = instruction
+                {
+                    let (instructions, reasons) = self.body(*callee);
+                    cost = cost.zip(instructions).map(|(cost, call)| cost + call);
+                    unbounded.extend(reasons);
+                }
+            }
+            costs.push(cost);
+        }
+        self.path.pop();
+
+        let mut loops: HashMap<BlockId, Loop> = HashMap::new();
+        for (header, nodes) in natural_loops(body) {
+            let bound = self.bound(body, header);
+            if let Err(reason) = &bound {
+                unbounded.push(reason.clone());
+            }
+            loops.insert(
+                header,
+                Loop {
+                    nodes,
+                    bound: bound.ok(),
+                },
+            );
+        }
+
+        let mut flow = Flow {
+            body,
+            costs,
+            loops,
+            totals: HashMap::new(),
+        };
+        let all: HashSet<BlockId> = (0..body.blocks.len()).collect();
+        let instructions = flow.longest(0, None, &all, &mut HashMap::new(), &mut HashSet::new());
+
+        let mut seen = HashSet::new();
+        unbounded.retain(|reason| seen.insert(reason.clone()));
+        let cost = (instructions, unbounded);
+        self.memo.insert(id, cost.clone());
+        cost
+    }
+
+    /// How many times the loop at `header` runs: the items of a `for` over
+    /// a record or a packet of known length. Fails with why there is no
+    /// bound.
+    fn bound(&self, body: &Body, header: BlockId) -> Result<u64, String> {
+        let block = &body.blocks[header];
+        let line = block.lines.last().copied().unwrap_or_default();
+        let while_loop = || format!("'while' loop at line {}", line);
+
+        // A lowered `for` compares its position with the length of the items
+        // of the collection, which it loads into a temporary.
+        let more = match &block.terminator {
+            Terminator::Branch {
+                condition: Operand::Temp(more),
+                ..
+            } => *more,
+            _ => return Err(while_loop()),
+        };
+        let length = match definition(body, more) {
+            Some(Instruction::Binary {
+                operator: BinaryOperator::Less,
+                rhs: Operand::Temp(length),
+                ..
+            }) => *length,
+            _ => return Err(while_loop()),
+        };
+        let items = match definition(body, length) {
+            Some(Instruction::Length {
+                list: Operand::Temp(items),
+                ..
+            }) => *items,
+            _ => return Err(while_loop()),
+        };
+        let variable = match definition(body, items) {
+            Some(Instruction::Items {
+                collection: Operand::Temp(collection),
+                ..
+            }) => match definition(body, *collection) {
+                Some(Instruction::Load { variable, .. }) => Some(*variable),
+                _ => None,
+            },
+            _ => return Err(while_loop()),
+        };
+
+        let bound = match (variable, &body.kind) {
+            (Some(Variable::Record(record)), _) => Some(self.program.records[record].length),
+            (Some(Variable::Local(0)), BodyKind::When { guard, .. }) if !assigns_packet(body) => {
+                packet_length(guard)
+            }
+            _ => None,
+        };
+        bound.map(|bound| bound as u64).ok_or_else(|| {
+            format!(
+                "'for' loop at line {} over a collection of unknown length",
+                line
+            )
+        })
+    }
+}
+
+/// A natural loop: the blocks that reach back to its header without going
+/// through it, and how many times it runs when that is known.
+struct Loop {
+    nodes: HashSet<BlockId>,
+    bound: Option<u64>,
+}
+
+/// The longest paths through the blocks of a body, where a loop counts as
+/// many times the longest path around it as it runs, plus one for its last
+/// test.
+struct Flow<'a> {
+    body: &'a Body,
+    costs: Vec<Option<u64>>,
+    loops: HashMap<BlockId, Loop>,
+    totals: HashMap<BlockId, Option<u64>>,
+}
+
+impl Flow<'_> {
+    /// The longest path from `block` to where the region of `nodes` is left
+    /// or goes back to its `header`.
+    fn longest(
+        &mut self,
+        block: BlockId,
+        header: Option<BlockId>,
+        nodes: &HashSet<BlockId>,
+        memo: &mut HashMap<BlockId, Option<u64>>,
+        visiting: &mut HashSet<BlockId>,
+    ) -> Option<u64> {
+        if let Some(&cost) = memo.get(&block) {
+            return cost;
+        }
+        // Only an irreducible flow, which lowering never makes, gets here.
+        if !visiting.insert(block) {
+            return None;
+        }
+
+        let (cost, successors) = match self.loops.get(&block) {
+            Some(inner) if Some(block) != header => {
+                let exits: Vec<BlockId> = inner
+                    .nodes
+                    .iter()
+                    .flat_map(|&node| self.body.blocks[node].terminator.successors())
+                    .filter(|next| !inner.nodes.contains(next))
+                    .collect();
+                (self.total(block), exits)
+            }
+            _ => (
+                self.costs[block],
+                self.body.blocks[block].terminator.successors(),
+            ),
+        };
+
+        let mut rest = Some(0);
+        for next in successors {
+            if Some(next) == header || !nodes.contains(&next) {
+                continue;
+            }
+            let after = self.longest(next, header, nodes, memo, visiting);
+            rest = rest.zip(after).map(|(rest, after)| rest.max(after));
+        }
+
+        visiting.remove(&block);
+        let cost = cost.zip(rest).map(|(cost, rest)| cost + rest);
+        memo.insert(block, cost);
+        cost
+    }
+
+    /// Everything the loop at `header` may run.
+    fn total(&mut self, header: BlockId) -> Option<u64> {
+        if let Some(&total) = self.totals.get(&header) {
+            return total;
+        }
+        let (nodes, bound) = {
+            let inner = &self.loops[&header];
+            (inner.nodes.clone(), inner.bound)
+        };
+        let around = self.longest(
+            header,
+            Some(header),
+            &nodes,
+            &mut HashMap::new(),
+            &mut HashSet::new(),
+        );
+        let total = bound
+            .zip(around)
+            .map(|(bound, around)| (bound + 1).saturating_mul(around));
+        self.totals.insert(header, total);
+        total
+    }
+}
+
+/// The loops of `body` by header, found from the jumps back to a block that
+/// dominates where they jump from.
+fn natural_loops(body: &Body) -> HashMap<BlockId, HashSet<BlockId>> {
+    let count = body.blocks.len();
+    let mut predecessors = vec![vec![]; count];
+    for (id, block) in body.blocks.iter().enumerate() {
+        for next in block.terminator.successors() {
+            predecessors[next].push(id);
+        }
+    }
+
+    let mut dominators: Vec<HashSet<BlockId>> = (0..count)
+        .map(|id| match id {
+            0 => std::iter::once(0).collect(),
+            _ => (0..count).collect(),
+        })
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in 1..count {
+            let mut common: Option<HashSet<BlockId>> = None;
+            for &predecessor in predecessors[id].iter() {
+                common = Some(match common {
+                    None => dominators[predecessor].clone(),
+                    Some(common) => common
+                        .intersection(&dominators[predecessor])
+                        .copied()
+                        .collect(),
+                });
+            }
+            let mut next = common.unwrap_or_default();
+            next.insert(id);
+            if next != dominators[id] {
+                dominators[id] = next;
+                changed = true;
+            }
+        }
+    }
+
+    let mut loops: HashMap<BlockId, HashSet<BlockId>> = HashMap::new();
+    for (from, block) in body.blocks.iter().enumerate() {
+        for header in block.terminator.successors() {
+            if !dominators[from].contains(&header) {
+                continue;
+            }
+            let nodes = loops
+                .entry(header)
+                .or_insert_with(|| std::iter::once(header).collect());
+            let mut pending = vec![from];
+            while let Some(node) = pending.pop() {
+                if nodes.insert(node) {
+                    pending.extend(predecessors[node].iter().copied());
+                }
+            }
+        }
+    }
+    loops
+}
+
+/// The instruction that first writes `temp`.
+fn definition(body: &Body, temp: Temp) -> Option<&Instruction> {
+    body.blocks
+        .iter()
+        .flat_map(|block| block.instructions.iter())
+        .find(|instruction| instruction.defined().contains(&temp))
+}
+
+/// Whether a handler assigns its packet, which then may no longer be as
+/// long as its guard says.
+fn assigns_packet(body: &Body) -> bool {
+    body.blocks
+        .iter()
+        .flat_map(|block| block.instructions.iter())
+        .any(|instruction| {
+            matches!(
+                instruction,
+                Instruction::Store {
+                    variable: Variable::Local(0),
+                    ..
+                }
+            )
+        })
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .costs
+            .iter()
+            .map(|cost| cost.body.len())
+            .max()
+            .unwrap_or(0);
+
+        match self.limit {
+            Some(limit) => writeln!(
+                f,
+                "Worst-case cost (IR instructions per run, limit {}):",
+                limit
+            )?,
+            None => writeln!(f, "Worst-case cost (IR instructions per run):")?,
+        }
+        for cost in self.costs.iter() {
+            let instructions = match cost.instructions {
+                Some(instructions) => instructions.to_string(),
+                None => format!("no bound: {}", cost.unbounded.join(", ")),
+            };
+            let over = match (cost.instructions, self.limit) {
+                (Some(instructions), Some(limit)) if instructions <= limit => "",
+                (_, Some(_)) => "  over the limit",
+                (_, None) => "",
+            };
+            writeln!(
+                f,
+                "  {:<width$}  {}{}",
+                cost.body,
+                instructions,
+                over,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parser};
+
+    #[test]
+    fn bounds_loops_over_records_and_packets() {
+        let source = "
+            function fact(n)
+                if (n < 2)
+                    return 1;
+                end
+                return n * fact(n - 1);
+            end
+
+            record samples[8, 2];
+
+            task main @ 1000
+                for (s in samples)
+                    print(\"%d\", s);
+                end
+            end
+
+            task spin @ 1000
+                i = 0;
+                while (i < 10)
+                    i = i + 1;
+                end
+                print(\"%d\", fact(3));
+            end
+
+            when \"uart\" => msg::\"ab..\"
+                for (b in msg)
+                    for (c in msg)
+                        print(\"%d\", b + c);
+                    end
+                end
+            end
+
+            when \"spi\" => word
+                word = [1, 2, 3];
+                for (w in word)
+                    print(\"%d\", w);
+                end
+            end
+        ";
+        let program = ir::lower(&parser::parse_source(source)).unwrap();
+        let report = analyze(&program, Some(100));
+
+        let costs: Vec<_> = report
+            .costs
+            .iter()
+            .map(|cost| (cost.body.as_str(), cost.instructions))
+            .collect();
+        // The loop over the record runs its header 9 times around a body of
+        // 6 instructions, between 5 instructions before it and 1 after.
+        assert_eq!(costs[0], ("task main", Some(5 + 9 * 8 + 1)));
+        assert_eq!(costs[1], ("task spin", None));
+        assert_eq!(
+            report.costs[1].unbounded,
+            ["recursive call to fact", "'while' loop at line 19"]
+        );
+        assert!(costs[2].1.unwrap() > 100);
+        assert_eq!(
+            report.costs[3].unbounded,
+            ["'for' loop at line 35 over a collection of unknown length"]
+        );
+
+        let errors: Vec<_> = report
+            .check()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[2].contains("no bound to fit the limit of 100"));
+    }
+}
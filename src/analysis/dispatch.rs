@@ -0,0 +1,237 @@
+use crate::automaton::Dfa;
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Framing, Guard, AST};
+use crate::token::Span;
+
+/// The `when` handlers of one interface, in the order they are offered
+/// incoming bytes.
+///
+/// Handlers are ordered from the most to the least constrained framing:
+/// delimited on both sides, start-delimited, end-delimited, continuation,
+/// fixed width and finally the default one-byte guard. Handlers with the
+/// same kind of framing keep their source order.
+#[derive(Debug)]
+pub struct Dispatch {
+    pub interface: String,
+    /// Indexes of the handlers in the program.
+    pub handlers: Vec<usize>,
+}
+
+pub fn analyze(program: &[AST]) -> (Vec<Dispatch>, Vec<Diagnostic>) {
+    let mut dispatches: Vec<Dispatch> = vec![];
+    for (index, ast) in program.iter().enumerate() {
+        if let AST::When { interface, .. } = ast {
+            match dispatches.iter_mut().find(|d| &d.interface == interface) {
+                Some(dispatch) => dispatch.handlers.push(index),
+                None => dispatches.push(Dispatch {
+                    interface: interface.clone(),
+                    handlers: vec![index],
+                }),
+            }
+        }
+    }
+
+    let mut diagnostics = vec![];
+    for dispatch in dispatches.iter_mut() {
+        dispatch
+            .handlers
+            .sort_by_key(|&index| rank(handler(&program[index]).0));
+        check_overlaps(program, dispatch, &mut diagnostics);
+    }
+
+    (dispatches, diagnostics)
+}
+
+fn handler(ast: &AST) -> (&Guard, Span) {
+    match ast {
+        AST::When { guard, span, .. } => (guard, *span),
+        _ => unreachable!("dispatch tables only hold 'when' handlers"),
+    }
+}
+
+fn rank(guard: &Guard) -> usize {
+    match guard {
+        Guard::Regex { framing, .. } => match framing {
+            Framing::Delimited { .. } => 0,
+            Framing::Start(_) => 1,
+            Framing::End(_) => 2,
+            Framing::Continuation => 3,
+        },
+        Guard::Numeric { .. } => 4,
+        Guard::Default => 5,
+    }
+}
+
+fn is_fixed_width(guard: &Guard) -> bool {
+    !matches!(guard, Guard::Regex { .. })
+}
+
+fn check_overlaps(program: &[AST], dispatch: &Dispatch, diagnostics: &mut Vec<Diagnostic>) {
+    for (position, &index) in dispatch.handlers.iter().enumerate() {
+        let (guard, span) = handler(&program[index]);
+        let earlier = dispatch.handlers[..position]
+            .iter()
+            .map(|&index| handler(&program[index]));
+
+        if is_fixed_width(guard) {
+            if let Some((_, first_span)) = earlier.clone().find(|(g, _)| is_fixed_width(g)) {
+                diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "'when' handler on \"{}\" is shadowed by another fixed-width handler",
+                        dispatch.interface
+                    ))
+                    .with_label(span, "never receives a packet")
+                    .with_label(first_span, "receives every byte first"),
+                );
+                continue;
+            }
+
+            let mut regex_spans = earlier.map(|(_, span)| span).peekable();
+            if regex_spans.peek().is_some() {
+                let mut diagnostic = Diagnostic::warning(format!(
+                    "fixed-width 'when' handler on \"{}\" takes bytes before a delimiter arrives",
+                    dispatch.interface
+                ))
+                .with_label(span, "consumes every byte");
+                for regex_span in regex_spans {
+                    diagnostic = diagnostic.with_label(regex_span, "may never see a whole packet");
+                }
+                diagnostics.push(diagnostic);
+            }
+            continue;
+        }
+
+        let (pattern, framing) = match guard {
+            Guard::Regex { pattern, framing } => (pattern, framing),
+            _ => unreachable!(),
+        };
+        for (earlier_guard, earlier_span) in earlier {
+            let (earlier_pattern, earlier_framing) = match earlier_guard {
+                Guard::Regex { pattern, framing } => (pattern, framing),
+                _ => unreachable!("fixed-width handlers are dispatched last"),
+            };
+
+            match relation(earlier_pattern, pattern) {
+                Relation::Disjoint => {}
+                Relation::Subsumed if earlier_framing == framing => diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "'when' handler on \"{}\" is shadowed by a handler that matches all its packets",
+                        dispatch.interface
+                    ))
+                    .with_label(span, "never receives a packet")
+                    .with_label(earlier_span, "receives every packet first"),
+                ),
+                Relation::Subsumed => diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "'when' handler on \"{}\" only matches packets that another handler matches",
+                        dispatch.interface
+                    ))
+                    .with_label(span, "receives a packet only when it frames it first")
+                    .with_label(earlier_span, "matches all of its packets"),
+                ),
+                Relation::Overlapping => diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "'when' handlers on \"{}\" can frame the same bytes",
+                        dispatch.interface
+                    ))
+                    .with_label(earlier_span, "is offered the packet first")
+                    .with_label(span, "is offered the packet second"),
+                ),
+            }
+        }
+    }
+}
+
+/// How the packets a handler matches relate to those of a handler offered
+/// them first.
+enum Relation {
+    /// No packet matches both.
+    Disjoint,
+    /// Every packet of the later handler matches the earlier one.
+    Subsumed,
+    Overlapping,
+}
+
+fn relation(earlier: &str, later: &str) -> Relation {
+    let (earlier, later) = match (Dfa::new(earlier), Dfa::new(later)) {
+        (Ok(earlier), Ok(later)) => (earlier, later),
+        // Assume the worst of a pattern the automaton cannot express.
+        _ => return Relation::Overlapping,
+    };
+    let overlap = earlier.overlap(&later);
+    if !overlap.both {
+        Relation::Disjoint
+    } else if !overlap.only_second {
+        Relation::Subsumed
+    } else {
+        Relation::Overlapping
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// The warnings for handlers on one interface with the given guards,
+    /// each with the lines of its labels.
+    fn warnings(guards: &[&str]) -> Vec<(String, Vec<usize>)> {
+        let source: String = guards
+            .iter()
+            .map(|guard| format!("when \"uart\" => msg::{}\nend\n", guard))
+            .collect();
+        let program = parser::parse_source(&source);
+        analyze(&program)
+            .1
+            .into_iter()
+            .map(|diagnostic| {
+                let lines = diagnostic.labels.iter().map(|label| label.0.line).collect();
+                (diagnostic.message, lines)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn disjoint_patterns_do_not_overlap() {
+        assert_eq!(warnings(&[r#"":a;""#, r#"":b;""#]), []);
+        assert_eq!(warnings(&[r#"":[0-4]+;""#, r#"":[5-9]+;""#]), []);
+    }
+
+    #[test]
+    fn reports_overlapping_patterns() {
+        assert_eq!(
+            warnings(&[r#"":a.;""#, r#"":.b;""#]),
+            [(
+                "'when' handlers on \"uart\" can frame the same bytes".to_string(),
+                vec![1, 3]
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_subsumed_patterns() {
+        assert_eq!(
+            warnings(&[r#"":.;""#, r#"":[ab];""#]),
+            [(
+                "'when' handler on \"uart\" is shadowed by a handler that matches all its packets"
+                    .to_string(),
+                vec![3, 1]
+            )]
+        );
+        // Different framings may still complete the later handler's packet
+        // first.
+        assert_eq!(
+            warnings(&[r"'.*\x3f'", r#""[0-9]\x3f|\x3f\x3f""#]),
+            [(
+                "'when' handler on \"uart\" only matches packets that another handler matches"
+                    .to_string(),
+                vec![3, 1]
+            )]
+        );
+        // The earlier handler matching fewer packets is only an overlap.
+        assert_eq!(
+            warnings(&[r#"":a.;""#, r#"":..;""#])[0].0,
+            "'when' handlers on \"uart\" can frame the same bytes"
+        );
+    }
+}
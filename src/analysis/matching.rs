@@ -0,0 +1,290 @@
+use crate::analysis::walk_statements;
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Expression, Literal, MatchArm, Pattern, Statement, AST};
+use crate::token::Span;
+
+/// Warns about `match` statements with unreachable or overlapping patterns,
+/// patterns of a different type than the target, or no default arm on a
+/// target that is not a boolean.
+pub fn check(program: &[AST]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for ast in program {
+        walk_statements(ast.body(), &mut |statement| {
+            if let Statement::Match {
+                target,
+                cases,
+                default,
+                span,
+            } = statement
+            {
+                check_match(target, cases, default.is_some(), *span, &mut diagnostics);
+            }
+        });
+    }
+
+    diagnostics
+}
+
+fn check_match(
+    target: &Expression,
+    cases: &[MatchArm],
+    has_default: bool,
+    span: Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let patterns: Vec<&(Pattern, Span)> = cases.iter().flat_map(|arm| &arm.patterns).collect();
+
+    let (expected, expected_span, expected_label) = match expression_type(target) {
+        Some(target_type) => (target_type, span, "match target"),
+        None => match patterns.first() {
+            Some((pattern, pattern_span)) => {
+                (pattern_type(pattern), *pattern_span, "first pattern")
+            }
+            None => return,
+        },
+    };
+
+    for (pattern, pattern_span) in patterns.iter() {
+        let found = pattern_type(pattern);
+        if found != expected {
+            diagnostics.push(
+                Diagnostic::warning(format!(
+                    "match pattern is {} but {} was expected",
+                    found, expected
+                ))
+                .with_label(*pattern_span, "this pattern")
+                .with_label(expected_span, expected_label),
+            );
+        }
+    }
+
+    for (position, (pattern, pattern_span)) in patterns.iter().enumerate() {
+        let earlier = &patterns[..position];
+        if let Some((_, earlier_span)) =
+            earlier.iter().find(|(earlier, _)| covers(earlier, pattern))
+        {
+            diagnostics.push(
+                Diagnostic::warning("unreachable match pattern".to_string())
+                    .with_label(*pattern_span, "never matches")
+                    .with_label(*earlier_span, "already matched here"),
+            );
+        } else if let Some((_, earlier_span)) = earlier
+            .iter()
+            .find(|(earlier, _)| patterns_overlap(earlier, pattern))
+        {
+            diagnostics.push(
+                Diagnostic::warning("match pattern overlaps an earlier pattern".to_string())
+                    .with_label(*pattern_span, "only matches the values left over")
+                    .with_label(*earlier_span, "matches some of them first"),
+            );
+        }
+    }
+
+    if has_default {
+        return;
+    }
+
+    if expected == "a boolean" {
+        for value in [true, false] {
+            let covered = patterns
+                .iter()
+                .any(|(pattern, _)| matches!(pattern, Pattern::Literal(Literal::Boolean(b)) if *b == value));
+            if !covered {
+                diagnostics.push(
+                    Diagnostic::warning(format!("match does not cover `{}`", value))
+                        .with_label(span, "add the missing arm or a `_` arm"),
+                );
+            }
+        }
+    } else {
+        diagnostics.push(
+            Diagnostic::warning(format!("match on {} has no default arm", expected))
+                .with_label(span, "add a `_` arm"),
+        );
+    }
+}
+
+fn expression_type(expression: &Expression) -> Option<&'static str> {
+    match expression {
+        Expression::Literal(literal) => Some(literal_type(literal)),
+        Expression::Equal(..)
+        | Expression::NotEqual(..)
+        | Expression::Less(..)
+        | Expression::Greater(..)
+        | Expression::LessOrEqual(..)
+        | Expression::GreaterOrEqual(..)
+        | Expression::And(..)
+        | Expression::Or(..)
+        | Expression::Xor(..)
+        | Expression::Not(..) => Some("a boolean"),
+        // `+` also joins strings, bytes and lists, so its type is the one
+        // either operand is known to have.
+        Expression::Sum(lhs, rhs) => expression_type(lhs).or_else(|| expression_type(rhs)),
+        Expression::Minus(..)
+        | Expression::Multiply(..)
+        | Expression::Division(..)
+        | Expression::Modulus(..)
+        | Expression::Element { .. } => Some("an integer"),
+        _ => None,
+    }
+}
+
+fn literal_type(literal: &Literal) -> &'static str {
+    match literal {
+        Literal::Integer(_) => "an integer",
+        Literal::String(_) => "a string",
+        Literal::Bytes(_) => "bytes",
+        Literal::Boolean(_) => "a boolean",
+        Literal::Nil => "nil",
+    }
+}
+
+fn pattern_type(pattern: &Pattern) -> &'static str {
+    match pattern {
+        Pattern::Literal(literal) => literal_type(literal),
+        Pattern::Range(..) => "an integer",
+    }
+}
+
+/// Whether every value `later` matches also matches `earlier`.
+fn covers(earlier: &Pattern, later: &Pattern) -> bool {
+    match (earlier, later) {
+        (Pattern::Range(start, end), Pattern::Range(later_start, later_end)) => {
+            start <= later_start && later_end <= end
+        }
+        (Pattern::Literal(_), Pattern::Range(start, end)) => {
+            start == end && patterns_overlap(earlier, later)
+        }
+        _ => patterns_overlap(earlier, later),
+    }
+}
+
+fn patterns_overlap(a: &Pattern, b: &Pattern) -> bool {
+    match (a, b) {
+        (Pattern::Literal(Literal::Integer(a)), Pattern::Literal(Literal::Integer(b))) => a == b,
+        (Pattern::Literal(Literal::Integer(value)), Pattern::Range(start, end))
+        | (Pattern::Range(start, end), Pattern::Literal(Literal::Integer(value))) => {
+            start <= value && value <= end
+        }
+        (Pattern::Range(a_start, a_end), Pattern::Range(b_start, b_end)) => {
+            a_start <= b_end && b_start <= a_end
+        }
+        (Pattern::Literal(Literal::String(a)), Pattern::Literal(Literal::String(b))) => a == b,
+        (Pattern::Literal(Literal::Boolean(a)), Pattern::Literal(Literal::Boolean(b))) => a == b,
+        (Pattern::Literal(Literal::Nil), Pattern::Literal(Literal::Nil)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// The warnings for a handler whose body is `body`, each with the lines
+    /// and columns of its labels.
+    fn warnings(body: &str) -> Vec<(String, Vec<(usize, usize)>)> {
+        let source = format!("when \"uart\" => msg\n{}\nend", body);
+        let program = parser::parse_source(&source);
+        check(&program)
+            .into_iter()
+            .map(|diagnostic| {
+                let spans = diagnostic
+                    .labels
+                    .iter()
+                    .map(|(span, _)| (span.line, span.column))
+                    .collect();
+                (diagnostic.message, spans)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_unreachable_patterns() {
+        let duplicate = "match (msg)\n    1 => print(\"a\");\n    1 => print(\"b\");\n    _ => print(\"c\");\nend";
+        assert_eq!(
+            warnings(duplicate),
+            [(
+                "unreachable match pattern".to_string(),
+                vec![(4, 5), (3, 5)]
+            )]
+        );
+
+        let in_range = "match (msg)\n    0..9 => print(\"a\");\n    5 | 7 => print(\"b\");\n    _ => print(\"c\");\nend";
+        assert_eq!(
+            warnings(in_range),
+            [
+                (
+                    "unreachable match pattern".to_string(),
+                    vec![(4, 5), (3, 5)]
+                ),
+                (
+                    "unreachable match pattern".to_string(),
+                    vec![(4, 9), (3, 5)]
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_overlapping_ranges() {
+        let source = "match (msg)\n    0..9 => print(\"a\");\n    5..20 => print(\"b\");\n    _ => print(\"c\");\nend";
+        assert_eq!(
+            warnings(source),
+            [(
+                "match pattern overlaps an earlier pattern".to_string(),
+                vec![(4, 5), (3, 5)]
+            )]
+        );
+    }
+
+    #[test]
+    fn reports_type_mismatches() {
+        let source = "match (msg + 1)\n    1 => print(\"a\");\n    \"x\" => print(\"b\");\n    _ => print(\"c\");\nend";
+        assert_eq!(
+            warnings(source),
+            [(
+                "match pattern is a string but an integer was expected".to_string(),
+                vec![(4, 5), (2, 1)]
+            )]
+        );
+    }
+
+    #[test]
+    fn takes_the_type_of_a_sum_from_its_operands() {
+        let joined =
+            "match (msg + \"b\")\n    \"ab\" => print(\"a\");\n    _ => print(\"c\");\nend";
+        assert_eq!(warnings(joined), []);
+
+        let source = "match (\"a\" + msg)\n    1 => print(\"a\");\n    _ => print(\"c\");\nend";
+        assert_eq!(
+            warnings(source),
+            [(
+                "match pattern is an integer but a string was expected".to_string(),
+                vec![(3, 5), (2, 1)]
+            )]
+        );
+    }
+
+    #[test]
+    fn requires_a_default_arm_unless_booleans_are_covered() {
+        let source = "match (msg)\n    1 => print(\"a\");\nend";
+        assert_eq!(
+            warnings(source),
+            [(
+                "match on an integer has no default arm".to_string(),
+                vec![(2, 1)]
+            )]
+        );
+
+        let source = "match (msg == 1)\n    true => print(\"a\");\n    false => print(\"b\");\nend";
+        assert_eq!(warnings(source), []);
+
+        let source = "match (msg == 1)\n    true => print(\"a\");\nend";
+        assert_eq!(
+            warnings(source),
+            [("match does not cover `false`".to_string(), vec![(2, 1)])]
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Estimates of the memory a program needs on a board, from its IR: the
+//! static RAM of its records and stored variables, the stack each handler
+//! and task grows through its calls, and the longest packet each interface
+//! has to buffer.
+//!
+//! Values are counted at the size they take in the C runtime on a 32-bit
+//! board.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::automaton::Dfa;
+use crate::codegen::framing_handlers;
+use crate::diagnostic::Diagnostic;
+use crate::ir::{Body, BodyId, BodyKind, Callee, Instruction, Program};
+use crate::parser::Guard;
+
+/// Bytes of a value in the C runtime on a 32-bit board: its kind, a 64-bit
+/// integer and two pointers, aligned to 8 bytes.
+pub const VALUE_BYTES: usize = 24;
+
+/// The limits `nxc build` checks the report against, in bytes.
+#[derive(Debug, Default)]
+pub struct Budget {
+    /// Static RAM of the records and stored variables.
+    pub ram: Option<usize>,
+    /// The stack of any handler or task.
+    pub stack: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct Report {
+    /// What takes static RAM, with its size in bytes.
+    pub statics: Vec<(String, usize)>,
+    pub stacks: Vec<Stack>,
+    pub packets: Vec<Packet>,
+}
+
+/// The stack of a `when` handler, a task or a function started as a task.
+#[derive(Debug)]
+pub struct Stack {
+    pub body: String,
+    /// Slots of the body's own frame.
+    pub frame: usize,
+    /// How deep its calls nest and the slots of its deepest frames together,
+    /// or `None` when it reaches a recursive call.
+    pub calls: Option<(usize, usize)>,
+}
+
+/// The longest packet an interface buffers.
+#[derive(Debug)]
+pub struct Packet {
+    pub interface: String,
+    /// Bytes, or `None` when a guard pattern has no limit and packets are
+    /// only cut at the runtime's maximum packet length.
+    pub bytes: Option<usize>,
+    /// The packet of the handler that asks for it, with its guard.
+    pub packet: String,
+}
+
+impl Report {
+    pub fn static_bytes(&self) -> usize {
+        self.statics.iter().map(|(_, bytes)| bytes).sum()
+    }
+
+    /// The largest stack of any handler or task, in bytes, or `None` when
+    /// one of them has no limit.
+    pub fn stack_bytes(&self) -> Option<usize> {
+        self.stacks.iter().try_fold(0, |largest, stack| {
+            stack
+                .calls
+                .map(|(_, slots)| largest.max(slots * VALUE_BYTES))
+        })
+    }
+
+    /// An error for every limit of `budget` the program goes over.
+    pub fn check(&self, budget: &Budget) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        if let Some(ram) = budget.ram {
+            if self.static_bytes() > ram {
+                diagnostics.push(Diagnostic::error(format!(
+                    "records and stored variables take {} bytes of RAM, over the budget of {}",
+                    self.static_bytes(),
+                    ram
+                )));
+            }
+        }
+        if let Some(budget) = budget.stack {
+            for stack in self.stacks.iter() {
+                match stack.calls {
+                    Some((_, slots)) if slots * VALUE_BYTES <= budget => {}
+                    Some((_, slots)) => diagnostics.push(Diagnostic::error(format!(
+                        "{} needs {} bytes of stack, over the budget of {}",
+                        stack.body,
+                        slots * VALUE_BYTES,
+                        budget
+                    ))),
+                    None => diagnostics.push(Diagnostic::error(format!(
+                        "{} makes recursive calls, so its stack has no limit to fit the budget of {}",
+                        stack.body, budget
+                    ))),
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Measures the memory `program` needs.
+pub fn analyze(program: &Program) -> Report {
+    let mut statics = vec![];
+    for record in program.records.iter() {
+        statics.push((
+            format!(
+                "record {}[{}, {}]",
+                record.name, record.length, record.data_size
+            ),
+            record.length * record.data_size,
+        ));
+    }
+    if !program.globals.is_empty() {
+        statics.push((
+            format!("store {}", program.globals.join(", ")),
+            program.globals.len() * VALUE_BYTES,
+        ));
+    }
+    for body in program.bodies.iter().filter(|body| !body.stored.is_empty()) {
+        statics.push((
+            format!("store {} in {}", body.stored.join(", "), body.name()),
+            body.stored.len() * VALUE_BYTES,
+        ));
+    }
+
+    let mut callees: HashMap<BodyId, Vec<BodyId>> = HashMap::new();
+    for (id, instruction) in instructions(program) {
+        if let Instruction::Call {
+            callee: Callee::Function(callee),
+            ..
+        } = instruction
+        {
+            callees.entry(id).or_default().push(*callee);
+        }
+    }
+    let mut memo = HashMap::new();
+    let stacks = program
+        .run_bodies()
+        .into_iter()
+        .map(|id| {
+            let body = &program.bodies[id];
+            Stack {
+                body: label(body),
+                frame: body.locals.len() + body.temps,
+                calls: deepest(program, &callees, id, &mut memo, &mut vec![]),
+            }
+        })
+        .collect();
+
+    let packets = program
+        .interfaces
+        .iter()
+        .map(|interface| {
+            let mut largest: Option<(Option<usize>, BodyId)> = None;
+            for (id, guard) in framing_handlers(program, interface) {
+                let bytes = packet_length(guard);
+                let larger = match largest {
+                    None => true,
+                    Some((largest, _)) => match (bytes, largest) {
+                        (_, None) => false,
+                        (None, Some(_)) => true,
+                        (Some(bytes), Some(largest)) => bytes > largest,
+                    },
+                };
+                if larger {
+                    largest = Some((bytes, id));
+                }
+            }
+            let (bytes, id) = largest.expect("interfaces have handlers");
+            let packet = match &program.bodies[id].kind {
+                BodyKind::When { guard, .. } => {
+                    format!("{}{}", program.bodies[id].locals[0], guard)
+                }
+                _ => unreachable!("dispatch tables only hold 'when' handlers"),
+            };
+            Packet {
+                interface: interface.name.clone(),
+                bytes,
+                packet,
+            }
+        })
+        .collect();
+
+    Report {
+        statics,
+        stacks,
+        packets,
+    }
+}
+
+/// The length of the longest packet `guard` frames, or `None` when a
+/// pattern sets no limit.
+pub(crate) fn packet_length(guard: &Guard) -> Option<usize> {
+    match guard {
+        Guard::Default => Some(1),
+        Guard::Numeric { width, .. } => Some(*width),
+        Guard::Regex { pattern, .. } => Dfa::new(pattern).ok().and_then(|dfa| dfa.longest_match()),
+    }
+}
+
+/// How a body the runtime runs on its own is named in reports.
+pub(crate) fn label(body: &Body) -> String {
+    match body.kind {
+        BodyKind::Function { .. } => format!("function {} (started)", body.name()),
+        BodyKind::Task { .. } => format!("task {}", body.name()),
+        BodyKind::When { .. } => body.name(),
+    }
+}
+
+/// Every instruction of `program`, with the body it belongs to.
+fn instructions(program: &Program) -> impl Iterator<Item = (BodyId, &Instruction)> {
+    program.bodies.iter().enumerate().flat_map(|(id, body)| {
+        body.blocks
+            .iter()
+            .flat_map(|block| block.instructions.iter())
+            .map(move |instruction| (id, instruction))
+    })
+}
+
+/// How deep the calls of body `id` nest and the slots of its frame and of
+/// its deepest calls, or `None` when a call leads back to a body on the way.
+fn deepest(
+    program: &Program,
+    callees: &HashMap<BodyId, Vec<BodyId>>,
+    id: BodyId,
+    memo: &mut HashMap<BodyId, Option<(usize, usize)>>,
+    path: &mut Vec<BodyId>,
+) -> Option<(usize, usize)> {
+    if let Some(&calls) = memo.get(&id) {
+        return calls;
+    }
+    if path.contains(&id) {
+        return None;
+    }
+
+    path.push(id);
+    let mut calls = Some((0, 0));
+    for &callee in callees.get(&id).into_iter().flatten() {
+        let inner = deepest(program, callees, callee, memo, path);
+        calls = calls
+            .zip(inner)
+            .map(|((depth, slots), (inner_depth, inner_slots))| {
+                (depth.max(inner_depth + 1), slots.max(inner_slots))
+            });
+    }
+    path.pop();
+
+    let body = &program.bodies[id];
+    let calls = calls.map(|(depth, slots)| (depth, slots + body.locals.len() + body.temps));
+    memo.insert(id, calls);
+    calls
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let interfaces: Vec<String> = self
+            .packets
+            .iter()
+            .map(|packet| format!("\"{}\"", packet.interface))
+            .collect();
+        let width = self
+            .statics
+            .iter()
+            .map(|(what, _)| what)
+            .chain(self.stacks.iter().map(|stack| &stack.body))
+            .chain(interfaces.iter())
+            .map(String::len)
+            .max()
+            .unwrap_or(0);
+
+        writeln!(
+            f,
+            "Memory report ({}-byte values, as in the C runtime on a 32-bit board):",
+            VALUE_BYTES
+        )?;
+
+        writeln!(f, "  Static RAM: {} bytes", self.static_bytes())?;
+        for (what, bytes) in self.statics.iter() {
+            writeln!(f, "    {:<width$}  {} bytes", what, bytes, width = width)?;
+        }
+
+        match self.stack_bytes() {
+            Some(bytes) => writeln!(f, "  Stacks: up to {} bytes", bytes)?,
+            None => writeln!(f, "  Stacks: no limit, through recursive calls")?,
+        }
+        for stack in self.stacks.iter() {
+            let calls = match stack.calls {
+                Some((depth, slots)) => format!(
+                    "calls {} deep, stack {} slots ({} bytes)",
+                    depth,
+                    slots,
+                    slots * VALUE_BYTES
+                ),
+                None => "recursive calls, no limit".to_string(),
+            };
+            writeln!(
+                f,
+                "    {:<width$}  frame {} slots, {}",
+                stack.body,
+                stack.frame,
+                calls,
+                width = width
+            )?;
+        }
+
+        writeln!(f, "  Packet buffers:")?;
+        for (packet, interface) in self.packets.iter().zip(interfaces.iter()) {
+            let bytes = match packet.bytes {
+                Some(bytes) => format!("{} bytes", bytes),
+                None => "up to the maximum packet length".to_string(),
+            };
+            writeln!(
+                f,
+                "    {:<width$}  {}, for {}",
+                interface,
+                bytes,
+                packet.packet,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parser};
+
+    #[test]
+    fn measures_records_stacks_and_packets() {
+        let source = "
+            store boots;
+
+            function sq(x)
+                return x * x;
+            end
+
+            function area(w, h)
+                return sq(w) * h;
+            end
+
+            function fact(n)
+                if (n < 2)
+                    return 1;
+                end
+                return n * fact(n - 1);
+            end
+
+            record samples[16, 2];
+
+            task main @ 1000
+                print(\"%d\", area(2, 3));
+            end
+
+            task deep @ 1000
+                print(\"%d\", fact(3));
+            end
+
+            when \"uart\" => frame::\":..\\x3f\"
+                print(\"%a\", frame);
+            end
+
+            when \"uart\" => line::\".*\\x0a\"
+                print(\"%a\", line);
+            end
+
+            when \"spi\" => word::-2
+                print(\"%d\", word);
+            end
+        ";
+        let program = ir::lower(&parser::parse_source(source)).unwrap();
+        let report = analyze(&program);
+
+        assert_eq!(report.static_bytes(), 32 + VALUE_BYTES);
+        let calls: Vec<_> = report
+            .stacks
+            .iter()
+            .map(|stack| (stack.body.as_str(), stack.calls.map(|(depth, _)| depth)))
+            .collect();
+        assert_eq!(calls[..2], [("task main", Some(2)), ("task deep", None)]);
+        assert_eq!(report.stack_bytes(), None);
+        let packets: Vec<_> = report
+            .packets
+            .iter()
+            .map(|packet| (packet.interface.as_str(), packet.bytes))
+            .collect();
+        assert_eq!(packets, [("uart", None), ("spi", Some(2))]);
+
+        let budget = Budget {
+            ram: Some(32 + VALUE_BYTES),
+            stack: Some(1000),
+        };
+        let errors: Vec<_> = report
+            .check(&budget)
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+        assert_eq!(
+            errors,
+            ["task deep makes recursive calls, so its stack has no limit to fit the budget of 1000"]
+        );
+    }
+}
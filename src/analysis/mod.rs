@@ -0,0 +1,187 @@
+use crate::parser::{Expression, Statement};
+
+pub mod cost;
+pub mod dispatch;
+pub mod matching;
+pub mod memory;
+pub mod records;
+
+/// Calls `f` on every statement of `body`, visiting each statement before the
+/// statements nested in it.
+pub fn walk_statements<'a>(body: &'a [Statement], f: &mut impl FnMut(&'a Statement)) {
+    for statement in body {
+        f(statement);
+        match statement {
+            Statement::If {
+                body,
+                elif,
+                else_body,
+                ..
+            } => {
+                walk_statements(body, f);
+                for (_, elif_body) in elif {
+                    walk_statements(elif_body, f);
+                }
+                walk_statements(else_body, f);
+            }
+            Statement::For { body, .. } | Statement::While { body, .. } => walk_statements(body, f),
+            Statement::Match { cases, default, .. } => {
+                for arm in cases {
+                    walk_statements(&arm.body, f);
+                }
+                if let Some(default) = default {
+                    walk_statements(default, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Calls `f` on every statement of `body` the way `walk_statements` does,
+/// letting it change them.
+pub fn walk_statements_mut(body: &mut [Statement], f: &mut impl FnMut(&mut Statement)) {
+    for statement in body {
+        f(statement);
+        match statement {
+            Statement::If {
+                body,
+                elif,
+                else_body,
+                ..
+            } => {
+                walk_statements_mut(body, f);
+                for (_, elif_body) in elif {
+                    walk_statements_mut(elif_body, f);
+                }
+                walk_statements_mut(else_body, f);
+            }
+            Statement::For { body, .. } | Statement::While { body, .. } => {
+                walk_statements_mut(body, f)
+            }
+            Statement::Match { cases, default, .. } => {
+                for arm in cases {
+                    walk_statements_mut(&mut arm.body, f);
+                }
+                if let Some(default) = default {
+                    walk_statements_mut(default, f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The expressions written directly in `statement`, without those of the
+/// statements nested in it.
+pub fn statement_expressions(statement: &Statement) -> Vec<&Expression> {
+    match statement {
+        Statement::Assignment { expression, .. }
+        | Statement::AssignmentSum { expression, .. }
+        | Statement::AssignmentMinus { expression, .. }
+        | Statement::AssignmentMult { expression, .. }
+        | Statement::AssignmentDiv { expression, .. }
+        | Statement::AssignmentMod { expression, .. }
+        | Statement::Unpack { expression, .. }
+        | Statement::Return { expression } => vec![expression],
+        Statement::If {
+            condition, elif, ..
+        } => std::iter::once(condition)
+            .chain(elif.iter().map(|(condition, _)| condition))
+            .collect(),
+        Statement::While { condition, .. } => vec![condition],
+        Statement::Match { target, .. } => vec![target],
+        Statement::FunctionCall(call) => call.arguments.iter().collect(),
+        Statement::ElementAssignment {
+            index, expression, ..
+        } => vec![index, expression],
+        Statement::Start {
+            function_call,
+            interval,
+        } => function_call
+            .arguments
+            .iter()
+            .chain(std::iter::once(interval))
+            .collect(),
+        Statement::Delay { .. } | Statement::Store { .. } | Statement::For { .. } => vec![],
+    }
+}
+
+/// The expressions written directly in `statement`, as
+/// `statement_expressions` gives them, to change.
+pub fn statement_expressions_mut(statement: &mut Statement) -> Vec<&mut Expression> {
+    match statement {
+        Statement::Assignment { expression, .. }
+        | Statement::AssignmentSum { expression, .. }
+        | Statement::AssignmentMinus { expression, .. }
+        | Statement::AssignmentMult { expression, .. }
+        | Statement::AssignmentDiv { expression, .. }
+        | Statement::AssignmentMod { expression, .. }
+        | Statement::Unpack { expression, .. }
+        | Statement::Return { expression } => vec![expression],
+        Statement::If {
+            condition, elif, ..
+        } => std::iter::once(condition)
+            .chain(elif.iter_mut().map(|(condition, _)| condition))
+            .collect(),
+        Statement::While { condition, .. } => vec![condition],
+        Statement::Match { target, .. } => vec![target],
+        Statement::FunctionCall(call) => call.arguments.iter_mut().collect(),
+        Statement::ElementAssignment {
+            index, expression, ..
+        } => vec![index, expression],
+        Statement::Start {
+            function_call,
+            interval,
+        } => function_call
+            .arguments
+            .iter_mut()
+            .chain(std::iter::once(interval))
+            .collect(),
+        Statement::Delay { .. } | Statement::Store { .. } | Statement::For { .. } => vec![],
+    }
+}
+
+/// Calls `f` on `expression` and then on every expression nested in it.
+pub fn walk_expression<'a>(expression: &'a Expression, f: &mut impl FnMut(&'a Expression)) {
+    f(expression);
+    match expression {
+        Expression::Literal(_) | Expression::Variable(_) => {}
+        Expression::List(items) => {
+            for item in items {
+                walk_expression(item, f);
+            }
+        }
+        Expression::Element { index, .. } => walk_expression(index, f),
+        Expression::Equal(left, right)
+        | Expression::NotEqual(left, right)
+        | Expression::Less(left, right)
+        | Expression::Greater(left, right)
+        | Expression::LessOrEqual(left, right)
+        | Expression::GreaterOrEqual(left, right)
+        | Expression::And(left, right)
+        | Expression::Or(left, right)
+        | Expression::Xor(left, right)
+        | Expression::Sum(left, right)
+        | Expression::Minus(left, right)
+        | Expression::Multiply(left, right)
+        | Expression::Division(left, right)
+        | Expression::Modulus(left, right)
+        | Expression::Time(left, right) => {
+            walk_expression(left, f);
+            walk_expression(right, f);
+        }
+        Expression::Not(inner) | Expression::Guard(inner, _) => walk_expression(inner, f),
+        Expression::Pipe(inner, call) => {
+            walk_expression(inner, f);
+            for argument in call.arguments.iter() {
+                walk_expression(argument, f);
+            }
+        }
+        Expression::FunctionCall(call) => {
+            for argument in call.arguments.iter() {
+                walk_expression(argument, f);
+            }
+        }
+    }
+}
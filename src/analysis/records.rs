@@ -0,0 +1,181 @@
+use crate::analysis::{statement_expressions, walk_expression, walk_statements};
+use crate::diagnostic::Diagnostic;
+use crate::parser::{fits_width, Expression, Literal, Statement, AST};
+use crate::token::Span;
+use std::collections::HashMap;
+
+/// Rejects records declared twice, indexing of names that are not records,
+/// and the constant indices and values that are out of range for a record.
+/// Other indices and values are checked when they run.
+pub fn check(program: &[AST]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    let mut records = HashMap::new();
+    for ast in program {
+        if let AST::Record {
+            name,
+            length,
+            data_size,
+        } = ast
+        {
+            if records.contains_key(name) {
+                diagnostics.push(Diagnostic::error(format!(
+                    "record {} is declared more than once",
+                    name
+                )));
+                continue;
+            }
+            records.insert(name, (*length, *data_size));
+        }
+    }
+
+    for ast in program {
+        let mut elements = vec![];
+        walk_statements(ast.body(), &mut |statement| {
+            if let Statement::ElementAssignment {
+                record,
+                index,
+                expression,
+                span,
+            } = statement
+            {
+                elements.push((record, index, Some(expression), *span));
+            }
+            for expression in statement_expressions(statement) {
+                walk_expression(expression, &mut |expression| {
+                    if let Expression::Element {
+                        record,
+                        index,
+                        span,
+                    } = expression
+                    {
+                        elements.push((record, index, None, *span));
+                    }
+                });
+            }
+        });
+
+        for (record, index, value, span) in elements {
+            check_element(&records, record, index, value, span, &mut diagnostics);
+        }
+    }
+
+    diagnostics
+}
+
+fn check_element(
+    records: &HashMap<&String, (usize, usize)>,
+    record: &String,
+    index: &Expression,
+    value: Option<&Expression>,
+    span: Span,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let (length, data_size) = match records.get(record) {
+        Some(record) => *record,
+        None => {
+            diagnostics.push(
+                Diagnostic::error(format!("{} is not a record", record))
+                    .with_label(span, "indexed here"),
+            );
+            return;
+        }
+    };
+
+    if let Expression::Literal(Literal::Integer(index)) = index {
+        if *index < 0 || *index as usize >= length {
+            diagnostics.push(
+                Diagnostic::error(format!(
+                    "index {} is out of bounds for {} of length {}",
+                    index, record, length
+                ))
+                .with_label(span, "indexed here"),
+            );
+        }
+    }
+
+    if let Some(Expression::Literal(Literal::Integer(value))) = value {
+        if !fits_width(*value, data_size) {
+            diagnostics.push(
+                Diagnostic::error(format!(
+                    "{} does not fit in the {} byte(s) of an element of {}",
+                    value, data_size, record
+                ))
+                .with_label(span, "assigned here"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    /// The errors for `source`, each with the line of its label.
+    fn errors(source: &str) -> Vec<(String, usize)> {
+        let program = parser::parse_source(source);
+        check(&program)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.message, diagnostic.labels[0].0.line))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_elements_in_range() {
+        let source = "record r[2, 2];
+task t
+    r[1] = 65535;
+    r[0] = -32768;
+    x = r[1];
+end";
+        assert_eq!(errors(source), []);
+    }
+
+    #[test]
+    fn reports_constant_indices_out_of_bounds() {
+        let source = "record r[2];
+task t
+    r[2] = 1;
+    x = r[-1];
+end";
+        assert_eq!(
+            errors(source),
+            [
+                ("index 2 is out of bounds for r of length 2".to_string(), 3),
+                ("index -1 is out of bounds for r of length 2".to_string(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_constant_values_that_do_not_fit() {
+        let source = "record r[2, 1];
+task t
+    r[0] = 256;
+    r[1] = -129;
+end";
+        assert_eq!(
+            errors(source),
+            [
+                (
+                    "256 does not fit in the 1 byte(s) of an element of r".to_string(),
+                    3
+                ),
+                (
+                    "-129 does not fit in the 1 byte(s) of an element of r".to_string(),
+                    4
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_indexing_of_other_names() {
+        let source = "record r[2];
+task t
+    x = s[0];
+end";
+        assert_eq!(errors(source), [("s is not a record".to_string(), 3)]);
+    }
+}
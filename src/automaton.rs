@@ -0,0 +1,313 @@
+use crate::parser::Guard;
+use regex_syntax::hir::{self, Hir, HirKind, RepetitionKind, RepetitionRange};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Most states a guard pattern can compile to.
+const MAX_STATES: usize = 4096;
+
+/// A deterministic automaton that tells whether a whole packet matches a
+/// guard pattern, for targets without a regex engine and for comparing
+/// guards before the program runs.
+///
+/// Bytes that the pattern never tells apart share a class, so the table
+/// holds one row of `class_count` next states per state. State 0 is the
+/// dead state, which no packet leaves, and state 1 is the start.
+#[derive(Debug)]
+pub struct Dfa {
+    /// The class of every byte.
+    pub classes: Vec<u8>,
+    pub class_count: usize,
+    pub transitions: Vec<u16>,
+    pub accepting: Vec<bool>,
+}
+
+/// Which packets two patterns tell apart, from `Dfa::overlap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overlap {
+    /// Some packet matches both patterns.
+    pub both: bool,
+    /// Some packet matches only the first pattern.
+    pub only_first: bool,
+    /// Some packet matches only the second pattern.
+    pub only_second: bool,
+}
+
+/// A Thompson automaton, built from the end of the pattern backwards.
+enum State {
+    Ranges(Vec<(u8, u8)>, usize),
+    Split(Vec<usize>),
+    Match,
+}
+
+impl Dfa {
+    pub fn new(pattern: &str) -> Result<Dfa, String> {
+        let hir = Guard::parse_pattern(pattern)?;
+        let mut states = vec![State::Match];
+        let start = compile(&hir, 0, &mut states)
+            .map_err(|what| format!("The guard pattern \"{}\" uses {}", pattern, what))?;
+
+        let classes = byte_classes(&states);
+        let class_count = classes[255] as usize + 1;
+        let representatives: Vec<u8> = (0..=255u8)
+            .filter(|&b| b == 0 || classes[b as usize] != classes[b as usize - 1])
+            .collect();
+
+        let mut sets = vec![BTreeSet::new(), closure(&states, [start])];
+        let mut ids: HashMap<BTreeSet<usize>, usize> = sets
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(id, set)| (set, id))
+            .collect();
+        let mut transitions = vec![0; 2 * class_count];
+
+        let mut next = 1;
+        while next < sets.len() {
+            for (class, &b) in representatives.iter().enumerate() {
+                let moved = sets[next].iter().filter_map(|&id| match &states[id] {
+                    State::Ranges(ranges, to)
+                        if ranges.iter().any(|r| (r.0..=r.1).contains(&b)) =>
+                    {
+                        Some(*to)
+                    }
+                    _ => None,
+                });
+                let set = closure(&states, moved);
+                let id = match ids.get(&set) {
+                    Some(&id) => id,
+                    None => {
+                        if sets.len() == MAX_STATES {
+                            return Err(format!(
+                                "The guard pattern \"{}\" needs more than {} states",
+                                pattern, MAX_STATES
+                            ));
+                        }
+                        ids.insert(set.clone(), sets.len());
+                        sets.push(set);
+                        transitions.extend(vec![0; class_count]);
+                        sets.len() - 1
+                    }
+                };
+                transitions[next * class_count + class] = id as u16;
+            }
+            next += 1;
+        }
+
+        Ok(Dfa {
+            classes,
+            class_count,
+            transitions,
+            accepting: sets.iter().map(|set| set.contains(&0)).collect(),
+        })
+    }
+
+    /// The length of the longest packet the pattern matches, or `None` when
+    /// a loop lets packets grow without end.
+    pub fn longest_match(&self) -> Option<usize> {
+        let states = self.accepting.len();
+
+        // The states from which a packet can still end in an accepting one.
+        let mut live = self.accepting.clone();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for state in 1..states {
+                if !live[state] && self.next_states(state).any(|next| live[next]) {
+                    live[state] = true;
+                    changed = true;
+                }
+            }
+        }
+
+        if !live[1] {
+            return Some(0);
+        }
+        self.longest_path(1, &live, &mut vec![None; states], &mut vec![false; states])
+    }
+
+    /// Compares the packets this automaton and `other` match, walking both
+    /// at once over every byte.
+    pub fn overlap(&self, other: &Dfa) -> Overlap {
+        let mut overlap = Overlap {
+            both: false,
+            only_first: false,
+            only_second: false,
+        };
+        let mut seen = HashSet::new();
+        let mut pending = vec![(1, 1)];
+        seen.insert((1, 1));
+        while let Some((first, second)) = pending.pop() {
+            match (self.accepting[first], other.accepting[second]) {
+                (true, true) => overlap.both = true,
+                (true, false) => overlap.only_first = true,
+                (false, true) => overlap.only_second = true,
+                (false, false) => {}
+            }
+            for b in 0..=255 {
+                let next = (self.next(first, b), other.next(second, b));
+                if next != (0, 0) && seen.insert(next) {
+                    pending.push(next);
+                }
+            }
+        }
+        overlap
+    }
+
+    fn next(&self, state: usize, b: u8) -> usize {
+        self.transitions[state * self.class_count + self.classes[b as usize] as usize] as usize
+    }
+
+    fn next_states(&self, state: usize) -> impl Iterator<Item = usize> + '_ {
+        self.transitions[state * self.class_count..(state + 1) * self.class_count]
+            .iter()
+            .map(|&next| next as usize)
+    }
+
+    /// The longest way from `state` through live states to an accepting
+    /// one, or `None` when it can loop.
+    fn longest_path(
+        &self,
+        state: usize,
+        live: &[bool],
+        memo: &mut Vec<Option<Option<usize>>>,
+        visiting: &mut Vec<bool>,
+    ) -> Option<usize> {
+        if let Some(length) = memo[state] {
+            return length;
+        }
+        if visiting[state] {
+            return None;
+        }
+        visiting[state] = true;
+        let mut length = Some(0);
+        for next in self.next_states(state).filter(|&next| live[next]) {
+            let rest = self.longest_path(next, live, memo, visiting);
+            length = length.zip(rest).map(|(length, rest)| length.max(rest + 1));
+        }
+        visiting[state] = false;
+        memo[state] = Some(length);
+        length
+    }
+}
+
+/// Adds the states that match `hir` and then go on at `next`, returning the
+/// first one. Fails with what the automaton cannot express.
+fn compile(hir: &Hir, next: usize, states: &mut Vec<State>) -> Result<usize, &'static str> {
+    let push = |state: State, states: &mut Vec<State>| {
+        states.push(state);
+        states.len() - 1
+    };
+    Ok(match hir.kind() {
+        HirKind::Empty => next,
+        HirKind::Literal(hir::Literal::Byte(b)) => {
+            push(State::Ranges(vec![(*b, *b)], next), states)
+        }
+        HirKind::Literal(hir::Literal::Unicode(c)) => {
+            let mut buffer = [0; 4];
+            let bytes = c.encode_utf8(&mut buffer).as_bytes();
+            bytes.iter().rev().fold(next, |next, &b| {
+                push(State::Ranges(vec![(b, b)], next), states)
+            })
+        }
+        HirKind::Class(hir::Class::Bytes(class)) => {
+            let ranges = class.iter().map(|r| (r.start(), r.end())).collect();
+            push(State::Ranges(ranges, next), states)
+        }
+        HirKind::Class(hir::Class::Unicode(class)) => {
+            let mut ranges = vec![];
+            for range in class.iter() {
+                if range.end() as u32 > 0x7f {
+                    return Err("a class of non-ASCII characters");
+                }
+                ranges.push((range.start() as u8, range.end() as u8));
+            }
+            push(State::Ranges(ranges, next), states)
+        }
+        HirKind::Anchor(_) => return Err("an anchor"),
+        HirKind::WordBoundary(_) => return Err("a word boundary"),
+        HirKind::Group(group) => compile(&group.hir, next, states)?,
+        HirKind::Concat(items) => {
+            let mut next = next;
+            for item in items.iter().rev() {
+                next = compile(item, next, states)?;
+            }
+            next
+        }
+        HirKind::Alternation(items) => {
+            let mut targets = vec![];
+            for item in items {
+                targets.push(compile(item, next, states)?);
+            }
+            push(State::Split(targets), states)
+        }
+        HirKind::Repetition(repetition) => {
+            let (min, max) = match &repetition.kind {
+                RepetitionKind::ZeroOrOne => (0, Some(1)),
+                RepetitionKind::ZeroOrMore => (0, None),
+                RepetitionKind::OneOrMore => (1, None),
+                RepetitionKind::Range(RepetitionRange::Exactly(n)) => (*n, Some(*n)),
+                RepetitionKind::Range(RepetitionRange::AtLeast(n)) => (*n, None),
+                RepetitionKind::Range(RepetitionRange::Bounded(n, m)) => (*n, Some(*m)),
+            };
+
+            let mut start = match max {
+                None => {
+                    let split = push(State::Split(vec![]), states);
+                    let body = compile(&repetition.hir, split, states)?;
+                    states[split] = State::Split(vec![body, next]);
+                    split
+                }
+                Some(max) => {
+                    let mut start = next;
+                    for _ in min..max {
+                        let body = compile(&repetition.hir, start, states)?;
+                        start = push(State::Split(vec![body, next]), states);
+                    }
+                    start
+                }
+            };
+            for _ in 0..min {
+                start = compile(&repetition.hir, start, states)?;
+            }
+            start
+        }
+    })
+}
+
+/// The states reachable from `from` without reading a byte.
+fn closure(states: &[State], from: impl IntoIterator<Item = usize>) -> BTreeSet<usize> {
+    let mut set = BTreeSet::new();
+    let mut pending: Vec<usize> = from.into_iter().collect();
+    while let Some(id) = pending.pop() {
+        if set.insert(id) {
+            if let State::Split(targets) = &states[id] {
+                pending.extend(targets);
+            }
+        }
+    }
+    set
+}
+
+/// Numbers the runs of bytes that every range of the automaton either
+/// contains or leaves out entirely.
+fn byte_classes(states: &[State]) -> Vec<u8> {
+    let mut boundaries = [false; 257];
+    for state in states {
+        if let State::Ranges(ranges, _) = state {
+            for &(start, end) in ranges {
+                boundaries[start as usize] = true;
+                boundaries[end as usize + 1] = true;
+            }
+        }
+    }
+
+    let mut class = 0u8;
+    (0..256)
+        .map(|b| {
+            if b > 0 && boundaries[b] {
+                class += 1;
+            }
+            class
+        })
+        .collect()
+}
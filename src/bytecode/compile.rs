@@ -0,0 +1,566 @@
+use crate::bytecode::{Code, Module, Op, BUILTINS};
+use crate::ir::{
+    BinaryOperator, Body, BodyKind, Callee, Constant, Instruction, Operand, Program, Temp,
+    Terminator, Variable,
+};
+use crate::parser::{Endianness, Guard};
+use std::convert::TryFrom;
+
+/// Compiles a verified program to bytecode.
+///
+/// Blocks are laid out in the order of the IR, so a jump to the next block
+/// is left out. A temporary that is read once stays on the stack instead of
+/// going through its slot, as long as it is read in the same block and in
+/// the order the stack gives it back.
+pub fn compile(program: &Program) -> Result<Module, String> {
+    let mut constants = vec![];
+    let mut bodies = vec![];
+    for body in program.bodies.iter() {
+        let code = BodyCompiler::new(body, &mut constants)
+            .compile()
+            .map_err(|err| format!("{} in {}", err, body.name()))?;
+        bodies.push(code);
+    }
+
+    let module = Module {
+        constants,
+        records: program.records.clone(),
+        globals: program.globals.clone(),
+        bodies,
+        interfaces: program.interfaces.clone(),
+    };
+    check_sizes(&module)?;
+    Ok(module)
+}
+
+/// Rejects a module with a count, length or value too large for the field
+/// that `container::write` stores it in, instead of truncating it.
+fn check_sizes(module: &Module) -> Result<(), String> {
+    fn fits<T: TryFrom<usize>>(value: usize, what: impl FnOnce() -> String) -> Result<(), String> {
+        T::try_from(value)
+            .map(|_| ())
+            .map_err(|_| format!("{}, more than a bytecode file holds", what()))
+    }
+    let count = |items: usize, what: &str| {
+        fits::<u16>(items, || format!("The program has {} {}", items, what))
+    };
+    let name = |name: &str| fits::<u16>(name.len(), || format!("A name is {} bytes", name.len()));
+
+    count(module.constants.len(), "constants")?;
+
+    count(module.records.len(), "records")?;
+    for record in module.records.iter() {
+        name(&record.name)?;
+        fits::<u32>(record.length, || {
+            format!("The record {} has {} elements", record.name, record.length)
+        })?;
+        fits::<u8>(record.data_size, || {
+            format!(
+                "The elements of record {} are {} bytes",
+                record.name, record.data_size
+            )
+        })?;
+    }
+
+    count(module.globals.len(), "globals")?;
+    module.globals.iter().try_for_each(|global| name(global))?;
+
+    count(module.bodies.len(), "functions, tasks and handlers")?;
+    for body in module.bodies.iter() {
+        match &body.kind {
+            BodyKind::Function {
+                name: function,
+                parameters,
+            } => {
+                name(function)?;
+                fits::<u8>(*parameters, || {
+                    format!("The function {} has {} parameters", function, parameters)
+                })?;
+            }
+            BodyKind::Task {
+                name: task,
+                interval_ms,
+            } => {
+                name(task)?;
+                fits::<u32>(*interval_ms, || {
+                    format!("The interval of task {} is {}ms", task, interval_ms)
+                })?;
+            }
+            BodyKind::When { interface, guard } => {
+                name(interface)?;
+                if let Guard::Regex { pattern, .. } = guard {
+                    name(pattern)?;
+                }
+            }
+        }
+        count(body.locals.len(), "locals")?;
+        body.locals.iter().try_for_each(|local| name(local))?;
+        count(body.stored.len(), "stored variables")?;
+        body.stored.iter().try_for_each(|stored| name(stored))?;
+        count(body.slots, "slots")?;
+        count(body.lines.len(), "line changes")?;
+        for (_, line) in body.lines.iter() {
+            fits::<u32>(*line, || format!("The line {}", line))?;
+        }
+    }
+
+    count(module.interfaces.len(), "interfaces")?;
+    for interface in module.interfaces.iter() {
+        name(&interface.name)?;
+        count(interface.handlers.len(), "handlers")?;
+    }
+    Ok(())
+}
+
+/// An instruction with the operand bytes that follow it, which only the
+/// widths of `Op::Unpack` use, and its source line. Jumps hold block ids
+/// until the layout is known.
+type Item = (Op, Vec<u8>, usize);
+
+struct BodyCompiler<'a> {
+    body: &'a Body,
+    constants: &'a mut Vec<Constant>,
+    /// How many times each temporary is read.
+    reads: Vec<usize>,
+    /// The temporaries left on the stack for their only read, from the
+    /// bottom of the stack up.
+    pending: Vec<Temp>,
+    items: Vec<Item>,
+    /// The line of the IR instruction being compiled.
+    line: usize,
+}
+
+impl<'a> BodyCompiler<'a> {
+    fn new(body: &'a Body, constants: &'a mut Vec<Constant>) -> Self {
+        let mut reads = vec![0; body.temps];
+        for block in body.blocks.iter() {
+            let terminator = match &block.terminator {
+                Terminator::Branch { condition, .. } | Terminator::Return(condition) => {
+                    Some(condition)
+                }
+                _ => None,
+            };
+            let operands = block
+                .instructions
+                .iter()
+                .flat_map(Instruction::operands)
+                .chain(terminator);
+            for operand in operands {
+                if let Operand::Temp(temp) = operand {
+                    reads[*temp] += 1;
+                }
+            }
+        }
+
+        Self {
+            body,
+            constants,
+            reads,
+            pending: vec![],
+            items: vec![],
+            line: 0,
+        }
+    }
+
+    fn compile(mut self) -> Result<Code, String> {
+        let body = self.body;
+        let mut blocks = vec![];
+        for (id, block) in body.blocks.iter().enumerate() {
+            for (instruction, line) in block.instructions.iter().zip(block.lines.iter()) {
+                self.line = *line;
+                self.instruction(instruction)?;
+            }
+            self.line = block.lines.last().copied().unwrap_or(self.line);
+            self.terminator(&block.terminator, id + 1)?;
+            blocks.push(std::mem::take(&mut self.items));
+        }
+
+        let mut offsets = vec![];
+        let mut offset = 0;
+        for block in blocks.iter() {
+            offsets.push(offset);
+            offset += block
+                .iter()
+                .map(|(op, operands, _)| op.size() + operands.len())
+                .sum::<usize>();
+        }
+        if offset > u32::MAX as usize {
+            return Err("The body is too long".to_string());
+        }
+
+        let mut code = vec![];
+        let mut lines = vec![];
+        for (op, operands, line) in blocks.into_iter().flatten() {
+            if line != 0 && lines.last().map(|(_, last)| *last) != Some(line) {
+                lines.push((code.len(), line));
+            }
+            let op = match op {
+                Op::Jump(block) => Op::Jump(offsets[block as usize] as u32),
+                Op::JumpIfFalse(block) => Op::JumpIfFalse(offsets[block as usize] as u32),
+                op => op,
+            };
+            op.encode(&mut code);
+            code.extend(operands);
+        }
+
+        Ok(Code {
+            kind: body.kind.clone(),
+            locals: body.locals.clone(),
+            stored: body.stored.clone(),
+            slots: body.locals.len() + body.temps,
+            code,
+            lines,
+        })
+    }
+
+    fn emit(&mut self, op: Op) {
+        self.items.push((op, vec![], self.line));
+    }
+
+    fn constant(&mut self, constant: Constant) -> Result<u16, String> {
+        let index = match self.constants.iter().position(|c| *c == constant) {
+            Some(index) => index,
+            None => {
+                self.constants.push(constant);
+                self.constants.len() - 1
+            }
+        };
+        u16::try_from(index).map_err(|_| "Too many constants".to_string())
+    }
+
+    fn slot(&self, temp: Temp) -> Result<u16, String> {
+        index(self.body.locals.len() + temp, "slots")
+    }
+
+    fn push(&mut self, operand: &Operand) -> Result<(), String> {
+        let op = match operand {
+            Operand::Temp(temp) => Op::LoadLocal(self.slot(*temp)?),
+            Operand::Constant(constant) => Op::Constant(self.constant(constant.clone())?),
+        };
+        self.emit(op);
+        Ok(())
+    }
+
+    /// Pushes the operands of an instruction in order. The leading operands
+    /// already at the top of the stack are left there, and a value loaded
+    /// by the previous instruction is loaded again where it is needed; when
+    /// another operand is still on the stack, every pending temporary is
+    /// stored first.
+    fn push_all<'o>(
+        &mut self,
+        operands: impl IntoIterator<Item = &'o Operand>,
+    ) -> Result<u8, String> {
+        let operands: Vec<&Operand> = operands.into_iter().collect();
+
+        let mut reloads = vec![];
+        let on_stack = loop {
+            if let Some(count) = self.on_stack(&operands) {
+                break count;
+            }
+            match (self.pending.last(), self.items.last()) {
+                (Some(&temp), Some((op, _, _)))
+                    if op.is_load() && operands.contains(&&Operand::Temp(temp)) =>
+                {
+                    reloads.push((temp, *op));
+                    self.pending.pop();
+                    self.items.pop();
+                }
+                _ => {
+                    self.spill()?;
+                    break 0;
+                }
+            }
+        };
+
+        self.pending.truncate(self.pending.len() - on_stack);
+        for operand in &operands[on_stack..] {
+            let reload = reloads
+                .iter()
+                .find(|(temp, _)| **operand == Operand::Temp(*temp));
+            match reload {
+                Some((_, op)) => self.emit(*op),
+                None => self.push(operand)?,
+            }
+        }
+        u8::try_from(operands.len()).map_err(|_| "Too many arguments".to_string())
+    }
+
+    /// How many leading operands are the pending temporaries at the top of
+    /// the stack, or `None` when a later operand is pending too.
+    fn on_stack(&self, operands: &[&Operand]) -> Option<usize> {
+        let count = (0..=operands.len().min(self.pending.len()))
+            .rev()
+            .find(|&count| {
+                let top = &self.pending[self.pending.len() - count..];
+                operands[..count]
+                    .iter()
+                    .zip(top)
+                    .all(|(operand, temp)| **operand == Operand::Temp(*temp))
+            })
+            .unwrap_or(0);
+        let pending = |operand: &&&Operand| match operand {
+            Operand::Temp(temp) => self.pending.contains(temp),
+            _ => false,
+        };
+        match operands[count..].iter().any(|operand| pending(&operand)) {
+            true => None,
+            false => Some(count),
+        }
+    }
+
+    /// Takes the value at the top of the stack as `temp`, leaving it there
+    /// when it is read once and dropping it when it is never read.
+    fn store(&mut self, temp: Temp) -> Result<(), String> {
+        match self.reads[temp] {
+            0 => self.emit(Op::Pop),
+            1 => self.pending.push(temp),
+            _ => self.store_slot(temp)?,
+        }
+        Ok(())
+    }
+
+    fn store_slot(&mut self, temp: Temp) -> Result<(), String> {
+        match self.reads[temp] {
+            0 => self.emit(Op::Pop),
+            _ => self.emit(Op::StoreLocal(self.slot(temp)?)),
+        }
+        Ok(())
+    }
+
+    /// Stores every temporary left on the stack in its slot.
+    fn spill(&mut self) -> Result<(), String> {
+        while let Some(temp) = self.pending.pop() {
+            self.store_slot(temp)?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &Instruction) -> Result<(), String> {
+        match instruction {
+            Instruction::Copy { dest, value } => {
+                self.push_all([value])?;
+                self.store(*dest)?;
+            }
+            Instruction::Load { dest, variable } => {
+                let op = match *variable {
+                    Variable::Local(local) => Op::LoadLocal(index(local, "slots")?),
+                    Variable::Stored(stored) => Op::LoadStored(index(stored, "stored variables")?),
+                    Variable::Global(global) => Op::LoadGlobal(index(global, "globals")?),
+                    Variable::Record(record) => Op::LoadRecord(index(record, "records")?),
+                };
+                self.emit(op);
+                self.store(*dest)?;
+            }
+            Instruction::Store { variable, value } => {
+                self.push_all([value])?;
+                let op = match *variable {
+                    Variable::Local(local) => Op::StoreLocal(index(local, "slots")?),
+                    Variable::Stored(stored) => Op::StoreStored(index(stored, "stored variables")?),
+                    Variable::Global(global) => Op::StoreGlobal(index(global, "globals")?),
+                    Variable::Record(_) => {
+                        return Err("A record cannot be assigned as a whole".to_string())
+                    }
+                };
+                self.emit(op);
+            }
+            Instruction::Not { dest, operand } => {
+                self.push_all([operand])?;
+                self.emit(Op::Not);
+                self.store(*dest)?;
+            }
+            Instruction::Binary {
+                dest,
+                operator,
+                lhs,
+                rhs,
+            } => {
+                self.push_all([lhs, rhs])?;
+                self.emit(binary(*operator));
+                self.store(*dest)?;
+            }
+            Instruction::List { dest, items } => {
+                self.push_all(items)?;
+                let count = u16::try_from(items.len()).map_err(|_| "The list is too long")?;
+                self.emit(Op::MakeList(count));
+                self.store(*dest)?;
+            }
+            Instruction::Pack {
+                dest,
+                value,
+                width,
+                endianness,
+            } => {
+                self.push_all([value])?;
+                self.emit(Op::Pack(signed_width(*width, *endianness)));
+                self.store(*dest)?;
+            }
+            Instruction::Unpack { fields, bytes } => {
+                self.push_all([bytes])?;
+                let count = u8::try_from(fields.len()).map_err(|_| "Too many fields")?;
+                let widths = fields
+                    .iter()
+                    .map(|(_, width, endianness)| signed_width(*width, *endianness) as u8)
+                    .collect();
+                self.items.push((Op::Unpack(count), widths, self.line));
+                for (temp, _, _) in fields.iter().rev() {
+                    self.store_slot(*temp)?;
+                }
+            }
+            Instruction::Element {
+                dest,
+                record,
+                index: element,
+            } => {
+                self.push_all([element])?;
+                self.emit(Op::GetElement(index(*record, "records")?));
+                self.store(*dest)?;
+            }
+            Instruction::SetElement {
+                record,
+                index: element,
+                value,
+            } => {
+                self.push_all([element, value])?;
+                self.emit(Op::SetElement(index(*record, "records")?));
+            }
+            Instruction::Items { dest, collection } => {
+                self.push_all([collection])?;
+                self.emit(Op::Items);
+                self.store(*dest)?;
+            }
+            Instruction::Length { dest, list } => {
+                self.push_all([list])?;
+                self.emit(Op::Length);
+                self.store(*dest)?;
+            }
+            Instruction::Index { dest, list, index } => {
+                self.push_all([list, index])?;
+                self.emit(Op::Index);
+                self.store(*dest)?;
+            }
+            Instruction::InRange {
+                dest,
+                value,
+                start,
+                end,
+            } => {
+                self.push_all([value])?;
+                let start = self.constant(Constant::Integer(*start))?;
+                let end = self.constant(Constant::Integer(*end))?;
+                self.emit(Op::InRange(start, end));
+                self.store(*dest)?;
+            }
+            Instruction::Call { dest, callee, args } => {
+                let argc = self.push_all(args)?;
+                let op = match callee {
+                    Callee::Builtin(builtin) => {
+                        let id = BUILTINS
+                            .iter()
+                            .position(|name| *name == builtin.name())
+                            .expect("every builtin has a number");
+                        Op::Builtin(id as u8, argc)
+                    }
+                    Callee::Function(body) => Op::Call(index(*body, "bodies")?, argc),
+                };
+                self.emit(op);
+                self.store(*dest)?;
+            }
+            Instruction::Start {
+                dest,
+                target,
+                args,
+                interval,
+            } => {
+                let operands = std::iter::once(target).chain(args).chain(interval);
+                self.push_all(operands)?;
+                let argc = u8::try_from(args.len()).map_err(|_| "Too many arguments")?;
+                self.emit(Op::Start(argc, interval.is_some()));
+                self.store(*dest)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, next: usize) -> Result<(), String> {
+        let jump = |block: usize| Op::Jump(block as u32);
+        match terminator {
+            Terminator::Jump(target) if *target == next => self.spill()?,
+            Terminator::Jump(target) => {
+                self.spill()?;
+                self.emit(jump(*target));
+            }
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                self.push_last(condition)?;
+                if *otherwise == next {
+                    self.emit(Op::Not);
+                    self.emit(Op::JumpIfFalse(*then as u32));
+                } else {
+                    self.emit(Op::JumpIfFalse(*otherwise as u32));
+                    if *then != next {
+                        self.emit(jump(*then));
+                    }
+                }
+            }
+            Terminator::Suspend { delay_ms, resume } => {
+                let delay_ms = u32::try_from(*delay_ms).map_err(|_| "The delay is too long")?;
+                self.spill()?;
+                self.emit(Op::Delay(delay_ms));
+                if *resume != next {
+                    self.emit(jump(*resume));
+                }
+            }
+            Terminator::Return(value) => {
+                self.push_last(value)?;
+                self.emit(Op::Return);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes the operand of a terminator, which must leave nothing else
+    /// of the block on the stack.
+    fn push_last(&mut self, operand: &Operand) -> Result<(), String> {
+        if self.pending.len() > 1
+            || self
+                .pending
+                .first()
+                .is_some_and(|temp| *operand != Operand::Temp(*temp))
+        {
+            self.spill()?;
+        }
+        self.push_all([operand])?;
+        Ok(())
+    }
+}
+
+fn index(index: usize, what: &str) -> Result<u16, String> {
+    u16::try_from(index).map_err(|_| format!("Too many {}", what))
+}
+
+fn signed_width(width: usize, endianness: Endianness) -> i8 {
+    match endianness {
+        Endianness::Little => width as i8,
+        Endianness::Big => -(width as i8),
+    }
+}
+
+fn binary(operator: BinaryOperator) -> Op {
+    match operator {
+        BinaryOperator::Sum => Op::Add,
+        BinaryOperator::Minus => Op::Sub,
+        BinaryOperator::Multiply => Op::Mul,
+        BinaryOperator::Division => Op::Div,
+        BinaryOperator::Modulus => Op::Mod,
+        BinaryOperator::Equal => Op::Equal,
+        BinaryOperator::NotEqual => Op::NotEqual,
+        BinaryOperator::Less => Op::Less,
+        BinaryOperator::Greater => Op::Greater,
+        BinaryOperator::LessOrEqual => Op::LessOrEqual,
+        BinaryOperator::GreaterOrEqual => Op::GreaterOrEqual,
+        BinaryOperator::Xor => Op::Xor,
+    }
+}
@@ -0,0 +1,411 @@
+use crate::bytecode::{Code, Module};
+use crate::ir::{BodyKind, Constant, Interface, Record};
+use crate::parser::{Endianness, Framing, Guard, MAX_GUARD_WIDTH};
+use std::convert::TryInto;
+
+/// The first bytes of every `.nxb` file.
+const MAGIC: &[u8; 4] = b"NXB1";
+const VERSION: u16 = 1;
+/// Starts the optional section that maps code offsets to source lines.
+const DEBUG: &[u8; 4] = b"DBG1";
+
+/// Encodes a module as the contents of a `.nxb` file: the magic and the
+/// version, then the constant pool, the records, the globals, the bodies
+/// with their kind and code, and the `when` dispatch table of each
+/// interface. When some body has line information, a debug section
+/// follows with the `u32` offset and line pairs of every body.
+///
+/// Integers are little-endian, counts are `u16` and strings are prefixed by
+/// their `u16` length, except string constants and code, whose length is a
+/// `u32`.
+pub fn write(module: &Module) -> Vec<u8> {
+    let mut writer = Writer { bytes: vec![] };
+    writer.bytes.extend(MAGIC);
+    writer.u16(VERSION);
+
+    writer.count(module.constants.len());
+    for constant in module.constants.iter() {
+        match constant {
+            Constant::Nil => writer.u8(0),
+            Constant::Boolean(value) => {
+                writer.u8(1);
+                writer.u8(*value as u8);
+            }
+            Constant::Integer(value) => {
+                writer.u8(2);
+                writer.bytes.extend(value.to_le_bytes());
+            }
+            Constant::String(data) => {
+                writer.u8(3);
+                writer.data(data);
+            }
+            Constant::Function(id) => {
+                writer.u8(4);
+                writer.count(*id);
+            }
+            Constant::Bytes(data) => {
+                writer.u8(6);
+                writer.data(data);
+            }
+            Constant::Task(id) => {
+                writer.u8(5);
+                writer.count(*id);
+            }
+        }
+    }
+
+    writer.count(module.records.len());
+    for record in module.records.iter() {
+        writer.string(&record.name);
+        writer.u32(record.length as u32);
+        writer.u8(record.data_size as u8);
+    }
+
+    writer.names(&module.globals);
+
+    writer.count(module.bodies.len());
+    for body in module.bodies.iter() {
+        match &body.kind {
+            BodyKind::Function { name, parameters } => {
+                writer.u8(0);
+                writer.string(name);
+                writer.u8(*parameters as u8);
+            }
+            BodyKind::Task { name, interval_ms } => {
+                writer.u8(1);
+                writer.string(name);
+                writer.u32(*interval_ms as u32);
+            }
+            BodyKind::When { interface, guard } => {
+                writer.u8(2);
+                writer.string(interface);
+                writer.guard(guard);
+            }
+        }
+        writer.names(&body.locals);
+        writer.names(&body.stored);
+        writer.count(body.slots);
+        writer.data(&body.code);
+    }
+
+    writer.count(module.interfaces.len());
+    for interface in module.interfaces.iter() {
+        writer.string(&interface.name);
+        writer.count(interface.handlers.len());
+        for id in interface.handlers.iter() {
+            writer.count(*id);
+        }
+    }
+
+    if module.bodies.iter().any(|body| !body.lines.is_empty()) {
+        writer.bytes.extend(DEBUG);
+        for body in module.bodies.iter() {
+            writer.count(body.lines.len());
+            for (offset, line) in body.lines.iter() {
+                writer.u32(*offset as u32);
+                writer.u32(*line as u32);
+            }
+        }
+    }
+
+    writer.bytes
+}
+
+/// Decodes the contents of a `.nxb` file written by `write`.
+pub fn read(bytes: &[u8]) -> Result<Module, String> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err("Not a bytecode file".to_string());
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(format!("Unsupported bytecode version {}", version));
+    }
+
+    let mut constants = vec![];
+    for _ in 0..reader.u16()? {
+        constants.push(match reader.u8()? {
+            0 => Constant::Nil,
+            1 => Constant::Boolean(reader.u8()? != 0),
+            2 => Constant::Integer(i64::from_le_bytes(reader.array()?)),
+            3 => Constant::String(reader.data()?),
+            4 => Constant::Function(reader.u16()? as usize),
+            5 => Constant::Task(reader.u16()? as usize),
+            6 => Constant::Bytes(reader.data()?),
+            tag => return Err(reader.invalid(&format!("constant tag {}", tag))),
+        });
+    }
+
+    let mut records = vec![];
+    for _ in 0..reader.u16()? {
+        let name = reader.string()?;
+        let length = reader.u32()? as usize;
+        let data_size = reader.u8()? as usize;
+        if !(1..=MAX_GUARD_WIDTH).contains(&data_size) {
+            return Err(reader.invalid(&format!("element size {}", data_size)));
+        }
+        records.push(Record {
+            name,
+            length,
+            data_size,
+        });
+    }
+
+    let globals = reader.names()?;
+
+    let mut bodies = vec![];
+    for _ in 0..reader.u16()? {
+        let kind = match reader.u8()? {
+            0 => BodyKind::Function {
+                name: reader.string()?,
+                parameters: reader.u8()? as usize,
+            },
+            1 => BodyKind::Task {
+                name: reader.string()?,
+                interval_ms: reader.u32()? as usize,
+            },
+            2 => BodyKind::When {
+                interface: reader.string()?,
+                guard: reader.guard()?,
+            },
+            tag => return Err(reader.invalid(&format!("body tag {}", tag))),
+        };
+        let locals = reader.names()?;
+        let stored = reader.names()?;
+        let slots = reader.u16()? as usize;
+        let code = reader.data()?;
+
+        let fixed = match &kind {
+            BodyKind::Function { parameters, .. } => *parameters,
+            BodyKind::Task { .. } => 0,
+            BodyKind::When { .. } => 1,
+        };
+        if fixed > locals.len() || locals.len() > slots {
+            return Err(reader.invalid("local count"));
+        }
+        bodies.push(Code {
+            kind,
+            locals,
+            stored,
+            slots,
+            code,
+            lines: vec![],
+        });
+    }
+
+    let mut interfaces = vec![];
+    for _ in 0..reader.u16()? {
+        let name = reader.string()?;
+        let mut handlers = vec![];
+        for _ in 0..reader.u16()? {
+            let id = reader.u16()? as usize;
+            match bodies.get(id).map(|body| &body.kind) {
+                Some(BodyKind::When { interface, .. }) if *interface == name => handlers.push(id),
+                _ => return Err(reader.invalid(&format!("handler {}", id))),
+            }
+        }
+        interfaces.push(Interface { name, handlers });
+    }
+
+    if reader.position < bytes.len() {
+        if reader.take(DEBUG.len())? != DEBUG {
+            return Err(reader.invalid("debug section"));
+        }
+        for body in bodies.iter_mut() {
+            for _ in 0..reader.u16()? {
+                let offset = reader.u32()? as usize;
+                let line = reader.u32()? as usize;
+                body.lines.push((offset, line));
+            }
+        }
+    }
+    if reader.position != bytes.len() {
+        return Err(reader.invalid("trailing data"));
+    }
+
+    for constant in constants.iter() {
+        let valid = match constant {
+            Constant::Function(id) => matches!(
+                bodies.get(*id).map(|body| &body.kind),
+                Some(BodyKind::Function { .. })
+            ),
+            Constant::Task(id) => matches!(
+                bodies.get(*id).map(|body| &body.kind),
+                Some(BodyKind::Task { .. })
+            ),
+            _ => true,
+        };
+        if !valid {
+            return Err("Invalid bytecode file: a constant refers to a missing body".to_string());
+        }
+    }
+
+    Ok(Module {
+        constants,
+        records,
+        globals,
+        bodies,
+        interfaces,
+    })
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    /// Counts and indices, which the compiler keeps within `u16`.
+    fn count(&mut self, value: usize) {
+        self.u16(value as u16);
+    }
+
+    fn string(&mut self, value: &str) {
+        self.count(value.len());
+        self.bytes.extend(value.as_bytes());
+    }
+
+    fn data(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.bytes.extend(value);
+    }
+
+    fn names(&mut self, names: &[String]) {
+        self.count(names.len());
+        for name in names {
+            self.string(name);
+        }
+    }
+
+    fn guard(&mut self, guard: &Guard) {
+        match guard {
+            Guard::Default => self.u8(0),
+            Guard::Numeric { width, endianness } => {
+                self.u8(1);
+                self.u8(*width as u8);
+                self.u8((*endianness == Endianness::Big) as u8);
+            }
+            Guard::Regex { pattern, framing } => {
+                self.u8(2);
+                self.string(pattern);
+                match framing {
+                    Framing::Start(delimiter) => {
+                        self.u8(0);
+                        self.data(delimiter);
+                    }
+                    Framing::End(delimiter) => {
+                        self.u8(1);
+                        self.data(delimiter);
+                    }
+                    Framing::Delimited { start, end } => {
+                        self.u8(2);
+                        self.data(start);
+                        self.data(end);
+                    }
+                    Framing::Continuation => self.u8(3),
+                }
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn invalid(&self, what: &str) -> String {
+        format!(
+            "Invalid bytecode file: bad {} at offset {}",
+            what, self.position
+        )
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self.position + len;
+        if end > self.bytes.len() {
+            return Err("Invalid bytecode file: unexpected end of file".to_string());
+        }
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        let bytes = self.take(len)?.to_vec();
+        String::from_utf8(bytes).map_err(|_| self.invalid("string"))
+    }
+
+    fn data(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn names(&mut self) -> Result<Vec<String>, String> {
+        (0..self.u16()?).map(|_| self.string()).collect()
+    }
+
+    fn guard(&mut self) -> Result<Guard, String> {
+        Ok(match self.u8()? {
+            0 => Guard::Default,
+            1 => {
+                let width = self.u8()? as usize;
+                if !(1..=MAX_GUARD_WIDTH).contains(&width) {
+                    return Err(self.invalid(&format!("guard width {}", width)));
+                }
+                let endianness = match self.u8()? {
+                    0 => Endianness::Little,
+                    _ => Endianness::Big,
+                };
+                Guard::Numeric { width, endianness }
+            }
+            2 => {
+                let pattern = self.string()?;
+                if Guard::build_regex(&pattern).is_err() {
+                    return Err(self.invalid("guard pattern"));
+                }
+                let framing = match self.u8()? {
+                    0 => Framing::Start(self.data()?),
+                    1 => Framing::End(self.data()?),
+                    2 => Framing::Delimited {
+                        start: self.data()?,
+                        end: self.data()?,
+                    },
+                    3 => Framing::Continuation,
+                    tag => return Err(self.invalid(&format!("framing tag {}", tag))),
+                };
+                Guard::Regex { pattern, framing }
+            }
+            tag => return Err(self.invalid(&format!("guard tag {}", tag))),
+        })
+    }
+}
@@ -0,0 +1,268 @@
+use crate::bytecode::{Code, Module, Op, BUILTINS};
+use crate::ir::{quote, BodyKind, Constant};
+use std::fmt::Write;
+
+/// Lists a module for reading: the constant pool, the records and globals,
+/// the function and task tables, the `when` dispatch table of each
+/// interface, then the code of every body. Each instruction shows its
+/// offset, the source line where it changes, when the file has a debug
+/// section, and what its operands stand for.
+pub fn disassemble(module: &Module) -> Result<String, String> {
+    let mut text = String::new();
+    let out = &mut text;
+
+    writeln!(out, "constants:").unwrap();
+    for (index, constant) in module.constants.iter().enumerate() {
+        writeln!(out, "  {:>4}  {}", index, describe(module, constant)).unwrap();
+    }
+
+    if !module.records.is_empty() {
+        writeln!(out, "records:").unwrap();
+        for (index, record) in module.records.iter().enumerate() {
+            writeln!(
+                out,
+                "  {:>4}  {}[{}, {}]",
+                index, record.name, record.length, record.data_size
+            )
+            .unwrap();
+        }
+    }
+
+    if !module.globals.is_empty() {
+        writeln!(out, "globals:").unwrap();
+        for (index, global) in module.globals.iter().enumerate() {
+            writeln!(out, "  {:>4}  {}", index, global).unwrap();
+        }
+    }
+
+    writeln!(out, "functions:").unwrap();
+    for (id, body) in module.bodies.iter().enumerate() {
+        if let BodyKind::Function { .. } = body.kind {
+            writeln!(out, "  {:>4}  {}", id, heading(body)).unwrap();
+        }
+    }
+
+    writeln!(out, "tasks:").unwrap();
+    for (id, body) in module.bodies.iter().enumerate() {
+        if let BodyKind::Task { .. } = body.kind {
+            writeln!(out, "  {:>4}  {}", id, heading(body)).unwrap();
+        }
+    }
+
+    for interface in module.interfaces.iter() {
+        writeln!(out, "dispatch \"{}\":", interface.name).unwrap();
+        for &id in interface.handlers.iter() {
+            let body = &module.bodies[id];
+            if let BodyKind::When { guard, .. } = &body.kind {
+                writeln!(out, "  {:>4}  {}{}", id, body.locals[0], guard).unwrap();
+            }
+        }
+    }
+
+    for (id, body) in module.bodies.iter().enumerate() {
+        writeln!(out).unwrap();
+        writeln!(out, "body {}: {}", id, heading(body)).unwrap();
+        if !body.locals.is_empty() {
+            writeln!(out, "  locals {}", body.locals.join(", ")).unwrap();
+        }
+        if !body.stored.is_empty() {
+            writeln!(out, "  store {}", body.stored.join(", ")).unwrap();
+        }
+        code(module, body, out).map_err(|err| format!("{} in body {}", err, id))?;
+    }
+
+    Ok(text)
+}
+
+fn code(module: &Module, body: &Code, out: &mut String) -> Result<(), String> {
+    let mut lines = body.lines.iter().peekable();
+    let mut pc = 0;
+    while pc < body.code.len() {
+        let (op, mut next) = Op::decode(&body.code, pc)?;
+
+        let mut widths = vec![];
+        if let Op::Unpack(count) = op {
+            widths = body
+                .code
+                .get(next..next + count as usize)
+                .ok_or_else(|| format!("Truncated instruction at {}", pc))?
+                .iter()
+                .map(|&width| width as i8)
+                .collect();
+            next += count as usize;
+        }
+
+        let mut line = String::new();
+        while let Some((_, number)) = lines.next_if(|(offset, _)| *offset <= pc) {
+            line = format!("line {}", number);
+        }
+
+        let comment = comment(module, body, op, &widths);
+        let op = op.to_string();
+        match comment {
+            Some(comment) => writeln!(out, "  {:>6}  {:<9} {:<16} ; {}", pc, line, op, comment),
+            None => writeln!(out, "  {:>6}  {:<9} {}", pc, line, op),
+        }
+        .unwrap();
+
+        pc = next;
+    }
+    Ok(())
+}
+
+/// What the operands of an instruction stand for, such as the value of a
+/// constant or the name of a variable.
+fn comment(module: &Module, body: &Code, op: Op, widths: &[i8]) -> Option<String> {
+    let name = |names: &[String], index: u16| {
+        names
+            .get(index as usize)
+            .cloned()
+            .unwrap_or_else(|| "?".to_string())
+    };
+    let constant = |index: u16| match module.constants.get(index as usize) {
+        Some(constant) => describe(module, constant),
+        None => "?".to_string(),
+    };
+    let record = |index: u16| match module.records.get(index as usize) {
+        Some(record) => record.name.clone(),
+        None => "?".to_string(),
+    };
+
+    Some(match op {
+        Op::Constant(index) => constant(index),
+        Op::LoadLocal(slot) | Op::StoreLocal(slot) => match body.locals.get(slot as usize) {
+            Some(local) => local.clone(),
+            None => format!("t{}", slot as usize - body.locals.len()),
+        },
+        Op::LoadStored(index) | Op::StoreStored(index) => name(&body.stored, index),
+        Op::LoadGlobal(index) | Op::StoreGlobal(index) => name(&module.globals, index),
+        Op::LoadRecord(index) | Op::GetElement(index) | Op::SetElement(index) => record(index),
+        Op::Unpack(_) => {
+            let widths: Vec<String> = widths.iter().map(|width| format!("::{}", width)).collect();
+            widths.join(", ")
+        }
+        Op::InRange(start, end) => format!("{}..={}", constant(start), constant(end)),
+        Op::Call(id, _) => match module.bodies.get(id as usize) {
+            Some(body) => heading(body),
+            None => "?".to_string(),
+        },
+        Op::Builtin(id, _) => BUILTINS.get(id as usize).unwrap_or(&"?").to_string(),
+        _ => return None,
+    })
+}
+
+fn heading(body: &Code) -> String {
+    match &body.kind {
+        BodyKind::Function { name, parameters } => {
+            format!(
+                "function {}({})",
+                name,
+                body.locals[..*parameters].join(", ")
+            )
+        }
+        BodyKind::Task { name, interval_ms } => format!("task {} @ {}", name, interval_ms),
+        BodyKind::When { interface, guard } => {
+            format!("when \"{}\" => {}{}", interface, body.locals[0], guard)
+        }
+    }
+}
+
+fn describe(module: &Module, constant: &Constant) -> String {
+    match constant {
+        Constant::Nil => "nil".to_string(),
+        Constant::Boolean(value) => value.to_string(),
+        Constant::Integer(value) => value.to_string(),
+        Constant::String(data) => quote(data),
+        Constant::Bytes(data) => format!("b{}", quote(data)),
+        Constant::Function(id) | Constant::Task(id) => match module.bodies.get(*id) {
+            Some(body) => format!("&{}", heading(body)),
+            None => "?".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{bytecode, ir, parser};
+
+    #[test]
+    fn lists_a_module() {
+        let source = r#"record log[2, 1];
+
+            function twice(n)
+                return n * 2;
+            end
+
+            task beat @ 500
+                log[0] = twice(3);
+                send("uart", 'hi');
+            end
+
+            when "uart" => msg::"\x3a.*\x3f"
+                @10;
+            end
+
+            when "uart" => msg::-2
+                print("%d\n", msg);
+            end
+        "#;
+        let program = parser::parse_source(source);
+        let module = bytecode::compile(&ir::lower(&program).unwrap()).unwrap();
+        let module = bytecode::read(&bytecode::write(&module)).unwrap();
+        let expected = r#"constants:
+     0  2
+     1  3
+     2  0
+     3  "uart"
+     4  "hi"
+     5  nil
+     6  "%d\x0a"
+records:
+     0  log[2, 1]
+functions:
+     0  function twice(n)
+tasks:
+     1  task beat @ 500
+dispatch "uart":
+     2  msg::"\x3a.*\x3f"
+     3  msg::-2
+
+body 0: function twice(n)
+  locals n
+       0  line 4    load 0           ; n
+       3            const 0          ; 2
+       6            mul
+       7            return
+
+body 1: task beat @ 500
+       0  line 8    const 1          ; 3
+       3            call 0, 1        ; function twice(n)
+       7            store 0          ; t0
+      10            const 2          ; 0
+      13            load 0           ; t0
+      16            set 0            ; log
+      19  line 9    const 3          ; "uart"
+      22            const 4          ; "hi"
+      25            builtin 1, 2     ; send
+      28            pop
+      29            const 5          ; nil
+      32            return
+
+body 2: when "uart" => msg::"\x3a.*\x3f"
+  locals msg
+       0  line 13   delay 10
+       5            const 5          ; nil
+       8            return
+
+body 3: when "uart" => msg::-2
+  locals msg
+       0  line 17   const 6          ; "%d\x0a"
+       3            load 0           ; msg
+       6            builtin 0, 2     ; print
+       9            pop
+      10            const 5          ; nil
+      13            return
+"#;
+        assert_eq!(bytecode::disassemble(&module).unwrap(), expected);
+    }
+}
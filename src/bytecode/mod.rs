@@ -0,0 +1,279 @@
+//! A compact stack-based form of a program for small devices, stored in
+//! `.nxb` files and run by `vm::Vm`.
+//!
+//! Every body keeps its named locals followed by the temporaries of its IR
+//! in numbered slots. Instructions take their operands from the stack and
+//! push their result on it; jumps are byte offsets into the code of the
+//! body.
+
+use crate::ir::{BodyKind, Constant, Interface, Record};
+use std::fmt::{Display, Formatter};
+
+mod compile;
+mod container;
+mod disasm;
+pub mod vm;
+
+pub use compile::compile;
+pub use container::{read, write};
+pub use disasm::disassemble;
+
+#[derive(Debug)]
+pub struct Module {
+    pub constants: Vec<Constant>,
+    pub records: Vec<Record>,
+    pub globals: Vec<String>,
+    pub bodies: Vec<Code>,
+    /// The `when` handlers of each interface, in dispatch order.
+    pub interfaces: Vec<Interface>,
+}
+
+#[derive(Debug)]
+pub struct Code {
+    pub kind: BodyKind,
+    /// Names of the named locals, which take the first slots.
+    pub locals: Vec<String>,
+    pub stored: Vec<String>,
+    /// Number of slots: the named locals and the temporaries.
+    pub slots: usize,
+    pub code: Vec<u8>,
+    /// Where the source line changes in the code, as offsets with the line
+    /// from there on. Empty when the file has no debug section.
+    pub lines: Vec<(usize, usize)>,
+}
+
+/// The builtins called by `Op::Builtin`, by number.
+pub const BUILTINS: [&str; 5] = ["print", "send", "millis", "rand", "stop"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// Pushes a constant of the pool.
+    Constant(u16),
+    Pop,
+    LoadLocal(u16),
+    StoreLocal(u16),
+    LoadStored(u16),
+    StoreStored(u16),
+    LoadGlobal(u16),
+    StoreGlobal(u16),
+    /// Pushes the elements of a record as a list.
+    LoadRecord(u16),
+    /// Pops an index and pushes that element of a record.
+    GetElement(u16),
+    /// Pops a value and an index and writes that element of a record.
+    SetElement(u16),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Xor,
+    Not,
+    /// Pops that many values and pushes them as a list.
+    MakeList(u16),
+    /// `::N`: packs an integer into `|N|` bytes, big-endian when negative.
+    Pack(i8),
+    /// Pops bytes and pushes the integers of the fields of a matching
+    /// assignment, whose widths follow in the code.
+    Unpack(u8),
+    /// Pops a collection and pushes the items a `for` loop visits.
+    Items,
+    Length,
+    /// Pops an index and a list and pushes that item.
+    Index,
+    /// Pops a value and pushes whether it is an integer between two
+    /// constants, both included.
+    InRange(u16, u16),
+    /// Calls a body with that many arguments.
+    Call(u16, u8),
+    Builtin(u8, u8),
+    /// Pops the target of `start`, that many arguments and, when the flag
+    /// is set, an interval, and pushes the handle of the task.
+    Start(u8, bool),
+    Jump(u32),
+    /// Pops a boolean and jumps when it is `false`.
+    JumpIfFalse(u32),
+    /// An `@` delay.
+    Delay(u32),
+    Return,
+}
+
+impl Op {
+    /// Appends the encoding of this instruction. The widths of `Unpack`
+    /// follow separately.
+    pub fn encode(&self, code: &mut Vec<u8>) {
+        let (opcode, operands): (u8, Vec<u8>) = match *self {
+            Op::Constant(index) => (0x01, index.to_le_bytes().to_vec()),
+            Op::Pop => (0x02, vec![]),
+            Op::LoadLocal(slot) => (0x03, slot.to_le_bytes().to_vec()),
+            Op::StoreLocal(slot) => (0x04, slot.to_le_bytes().to_vec()),
+            Op::LoadStored(index) => (0x05, index.to_le_bytes().to_vec()),
+            Op::StoreStored(index) => (0x06, index.to_le_bytes().to_vec()),
+            Op::LoadGlobal(index) => (0x07, index.to_le_bytes().to_vec()),
+            Op::StoreGlobal(index) => (0x08, index.to_le_bytes().to_vec()),
+            Op::LoadRecord(record) => (0x09, record.to_le_bytes().to_vec()),
+            Op::GetElement(record) => (0x0a, record.to_le_bytes().to_vec()),
+            Op::SetElement(record) => (0x0b, record.to_le_bytes().to_vec()),
+            Op::Add => (0x10, vec![]),
+            Op::Sub => (0x11, vec![]),
+            Op::Mul => (0x12, vec![]),
+            Op::Div => (0x13, vec![]),
+            Op::Mod => (0x14, vec![]),
+            Op::Equal => (0x18, vec![]),
+            Op::NotEqual => (0x19, vec![]),
+            Op::Less => (0x1a, vec![]),
+            Op::Greater => (0x1b, vec![]),
+            Op::LessOrEqual => (0x1c, vec![]),
+            Op::GreaterOrEqual => (0x1d, vec![]),
+            Op::Xor => (0x1e, vec![]),
+            Op::Not => (0x1f, vec![]),
+            Op::MakeList(count) => (0x20, count.to_le_bytes().to_vec()),
+            Op::Pack(width) => (0x21, vec![width as u8]),
+            Op::Unpack(count) => (0x22, vec![count]),
+            Op::Items => (0x23, vec![]),
+            Op::Length => (0x24, vec![]),
+            Op::Index => (0x25, vec![]),
+            Op::InRange(start, end) => (0x26, [start.to_le_bytes(), end.to_le_bytes()].concat()),
+            Op::Call(body, argc) => (0x30, [&body.to_le_bytes()[..], &[argc]].concat()),
+            Op::Builtin(builtin, argc) => (0x31, vec![builtin, argc]),
+            Op::Start(argc, interval) => (0x32, vec![argc, interval as u8]),
+            Op::Jump(target) => (0x38, target.to_le_bytes().to_vec()),
+            Op::JumpIfFalse(target) => (0x39, target.to_le_bytes().to_vec()),
+            Op::Delay(delay_ms) => (0x3a, delay_ms.to_le_bytes().to_vec()),
+            Op::Return => (0x3b, vec![]),
+        };
+        code.push(opcode);
+        code.extend(operands);
+    }
+
+    /// Whether this instruction only pushes a value, so that it can be
+    /// moved past other pushes.
+    pub fn is_load(&self) -> bool {
+        matches!(
+            self,
+            Op::Constant(_)
+                | Op::LoadLocal(_)
+                | Op::LoadStored(_)
+                | Op::LoadGlobal(_)
+                | Op::LoadRecord(_)
+        )
+    }
+
+    /// The encoded length of this instruction, without `Unpack` widths.
+    pub fn size(&self) -> usize {
+        let mut code = vec![];
+        self.encode(&mut code);
+        code.len()
+    }
+
+    /// Decodes the instruction at `pc`, returning it with the position of
+    /// the next one. The widths of `Unpack` are left to the caller.
+    pub fn decode(code: &[u8], pc: usize) -> Result<(Op, usize), String> {
+        let invalid = || format!("Truncated instruction at {}", pc);
+        let byte = |at: usize| code.get(pc + at).copied().ok_or_else(invalid);
+        let u16_at = |at: usize| Ok::<_, String>(u16::from_le_bytes([byte(at)?, byte(at + 1)?]));
+        let u32_at = |at: usize| {
+            Ok::<_, String>(u32::from_le_bytes([
+                byte(at)?,
+                byte(at + 1)?,
+                byte(at + 2)?,
+                byte(at + 3)?,
+            ]))
+        };
+
+        let op = match byte(0)? {
+            0x01 => Op::Constant(u16_at(1)?),
+            0x02 => Op::Pop,
+            0x03 => Op::LoadLocal(u16_at(1)?),
+            0x04 => Op::StoreLocal(u16_at(1)?),
+            0x05 => Op::LoadStored(u16_at(1)?),
+            0x06 => Op::StoreStored(u16_at(1)?),
+            0x07 => Op::LoadGlobal(u16_at(1)?),
+            0x08 => Op::StoreGlobal(u16_at(1)?),
+            0x09 => Op::LoadRecord(u16_at(1)?),
+            0x0a => Op::GetElement(u16_at(1)?),
+            0x0b => Op::SetElement(u16_at(1)?),
+            0x10 => Op::Add,
+            0x11 => Op::Sub,
+            0x12 => Op::Mul,
+            0x13 => Op::Div,
+            0x14 => Op::Mod,
+            0x18 => Op::Equal,
+            0x19 => Op::NotEqual,
+            0x1a => Op::Less,
+            0x1b => Op::Greater,
+            0x1c => Op::LessOrEqual,
+            0x1d => Op::GreaterOrEqual,
+            0x1e => Op::Xor,
+            0x1f => Op::Not,
+            0x20 => Op::MakeList(u16_at(1)?),
+            0x21 => Op::Pack(byte(1)? as i8),
+            0x22 => Op::Unpack(byte(1)?),
+            0x23 => Op::Items,
+            0x24 => Op::Length,
+            0x25 => Op::Index,
+            0x26 => Op::InRange(u16_at(1)?, u16_at(3)?),
+            0x30 => Op::Call(u16_at(1)?, byte(3)?),
+            0x31 => Op::Builtin(byte(1)?, byte(2)?),
+            0x32 => Op::Start(byte(1)?, byte(2)? != 0),
+            0x38 => Op::Jump(u32_at(1)?),
+            0x39 => Op::JumpIfFalse(u32_at(1)?),
+            0x3a => Op::Delay(u32_at(1)?),
+            0x3b => Op::Return,
+            other => return Err(format!("Unknown opcode 0x{:02x} at {}", other, pc)),
+        };
+        Ok((op, pc + op.size()))
+    }
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Op::Constant(index) => write!(f, "const {}", index),
+            Op::Pop => write!(f, "pop"),
+            Op::LoadLocal(slot) => write!(f, "load {}", slot),
+            Op::StoreLocal(slot) => write!(f, "store {}", slot),
+            Op::LoadStored(index) => write!(f, "load.stored {}", index),
+            Op::StoreStored(index) => write!(f, "store.stored {}", index),
+            Op::LoadGlobal(index) => write!(f, "load.global {}", index),
+            Op::StoreGlobal(index) => write!(f, "store.global {}", index),
+            Op::LoadRecord(record) => write!(f, "load.record {}", record),
+            Op::GetElement(record) => write!(f, "get {}", record),
+            Op::SetElement(record) => write!(f, "set {}", record),
+            Op::Add => write!(f, "add"),
+            Op::Sub => write!(f, "sub"),
+            Op::Mul => write!(f, "mul"),
+            Op::Div => write!(f, "div"),
+            Op::Mod => write!(f, "mod"),
+            Op::Equal => write!(f, "eq"),
+            Op::NotEqual => write!(f, "ne"),
+            Op::Less => write!(f, "lt"),
+            Op::Greater => write!(f, "gt"),
+            Op::LessOrEqual => write!(f, "le"),
+            Op::GreaterOrEqual => write!(f, "ge"),
+            Op::Xor => write!(f, "xor"),
+            Op::Not => write!(f, "not"),
+            Op::MakeList(count) => write!(f, "list {}", count),
+            Op::Pack(width) => write!(f, "pack {}", width),
+            Op::Unpack(count) => write!(f, "unpack {}", count),
+            Op::Items => write!(f, "items"),
+            Op::Length => write!(f, "length"),
+            Op::Index => write!(f, "index"),
+            Op::InRange(start, end) => write!(f, "in {}, {}", start, end),
+            Op::Call(body, argc) => write!(f, "call {}, {}", body, argc),
+            Op::Builtin(builtin, argc) => write!(f, "builtin {}, {}", builtin, argc),
+            Op::Start(argc, false) => write!(f, "start {}", argc),
+            Op::Start(argc, true) => write!(f, "start {} @", argc),
+            Op::Jump(target) => write!(f, "jump {}", target),
+            Op::JumpIfFalse(target) => write!(f, "jump.false {}", target),
+            Op::Delay(delay_ms) => write!(f, "delay {}", delay_ms),
+            Op::Return => write!(f, "return"),
+        }
+    }
+}
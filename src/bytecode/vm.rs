@@ -0,0 +1,810 @@
+use crate::bytecode::{Module, Op, BUILTINS};
+use crate::ir::{BodyKind, Constant};
+use crate::parser::{Endianness, Guard};
+use crate::runtime::builtins::{self, Host};
+use crate::runtime::framer::{Frame as Packet, Framer};
+use crate::runtime::interface::Interface;
+use crate::runtime::interpreter::{arithmetic, Attached, Operator, Step};
+use crate::runtime::random::Random;
+use crate::runtime::record::Record;
+use crate::runtime::scheduler::Scheduler;
+use crate::runtime::trace::{Event, Trace};
+use crate::runtime::value::{TaskId, Value};
+use crate::runtime::{Machine, Options};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Runs a bytecode module the way the interpreter runs the program it was
+/// compiled from. Stored variables live in memory only.
+pub struct Vm {
+    module: Module,
+    /// Function bodies by name, for the function values given to `start`.
+    functions: HashMap<String, usize>,
+    /// The handles of the declared tasks, by body.
+    tasks: HashMap<usize, TaskId>,
+    records: Vec<Record>,
+    globals: Vec<Value>,
+    /// The variables stored by each body.
+    stored: Vec<Vec<Value>>,
+    /// Framers by interface, which name the handlers by body.
+    framers: HashMap<String, Framer>,
+    output: Box<dyn Write>,
+    trace: Option<Trace>,
+    random: Random,
+    interfaces: HashMap<String, Attached>,
+    scheduler: Scheduler<Fiber>,
+}
+
+/// A body in execution with the calls it made, which can stop at `@` delays
+/// and be resumed later. As in the interpreter, only the first frame can
+/// wait.
+#[derive(Clone)]
+pub struct Fiber {
+    frames: Vec<Frame>,
+    stack: Vec<Value>,
+}
+
+#[derive(Clone)]
+struct Frame {
+    body: usize,
+    pc: usize,
+    /// The named locals followed by the temporaries.
+    locals: Vec<Value>,
+}
+
+impl Fiber {
+    fn new(body: usize, slots: usize, args: Vec<Value>) -> Self {
+        let mut locals = args;
+        locals.resize(slots.max(locals.len()), Value::Nil);
+        Self {
+            frames: vec![Frame {
+                body,
+                pc: 0,
+                locals,
+            }],
+            stack: vec![],
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, String> {
+        self.stack
+            .pop()
+            .ok_or_else(|| "The bytecode pops an empty stack".to_string())
+    }
+
+    fn pop_many(&mut self, count: usize) -> Result<Vec<Value>, String> {
+        if count > self.stack.len() {
+            return Err("The bytecode pops an empty stack".to_string());
+        }
+        Ok(self.stack.split_off(self.stack.len() - count))
+    }
+
+    fn local(&mut self, slot: u16) -> Result<&mut Value, String> {
+        self.frames
+            .last_mut()
+            .and_then(|frame| frame.locals.get_mut(slot as usize))
+            .ok_or_else(|| format!("The bytecode uses the missing slot {}", slot))
+    }
+}
+
+impl Vm {
+    pub fn new(
+        module: Module,
+        output: Box<dyn Write>,
+        interfaces: HashMap<String, Box<dyn Interface>>,
+        options: &Options,
+    ) -> Result<Self, String> {
+        let mut functions = HashMap::new();
+        let mut tasks = HashMap::new();
+        let mut scheduler = Scheduler::default();
+        for (id, body) in module.bodies.iter().enumerate() {
+            match &body.kind {
+                BodyKind::Function { name, .. } => {
+                    functions.insert(name.clone(), id);
+                }
+                BodyKind::Task { name, interval_ms } => {
+                    let fiber = Fiber::new(id, body.slots, vec![]);
+                    tasks.insert(id, scheduler.spawn(name, fiber, *interval_ms, true));
+                }
+                BodyKind::When { .. } => {}
+            }
+        }
+
+        let framers = module
+            .interfaces
+            .iter()
+            .map(|interface| {
+                let handlers: Vec<(usize, &Guard)> = interface
+                    .handlers
+                    .iter()
+                    .map(|&id| match &module.bodies[id].kind {
+                        BodyKind::When { guard, .. } => (id, guard),
+                        _ => unreachable!("dispatch tables only hold 'when' handlers"),
+                    })
+                    .collect();
+                let framer = Framer::new(&handlers, options.framing);
+                (interface.name.clone(), framer)
+            })
+            .collect();
+
+        let interfaces = interfaces
+            .into_iter()
+            .map(|(name, interface)| {
+                let attached = Attached {
+                    interface,
+                    open: true,
+                };
+                (name, attached)
+            })
+            .collect();
+
+        let trace = match &options.trace_path {
+            Some(path) => {
+                let file = File::create(path)
+                    .map_err(|err| format!("Cannot create {}: {}", path.display(), err))?;
+                Some(Trace::new(Box::new(BufWriter::new(file))))
+            }
+            None => None,
+        };
+
+        let seed = options.seed.unwrap_or_else(Random::clock_seed);
+
+        let mut vm = Self {
+            records: module
+                .records
+                .iter()
+                .map(|record| Record::new(&record.name, record.length, record.data_size))
+                .collect(),
+            globals: vec![Value::Nil; module.globals.len()],
+            stored: module
+                .bodies
+                .iter()
+                .map(|body| vec![Value::Nil; body.stored.len()])
+                .collect(),
+            module,
+            functions,
+            tasks,
+            framers,
+            output,
+            trace,
+            random: Random::new(seed),
+            interfaces,
+            scheduler,
+        };
+
+        vm.trace(Event::Seed { seed })?;
+        for id in 0..vm.tasks.len() {
+            vm.trace_start(id)?;
+        }
+
+        Ok(vm)
+    }
+
+    fn trace_start(&mut self, id: TaskId) -> Result<(), String> {
+        match &mut self.trace {
+            Some(trace) => {
+                let task = self.scheduler.name(id);
+                let interval_ms = self.scheduler.interval(id);
+                trace.log(
+                    self.scheduler.now(),
+                    Event::Start {
+                        id,
+                        task,
+                        interval_ms,
+                    },
+                )
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Frames the bytes received on `interface` and starts a handler for
+    /// each packet they complete.
+    pub fn receive(&mut self, interface: &str, bytes: Vec<u8>) -> Result<(), String> {
+        let now = self.scheduler.now();
+        match self.framers.get_mut(interface) {
+            Some(framer) => {
+                let packets = framer.push(&bytes, now);
+                self.handle_packets(interface, packets)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn handle_packets(&mut self, interface: &str, packets: Vec<Packet>) -> Result<(), String> {
+        for packet in packets {
+            match packet {
+                Packet::Packet { handler, bytes } => {
+                    let body = &self.module.bodies[handler];
+                    let (name, description) = match &body.kind {
+                        BodyKind::When { guard, .. } => (
+                            format!("when \"{}\" => {}{}", interface, body.locals[0], guard),
+                            format!("{}{}", body.locals[0], guard),
+                        ),
+                        _ => unreachable!("framers only hold 'when' handlers"),
+                    };
+                    let fiber = Fiber::new(handler, body.slots, vec![Value::Bytes(bytes.clone())]);
+                    self.trace(Event::Packet {
+                        interface,
+                        handler: &description,
+                        bytes: &bytes,
+                    })?;
+                    self.scheduler.spawn_detached(&name, fiber);
+                }
+                Packet::Garbage(bytes) => self.trace(Event::Garbage {
+                    interface,
+                    bytes: &bytes,
+                })?,
+            }
+        }
+        Ok(())
+    }
+
+    fn body_name(&self, body: usize) -> &str {
+        match &self.module.bodies[body].kind {
+            BodyKind::Function { name, .. } | BodyKind::Task { name, .. } => name,
+            BodyKind::When { interface, .. } => interface,
+        }
+    }
+
+    fn constant(&self, index: u16) -> Result<Value, String> {
+        let constant = self
+            .module
+            .constants
+            .get(index as usize)
+            .ok_or_else(|| format!("The bytecode uses the missing constant {}", index))?;
+        Ok(match constant {
+            Constant::Nil => Value::Nil,
+            Constant::Boolean(value) => Value::Boolean(*value),
+            Constant::Integer(value) => Value::Integer(*value),
+            Constant::String(data) => Value::String(data.clone()),
+            Constant::Bytes(data) => Value::Bytes(data.clone()),
+            Constant::Function(id) => Value::Function(self.body_name(*id).to_string()),
+            Constant::Task(id) => Value::Task(self.tasks[id]),
+        })
+    }
+
+    fn record(&mut self, index: u16) -> Result<&mut Record, String> {
+        self.records
+            .get_mut(index as usize)
+            .ok_or_else(|| format!("The bytecode uses the missing record {}", index))
+    }
+
+    fn stored(&mut self, body: usize, index: u16) -> Result<&mut Value, String> {
+        self.stored[body]
+            .get_mut(index as usize)
+            .ok_or_else(|| format!("The bytecode uses the missing stored variable {}", index))
+    }
+
+    fn global(&mut self, index: u16) -> Result<&mut Value, String> {
+        self.globals
+            .get_mut(index as usize)
+            .ok_or_else(|| format!("The bytecode uses the missing global {}", index))
+    }
+
+    /// Runs until the first frame returns or reaches an `@` delay.
+    fn resume(&mut self, fiber: &mut Fiber) -> Result<Step, String> {
+        loop {
+            let frame = fiber
+                .frames
+                .last_mut()
+                .expect("a running fiber has a frame");
+            let body = frame.body;
+            let code = &self.module.bodies[body].code;
+            let (op, mut next) = Op::decode(code, frame.pc)?;
+            let widths = match op {
+                Op::Unpack(count) => {
+                    let widths = code
+                        .get(next..next + count as usize)
+                        .ok_or_else(|| format!("Truncated instruction at {}", frame.pc))?;
+                    next += count as usize;
+                    widths.iter().map(|&width| width as i8).collect()
+                }
+                _ => vec![],
+            };
+            frame.pc = next;
+
+            let value = match op {
+                Op::Constant(index) => self.constant(index)?,
+                Op::Pop => {
+                    fiber.pop()?;
+                    continue;
+                }
+                Op::LoadLocal(slot) => fiber.local(slot)?.clone(),
+                Op::StoreLocal(slot) => {
+                    let value = fiber.pop()?;
+                    *fiber.local(slot)? = value;
+                    continue;
+                }
+                Op::LoadStored(index) => self.stored(body, index)?.clone(),
+                Op::StoreStored(index) => {
+                    *self.stored(body, index)? = fiber.pop()?;
+                    continue;
+                }
+                Op::LoadGlobal(index) => self.global(index)?.clone(),
+                Op::StoreGlobal(index) => {
+                    *self.global(index)? = fiber.pop()?;
+                    continue;
+                }
+                Op::LoadRecord(index) => self.record(index)?.to_value(),
+                Op::GetElement(index) => {
+                    let element = fiber.pop()?.as_integer()?;
+                    self.record(index)?.get(element)?
+                }
+                Op::SetElement(index) => {
+                    let value = fiber.pop()?;
+                    let element = fiber.pop()?.as_integer()?;
+                    self.record(index)?.set(element, &value)?;
+                    continue;
+                }
+                Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => {
+                    let operator = match op {
+                        Op::Add => Operator::Sum,
+                        Op::Sub => Operator::Minus,
+                        Op::Mul => Operator::Multiply,
+                        Op::Div => Operator::Division,
+                        _ => Operator::Modulus,
+                    };
+                    let rhs = fiber.pop()?;
+                    let lhs = fiber.pop()?;
+                    arithmetic(operator, lhs, rhs)?
+                }
+                Op::Equal | Op::NotEqual => {
+                    let rhs = fiber.pop()?;
+                    let lhs = fiber.pop()?;
+                    Value::Boolean((lhs == rhs) == (op == Op::Equal))
+                }
+                Op::Less | Op::Greater | Op::LessOrEqual | Op::GreaterOrEqual => {
+                    let rhs = fiber.pop()?.as_integer()?;
+                    let lhs = fiber.pop()?.as_integer()?;
+                    let ordering = lhs.cmp(&rhs);
+                    Value::Boolean(match op {
+                        Op::Less => ordering.is_lt(),
+                        Op::Greater => ordering.is_gt(),
+                        Op::LessOrEqual => ordering.is_le(),
+                        _ => ordering.is_ge(),
+                    })
+                }
+                Op::Xor => {
+                    let rhs = fiber.pop()?.as_boolean()?;
+                    let lhs = fiber.pop()?.as_boolean()?;
+                    Value::Boolean(lhs ^ rhs)
+                }
+                Op::Not => Value::Boolean(!fiber.pop()?.as_boolean()?),
+                Op::MakeList(count) => Value::List(fiber.pop_many(count as usize)?),
+                Op::Pack(width) => {
+                    let endianness = if width < 0 {
+                        Endianness::Big
+                    } else {
+                        Endianness::Little
+                    };
+                    let guard = Guard::Numeric {
+                        width: width.unsigned_abs() as usize,
+                        endianness,
+                    };
+                    fiber.pop()?.pack(&guard)?
+                }
+                Op::Unpack(_) => {
+                    let bytes = fiber.pop()?.to_bytes()?;
+                    let expected: usize = widths.iter().map(|w| w.unsigned_abs() as usize).sum();
+                    if expected != bytes.len() {
+                        return Err(format!(
+                            "Cannot unpack {} byte(s) into targets of {} byte(s)",
+                            bytes.len(),
+                            expected
+                        ));
+                    }
+
+                    let mut offset = 0;
+                    for width in widths {
+                        let endianness = if width < 0 {
+                            Endianness::Big
+                        } else {
+                            Endianness::Little
+                        };
+                        let end = offset + width.unsigned_abs() as usize;
+                        fiber
+                            .stack
+                            .push(Value::unpack(&bytes[offset..end], endianness));
+                        offset = end;
+                    }
+                    continue;
+                }
+                Op::Items => Value::List(fiber.pop()?.items()?),
+                Op::Length => match fiber.pop()? {
+                    Value::List(items) => Value::Integer(items.len() as i64),
+                    other => return Err(format!("Expected a list, found {}", other.type_name())),
+                },
+                Op::Index => {
+                    let index = fiber.pop()?.as_integer()?;
+                    let items = fiber.pop()?.items()?;
+                    usize::try_from(index)
+                        .ok()
+                        .and_then(|index| items.get(index).cloned())
+                        .ok_or_else(|| format!("The index {} is out of bounds", index))?
+                }
+                Op::InRange(start, end) => {
+                    let start = self.constant(start)?.as_integer()?;
+                    let end = self.constant(end)?.as_integer()?;
+                    match fiber.pop()? {
+                        Value::Integer(value) => Value::Boolean((start..=end).contains(&value)),
+                        _ => Value::Boolean(false),
+                    }
+                }
+                Op::Call(callee, argc) => {
+                    let args = fiber.pop_many(argc as usize)?;
+                    let callee = callee as usize;
+                    let slots = match self.module.bodies.get(callee) {
+                        Some(body) if matches!(body.kind, BodyKind::Function { .. }) => body.slots,
+                        _ => {
+                            return Err(format!(
+                                "The bytecode calls the missing function {}",
+                                callee
+                            ))
+                        }
+                    };
+                    let frame = Fiber::new(callee, slots, args).frames.remove(0);
+                    fiber.frames.push(frame);
+                    continue;
+                }
+                Op::Builtin(builtin, argc) => {
+                    let args = fiber.pop_many(argc as usize)?;
+                    let name = BUILTINS.get(builtin as usize).ok_or_else(|| {
+                        format!("The bytecode calls the missing builtin {}", builtin)
+                    })?;
+                    builtins::call(self, name, &args).expect("every numbered builtin exists")?
+                }
+                Op::Start(argc, has_interval) => {
+                    let interval_ms = match has_interval {
+                        true => {
+                            let interval_ms = fiber.pop()?.as_integer()?;
+                            if interval_ms < 0 {
+                                return Err("The task interval cannot be negative".to_string());
+                            }
+                            Some(interval_ms as usize)
+                        }
+                        false => None,
+                    };
+                    let mut args = fiber.pop_many(argc as usize)?;
+                    args.insert(0, fiber.pop()?);
+                    builtins::start(self, &args, interval_ms)?
+                }
+                Op::Jump(target) => {
+                    fiber.frames.last_mut().unwrap().pc = target as usize;
+                    continue;
+                }
+                Op::JumpIfFalse(target) => {
+                    if !fiber.pop()?.as_boolean()? {
+                        fiber.frames.last_mut().unwrap().pc = target as usize;
+                    }
+                    continue;
+                }
+                Op::Delay(delay_ms) => {
+                    if fiber.frames.len() > 1 {
+                        return Err(format!(
+                            "The function '{}' cannot wait with @ when it is called directly",
+                            self.body_name(body)
+                        ));
+                    }
+                    return Ok(Step::Suspended(delay_ms as usize));
+                }
+                Op::Return => {
+                    let value = fiber.pop()?;
+                    fiber.frames.pop();
+                    if fiber.frames.is_empty() {
+                        fiber.stack.clear();
+                        return Ok(Step::Finished(value));
+                    }
+                    value
+                }
+            };
+            fiber.stack.push(value);
+        }
+    }
+}
+
+impl Host for Vm {
+    fn now(&self) -> u64 {
+        self.scheduler.now()
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+
+    fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    fn trace(&mut self, event: Event) -> Result<(), String> {
+        match &mut self.trace {
+            Some(trace) => trace.log(self.scheduler.now(), event),
+            None => Ok(()),
+        }
+    }
+
+    fn send(&mut self, interface: &str, bytes: Vec<u8>) -> Result<(), String> {
+        self.trace(Event::Send {
+            interface,
+            bytes: &bytes,
+        })?;
+        match self.interfaces.get_mut(interface) {
+            Some(attached) => attached
+                .interface
+                .send(&bytes)
+                .map_err(|err| format!("Cannot send on {}: {}", interface, err)),
+            None => writeln!(self.output, "{} <- {}", interface, Value::Bytes(bytes))
+                .map_err(|err| err.to_string()),
+        }
+    }
+
+    fn spawn_function(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        interval_ms: usize,
+        immediate: bool,
+    ) -> Result<TaskId, String> {
+        let id = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| format!("Undefined function '{}'", name))?;
+        let body = &self.module.bodies[id];
+        if let BodyKind::Function { parameters, .. } = body.kind {
+            if parameters != args.len() {
+                return Err(format!(
+                    "The function '{}' takes {} argument(s) but {} were given",
+                    name,
+                    parameters,
+                    args.len()
+                ));
+            }
+        }
+
+        let fiber = Fiber::new(id, body.slots, args);
+        let id = self.scheduler.spawn(name, fiber, interval_ms, immediate);
+        self.trace_start(id)?;
+        Ok(id)
+    }
+
+    fn restart_task(
+        &mut self,
+        id: TaskId,
+        interval_ms: Option<usize>,
+        immediate: bool,
+    ) -> Result<(), String> {
+        self.scheduler.restart(id, interval_ms, immediate);
+        self.trace_start(id)
+    }
+
+    fn stop_task(&mut self, id: TaskId) -> Result<(), String> {
+        self.scheduler.stop(id);
+        match &mut self.trace {
+            Some(trace) => {
+                let task = self.scheduler.name(id);
+                trace.log(self.scheduler.now(), Event::Stop { id, task })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Machine for Vm {
+    fn advance_to(&mut self, time_ms: u64) {
+        self.scheduler.advance_to(time_ms);
+    }
+
+    fn poll_interfaces(&mut self) -> Result<bool, String> {
+        let mut received = vec![];
+        for (name, attached) in self.interfaces.iter_mut().filter(|(_, a)| a.open) {
+            match attached.interface.receive() {
+                Some(bytes) if bytes.is_empty() => {}
+                Some(bytes) => received.push((name.clone(), bytes)),
+                None => attached.open = false,
+            }
+        }
+
+        for (name, bytes) in received {
+            self.receive(&name, bytes)?;
+        }
+        Ok(self.interfaces.values().any(|attached| attached.open))
+    }
+
+    fn next_wake(&self) -> Option<u64> {
+        self.framers
+            .values()
+            .filter_map(Framer::deadline)
+            .chain(self.scheduler.next_wake())
+            .min()
+    }
+
+    /// Handles what is due at `next_wake` in the same order as the
+    /// interpreter: partial packets that timed out, then the next task.
+    fn step(&mut self) -> Result<(), String> {
+        let wake_at = match self.next_wake() {
+            Some(wake_at) => wake_at,
+            None => return Ok(()),
+        };
+        self.scheduler.advance_to(wake_at);
+
+        let now = self.scheduler.now();
+        let mut expired = vec![];
+        for (interface, framer) in self.framers.iter_mut() {
+            let packets = framer.expire(now);
+            if !packets.is_empty() {
+                expired.push((interface.clone(), packets));
+            }
+        }
+        if !expired.is_empty() {
+            expired.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (interface, packets) in expired {
+                self.handle_packets(&interface, packets)?;
+            }
+            return Ok(());
+        }
+
+        if let Some((id, mut fiber, new_tick)) = self.scheduler.take_due() {
+            if let (Some(trace), true) = (&mut self.trace, new_tick) {
+                let task = self.scheduler.name(id);
+                trace.log(self.scheduler.now(), Event::Tick { id, task })?;
+            }
+            let step = self.resume(&mut fiber)?;
+            if let (Some(trace), Step::Suspended(delay_ms)) = (&mut self.trace, &step) {
+                let task = self.scheduler.name(id);
+                let delay_ms = *delay_ms;
+                trace.log(self.scheduler.now(), Event::Delay { id, task, delay_ms })?;
+            }
+            self.scheduler.park(id, fiber, step);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{self, Captured};
+    use crate::{bytecode, ir, parser};
+    use std::fs;
+
+    /// The traces of `source` run by the interpreter and by the VM for `ms`
+    /// simulated milliseconds, with `input` received on "uart".
+    fn traces(name: &str, source: &str, input: &[u8], ms: u64) -> (String, String) {
+        let program = parser::parse_source(source);
+        let module = bytecode::compile(&ir::lower(&program).unwrap()).unwrap();
+        let module = bytecode::read(&bytecode::write(&module)).unwrap();
+
+        let path = |suffix: &str| {
+            std::env::temp_dir().join(format!("nxc-vm-{}-{}.{}", std::process::id(), name, suffix))
+        };
+        fs::write(path("in"), input).unwrap();
+        let options = |trace: &str| runtime::Options {
+            sim_time_ms: Some(ms),
+            seed: Some(3),
+            interfaces: vec![(
+                "uart".to_string(),
+                format!("file:{},", path("in").display()),
+            )],
+            trace_path: Some(path(trace)),
+            ..runtime::Options::default()
+        };
+
+        let interpreted = runtime::run(&program, Box::new(Captured::default()), &options("a"));
+        let interfaces = runtime::open_interfaces(&options("b")).unwrap();
+        let mut vm = Vm::new(
+            module,
+            Box::new(Captured::default()),
+            interfaces,
+            &options("b"),
+        )
+        .unwrap();
+        let compiled = runtime::drive(&mut vm, Some(ms));
+        drop(vm);
+
+        let read = |suffix: &str| fs::read_to_string(path(suffix)).unwrap();
+        let (a, b) = (read("a"), read("b"));
+        for suffix in ["in", "a", "b"] {
+            fs::remove_file(path(suffix)).unwrap();
+        }
+        assert_eq!(interpreted, compiled);
+        (a, b)
+    }
+
+    #[test]
+    fn round_trips_modules_through_files() {
+        let program = parser::parse_source(include_str!("../../example.nx"));
+        let module = bytecode::compile(&ir::lower(&program).unwrap()).unwrap();
+
+        let bytes = bytecode::write(&module);
+        let read = bytecode::read(&bytes).unwrap();
+        assert_eq!(format!("{:?}", read), format!("{:?}", module));
+        assert_eq!(bytecode::write(&read), bytes);
+        assert!(bytecode::read(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_a_module_cannot_hold() {
+        let compile = |source: &str| {
+            let program = parser::parse_source(source);
+            bytecode::compile(&ir::lower(&program).unwrap()).map(|_| ())
+        };
+
+        assert_eq!(compile("task t @ 4294967295 end"), Ok(()));
+        assert_eq!(
+            compile("task t @ 4294967396 end"),
+            Err(
+                "The interval of task t is 4294967396ms, more than a bytecode file holds"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            compile("record r[4294967296, 1];"),
+            Err(
+                "The record r has 4294967296 elements, more than a bytecode file holds".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn runs_the_example_like_the_interpreter() {
+        let input = [
+            [0x10, 0x07, 0x2c, 0x01],
+            [0x00, 0x02, 0x64, 0x00],
+            [0x11, 0x07, 0x00, 0x00],
+            [0x31, 0x00, 0x00, 0x00],
+        ]
+        .concat();
+        let (interpreted, compiled) =
+            traces("example", include_str!("../../example.nx"), &input, 2500);
+        assert!(interpreted.contains("\"event\":\"stop\""));
+        assert_eq!(interpreted, compiled);
+    }
+
+    #[test]
+    fn runs_tasks_records_and_stored_values_like_the_interpreter() {
+        let source = "
+            record samples[4, 2];
+
+            function checksum(bytes)
+                sum = 0;
+                for (b in bytes)
+                    sum = (sum + b) % 256;
+                end
+                return sum;
+            end
+
+            function report(tag)
+                store count;
+                if (count == nil)
+                    count = 0;
+                end
+                count = count + 1;
+                print(\"%s %d %d\\n\", tag, count, millis());
+                @30;
+                send(\"uart\", [tag::1, checksum([count, 7, 250])]);
+            end
+
+            task main @ 100
+                r = report.start([1]) @ 40;
+                @90;
+                r.stop();
+            end
+
+            when \"uart\" => msg::3
+                [kind, value::-2] = msg;
+                samples[kind % 4] = value;
+                match (kind)
+                    0..3 => print(\"low %d %a\\n\", value, samples);
+                    9 => print(\"nine\\n\");
+                    _ => print(\"other %x\\n\", rand(0, 1000));
+                end
+            end
+        ";
+        let input = [1, 0x12, 0x34, 9, 0, 0, 200, 0xff, 0xff];
+        let (interpreted, compiled) = traces("tasks", source, &input, 450);
+        assert!(interpreted.contains("\"event\":\"delay\""));
+        assert_eq!(interpreted, compiled);
+    }
+}
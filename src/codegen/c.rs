@@ -0,0 +1,841 @@
+use crate::automaton::Dfa;
+use crate::codegen::{declared_tasks, framing_handlers, task_handle};
+use crate::ir::{
+    BinaryOperator, Body, BodyKind, Builtin, Callee, Constant, Instruction, Operand, Program,
+    Terminator, Variable,
+};
+use crate::parser::{Endianness, Framing, Guard};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+/// The header of the functions the application implements for its board,
+/// which the generated source includes as `nx_hal.h`.
+pub const HAL_HEADER: &str = include_str!("c/nx_hal.h");
+
+const TYPES: &str = include_str!("c/types.c");
+const RUNTIME: &str = include_str!("c/runtime.c");
+
+/// Translates a verified program to a C99 source file for `nx_hal.h`.
+///
+/// The file holds the runtime the program needs: values that live in a
+/// fixed pool of reference-counted objects, the task scheduler and the
+/// framing of the `when` handlers, whose patterns become automata. Every
+/// body becomes a function over its slots that starts over at the block
+/// after an `@` delay, so tasks and handlers are state machines and
+/// functions are called directly. Records are static arrays of their
+/// element size.
+pub fn generate(program: &Program, source_name: &str) -> Result<String, String> {
+    let mut generator = Generator {
+        program,
+        out: String::new(),
+    };
+    generator.header(source_name);
+    generator.out.push_str(TYPES);
+    generator.data()?;
+    generator.out.push_str(RUNTIME);
+    generator.bodies()?;
+    Ok(generator.out)
+}
+
+struct Generator<'a> {
+    program: &'a Program,
+    out: String,
+}
+
+impl Generator<'_> {
+    fn header(&mut self, source_name: &str) {
+        let program = self.program;
+        let slots = |body: &Body| body.locals.len() + body.temps;
+        let all_slots = program.bodies.iter().map(slots).max().unwrap_or(0);
+        let call_slots = program
+            .bodies
+            .iter()
+            .filter(|body| matches!(body.kind, BodyKind::Function { .. }))
+            .map(slots)
+            .max()
+            .unwrap_or(0);
+        let args = program
+            .bodies
+            .iter()
+            .map(|body| match body.kind {
+                BodyKind::Function { parameters, .. } => parameters,
+                _ => 1,
+            })
+            .max()
+            .unwrap_or(0);
+        let items = program
+            .records
+            .iter()
+            .map(|record| record.length)
+            .chain(program.bodies.iter().flat_map(|body| {
+                body.blocks
+                    .iter()
+                    .flat_map(|block| block.instructions.iter())
+                    .map(|instruction| match instruction {
+                        Instruction::List { items, .. } => items.len(),
+                        _ => 0,
+                    })
+            }))
+            .fold(16, usize::max);
+        let declared_tasks = declared_tasks(self.program).len();
+
+        let out = &mut self.out;
+        writeln!(
+            out,
+            "/*\n * Generated by nxc from {}. Build it with nx_hal.h and an\n * implementation of its functions for the board.\n */",
+            source_name
+        )
+        .unwrap();
+        out.push_str("#include <stdbool.h>\n#include <stdint.h>\n#include <string.h>\n\n");
+        out.push_str("#include \"nx_hal.h\"\n\n");
+        out.push_str("#ifdef __GNUC__\n");
+        out.push_str("#pragma GCC diagnostic ignored \"-Wunused-function\"\n");
+        out.push_str("#pragma GCC diagnostic ignored \"-Wunused-const-variable\"\n");
+        out.push_str("#endif\n\n");
+
+        out.push_str("/* Limits of the runtime, which -D can change. */\n");
+        let limits = [
+            ("NX_OBJECTS", 32),
+            ("NX_OBJECT_BYTES", 256),
+            ("NX_OBJECT_ITEMS", items),
+            ("NX_TASKS", declared_tasks + 8),
+            ("NX_MAX_PACKET", 256),
+            ("NX_FRAME_TIMEOUT", 100),
+            ("NX_TEXT_SIZE", 128),
+        ];
+        for (name, value) in limits.iter() {
+            writeln!(out, "#ifndef {0}\n#define {0} {1}\n#endif", name, value).unwrap();
+        }
+
+        out.push_str("\n/* Sizes of the program. */\n");
+        let sizes = [
+            ("NX_SLOTS", all_slots.max(1)),
+            ("NX_CALL_SLOTS", call_slots.max(1)),
+            ("NX_ARGS", args.max(1)),
+            ("NX_BODIES", program.bodies.len()),
+            ("NX_DECLARED_TASKS", declared_tasks),
+            ("NX_INTERFACES", program.interfaces.len()),
+        ];
+        for (name, value) in sizes.iter() {
+            writeln!(out, "#define {} {}", name, value).unwrap();
+        }
+        out.push('\n');
+    }
+
+    fn data(&mut self) -> Result<(), String> {
+        let program = self.program;
+        self.out.push_str("\n/* ---- Program ---- */\n\n");
+
+        for body in program.bodies.iter() {
+            match &body.kind {
+                BodyKind::Function { name, parameters } if u8::try_from(*parameters).is_err() => {
+                    return Err(format!(
+                        "The function {} has {} parameters, more than the C runtime holds",
+                        name, parameters
+                    ));
+                }
+                BodyKind::Task { name, interval_ms } if u32::try_from(*interval_ms).is_err() => {
+                    return Err(format!(
+                        "The interval of task {} is {}ms, more than the C runtime holds",
+                        name, interval_ms
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        let bodies: Vec<String> = program
+            .bodies
+            .iter()
+            .map(|body| match &body.kind {
+                BodyKind::Function { name, parameters } => {
+                    format!("{{\"{}\", NX_FUNCTION_BODY, {}, 0}}", name, parameters)
+                }
+                BodyKind::Task { name, interval_ms } => {
+                    format!("{{\"{}\", NX_TASK_BODY, 0, {}}}", name, interval_ms)
+                }
+                BodyKind::When { interface, .. } => {
+                    format!("{{{}, NX_WHEN_BODY, 1, 0}}", c_string(interface.as_bytes()))
+                }
+            })
+            .collect();
+        self.array("static const nx_body_info", "nx_bodies", &bodies);
+
+        let declared_tasks: Vec<String> = declared_tasks(program)
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        self.array(
+            "static const uint16_t",
+            "nx_declared_tasks",
+            &declared_tasks,
+        );
+
+        let out = &mut self.out;
+        if !program.globals.is_empty() {
+            writeln!(
+                out,
+                "/* {} */\nstatic nx_value nx_globals[{}];",
+                program.globals.join(", "),
+                program.globals.len()
+            )
+            .unwrap();
+        }
+        for (id, body) in program.bodies.iter().enumerate() {
+            if !body.stored.is_empty() {
+                writeln!(
+                    out,
+                    "/* {} of {} */\nstatic nx_value nx_stored_{}[{}];",
+                    body.stored.join(", "),
+                    comment(&body.name()),
+                    id,
+                    body.stored.len()
+                )
+                .unwrap();
+            }
+        }
+
+        let mut records = vec![];
+        for (id, record) in program.records.iter().enumerate() {
+            let element = match record.data_size {
+                1 => "uint8_t",
+                2 => "uint16_t",
+                3 | 4 => "uint32_t",
+                8 => "int64_t",
+                _ => "uint64_t",
+            };
+            writeln!(
+                self.out,
+                "static {} nx_record_{}[{}];",
+                element, id, record.length
+            )
+            .unwrap();
+            records.push(format!(
+                "{{\"{}\", nx_record_{}, {}, {}}}",
+                record.name, id, record.length, record.data_size
+            ));
+        }
+        self.array("static const nx_record", "nx_records", &records);
+
+        let mut interfaces = vec![];
+        for (index, interface) in program.interfaces.iter().enumerate() {
+            let mut handlers = vec![];
+            for (id, guard) in framing_handlers(program, interface) {
+                handlers.push(self.handler(id, guard)?);
+            }
+            self.array(
+                "static const nx_handler",
+                &format!("nx_handlers_{}", index),
+                &handlers,
+            );
+            interfaces.push(format!(
+                "{{{}, nx_handlers_{}, {}, {{0}}, 0, 0}}",
+                c_string(interface.name.as_bytes()),
+                index,
+                handlers.len()
+            ));
+        }
+        self.array("static nx_interface", "nx_interfaces", &interfaces);
+        Ok(())
+    }
+
+    /// Writes the tables of a handler and returns its `nx_handler`.
+    fn handler(&mut self, id: usize, guard: &Guard) -> Result<String, String> {
+        let (pattern, framing) = match guard {
+            Guard::Default => return Ok(fixed_width(id, 1)),
+            Guard::Numeric { width, .. } => return Ok(fixed_width(id, *width)),
+            Guard::Regex { pattern, framing } => (pattern, framing),
+        };
+
+        let dfa = Dfa::new(pattern)?;
+        let (kind, start, end): (_, &[u8], &[u8]) = match framing {
+            Framing::Start(start) => ("NX_START", start, &[]),
+            Framing::End(end) => ("NX_END", &[], end),
+            Framing::Delimited { start, end } => ("NX_DELIMITED", start, end),
+            Framing::Continuation => ("NX_CONTINUATION", &[], &[]),
+        };
+
+        let bytes = |bytes: &[u8]| -> Vec<String> { bytes.iter().map(u8::to_string).collect() };
+        writeln!(
+            self.out,
+            "/* {} */",
+            comment(&self.program.bodies[id].name())
+        )
+        .unwrap();
+        let delimiter = |name: &str, data: &[u8], out: &mut Self| {
+            if data.is_empty() {
+                return "NULL".to_string();
+            }
+            let name = format!("nx_{}_{}", name, id);
+            out.array("static const uint8_t", &name, &bytes(data));
+            name
+        };
+        let start_name = delimiter("start", start, self);
+        let end_name = delimiter("end", end, self);
+        self.array(
+            "static const uint8_t",
+            &format!("nx_classes_{}", id),
+            &bytes(&dfa.classes),
+        );
+        let transitions: Vec<String> = dfa.transitions.iter().map(u16::to_string).collect();
+        self.array(
+            "static const uint16_t",
+            &format!("nx_transitions_{}", id),
+            &transitions,
+        );
+        let accepting: Vec<String> = dfa
+            .accepting
+            .iter()
+            .map(|&accepting| (accepting as u8).to_string())
+            .collect();
+        self.array(
+            "static const uint8_t",
+            &format!("nx_accepting_{}", id),
+            &accepting,
+        );
+
+        Ok(format!(
+            "{{{0}, {1}, 0, {2}, {3}, {4}, {5}, nx_classes_{0}, {6}, nx_transitions_{0}, nx_accepting_{0}}}",
+            id,
+            kind,
+            start_name,
+            start.len(),
+            end_name,
+            end.len(),
+            dfa.class_count
+        ))
+    }
+
+    /// Writes an array definition, with a zeroed element when it is empty
+    /// since C has no empty arrays.
+    fn array(&mut self, declaration: &str, name: &str, items: &[String]) {
+        if items.is_empty() {
+            writeln!(self.out, "{} {}[1];", declaration, name).unwrap();
+            return;
+        }
+        let one_line = items.join(", ");
+        if one_line.len() <= 64 && items.len() <= 16 {
+            writeln!(self.out, "{} {}[] = {{{}}};", declaration, name, one_line).unwrap();
+            return;
+        }
+        writeln!(self.out, "{} {}[] = {{", declaration, name).unwrap();
+        let short = items.iter().all(|item| item.len() <= 6);
+        if short {
+            for chunk in items.chunks(16) {
+                writeln!(self.out, "    {},", chunk.join(", ")).unwrap();
+            }
+        } else {
+            for item in items {
+                writeln!(self.out, "    {},", item).unwrap();
+            }
+        }
+        writeln!(self.out, "}};").unwrap();
+    }
+
+    fn bodies(&mut self) -> Result<(), String> {
+        let program = self.program;
+        self.out.push_str("\n/* ---- Bodies ---- */\n\n");
+        for id in 0..program.bodies.len() {
+            writeln!(self.out, "static bool nx_body_{}(nx_frame *frame);", id).unwrap();
+        }
+
+        self.out.push_str(
+            "\nstatic bool nx_run(uint16_t body, nx_frame *frame)\n{\n    switch (body) {\n",
+        );
+        for id in 0..program.bodies.len() {
+            writeln!(
+                self.out,
+                "    case {0}:\n        return nx_body_{0}(frame);",
+                id
+            )
+            .unwrap();
+        }
+        self.out
+            .push_str("    }\n    (void)frame;\n    return true;\n}\n");
+
+        for (id, body) in program.bodies.iter().enumerate() {
+            let code = BodyGenerator { program, id, body }
+                .generate()
+                .map_err(|err| format!("{} in {}", err, body.name()))?;
+            self.out.push('\n');
+            self.out.push_str(&code);
+        }
+        Ok(())
+    }
+}
+
+struct BodyGenerator<'a> {
+    program: &'a Program,
+    id: usize,
+    body: &'a Body,
+}
+
+impl BodyGenerator<'_> {
+    fn generate(&self) -> Result<String, String> {
+        let body = self.body;
+        let mut out = String::new();
+
+        let heading = match &body.kind {
+            BodyKind::Function { name, parameters } => {
+                format!(
+                    "function {}({})",
+                    name,
+                    body.locals[..*parameters].join(", ")
+                )
+            }
+            BodyKind::Task { name, interval_ms } => format!("task {} @ {}", name, interval_ms),
+            BodyKind::When { .. } => body.name(),
+        };
+        writeln!(out, "/* {} */", comment(&heading)).unwrap();
+        writeln!(out, "static bool nx_body_{}(nx_frame *frame)\n{{", self.id).unwrap();
+        if body.locals.len() + body.temps > 0 {
+            out.push_str("    nx_value *v = frame->slots;\n");
+        }
+
+        let resumes: BTreeSet<usize> = body
+            .blocks
+            .iter()
+            .filter_map(|block| match block.terminator {
+                Terminator::Suspend { resume, .. } => Some(resume),
+                _ => None,
+            })
+            .collect();
+        let mut labels = resumes.clone();
+        for (id, block) in body.blocks.iter().enumerate() {
+            labels.extend(self.gotos(id, &block.terminator));
+        }
+
+        if !resumes.is_empty() {
+            out.push_str("\n    switch (frame->resume) {\n");
+            for resume in resumes.iter() {
+                writeln!(out, "    case {0}:\n        goto b{0};", resume).unwrap();
+            }
+            out.push_str("    }\n");
+        }
+
+        for (id, block) in body.blocks.iter().enumerate() {
+            if labels.contains(&id) {
+                writeln!(out, "\nb{}:", id).unwrap();
+            }
+            for instruction in block.instructions.iter() {
+                writeln!(out, "    {}", self.instruction(instruction)?).unwrap();
+            }
+            self.terminator(id, &block.terminator, &mut out);
+        }
+        out.push_str("}\n");
+        Ok(out)
+    }
+
+    /// The blocks a terminator jumps to with a `goto`: the next block is
+    /// reached by falling through.
+    fn gotos(&self, id: usize, terminator: &Terminator) -> Vec<usize> {
+        match *terminator {
+            Terminator::Jump(target) if target != id + 1 => vec![target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } if then == id + 1 => vec![otherwise],
+            Terminator::Branch {
+                then, otherwise, ..
+            } if otherwise == id + 1 => vec![then],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![then, otherwise],
+            _ => vec![],
+        }
+    }
+
+    fn terminator(&self, id: usize, terminator: &Terminator, out: &mut String) {
+        match terminator {
+            Terminator::Jump(target) if *target == id + 1 => {}
+            Terminator::Jump(target) => writeln!(out, "    goto b{};", target).unwrap(),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let condition = self.operand(condition);
+                if *then == id + 1 {
+                    writeln!(
+                        out,
+                        "    if (!nx_boolean({})) goto b{};",
+                        condition, otherwise
+                    )
+                } else if *otherwise == id + 1 {
+                    writeln!(out, "    if (nx_boolean({})) goto b{};", condition, then)
+                } else {
+                    writeln!(
+                        out,
+                        "    if (nx_boolean({})) goto b{};\n    goto b{};",
+                        condition, then, otherwise
+                    )
+                }
+                .unwrap()
+            }
+            Terminator::Suspend { delay_ms, resume } => writeln!(
+                out,
+                "    frame->resume = {};\n    frame->delay_ms = {};\n    return false;",
+                resume, delay_ms
+            )
+            .unwrap(),
+            Terminator::Return(value) => writeln!(
+                out,
+                "    nx_assign(&frame->result, {});\n    return true;",
+                self.operand(value)
+            )
+            .unwrap(),
+        }
+    }
+
+    fn slot(&self, temp: usize) -> String {
+        format!("v[{}]", self.body.locals.len() + temp)
+    }
+
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Temp(temp) => self.slot(*temp),
+            Operand::Constant(constant) => match constant {
+                Constant::Nil => "nx_nil()".to_string(),
+                Constant::Boolean(value) => format!("nx_bool({})", *value as u8),
+                Constant::Integer(value) => format!("nx_int({})", c_integer(*value)),
+                Constant::String(data) => format!("nx_text({}, {})", c_string(data), data.len()),
+                Constant::Bytes(data) => format!("nx_bytes({}, {})", c_string(data), data.len()),
+                Constant::Function(id) => format!("nx_function({})", id),
+                Constant::Task(id) => format!("nx_task({})", task_handle(self.program, *id)),
+            },
+        }
+    }
+
+    /// An array of operands for a runtime function that takes a pointer and
+    /// a count.
+    fn operands(&self, operands: &[Operand]) -> String {
+        if operands.is_empty() {
+            return "NULL, 0".to_string();
+        }
+        let items: Vec<String> = operands
+            .iter()
+            .map(|operand| self.operand(operand))
+            .collect();
+        format!("(nx_value[]){{{}}}, {}", items.join(", "), items.len())
+    }
+
+    fn variable(&self, variable: &Variable) -> Result<String, String> {
+        Ok(match *variable {
+            Variable::Local(local) => format!("v[{}]", local),
+            Variable::Stored(stored) => format!("nx_stored_{}[{}]", self.id, stored),
+            Variable::Global(global) => format!("nx_globals[{}]", global),
+            Variable::Record(_) => return Err("A record cannot be assigned as a whole".to_string()),
+        })
+    }
+
+    fn instruction(&self, instruction: &Instruction) -> Result<String, String> {
+        let operand = |operand: &Operand| self.operand(operand);
+        let set = |dest: usize, value: String| format!("nx_move(&{}, {});", self.slot(dest), value);
+
+        Ok(match instruction {
+            Instruction::Copy { dest, value } => {
+                format!("nx_assign(&{}, {});", self.slot(*dest), operand(value))
+            }
+            Instruction::Load { dest, variable } => match variable {
+                Variable::Record(record) => {
+                    set(*dest, format!("nx_record_list(&nx_records[{}])", record))
+                }
+                _ => format!(
+                    "nx_assign(&{}, {});",
+                    self.slot(*dest),
+                    self.variable(variable)?
+                ),
+            },
+            Instruction::Store { variable, value } => {
+                format!(
+                    "nx_assign(&{}, {});",
+                    self.variable(variable)?,
+                    operand(value)
+                )
+            }
+            Instruction::Not {
+                dest,
+                operand: value,
+            } => set(*dest, format!("nx_not({})", operand(value))),
+            Instruction::Binary {
+                dest,
+                operator,
+                lhs,
+                rhs,
+            } => {
+                let function = match operator {
+                    BinaryOperator::Sum => "nx_add",
+                    BinaryOperator::Minus => "nx_sub",
+                    BinaryOperator::Multiply => "nx_mul",
+                    BinaryOperator::Division => "nx_div",
+                    BinaryOperator::Modulus => "nx_mod",
+                    BinaryOperator::Equal => "nx_eq",
+                    BinaryOperator::NotEqual => "nx_ne",
+                    BinaryOperator::Less => "nx_lt",
+                    BinaryOperator::Greater => "nx_gt",
+                    BinaryOperator::LessOrEqual => "nx_le",
+                    BinaryOperator::GreaterOrEqual => "nx_ge",
+                    BinaryOperator::Xor => "nx_xor",
+                };
+                set(
+                    *dest,
+                    format!("{}({}, {})", function, operand(lhs), operand(rhs)),
+                )
+            }
+            Instruction::List { dest, items } => {
+                set(*dest, format!("nx_list({})", self.operands(items)))
+            }
+            Instruction::Pack {
+                dest,
+                value,
+                width,
+                endianness,
+            } => set(
+                *dest,
+                format!(
+                    "nx_pack({}, {}, {})",
+                    operand(value),
+                    width,
+                    (*endianness == Endianness::Big) as u8
+                ),
+            ),
+            Instruction::Unpack { fields, bytes } => {
+                let widths: Vec<String> = fields
+                    .iter()
+                    .map(|(_, width, endianness)| match endianness {
+                        Endianness::Big => format!("-{}", width),
+                        Endianness::Little => width.to_string(),
+                    })
+                    .collect();
+                let targets: Vec<String> = fields
+                    .iter()
+                    .map(|(temp, _, _)| format!("&{}", self.slot(*temp)))
+                    .collect();
+                format!(
+                    "nx_unpack({}, (const int8_t[]){{{}}}, {}, (nx_value *const[]){{{}}});",
+                    operand(bytes),
+                    widths.join(", "),
+                    fields.len(),
+                    targets.join(", ")
+                )
+            }
+            Instruction::Element {
+                dest,
+                record,
+                index,
+            } => set(
+                *dest,
+                format!("nx_element(&nx_records[{}], {})", record, operand(index)),
+            ),
+            Instruction::SetElement {
+                record,
+                index,
+                value,
+            } => format!(
+                "nx_set_element(&nx_records[{}], {}, {});",
+                record,
+                operand(index),
+                operand(value)
+            ),
+            Instruction::Items { dest, collection } => {
+                set(*dest, format!("nx_items({})", operand(collection)))
+            }
+            Instruction::Length { dest, list } => {
+                set(*dest, format!("nx_count({})", operand(list)))
+            }
+            Instruction::Index { dest, list, index } => set(
+                *dest,
+                format!("nx_index({}, {})", operand(list), operand(index)),
+            ),
+            Instruction::InRange {
+                dest,
+                value,
+                start,
+                end,
+            } => set(
+                *dest,
+                format!(
+                    "nx_in_range({}, {}, {})",
+                    operand(value),
+                    c_integer(*start),
+                    c_integer(*end)
+                ),
+            ),
+            Instruction::Call { dest, callee, args } => match callee {
+                Callee::Builtin(builtin) => {
+                    let function = match builtin {
+                        Builtin::Print => "nx_builtin_print",
+                        Builtin::Send => "nx_builtin_send",
+                        Builtin::Millis => "nx_builtin_millis",
+                        Builtin::Rand => "nx_builtin_rand",
+                        Builtin::Stop => "nx_builtin_stop",
+                    };
+                    set(*dest, format!("{}({})", function, self.operands(args)))
+                }
+                Callee::Function(id) => {
+                    set(*dest, format!("nx_call({}, {})", id, self.operands(args)))
+                }
+            },
+            Instruction::Start {
+                dest,
+                target,
+                args,
+                interval,
+            } => {
+                let interval = match interval {
+                    Some(interval) => format!("1, {}", operand(interval)),
+                    None => "0, nx_nil()".to_string(),
+                };
+                set(
+                    *dest,
+                    format!(
+                        "nx_start({}, {}, {})",
+                        operand(target),
+                        self.operands(args),
+                        interval
+                    ),
+                )
+            }
+        })
+    }
+}
+
+/// The `nx_handler` of a handler that takes `width` bytes at a time.
+fn fixed_width(id: usize, width: usize) -> String {
+    format!(
+        "{{{}, NX_WIDTH, {}, NULL, 0, NULL, 0, NULL, 0, NULL, NULL}}",
+        id, width
+    )
+}
+
+/// An integer literal of type `int64_t`, which C cannot write for the
+/// smallest value.
+fn c_integer(value: i64) -> String {
+    if value == i64::MIN {
+        "(-INT64_C(9223372036854775807) - 1)".to_string()
+    } else {
+        format!("INT64_C({})", value)
+    }
+}
+
+/// A string literal, with octal escapes for the bytes that are not
+/// printable and for `?`, which could start a trigraph.
+fn c_string(data: &[u8]) -> String {
+    let mut literal = "\"".to_string();
+    for &b in data {
+        match b {
+            0x20..=0x7e if b != b'"' && b != b'\\' && b != b'?' => literal.push(b as char),
+            _ => literal.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Text for a comment, which a guard pattern could otherwise end.
+fn comment(text: &str) -> String {
+    text.replace("*/", "*\\/")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::codegen::tests::TempDir;
+    use std::fs;
+    use std::process::Command;
+
+    /// A board that runs the program from 0 to `argv[1]` milliseconds, one
+    /// millisecond per poll. The bytes of `argv[2]`, when given, arrive on
+    /// "uart" at 10 ms. Sends and failures go to standard output.
+    const STUB_HAL: &str = r#"
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include "nx_hal.h"
+
+static uint32_t now;
+static const char *input;
+
+uint32_t nx_millis(void) { return now; }
+
+void nx_send(const char *interface, const uint8_t *data, size_t len)
+{
+    size_t i;
+    printf("%s <- [", interface);
+    for (i = 0; i < len; i++) printf(i > 0 ? ", 0x%02x" : "0x%02x", data[i]);
+    printf("]\n");
+}
+
+size_t nx_iface_read(const char *interface, uint8_t *buffer, size_t capacity)
+{
+    size_t len;
+    if (strcmp(interface, "uart") != 0 || input == NULL || now < 10) return 0;
+    len = strlen(input) < capacity ? strlen(input) : capacity;
+    memcpy(buffer, input, len);
+    input += len;
+    return len;
+}
+
+void nx_print(const char *text, size_t len) { fwrite(text, 1, len, stdout); }
+
+void nx_fail(const char *message)
+{
+    printf("failed: %s\n", message);
+    exit(1);
+}
+
+int main(int argc, char **argv)
+{
+    uint32_t end = (uint32_t)atoi(argv[1]);
+    input = argc > 2 ? argv[2] : NULL;
+    nx_init(7);
+    for (now = 0; now <= end; now++) nx_poll();
+    return 0;
+}
+"#;
+
+    #[test]
+    fn rejects_intervals_the_runtime_cannot_hold() {
+        let program = crate::parser::parse_source("task t @ 4294967396 end");
+        assert_eq!(
+            generate(&crate::ir::lower(&program).unwrap(), "test.nx"),
+            Err(
+                "The interval of task t is 4294967396ms, more than the C runtime holds".to_string()
+            )
+        );
+    }
+
+    /// Compiles `program` with the host C compiler against the stub board
+    /// and returns what it writes when run for `ms` milliseconds.
+    pub(crate) fn run(name: &str, program: &Program, ms: u64, input: Option<&str>) -> String {
+        let generated = generate(program, "test.nx").unwrap();
+
+        let dir = TempDir::new("c", name);
+        let dir = &dir.0;
+        fs::write(dir.join("program.c"), generated).unwrap();
+        fs::write(dir.join("nx_hal.h"), HAL_HEADER).unwrap();
+        fs::write(dir.join("stub.c"), STUB_HAL).unwrap();
+
+        let binary = dir.join("program");
+        let compiled = Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Wextra", "-pedantic", "-Werror", "-o"])
+            .arg(&binary)
+            .arg(dir.join("program.c"))
+            .arg(dir.join("stub.c"))
+            .output()
+            .unwrap();
+        assert!(
+            compiled.status.success(),
+            "{}",
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+
+        let mut command = Command::new(&binary);
+        command.arg(ms.to_string());
+        if let Some(input) = input {
+            command.arg(input);
+        }
+        let output = command.output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
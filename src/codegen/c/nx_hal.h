@@ -0,0 +1,39 @@
+/*
+ * The hardware abstraction layer that programs compiled by `nxc build
+ * --emit c` run on. The application implements these functions for its
+ * board, then calls nx_init() once and nx_poll() from its main loop.
+ */
+#ifndef NX_HAL_H
+#define NX_HAL_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+/* Milliseconds since the board started. May wrap around. */
+uint32_t nx_millis(void);
+
+/* Sends bytes on the interface called `interface`. */
+void nx_send(const char *interface, const uint8_t *data, size_t len);
+
+/*
+ * Reads the bytes received on `interface` since the last call, at most
+ * `capacity` of them, without waiting. Returns how many were read.
+ */
+size_t nx_iface_read(const char *interface, uint8_t *buffer, size_t capacity);
+
+/* Writes the text of a print(). */
+void nx_print(const char *text, size_t len);
+
+/*
+ * Reports a runtime error, such as a division by zero or running out of
+ * value memory. The program cannot go on: this function must not return.
+ */
+void nx_fail(const char *message);
+
+/* Starts the program, seeding rand() with `seed`. */
+void nx_init(uint64_t seed);
+
+/* Receives bytes and runs the handlers and tasks that are due. */
+void nx_poll(void);
+
+#endif
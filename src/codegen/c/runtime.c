@@ -0,0 +1,1366 @@
+/* ---- Runtime ---- */
+
+#define NX_NONE ((size_t)-1)
+
+/* The sizes of the program as variables, which compilers do not warn about
+   when they are 0. */
+static const size_t nx_interface_count = NX_INTERFACES;
+static const size_t nx_declared_task_count = NX_DECLARED_TASKS;
+
+static nx_object nx_objects[NX_OBJECTS];
+
+static uint64_t nx_now;
+static uint32_t nx_last_millis;
+static uint64_t nx_random_state;
+
+static const char *const nx_type_names[] = {
+    "nil", "boolean", "integer", "string", "bytes", "list", "task", "function"
+};
+
+/* Collects text for print() and for error messages. Printed text goes out
+   whenever the buffer fills up; a message is cut short instead. */
+typedef struct {
+    char text[NX_TEXT_SIZE];
+    size_t len;
+    bool print;
+} nx_writer;
+
+static void nx_put(nx_writer *w, const char *data, size_t len)
+{
+    size_t i;
+    for (i = 0; i < len; i++) {
+        if (w->len == NX_TEXT_SIZE - 1) {
+            if (!w->print) {
+                return;
+            }
+            nx_print(w->text, w->len);
+            w->len = 0;
+        }
+        w->text[w->len++] = data[i];
+    }
+}
+
+static void nx_put_text(nx_writer *w, const char *text)
+{
+    nx_put(w, text, strlen(text));
+}
+
+static void nx_put_integer(nx_writer *w, int64_t value)
+{
+    char digits[20];
+    size_t count = 0;
+    uint64_t magnitude = value < 0 ? (uint64_t)0 - (uint64_t)value : (uint64_t)value;
+    if (value < 0) {
+        nx_put(w, "-", 1);
+    }
+    do {
+        digits[count++] = (char)('0' + magnitude % 10);
+        magnitude /= 10;
+    } while (magnitude > 0);
+    while (count > 0) {
+        nx_put(w, &digits[--count], 1);
+    }
+}
+
+static void nx_put_hex(nx_writer *w, uint64_t value, bool prefix)
+{
+    static const char hex[] = "0123456789abcdef";
+    char digits[16];
+    size_t count = 0;
+    if (prefix) {
+        nx_put(w, "0x", 2);
+    }
+    do {
+        digits[count++] = hex[value % 16];
+        value /= 16;
+    } while (value > 0);
+    if (prefix && count == 1) {
+        nx_put(w, "0", 1);
+    }
+    while (count > 0) {
+        nx_put(w, &digits[--count], 1);
+    }
+}
+
+static void nx_fail_message(nx_writer *w)
+{
+    w->text[w->len] = '\0';
+    nx_fail(w->text);
+}
+
+/* Fails with "<prefix><type name>". */
+static void nx_fail_type(const char *prefix, uint8_t kind)
+{
+    nx_writer w = {{0}, 0, false};
+    nx_put_text(&w, prefix);
+    nx_put_text(&w, nx_type_names[kind]);
+    nx_fail_message(&w);
+}
+
+/* Fails with "<prefix><integer><suffix>". */
+static void nx_fail_integer(const char *prefix, int64_t value, const char *suffix)
+{
+    nx_writer w = {{0}, 0, false};
+    nx_put_text(&w, prefix);
+    nx_put_integer(&w, value);
+    nx_put_text(&w, suffix);
+    nx_fail_message(&w);
+}
+
+/* ---- Values ---- */
+
+static nx_value nx_nil(void)
+{
+    nx_value value = {NX_NIL, 0, NULL, NULL};
+    return value;
+}
+
+static nx_value nx_scalar(uint8_t kind, int64_t integer)
+{
+    nx_value value = {0, 0, NULL, NULL};
+    value.kind = kind;
+    value.integer = integer;
+    return value;
+}
+
+static nx_value nx_bool(bool boolean)
+{
+    return nx_scalar(NX_BOOLEAN, boolean);
+}
+
+static nx_value nx_int(int64_t integer)
+{
+    return nx_scalar(NX_INTEGER, integer);
+}
+
+static nx_value nx_task(int64_t task)
+{
+    return nx_scalar(NX_TASK, task);
+}
+
+static nx_value nx_function(int64_t body)
+{
+    return nx_scalar(NX_FUNCTION, body);
+}
+
+/* Constant text of the program. */
+static nx_value nx_text(const char *text, size_t len)
+{
+    nx_value value = nx_scalar(NX_STRING, (int64_t)len);
+    value.data = (const uint8_t *)text;
+    return value;
+}
+
+/* Constant bytes of the program. */
+static nx_value nx_bytes(const char *data, size_t len)
+{
+    nx_value value = nx_scalar(NX_BYTES, (int64_t)len);
+    value.data = (const uint8_t *)data;
+    return value;
+}
+
+static nx_object *nx_alloc(void)
+{
+    size_t i;
+    for (i = 0; i < NX_OBJECTS; i++) {
+        if (nx_objects[i].refs == 0) {
+            nx_objects[i].refs = 1;
+            nx_objects[i].len = 0;
+            return &nx_objects[i];
+        }
+    }
+    nx_fail("Out of value memory: raise NX_OBJECTS");
+    return NULL;
+}
+
+static void nx_retain(nx_value value)
+{
+    if (value.object != NULL) {
+        value.object->refs++;
+    }
+}
+
+static void nx_release(nx_value value)
+{
+    uint16_t i;
+    if (value.object == NULL || --value.object->refs > 0) {
+        return;
+    }
+    if (value.kind == NX_LIST) {
+        for (i = 0; i < value.object->len; i++) {
+            nx_release(value.object->u.items[i]);
+        }
+    }
+}
+
+/* Stores a value the caller owns. */
+static void nx_move(nx_value *slot, nx_value value)
+{
+    nx_value old = *slot;
+    *slot = value;
+    nx_release(old);
+}
+
+/* Stores a borrowed value. */
+static void nx_assign(nx_value *slot, nx_value value)
+{
+    nx_retain(value);
+    nx_move(slot, value);
+}
+
+static void nx_clear(nx_value *slots, size_t count)
+{
+    size_t i;
+    for (i = 0; i < count; i++) {
+        nx_move(&slots[i], nx_nil());
+    }
+}
+
+/* New string or bytes holding a copy of `data`. */
+static nx_value nx_new_data(uint8_t kind, const uint8_t *data, size_t len)
+{
+    nx_value value = nx_scalar(kind, (int64_t)len);
+    if (len > NX_OBJECT_BYTES) {
+        nx_fail("A value is longer than NX_OBJECT_BYTES");
+    }
+    value.object = nx_alloc();
+    memcpy(value.object->u.bytes, data, len);
+    value.data = value.object->u.bytes;
+    return value;
+}
+
+static nx_value nx_list(const nx_value *items, size_t count)
+{
+    nx_value value = nx_scalar(NX_LIST, 0);
+    size_t i;
+    if (count > NX_OBJECT_ITEMS) {
+        nx_fail("A list is longer than NX_OBJECT_ITEMS");
+    }
+    value.object = nx_alloc();
+    value.object->len = (uint16_t)count;
+    for (i = 0; i < count; i++) {
+        nx_retain(items[i]);
+        value.object->u.items[i] = items[i];
+    }
+    return value;
+}
+
+static size_t nx_length(nx_value value)
+{
+    return value.kind == NX_LIST ? value.object->len : (size_t)value.integer;
+}
+
+static int64_t nx_integer(nx_value value)
+{
+    if (value.kind != NX_INTEGER) {
+        nx_fail_type("Expected an integer, found ", value.kind);
+    }
+    return value.integer;
+}
+
+static bool nx_boolean(nx_value value)
+{
+    if (value.kind != NX_BOOLEAN) {
+        nx_fail_type("Expected a boolean, found ", value.kind);
+    }
+    return value.integer != 0;
+}
+
+static bool nx_equal(nx_value a, nx_value b)
+{
+    size_t i;
+    if (a.kind != b.kind) {
+        return false;
+    }
+    switch (a.kind) {
+    case NX_NIL:
+        return true;
+    case NX_STRING:
+    case NX_BYTES:
+        return a.integer == b.integer && memcmp(a.data, b.data, (size_t)a.integer) == 0;
+    case NX_LIST:
+        if (a.object->len != b.object->len) {
+            return false;
+        }
+        for (i = 0; i < a.object->len; i++) {
+            if (!nx_equal(a.object->u.items[i], b.object->u.items[i])) {
+                return false;
+            }
+        }
+        return true;
+    default:
+        return a.integer == b.integer;
+    }
+}
+
+static void nx_put_value(nx_writer *w, nx_value value)
+{
+    size_t i;
+    switch (value.kind) {
+    case NX_NIL:
+        nx_put_text(w, "nil");
+        break;
+    case NX_BOOLEAN:
+        nx_put_text(w, value.integer ? "true" : "false");
+        break;
+    case NX_INTEGER:
+        nx_put_integer(w, value.integer);
+        break;
+    case NX_STRING:
+        nx_put(w, (const char *)value.data, (size_t)value.integer);
+        break;
+    case NX_BYTES:
+        nx_put_text(w, "[");
+        for (i = 0; i < (size_t)value.integer; i++) {
+            if (i > 0) {
+                nx_put_text(w, ", ");
+            }
+            nx_put_hex(w, value.data[i], true);
+        }
+        nx_put_text(w, "]");
+        break;
+    case NX_LIST:
+        nx_put_text(w, "[");
+        for (i = 0; i < value.object->len; i++) {
+            if (i > 0) {
+                nx_put_text(w, ", ");
+            }
+            nx_put_value(w, value.object->u.items[i]);
+        }
+        nx_put_text(w, "]");
+        break;
+    case NX_TASK:
+        nx_put_text(w, "<task ");
+        nx_put_integer(w, value.integer);
+        nx_put_text(w, ">");
+        break;
+    case NX_FUNCTION:
+        nx_put_text(w, "<function ");
+        nx_put_text(w, nx_bodies[value.integer].name);
+        nx_put_text(w, ">");
+        break;
+    }
+}
+
+/* Appends the bytes a value stands for when it is sent or unpacked. */
+static void nx_put_bytes(nx_value value, uint8_t *bytes, size_t *len)
+{
+    size_t i;
+    switch (value.kind) {
+    case NX_INTEGER:
+        if (value.integer < 0 || value.integer > 255) {
+            nx_fail_integer("The integer ", value.integer, " does not fit in a byte");
+        }
+        if (*len == NX_OBJECT_BYTES) {
+            nx_fail("A value is longer than NX_OBJECT_BYTES");
+        }
+        bytes[(*len)++] = (uint8_t)value.integer;
+        break;
+    case NX_STRING:
+    case NX_BYTES:
+        if (*len + (size_t)value.integer > NX_OBJECT_BYTES) {
+            nx_fail("A value is longer than NX_OBJECT_BYTES");
+        }
+        memcpy(bytes + *len, value.data, (size_t)value.integer);
+        *len += (size_t)value.integer;
+        break;
+    case NX_LIST:
+        for (i = 0; i < value.object->len; i++) {
+            nx_put_bytes(value.object->u.items[i], bytes, len);
+        }
+        break;
+    default:
+        nx_fail_type("Cannot convert to bytes: ", value.kind);
+    }
+}
+
+/* ---- Operators ---- */
+
+static nx_value nx_concat(nx_value a, nx_value b)
+{
+    nx_value value;
+    size_t i;
+    if (a.kind == NX_LIST) {
+        if (a.object->len + b.object->len > NX_OBJECT_ITEMS) {
+            nx_fail("A list is longer than NX_OBJECT_ITEMS");
+        }
+        value = nx_list(a.object->u.items, a.object->len);
+        for (i = 0; i < b.object->len; i++) {
+            nx_retain(b.object->u.items[i]);
+            value.object->u.items[value.object->len++] = b.object->u.items[i];
+        }
+        return value;
+    }
+
+    if ((size_t)(a.integer + b.integer) > NX_OBJECT_BYTES) {
+        nx_fail("A value is longer than NX_OBJECT_BYTES");
+    }
+    value = nx_new_data(a.kind, a.data, (size_t)a.integer);
+    memcpy(value.object->u.bytes + a.integer, b.data, (size_t)b.integer);
+    value.integer += b.integer;
+    return value;
+}
+
+static void nx_overflow(void)
+{
+    nx_fail("Integer overflow");
+}
+
+static nx_value nx_add(nx_value a, nx_value b)
+{
+    int64_t x, y;
+    if (a.kind == b.kind && (a.kind == NX_STRING || a.kind == NX_BYTES || a.kind == NX_LIST)) {
+        return nx_concat(a, b);
+    }
+    x = nx_integer(a);
+    y = nx_integer(b);
+    if ((y > 0 && x > INT64_MAX - y) || (y < 0 && x < INT64_MIN - y)) {
+        nx_overflow();
+    }
+    return nx_int(x + y);
+}
+
+static nx_value nx_sub(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    if ((y < 0 && x > INT64_MAX + y) || (y > 0 && x < INT64_MIN + y)) {
+        nx_overflow();
+    }
+    return nx_int(x - y);
+}
+
+static nx_value nx_mul(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    if (x > 0 ? (y > 0 ? x > INT64_MAX / y : y < INT64_MIN / x)
+              : (y > 0 ? x < INT64_MIN / y : x != 0 && y < INT64_MAX / x)) {
+        nx_overflow();
+    }
+    return nx_int(x * y);
+}
+
+static void nx_check_division(int64_t x, int64_t y)
+{
+    if (y == 0) {
+        nx_fail("Division by zero");
+    }
+    if (x == INT64_MIN && y == -1) {
+        nx_overflow();
+    }
+}
+
+static nx_value nx_div(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    nx_check_division(x, y);
+    return nx_int(x / y);
+}
+
+static nx_value nx_mod(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    nx_check_division(x, y);
+    return nx_int(x % y);
+}
+
+static nx_value nx_eq(nx_value a, nx_value b)
+{
+    return nx_bool(nx_equal(a, b));
+}
+
+static nx_value nx_ne(nx_value a, nx_value b)
+{
+    return nx_bool(!nx_equal(a, b));
+}
+
+static nx_value nx_lt(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    return nx_bool(x < y);
+}
+
+static nx_value nx_gt(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    return nx_bool(x > y);
+}
+
+static nx_value nx_le(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    return nx_bool(x <= y);
+}
+
+static nx_value nx_ge(nx_value a, nx_value b)
+{
+    int64_t x = nx_integer(a), y = nx_integer(b);
+    return nx_bool(x >= y);
+}
+
+static nx_value nx_xor(nx_value a, nx_value b)
+{
+    bool x = nx_boolean(a), y = nx_boolean(b);
+    return nx_bool(x != y);
+}
+
+static nx_value nx_not(nx_value a)
+{
+    return nx_bool(!nx_boolean(a));
+}
+
+/* Whether `value` fits in `width` bytes as either an unsigned or a two's
+   complement integer. */
+static bool nx_fits(int64_t value, uint8_t width)
+{
+    int64_t limit;
+    if (width >= 8) {
+        return true;
+    }
+    limit = (int64_t)1 << (8 * width);
+    return value >= -(limit / 2) && value < limit;
+}
+
+/* `value::width`, big-endian when `big`. */
+static nx_value nx_pack(nx_value value, uint8_t width, bool big)
+{
+    uint8_t bytes[8];
+    uint64_t integer = (uint64_t)nx_integer(value);
+    uint8_t i;
+    if (!nx_fits(value.integer, width)) {
+        nx_writer w = {{0}, 0, false};
+        nx_put_text(&w, "The value ");
+        nx_put_integer(&w, value.integer);
+        nx_put_text(&w, " does not fit in ");
+        nx_put_integer(&w, width);
+        nx_put_text(&w, " byte(s)");
+        nx_fail_message(&w);
+    }
+    for (i = 0; i < width; i++) {
+        bytes[big ? width - 1 - i : i] = (uint8_t)(integer >> (8 * i));
+    }
+    return nx_new_data(NX_BYTES, bytes, width);
+}
+
+/* `[a::2, b::-1] = value`: a negative width is big-endian. */
+static void nx_unpack(nx_value value, const int8_t *widths, size_t count, nx_value *const *fields)
+{
+    uint8_t bytes[NX_OBJECT_BYTES];
+    size_t len = 0, expected = 0, offset = 0, i, j;
+    nx_put_bytes(value, bytes, &len);
+    for (i = 0; i < count; i++) {
+        expected += (size_t)(widths[i] < 0 ? -widths[i] : widths[i]);
+    }
+    if (expected != len) {
+        nx_writer w = {{0}, 0, false};
+        nx_put_text(&w, "Cannot unpack ");
+        nx_put_integer(&w, (int64_t)len);
+        nx_put_text(&w, " byte(s) into targets of ");
+        nx_put_integer(&w, (int64_t)expected);
+        nx_put_text(&w, " byte(s)");
+        nx_fail_message(&w);
+    }
+
+    for (i = 0; i < count; i++) {
+        size_t width = (size_t)(widths[i] < 0 ? -widths[i] : widths[i]);
+        uint64_t integer = 0;
+        for (j = 0; j < width; j++) {
+            uint8_t b = bytes[offset + (widths[i] < 0 ? j : width - 1 - j)];
+            integer = (integer << 8) | b;
+        }
+        nx_move(fields[i], nx_int((int64_t)integer));
+        offset += width;
+    }
+}
+
+/* The items a `for` loop visits: strings and bytes are read byte by byte
+   by nx_index. */
+static nx_value nx_items(nx_value collection)
+{
+    if (collection.kind != NX_STRING && collection.kind != NX_BYTES && collection.kind != NX_LIST) {
+        nx_fail_type("Cannot iterate over ", collection.kind);
+    }
+    nx_retain(collection);
+    return collection;
+}
+
+static nx_value nx_count(nx_value items)
+{
+    return nx_int((int64_t)nx_length(items));
+}
+
+static nx_value nx_index(nx_value items, nx_value index)
+{
+    int64_t position = nx_integer(index);
+    if (position < 0 || (uint64_t)position >= nx_length(items)) {
+        nx_fail_integer("The index ", position, " is out of bounds");
+    }
+    if (items.kind == NX_LIST) {
+        nx_retain(items.object->u.items[position]);
+        return items.object->u.items[position];
+    }
+    return nx_int(items.data[position]);
+}
+
+static nx_value nx_in_range(nx_value value, int64_t start, int64_t end)
+{
+    return nx_bool(value.kind == NX_INTEGER && value.integer >= start && value.integer <= end);
+}
+
+/* ---- Records ---- */
+
+static size_t nx_position(const nx_record *record, nx_value index)
+{
+    int64_t position = nx_integer(index);
+    if (position < 0 || (uint64_t)position >= record->length) {
+        nx_writer w = {{0}, 0, false};
+        nx_put_text(&w, "The index ");
+        nx_put_integer(&w, position);
+        nx_put_text(&w, " is out of bounds for ");
+        nx_put_text(&w, record->name);
+        nx_put_text(&w, " of length ");
+        nx_put_integer(&w, record->length);
+        nx_fail_message(&w);
+    }
+    return (size_t)position;
+}
+
+static int64_t nx_get(const nx_record *record, size_t position)
+{
+    switch (record->size) {
+    case 1:
+        return ((uint8_t *)record->elements)[position];
+    case 2:
+        return ((uint16_t *)record->elements)[position];
+    case 3:
+    case 4:
+        return ((uint32_t *)record->elements)[position];
+    case 8:
+        return ((int64_t *)record->elements)[position];
+    default:
+        return (int64_t)((uint64_t *)record->elements)[position];
+    }
+}
+
+static nx_value nx_element(const nx_record *record, nx_value index)
+{
+    return nx_int(nx_get(record, nx_position(record, index)));
+}
+
+/* Stores an integer that fits the element as either an unsigned or a two's
+   complement value. */
+static void nx_set_element(const nx_record *record, nx_value index, nx_value value)
+{
+    size_t position = nx_position(record, index);
+    int64_t integer = nx_integer(value);
+    if (!nx_fits(integer, record->size)) {
+        nx_writer w = {{0}, 0, false};
+        nx_put_text(&w, "The value ");
+        nx_put_integer(&w, integer);
+        nx_put_text(&w, " does not fit in the ");
+        nx_put_integer(&w, record->size);
+        nx_put_text(&w, " byte(s) of an element of ");
+        nx_put_text(&w, record->name);
+        nx_fail_message(&w);
+    }
+    switch (record->size) {
+    case 1:
+        ((uint8_t *)record->elements)[position] = (uint8_t)integer;
+        break;
+    case 2:
+        ((uint16_t *)record->elements)[position] = (uint16_t)integer;
+        break;
+    case 3:
+    case 4:
+        ((uint32_t *)record->elements)[position] =
+            (uint32_t)integer & (uint32_t)(((uint64_t)1 << (8 * record->size)) - 1);
+        break;
+    case 8:
+        ((int64_t *)record->elements)[position] = integer;
+        break;
+    default:
+        ((uint64_t *)record->elements)[position] =
+            (uint64_t)integer & (((uint64_t)1 << (8 * record->size)) - 1);
+    }
+}
+
+/* A copy of the elements, as iterated by `for`. */
+static nx_value nx_record_list(const nx_record *record)
+{
+    nx_value value = nx_list(NULL, 0);
+    size_t i;
+    if (record->length > NX_OBJECT_ITEMS) {
+        nx_fail("A list is longer than NX_OBJECT_ITEMS");
+    }
+    for (i = 0; i < record->length; i++) {
+        value.object->u.items[i] = nx_int(nx_get(record, i));
+    }
+    value.object->len = (uint16_t)record->length;
+    return value;
+}
+
+/* ---- Tasks ---- */
+
+enum { NX_STOPPED, NX_WAITING, NX_RUNNING };
+
+/*
+ * A declared task, a function started as a task or a running `when`
+ * handler. Tasks due at the same time run in the order they were
+ * scheduled, by `sequence`.
+ */
+typedef struct {
+    uint8_t state;
+    /* Whether the program has no handle to the task, so that its entry can
+       be reused once it stops. */
+    bool detached;
+    /* Whether a tick is in progress, waiting at a delay. */
+    bool ticking;
+    uint16_t body;
+    uint32_t interval_ms;
+    uint64_t wake_at;
+    uint64_t sequence;
+    uint64_t tick_started;
+    uint8_t argc;
+    nx_value args[NX_ARGS];
+    nx_value slots[NX_SLOTS];
+    nx_frame frame;
+} nx_task_entry;
+
+static nx_task_entry nx_tasks[NX_TASKS];
+static size_t nx_task_count;
+static uint64_t nx_sequence;
+
+/* Starts a task over from the beginning of its body. A tick in progress
+   is abandoned. */
+static void nx_restart(size_t id, bool has_interval, uint32_t interval_ms, bool immediate)
+{
+    nx_task_entry *task = &nx_tasks[id];
+    if (task->state != NX_RUNNING) {
+        nx_clear(task->slots, NX_SLOTS);
+    }
+    if (has_interval) {
+        task->interval_ms = interval_ms;
+    }
+    task->state = NX_WAITING;
+    task->ticking = false;
+    task->wake_at = immediate ? nx_now : nx_now + task->interval_ms;
+    task->sequence = ++nx_sequence;
+}
+
+static size_t nx_new_task(uint16_t body, const nx_value *args, size_t argc, bool detached)
+{
+    nx_task_entry *task = NULL;
+    size_t id, i;
+    if (detached) {
+        for (id = 0; id < nx_task_count; id++) {
+            if (nx_tasks[id].detached && nx_tasks[id].state == NX_STOPPED) {
+                task = &nx_tasks[id];
+                break;
+            }
+        }
+    }
+    if (task == NULL) {
+        if (nx_task_count == NX_TASKS) {
+            nx_fail("Too many tasks: raise NX_TASKS");
+        }
+        id = nx_task_count++;
+        task = &nx_tasks[id];
+        task->frame.slots = task->slots;
+    }
+
+    task->detached = detached;
+    task->body = body;
+    task->interval_ms = 0;
+    nx_clear(task->args, NX_ARGS);
+    for (i = 0; i < argc; i++) {
+        nx_assign(&task->args[i], args[i]);
+    }
+    task->argc = (uint8_t)argc;
+    return id;
+}
+
+static void nx_stop_task(size_t id)
+{
+    nx_task_entry *task = &nx_tasks[id];
+    if (task->state != NX_RUNNING) {
+        nx_clear(task->slots, NX_SLOTS);
+    }
+    task->state = NX_STOPPED;
+    task->ticking = false;
+}
+
+/* Runs the next tick, or the rest of the tick in progress, of a due task. */
+static void nx_resume(size_t id)
+{
+    nx_task_entry *task = &nx_tasks[id];
+    bool finished;
+    size_t i;
+
+    if (!task->ticking) {
+        nx_clear(task->slots, NX_SLOTS);
+        for (i = 0; i < task->argc; i++) {
+            nx_assign(&task->slots[i], task->args[i]);
+        }
+        task->frame.resume = 0;
+        task->tick_started = nx_now;
+    }
+    task->state = NX_RUNNING;
+
+    finished = nx_run(task->body, &task->frame);
+    nx_move(&task->frame.result, nx_nil());
+
+    if (task->state != NX_RUNNING) {
+        nx_clear(task->slots, NX_SLOTS);
+        return;
+    }
+    task->sequence = ++nx_sequence;
+    if (!finished) {
+        task->state = NX_WAITING;
+        task->ticking = true;
+        task->wake_at = nx_now + task->frame.delay_ms;
+        return;
+    }
+
+    nx_clear(task->slots, NX_SLOTS);
+    task->ticking = false;
+    if (task->interval_ms > 0) {
+        task->state = NX_WAITING;
+        task->wake_at = task->tick_started + task->interval_ms;
+        if (task->wake_at < nx_now) {
+            task->wake_at = nx_now;
+        }
+    } else {
+        task->state = NX_STOPPED;
+    }
+}
+
+/* Calls a function directly, which cannot wait with `@`. */
+static nx_value nx_call(uint16_t body, const nx_value *args, size_t argc)
+{
+    nx_value slots[NX_CALL_SLOTS];
+    nx_frame frame;
+    size_t i;
+
+    for (i = 0; i < NX_CALL_SLOTS; i++) {
+        slots[i] = i < argc ? args[i] : nx_nil();
+        nx_retain(slots[i]);
+    }
+    frame.resume = 0;
+    frame.delay_ms = 0;
+    frame.result = nx_nil();
+    frame.slots = slots;
+
+    if (!nx_run(body, &frame)) {
+        nx_writer w = {{0}, 0, false};
+        nx_put_text(&w, "The function '");
+        nx_put_text(&w, nx_bodies[body].name);
+        nx_put_text(&w, "' cannot wait with @ when it is called directly");
+        nx_fail_message(&w);
+    }
+    nx_clear(slots, NX_CALL_SLOTS);
+    return frame.result;
+}
+
+/* ---- Builtins ---- */
+
+/* print(format, args...): %d, %x, %s and %a take the next argument and %%
+   writes %. */
+static nx_value nx_builtin_print(const nx_value *args, size_t count)
+{
+    nx_writer w = {{0}, 0, true};
+    const uint8_t *format;
+    size_t len, i, next = 1;
+
+    if (count == 0 || args[0].kind != NX_STRING) {
+        nx_fail("print requires a format string");
+    }
+    format = args[0].data;
+    len = (size_t)args[0].integer;
+
+    for (i = 0; i < len; i++) {
+        char c = (char)format[i];
+        if (c != '%') {
+            nx_put(&w, &c, 1);
+            continue;
+        }
+        if (++i == len) {
+            nx_fail("The format string cannot end with %");
+        }
+        c = (char)format[i];
+        if (c == '%') {
+            nx_put(&w, "%", 1);
+            continue;
+        }
+        if (next == count) {
+            nx_fail("Missing argument for the format string");
+        }
+        switch (c) {
+        case 'd':
+            nx_put_integer(&w, nx_integer(args[next]));
+            break;
+        case 'x':
+            nx_put_hex(&w, (uint64_t)nx_integer(args[next]), false);
+            break;
+        case 's':
+        case 'a':
+            nx_put_value(&w, args[next]);
+            break;
+        default: {
+            char message[] = "Unknown format directive %?";
+            message[sizeof message - 2] = c;
+            nx_fail(message);
+        }
+        }
+        next++;
+    }
+    if (next != count) {
+        nx_fail("Too many arguments for the format string");
+    }
+
+    nx_print(w.text, w.len);
+    return nx_nil();
+}
+
+/* send(interface, data...) sends the bytes of every argument after the
+   interface name. */
+static nx_value nx_builtin_send(const nx_value *args, size_t count)
+{
+    char interface[NX_TEXT_SIZE];
+    uint8_t bytes[NX_OBJECT_BYTES];
+    size_t len = 0, i;
+
+    if (count == 0 || args[0].kind != NX_STRING) {
+        nx_fail("send requires an interface name");
+    }
+    if ((size_t)args[0].integer >= NX_TEXT_SIZE) {
+        nx_fail("The interface name is longer than NX_TEXT_SIZE");
+    }
+    memcpy(interface, args[0].data, (size_t)args[0].integer);
+    interface[args[0].integer] = '\0';
+
+    for (i = 1; i < count; i++) {
+        nx_put_bytes(args[i], bytes, &len);
+    }
+    nx_send(interface, bytes, len);
+    return nx_nil();
+}
+
+static nx_value nx_builtin_millis(const nx_value *args, size_t count)
+{
+    (void)args;
+    if (count != 0) {
+        nx_fail("millis takes no arguments");
+    }
+    return nx_int((int64_t)nx_now);
+}
+
+/* The pseudo-random generator behind rand() (SplitMix64). */
+static uint64_t nx_random(void)
+{
+    uint64_t z;
+    nx_random_state += UINT64_C(0x9e3779b97f4a7c15);
+    z = nx_random_state;
+    z = (z ^ (z >> 30)) * UINT64_C(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)) * UINT64_C(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+/* The high 64 bits of a * b. */
+static uint64_t nx_multiply_high(uint64_t a, uint64_t b)
+{
+    uint64_t a_low = a & 0xffffffffu, a_high = a >> 32;
+    uint64_t b_low = b & 0xffffffffu, b_high = b >> 32;
+    uint64_t low = a_low * b_low;
+    uint64_t middle1 = a_high * b_low + (low >> 32);
+    uint64_t middle2 = a_low * b_high + (middle1 & 0xffffffffu);
+    return a_high * b_high + (middle1 >> 32) + (middle2 >> 32);
+}
+
+/* rand(min, max) is a number from `min` included to `max` excluded. */
+static nx_value nx_builtin_rand(const nx_value *args, size_t count)
+{
+    int64_t min, max;
+    uint64_t span;
+    if (count != 2) {
+        nx_fail("rand takes a minimum and a maximum");
+    }
+    min = nx_integer(args[0]);
+    max = nx_integer(args[1]);
+    if (max <= min) {
+        nx_writer w = {{0}, 0, false};
+        nx_put_text(&w, "rand needs min < max, got ");
+        nx_put_integer(&w, min);
+        nx_put_text(&w, " and ");
+        nx_put_integer(&w, max);
+        nx_fail_message(&w);
+    }
+    span = (uint64_t)max - (uint64_t)min;
+    return nx_int((int64_t)((uint64_t)min + nx_multiply_high(nx_random(), span)));
+}
+
+/* task.stop() stops a task; stopping nil does nothing. */
+static nx_value nx_builtin_stop(const nx_value *args, size_t count)
+{
+    if (count != 1) {
+        nx_fail("stop takes a single task");
+    }
+    if (args[0].kind == NX_TASK) {
+        nx_stop_task((size_t)args[0].integer);
+    } else if (args[0].kind != NX_NIL) {
+        nx_fail_type("Cannot stop ", args[0].kind);
+    }
+    return nx_nil();
+}
+
+/*
+ * `function.start(args, immediate) @ interval` runs a function as a task
+ * and returns its handle; `task.start() @ interval` starts a task over.
+ */
+static nx_value nx_start(nx_value target, const nx_value *args, size_t count, bool has_interval,
+                         nx_value interval)
+{
+    nx_value task_args[NX_ARGS];
+    size_t argc = 0, i;
+    uint32_t interval_ms = 0;
+    bool immediate = true;
+
+    if (has_interval) {
+        int64_t value = nx_integer(interval);
+        if (value < 0) {
+            nx_fail("The task interval cannot be negative");
+        }
+        interval_ms = (uint32_t)value;
+    }
+    if (count > 2) {
+        nx_fail("start takes an argument list and a boolean");
+    }
+    if (count == 2) {
+        immediate = nx_boolean(args[1]);
+    }
+    if (count >= 1) {
+        nx_value items = nx_items(args[0]);
+        argc = nx_length(items);
+        nx_release(items);
+    }
+
+    if (target.kind == NX_FUNCTION) {
+        const nx_body_info *body = &nx_bodies[target.integer];
+        size_t id;
+        if (argc != body->parameters) {
+            nx_writer w = {{0}, 0, false};
+            nx_put_text(&w, "The function '");
+            nx_put_text(&w, body->name);
+            nx_put_text(&w, "' takes ");
+            nx_put_integer(&w, body->parameters);
+            nx_put_text(&w, " argument(s) but ");
+            nx_put_integer(&w, (int64_t)argc);
+            nx_put_text(&w, " were given");
+            nx_fail_message(&w);
+        }
+        for (i = 0; i < argc; i++) {
+            task_args[i] = nx_index(args[0], nx_int((int64_t)i));
+        }
+        id = nx_new_task((uint16_t)target.integer, task_args, argc, false);
+        nx_clear(task_args, argc);
+        nx_tasks[id].interval_ms = interval_ms;
+        nx_restart(id, false, 0, immediate);
+        return nx_task((int64_t)id);
+    }
+    if (target.kind == NX_TASK) {
+        if (argc > 0) {
+            nx_fail("A task cannot be started with new arguments");
+        }
+        nx_restart((size_t)target.integer, has_interval, interval_ms, immediate);
+        return target;
+    }
+    nx_fail_type("Cannot start ", target.kind);
+    return nx_nil();
+}
+
+/* ---- Framing ---- */
+
+/* Where `needle` next appears in the buffer from `from` on, overlaps
+   included. */
+static size_t nx_find(const nx_interface *iface, size_t from, const uint8_t *needle, size_t len)
+{
+    size_t position;
+    for (position = from; position + len <= iface->len; position++) {
+        if (memcmp(iface->buffer + position, needle, len) == 0) {
+            return position;
+        }
+    }
+    return NX_NONE;
+}
+
+/* The length of the longest end of the buffer that begins `delimiter`
+   without completing it. */
+static size_t nx_partial_suffix(const nx_interface *iface, const uint8_t *delimiter, size_t len)
+{
+    size_t prefix = len - 1 < iface->len ? len - 1 : iface->len;
+    for (; prefix > 0; prefix--) {
+        if (memcmp(iface->buffer + iface->len - prefix, delimiter, prefix) == 0) {
+            return prefix;
+        }
+    }
+    return 0;
+}
+
+static bool nx_matches(const nx_interface *iface, const nx_handler *handler, size_t start,
+                       size_t end)
+{
+    uint16_t state = 1;
+    size_t i;
+    if (end - start > NX_MAX_PACKET) {
+        return false;
+    }
+    for (i = start; i < end; i++) {
+        state = handler->transitions[state * handler->class_count +
+                                     handler->classes[iface->buffer[i]]];
+    }
+    return handler->accepting[state] != 0;
+}
+
+/* A packet at start..end, complete once `ready` bytes arrived, or where the
+   handler may still find one. */
+typedef struct {
+    bool complete;
+    size_t start;
+    size_t end;
+    size_t ready;
+    size_t from;
+} nx_candidate;
+
+static nx_candidate nx_complete(size_t start, size_t end, size_t ready)
+{
+    nx_candidate candidate = {true, 0, 0, 0, 0};
+    candidate.start = start;
+    candidate.end = end;
+    candidate.ready = ready;
+    return candidate;
+}
+
+static nx_candidate nx_waiting(size_t from)
+{
+    nx_candidate candidate = {false, 0, 0, 0, 0};
+    candidate.from = from;
+    return candidate;
+}
+
+/* Frames packets the way the interpreter does. */
+static nx_candidate nx_candidate_of(const nx_interface *iface, const nx_handler *handler,
+                                    bool idle)
+{
+    size_t len = iface->len, position, begin, next, stop;
+
+    switch (handler->framing) {
+    case NX_WIDTH:
+        if (len >= handler->width) {
+            return nx_complete(0, handler->width, handler->width);
+        }
+        return nx_waiting(0);
+
+    case NX_END:
+        for (position = nx_find(iface, 0, handler->end, handler->end_len); position != NX_NONE;
+             position = nx_find(iface, position + 1, handler->end, handler->end_len)) {
+            stop = position + handler->end_len;
+            if (nx_matches(iface, handler, 0, stop)) {
+                return nx_complete(0, stop, stop);
+            }
+        }
+        return nx_waiting(0);
+
+    case NX_CONTINUATION:
+        for (stop = 1; stop <= len; stop++) {
+            if (nx_matches(iface, handler, 0, stop)) {
+                return nx_complete(0, stop, stop);
+            }
+        }
+        return nx_waiting(0);
+
+    case NX_DELIMITED:
+        for (position = nx_find(iface, 0, handler->end, handler->end_len); position != NX_NONE;
+             position = nx_find(iface, position + 1, handler->end, handler->end_len)) {
+            stop = position + handler->end_len;
+            begin = NX_NONE;
+            for (next = nx_find(iface, 0, handler->start, handler->start_len);
+                 next != NX_NONE && next + handler->start_len <= position;
+                 next = nx_find(iface, next + 1, handler->start, handler->start_len)) {
+                begin = next;
+            }
+            if (begin != NX_NONE && nx_matches(iface, handler, begin, stop)) {
+                return nx_complete(begin, stop, stop);
+            }
+        }
+        begin = NX_NONE;
+        for (next = nx_find(iface, 0, handler->start, handler->start_len); next != NX_NONE;
+             next = nx_find(iface, next + 1, handler->start, handler->start_len)) {
+            begin = next;
+        }
+        if (begin != NX_NONE) {
+            return nx_waiting(begin);
+        }
+        return nx_waiting(len - nx_partial_suffix(iface, handler->start, handler->start_len));
+
+    default:
+        begin = nx_find(iface, 0, handler->start, handler->start_len);
+        if (begin == NX_NONE) {
+            return nx_waiting(len - nx_partial_suffix(iface, handler->start, handler->start_len));
+        }
+        for (;;) {
+            next = nx_find(iface, begin + handler->start_len, handler->start, handler->start_len);
+            if (next != NX_NONE && nx_matches(iface, handler, begin, next)) {
+                return nx_complete(begin, next, next + handler->start_len);
+            }
+            if (next != NX_NONE) {
+                begin = next;
+            } else if (idle && nx_matches(iface, handler, begin, len)) {
+                return nx_complete(begin, len, len);
+            } else {
+                return nx_waiting(begin);
+            }
+        }
+    }
+}
+
+static void nx_drop(nx_interface *iface, size_t count)
+{
+    memmove(iface->buffer, iface->buffer + count, iface->len - count);
+    iface->len -= count;
+}
+
+/* Starts a handler for each packet the buffer completes. When `idle`, the
+   buffered partial packet timed out. */
+static void nx_frame_packets(nx_interface *iface, bool idle)
+{
+    while (iface->len > 0) {
+        const nx_handler *best = NULL;
+        nx_candidate packet = {false, 0, 0, 0, 0};
+        size_t from = iface->len, i;
+
+        for (i = 0; i < iface->handler_count; i++) {
+            nx_candidate candidate = nx_candidate_of(iface, &iface->handlers[i], idle);
+            if (candidate.complete) {
+                if (best == NULL || candidate.ready < packet.ready) {
+                    best = &iface->handlers[i];
+                    packet = candidate;
+                }
+            } else if (candidate.from < from) {
+                from = candidate.from;
+            }
+        }
+
+        if (best != NULL) {
+            nx_value bytes = nx_new_data(NX_BYTES, iface->buffer + packet.start,
+                                         packet.end - packet.start);
+            size_t id = nx_new_task(best->body, &bytes, 1, true);
+            nx_release(bytes);
+            nx_restart(id, false, 0, true);
+            nx_drop(iface, packet.end);
+        } else if (from > 0) {
+            nx_drop(iface, from);
+        } else if (iface->len > NX_MAX_PACKET) {
+            nx_drop(iface, 1);
+        } else {
+            break;
+        }
+    }
+    if (idle) {
+        iface->len = 0;
+    }
+}
+
+static void nx_receive(void)
+{
+    size_t i, count;
+    for (i = 0; i < nx_interface_count; i++) {
+        nx_interface *iface = &nx_interfaces[i];
+        while ((count = nx_iface_read(iface->name, iface->buffer + iface->len,
+                                      sizeof iface->buffer - iface->len)) > 0) {
+            iface->len += count;
+            iface->last_byte_at = nx_now;
+            nx_frame_packets(iface, false);
+        }
+    }
+}
+
+/* ---- Scheduling ---- */
+
+static bool nx_next_task(size_t *id)
+{
+    bool found = false;
+    size_t i;
+    for (i = 0; i < nx_task_count; i++) {
+        const nx_task_entry *task = &nx_tasks[i];
+        if (task->state == NX_WAITING &&
+            (!found || task->wake_at < nx_tasks[*id].wake_at ||
+             (task->wake_at == nx_tasks[*id].wake_at && task->sequence < nx_tasks[*id].sequence))) {
+            *id = i;
+            found = true;
+        }
+    }
+    return found;
+}
+
+/* When something is next due: a task or a partial packet timing out. */
+static bool nx_next_wake(uint64_t *wake_at)
+{
+    bool found = false;
+    size_t i = 0;
+    if (nx_next_task(&i)) {
+        *wake_at = nx_tasks[i].wake_at;
+        found = true;
+    }
+    for (i = 0; i < nx_interface_count; i++) {
+        uint64_t deadline = nx_interfaces[i].last_byte_at + NX_FRAME_TIMEOUT;
+        if (nx_interfaces[i].len > 0 && (!found || deadline < *wake_at)) {
+            *wake_at = deadline;
+            found = true;
+        }
+    }
+    return found;
+}
+
+/* Handles what is due: partial packets that timed out, else the next
+   task. */
+static void nx_step(void)
+{
+    bool expired = false;
+    size_t i;
+    for (i = 0; i < nx_interface_count; i++) {
+        nx_interface *iface = &nx_interfaces[i];
+        if (iface->len > 0 && iface->last_byte_at + NX_FRAME_TIMEOUT <= nx_now) {
+            nx_frame_packets(iface, true);
+            expired = true;
+        }
+    }
+    if (!expired && nx_next_task(&i)) {
+        nx_resume(i);
+    }
+}
+
+void nx_init(uint64_t seed)
+{
+    size_t i;
+    nx_random_state = seed;
+    nx_last_millis = nx_millis();
+    for (i = 0; i < nx_declared_task_count; i++) {
+        size_t id = nx_new_task(nx_declared_tasks[i], NULL, 0, false);
+        nx_tasks[id].interval_ms = nx_bodies[nx_declared_tasks[i]].interval_ms;
+        nx_restart(id, false, 0, true);
+    }
+}
+
+void nx_poll(void)
+{
+    uint32_t millis = nx_millis();
+    uint64_t wake_at;
+    nx_now += (uint32_t)(millis - nx_last_millis);
+    nx_last_millis = millis;
+
+    for (;;) {
+        nx_receive();
+        if (!nx_next_wake(&wake_at) || wake_at > nx_now) {
+            return;
+        }
+        nx_step();
+    }
+}
@@ -0,0 +1,101 @@
+/* ---- Runtime types ---- */
+
+#if NX_OBJECT_BYTES < NX_MAX_PACKET
+#error "NX_OBJECT_BYTES must hold a whole packet of NX_MAX_PACKET bytes"
+#endif
+
+enum {
+    NX_NIL,
+    NX_BOOLEAN,
+    NX_INTEGER,
+    NX_STRING,
+    NX_BYTES,
+    NX_LIST,
+    NX_TASK,
+    NX_FUNCTION
+};
+
+typedef struct nx_object nx_object;
+
+/*
+ * A value of the program. Strings and bytes point to their data, which a
+ * heap object holds unless it is constant text. Lists are heap objects.
+ * Variables own a reference to their object; arguments are borrowed.
+ */
+typedef struct {
+    uint8_t kind;
+    /* The boolean, the integer, the task, the function body or the length
+       of a string or bytes. */
+    int64_t integer;
+    const uint8_t *data;
+    nx_object *object;
+} nx_value;
+
+/* A reference-counted block of the value memory. */
+struct nx_object {
+    uint16_t refs;
+    /* The number of items of a list. */
+    uint16_t len;
+    union {
+        uint8_t bytes[NX_OBJECT_BYTES];
+        nx_value items[NX_OBJECT_ITEMS];
+    } u;
+};
+
+/* A body in execution, which stops at `@` delays and goes on later. */
+typedef struct {
+    /* The block to go on at: 0 runs the body from the start. */
+    uint16_t resume;
+    uint32_t delay_ms;
+    nx_value result;
+    /* The locals, then the temporaries. */
+    nx_value *slots;
+} nx_frame;
+
+typedef struct {
+    const char *name;
+    /* uint8_t, uint16_t, uint32_t or uint64_t by size, int64_t for 8 bytes. */
+    void *elements;
+    uint32_t length;
+    uint8_t size;
+} nx_record;
+
+enum { NX_WIDTH, NX_END, NX_CONTINUATION, NX_DELIMITED, NX_START };
+
+/* A `when` handler, with how it frames packets and, for a pattern, the
+   automaton that matches whole packets. */
+typedef struct {
+    uint16_t body;
+    uint8_t framing;
+    uint8_t width;
+    const uint8_t *start;
+    uint8_t start_len;
+    const uint8_t *end;
+    uint8_t end_len;
+    const uint8_t *classes;
+    uint16_t class_count;
+    const uint16_t *transitions;
+    const uint8_t *accepting;
+} nx_handler;
+
+typedef struct {
+    const char *name;
+    /* In dispatch order. */
+    const nx_handler *handlers;
+    uint8_t handler_count;
+    uint8_t buffer[2 * NX_MAX_PACKET];
+    size_t len;
+    uint64_t last_byte_at;
+} nx_interface;
+
+enum { NX_FUNCTION_BODY, NX_TASK_BODY, NX_WHEN_BODY };
+
+typedef struct {
+    const char *name;
+    uint8_t kind;
+    uint8_t parameters;
+    uint32_t interval_ms;
+} nx_body_info;
+
+/* Runs a body until it returns (true) or reaches an `@` delay (false). */
+static bool nx_run(uint16_t body, nx_frame *frame);
@@ -0,0 +1,255 @@
+//! Translations of the lowered program into source code for the targets
+//! that run it without the interpreter.
+
+use crate::ir::{BodyId, BodyKind, Interface, Program};
+use crate::parser::Guard;
+
+pub mod c;
+pub mod rust;
+pub mod wasm;
+
+/// The task bodies, in the order their handles are numbered.
+fn declared_tasks(program: &Program) -> Vec<BodyId> {
+    (0..program.bodies.len())
+        .filter(|&id| matches!(program.bodies[id].kind, BodyKind::Task { .. }))
+        .collect()
+}
+
+/// The handle of the declared task `body`.
+fn task_handle(program: &Program, body: BodyId) -> usize {
+    (0..body)
+        .filter(|&id| matches!(program.bodies[id].kind, BodyKind::Task { .. }))
+        .count()
+}
+
+/// The handlers that frame the packets of an interface, in dispatch order:
+/// only the first fixed-width handler does, as in the interpreter.
+pub(crate) fn framing_handlers<'a>(
+    program: &'a Program,
+    interface: &Interface,
+) -> Vec<(BodyId, &'a Guard)> {
+    let mut handlers = vec![];
+    let mut fixed_width = false;
+    for &id in interface.handlers.iter() {
+        let guard = match &program.bodies[id].kind {
+            BodyKind::When { guard, .. } => guard,
+            _ => unreachable!("dispatch tables only hold 'when' handlers"),
+        };
+        match guard {
+            Guard::Regex { .. } => {}
+            _ if fixed_width => continue,
+            _ => fixed_width = true,
+        }
+        handlers.push((id, guard));
+    }
+    handlers
+}
+
+/// Snake case identifiers for interface names, to follow `send_` or
+/// `when_`. Names that would clash are told apart by their position.
+fn identifiers(names: &[String]) -> Vec<String> {
+    let mut idents: Vec<String> = vec![];
+    for (position, name) in names.iter().enumerate() {
+        let mut ident: String = name
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' => c,
+                'A'..='Z' => c.to_ascii_lowercase(),
+                _ => '_',
+            })
+            .collect();
+        if ident.is_empty() || idents.contains(&ident) {
+            ident = format!("{}_{}", ident, position);
+        }
+        idents.push(ident);
+    }
+    idents
+}
+
+/// The conformance table every backend runs: each program must print and
+/// send the same as in the interpreter.
+#[cfg(test)]
+mod tests {
+    use crate::codegen::{c, rust, wasm};
+    use crate::runtime::{run_captured, Options};
+    use crate::{ir, parser};
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// A program run from 0 to `ms` milliseconds, with `input` arriving on
+    /// "uart" at 10 ms, and what it writes.
+    struct Case {
+        name: &'static str,
+        source: &'static str,
+        ms: u64,
+        input: Option<&'static str>,
+        output: &'static str,
+    }
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "tasks",
+            source: "
+                function sq(x)
+                    return x * x;
+                end
+
+                task main @ 100
+                    store runs;
+                    if (runs == nil)
+                        runs = 0;
+                    end
+                    runs += 1;
+                    print(\"tick %d at %d\\n\", sq(runs), millis());
+                    @ 30;
+                    print(\"late %d\\n\", millis());
+                end
+            ",
+            ms: 250,
+            input: None,
+            output: "tick 1 at 0\nlate 30\ntick 4 at 100\nlate 130\ntick 9 at 200\nlate 230\n",
+        },
+        Case {
+            name: "framing",
+            source: "
+                record seen[2, 2];
+
+                when \"uart\" => msg::':.*;'
+                    seen[0] = seen[0] + 1;
+                    print(\"packet %a\\n\", msg);
+                    send(\"uart\", [seen[0]::-2, msg]);
+                end
+            ",
+            ms: 50,
+            input: Some("x:ab;:c;"),
+            output:
+                "packet [0x3a, 0x61, 0x62, 0x3b]\nuart <- [0x00, 0x01, 0x3a, 0x61, 0x62, 0x3b]\n\
+                     packet [0x3a, 0x63, 0x3b]\nuart <- [0x00, 0x02, 0x3a, 0x63, 0x3b]\n",
+        },
+        Case {
+            name: "handles",
+            source: "
+                store beat;
+
+                function report(kind)
+                    print(\"%s %d %d\\n\", kind, millis(), rand(0, 100));
+                end
+
+                task watchdog @ 45
+                    beat.stop();
+                end
+
+                when \"uart\" => msg::2
+                    [op, arg] = msg;
+                    match (op)
+                        0x41 => beat = report.start([\"beat\"], false) @ arg;
+                        _ => print(\"%x %x\\n\", op, arg);
+                    end
+                end
+            ",
+            ms: 60,
+            input: Some("A\x14zy"),
+            output: "7a 79\nbeat 30 38\n",
+        },
+        Case {
+            name: "errors",
+            source: "
+                task main
+                    print(\"%d\\n\", 7 / (millis() - millis()));
+                end
+            ",
+            ms: 10,
+            input: None,
+            output: "failed: Division by zero\n",
+        },
+    ];
+
+    /// A directory that is removed when it goes out of scope, even when
+    /// the test that made it fails.
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(backend: &str, name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "nxc-{}-{}-{}",
+                backend,
+                std::process::id(),
+                name
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn program(case: &Case) -> (Vec<parser::AST>, ir::Program) {
+        let ast = parser::parse_source(case.source);
+        let program = ir::lower(&ast).unwrap();
+        ir::verify(&program).unwrap();
+        (ast, program)
+    }
+
+    /// What the interpreter writes for a case, with its failure, if any,
+    /// reading the input from a capture in `dir`.
+    fn interpret(case: &Case, dir: &TempDir) -> String {
+        let (ast, _) = program(case);
+        let capture = dir.0.join("input.nxcap");
+        let hex: String = case
+            .input
+            .unwrap_or_default()
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        fs::write(&capture, format!("# nxcap 1\n10 uart {}\n", hex)).unwrap();
+        let options = Options {
+            sim_time_ms: Some(case.ms),
+            seed: Some(7),
+            replay_path: Some(capture),
+            ..Options::default()
+        };
+
+        let (mut output, result) = run_captured(&ast, &options);
+        if let Err(err) = result {
+            output += &format!("failed: {}\n", err);
+        }
+        output
+    }
+
+    /// Runs every case with `backend` and checks it against the
+    /// interpreter.
+    fn conforms(name: &str, backend: impl Fn(&str, &ir::Program, u64, Option<&str>) -> String) {
+        for case in CASES {
+            let dir = TempDir::new(&format!("{}-interpreter", name), case.name);
+            let expected = interpret(case, &dir);
+            assert_eq!(expected, case.output, "the interpreter on {}", case.name);
+            let (_, program) = program(case);
+            assert_eq!(
+                backend(case.name, &program, case.ms, case.input),
+                expected,
+                "the {} backend on {}",
+                name,
+                case.name
+            );
+        }
+    }
+
+    #[test]
+    fn c_runs_like_the_interpreter() {
+        conforms("c", c::tests::run);
+    }
+
+    #[test]
+    fn rust_runs_like_the_interpreter() {
+        conforms("rust", rust::tests::run);
+    }
+
+    #[test]
+    fn wasm_runs_like_the_interpreter() {
+        conforms("wasm", wasm::tests::run);
+    }
+}
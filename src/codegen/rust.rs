@@ -0,0 +1,720 @@
+use crate::automaton::Dfa;
+use crate::codegen::{declared_tasks, framing_handlers, identifiers, task_handle};
+use crate::ir::{
+    BinaryOperator, Body, BodyKind, Builtin, Callee, Constant, Instruction, Operand, Program,
+    Terminator, Variable,
+};
+use crate::parser::{Endianness, Framing, Guard};
+use std::fmt::Write;
+
+const RUNTIME: &str = include_str!("rust/runtime.rs");
+
+/// Translates a verified program to a Rust module without dependencies,
+/// for a service to embed with `mod`.
+///
+/// The module exports `Program`, which holds the variables, tasks and
+/// partial packets, and the `Interfaces` trait the service implements,
+/// with a `send_*` method for every interface the program sends on by
+/// name. Each interface with `when` handlers has a `when_*` method that
+/// frames the bytes received on it; `Program::poll` then runs the
+/// handlers and tasks that are due at the time the caller gives. Every
+/// body becomes a method over its slots that goes on at the block after an
+/// `@` delay.
+pub fn generate(program: &Program, source_name: &str) -> Result<String, String> {
+    let mut generator = Generator {
+        program,
+        out: String::new(),
+        sends: sent_interfaces(program),
+    };
+    generator.header(source_name);
+    generator.interfaces();
+    generator.data()?;
+    generator.out.push('\n');
+    generator.out.push_str(RUNTIME);
+    generator.bodies()?;
+    Ok(generator.out)
+}
+
+struct Generator<'a> {
+    program: &'a Program,
+    out: String,
+    /// The interfaces `send` names, with the identifier of their method.
+    sends: Vec<(String, String)>,
+}
+
+/// The interfaces that `send` calls name with a constant, in the order
+/// they first appear, each with an identifier of its own.
+fn sent_interfaces(program: &Program) -> Vec<(String, String)> {
+    let mut names = vec![];
+    let calls = program
+        .bodies
+        .iter()
+        .flat_map(|body| body.blocks.iter())
+        .flat_map(|block| block.instructions.iter());
+    for instruction in calls {
+        let name = match instruction {
+            Instruction::Call {
+                callee: Callee::Builtin(Builtin::Send),
+                args,
+                ..
+            } => match args.first() {
+                Some(Operand::Constant(Constant::String(name))) => {
+                    String::from_utf8_lossy(name).into_owned()
+                }
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    let idents = identifiers(&names);
+    names.into_iter().zip(idents).collect()
+}
+
+impl Generator<'_> {
+    fn header(&mut self, source_name: &str) {
+        writeln!(
+            self.out,
+            "//! Generated by nxc from {}. Implement `Interfaces` for the service,\n\
+             //! hand the bytes it receives to the `when_*` methods of `Program` and\n\
+             //! call `Program::poll` with the time.\n\
+             //!\n\
+             //! There is one `when_*` method per interface, not per handler: the\n\
+             //! bytes are framed into packets and offered to the handlers in\n\
+             //! dispatch order, as in the interpreter, so the service never picks\n\
+             //! a handler itself.\n",
+            source_name
+        )
+        .unwrap();
+        self.out
+            .push_str("#![allow(dead_code, unused_mut, unused_variables)]\n\nuse std::fmt;\n\n");
+    }
+
+    fn interfaces(&mut self) {
+        let out = &mut self.out;
+        out.push_str("/// What the program needs from the service that runs it.\n");
+        out.push_str("pub trait Interfaces {\n");
+        for (name, ident) in self.sends.iter() {
+            writeln!(
+                out,
+                "    /// Sends bytes on {:?}.\n    fn send_{}(&mut self, data: &[u8]);\n",
+                name, ident
+            )
+            .unwrap();
+        }
+        out.push_str(
+            "    /// Writes the text of a `print`.\n    fn print(&mut self, text: &str) {\n        \
+             print!(\"{}\", text);\n    }\n}\n\n",
+        );
+
+        out.push_str("/// Sends on the interface called `interface`, if the program names it.\n");
+        out.push_str("fn send(io: &mut dyn Interfaces, interface: &[u8], data: &[u8]) -> bool {\n");
+        if self.sends.is_empty() {
+            out.push_str("    false\n}\n\n");
+            return;
+        }
+        out.push_str("    match interface {\n");
+        for (name, ident) in self.sends.iter() {
+            writeln!(
+                out,
+                "        {} => io.send_{}(data),",
+                byte_string(name.as_bytes()),
+                ident
+            )
+            .unwrap();
+        }
+        out.push_str("        _ => return false,\n    }\n    true\n}\n\n");
+    }
+
+    fn data(&mut self) -> Result<(), String> {
+        let program = self.program;
+
+        let bodies: Vec<String> = program
+            .bodies
+            .iter()
+            .map(|body| {
+                let (name, parameters, interval_ms) = match &body.kind {
+                    BodyKind::Function { name, parameters } => (name.as_str(), *parameters, 0),
+                    BodyKind::Task { name, interval_ms } => (name.as_str(), 0, *interval_ms),
+                    BodyKind::When { interface, .. } => (interface.as_str(), 1, 0),
+                };
+                format!(
+                    "BodyInfo {{ name: {:?}, parameters: {}, interval_ms: {}, slots: {}, stored: {} }}",
+                    name,
+                    parameters,
+                    interval_ms,
+                    body.locals.len() + body.temps,
+                    body.stored.len()
+                )
+            })
+            .collect();
+        self.array("BODIES", "BodyInfo", &bodies);
+
+        let declared_tasks: Vec<String> = declared_tasks(program)
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        self.array("DECLARED_TASKS", "usize", &declared_tasks);
+
+        if !program.globals.is_empty() {
+            writeln!(self.out, "/// {}", program.globals.join(", ")).unwrap();
+        }
+        writeln!(
+            self.out,
+            "const GLOBALS: usize = {};\n",
+            program.globals.len()
+        )
+        .unwrap();
+
+        let records: Vec<String> = program
+            .records
+            .iter()
+            .map(|record| {
+                format!(
+                    "RecordInfo {{ name: {:?}, length: {}, size: {} }}",
+                    record.name, record.length, record.data_size
+                )
+            })
+            .collect();
+        self.array("RECORDS", "RecordInfo", &records);
+
+        let mut interfaces = vec![];
+        for (index, interface) in program.interfaces.iter().enumerate() {
+            let mut handlers = vec![];
+            for (id, guard) in framing_handlers(program, interface) {
+                handlers.push(self.handler(id, guard)?);
+            }
+            self.array(&format!("HANDLERS_{}", index), "Handler", &handlers);
+            interfaces.push(format!(
+                "InterfaceInfo {{ name: {:?}, handlers: HANDLERS_{} }}",
+                interface.name, index
+            ));
+        }
+        self.array("INTERFACES", "InterfaceInfo", &interfaces);
+        Ok(())
+    }
+
+    /// Writes the tables of a handler and returns its `Handler`.
+    fn handler(&mut self, id: usize, guard: &Guard) -> Result<String, String> {
+        let (pattern, framing) = match guard {
+            Guard::Default => return Ok(fixed_width(id, 1)),
+            Guard::Numeric { width, .. } => return Ok(fixed_width(id, *width)),
+            Guard::Regex { pattern, framing } => (pattern, framing),
+        };
+
+        let dfa = Dfa::new(pattern)?;
+        let framing = match framing {
+            Framing::Start(start) => format!("Framing::Start({})", byte_string(start)),
+            Framing::End(end) => format!("Framing::End({})", byte_string(end)),
+            Framing::Delimited { start, end } => format!(
+                "Framing::Delimited({}, {})",
+                byte_string(start),
+                byte_string(end)
+            ),
+            Framing::Continuation => "Framing::Continuation".to_string(),
+        };
+
+        writeln!(self.out, "// {}", self.program.bodies[id].name()).unwrap();
+        let classes: Vec<String> = dfa.classes.iter().map(u8::to_string).collect();
+        self.array(&format!("CLASSES_{}", id), "u8", &classes);
+        let transitions: Vec<String> = dfa.transitions.iter().map(u16::to_string).collect();
+        self.array(&format!("TRANSITIONS_{}", id), "u16", &transitions);
+        let accepting: Vec<String> = dfa.accepting.iter().map(bool::to_string).collect();
+        self.array(&format!("ACCEPTING_{}", id), "bool", &accepting);
+
+        Ok(format!(
+            "Handler {{ body: {0}, framing: {1}, classes: CLASSES_{0}, class_count: {2}, \
+             transitions: TRANSITIONS_{0}, accepting: ACCEPTING_{0} }}",
+            id, framing, dfa.class_count
+        ))
+    }
+
+    fn array(&mut self, name: &str, item_type: &str, items: &[String]) {
+        let one_line = items.join(", ");
+        if one_line.len() <= 64 && items.len() <= 16 {
+            writeln!(
+                self.out,
+                "static {}: &[{}] = &[{}];\n",
+                name, item_type, one_line
+            )
+            .unwrap();
+            return;
+        }
+        writeln!(self.out, "static {}: &[{}] = &[", name, item_type).unwrap();
+        if items.iter().all(|item| item.len() <= 6) {
+            for chunk in items.chunks(16) {
+                writeln!(self.out, "    {},", chunk.join(", ")).unwrap();
+            }
+        } else {
+            for item in items {
+                writeln!(self.out, "    {},", item).unwrap();
+            }
+        }
+        self.out.push_str("];\n\n");
+    }
+
+    fn bodies(&mut self) -> Result<(), String> {
+        let program = self.program;
+        self.out
+            .push_str("\n// ---- Program ----\n\nimpl Program {\n");
+
+        let names: Vec<String> = program
+            .interfaces
+            .iter()
+            .map(|interface| interface.name.clone())
+            .collect();
+        for (index, (interface, ident)) in program
+            .interfaces
+            .iter()
+            .zip(identifiers(&names))
+            .enumerate()
+        {
+            writeln!(
+                self.out,
+                "    /// Receives bytes on {:?}, for its `when` handlers to run on the\n    \
+                 /// packets they complete at the next poll.\n    \
+                 pub fn when_{}(&mut self, bytes: &[u8]) {{\n        \
+                 self.receive({}, bytes);\n    }}\n",
+                interface.name, ident, index
+            )
+            .unwrap();
+        }
+
+        self.out.push_str(
+            "    /// Runs a body until it returns (true) or reaches an `@` delay (false).\n    \
+             fn run(&mut self, body: usize, frame: &mut Frame, io: &mut dyn Interfaces) \
+             -> Result<bool, String> {\n        match body {\n",
+        );
+        for id in 0..program.bodies.len() {
+            writeln!(self.out, "            {0} => self.body_{0}(frame, io),", id).unwrap();
+        }
+        self.out.push_str(
+            "            _ => unreachable!(\"bodies are numbered\"),\n        }\n    }\n",
+        );
+
+        for (id, body) in program.bodies.iter().enumerate() {
+            let code = BodyGenerator { program, id, body }
+                .generate()
+                .map_err(|err| format!("{} in {}", err, body.name()))?;
+            self.out.push('\n');
+            self.out.push_str(&code);
+        }
+        self.out.push_str("}\n");
+        Ok(())
+    }
+}
+
+struct BodyGenerator<'a> {
+    program: &'a Program,
+    id: usize,
+    body: &'a Body,
+}
+
+impl BodyGenerator<'_> {
+    fn generate(&self) -> Result<String, String> {
+        let body = self.body;
+        let mut out = String::new();
+
+        let heading = match &body.kind {
+            BodyKind::Function { name, parameters } => {
+                format!(
+                    "function {}({})",
+                    name,
+                    body.locals[..*parameters].join(", ")
+                )
+            }
+            BodyKind::Task { name, interval_ms } => format!("task {} @ {}", name, interval_ms),
+            BodyKind::When { .. } => body.name(),
+        };
+        writeln!(out, "    /// `{}`", heading.replace('`', "'")).unwrap();
+        writeln!(
+            out,
+            "    fn body_{}(&mut self, frame: &mut Frame, io: &mut dyn Interfaces) \
+             -> Result<bool, String> {{",
+            self.id
+        )
+        .unwrap();
+        if body.locals.len() + body.temps > 0 {
+            out.push_str("        let v = &mut frame.slots;\n");
+        }
+
+        // A body of one block runs straight through, and one that never
+        // jumps only needs to find the block to go on at.
+        if let [block] = body.blocks.as_slice() {
+            for instruction in block.instructions.iter() {
+                writeln!(out, "        {}", self.instruction(instruction)?).unwrap();
+            }
+            out.push_str(&self.terminator(&block.terminator, "        ", true));
+            out.push_str("    }\n");
+            return Ok(out);
+        }
+        let jumps = body.blocks.iter().any(|block| {
+            matches!(
+                block.terminator,
+                Terminator::Jump(_) | Terminator::Branch { .. }
+            )
+        });
+
+        let indent = if jumps {
+            out.push_str("        let mut block = frame.resume;\n        loop {\n            match block {\n");
+            "                "
+        } else {
+            out.push_str("        match frame.resume {\n");
+            "            "
+        };
+        for (id, block) in body.blocks.iter().enumerate() {
+            writeln!(out, "{}{} => {{", indent, id).unwrap();
+            for instruction in block.instructions.iter() {
+                writeln!(out, "{}    {}", indent, self.instruction(instruction)?).unwrap();
+            }
+            let code = self.terminator(&block.terminator, &format!("{}    ", indent), !jumps);
+            out.push_str(&code);
+            writeln!(out, "{}}}", indent).unwrap();
+        }
+        writeln!(out, "{}_ => unreachable!(\"blocks are numbered\"),", indent).unwrap();
+        if jumps {
+            out.push_str("            }\n        }\n    }\n");
+        } else {
+            out.push_str("        }\n    }\n");
+        }
+        Ok(out)
+    }
+
+    /// The code of a terminator, whose result is the value of the method
+    /// when it is the `tail` of its code.
+    fn terminator(&self, terminator: &Terminator, indent: &str, tail: bool) -> String {
+        let exit = |finished: bool| match tail {
+            true => format!("Ok({})", finished),
+            false => format!("return Ok({});", finished),
+        };
+        let lines = match terminator {
+            Terminator::Jump(target) => vec![format!("block = {};", target)],
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => vec![format!(
+                "block = if {}.boolean()? {{ {} }} else {{ {} }};",
+                self.place(condition),
+                then,
+                otherwise
+            )],
+            Terminator::Suspend { delay_ms, resume } => vec![
+                format!("frame.resume = {};", resume),
+                format!("frame.delay_ms = {};", delay_ms),
+                exit(false),
+            ],
+            Terminator::Return(value) => vec![
+                format!("frame.result = {};", self.operand(value)),
+                exit(true),
+            ],
+        };
+        lines
+            .iter()
+            .map(|line| format!("{}{}\n", indent, line))
+            .collect()
+    }
+
+    fn slot(&self, temp: usize) -> String {
+        format!("v[{}]", self.body.locals.len() + temp)
+    }
+
+    /// An operand as an owned value.
+    fn operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Temp(temp) => format!("{}.clone()", self.slot(*temp)),
+            Operand::Constant(_) => self.place(operand),
+        }
+    }
+
+    /// An operand as a borrowed value.
+    fn operand_ref(&self, operand: &Operand) -> String {
+        format!("&{}", self.place(operand))
+    }
+
+    /// The slot of a temporary or the expression of a constant.
+    fn place(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Temp(temp) => self.slot(*temp),
+            Operand::Constant(constant) => match constant {
+                Constant::Nil => "Value::Nil".to_string(),
+                Constant::Boolean(value) => format!("Value::Boolean({})", value),
+                Constant::Integer(i64::MIN) => "Value::Integer(i64::MIN)".to_string(),
+                Constant::Integer(value) => format!("Value::Integer({})", value),
+                Constant::String(data) => {
+                    format!("Value::String({}.to_vec())", byte_string(data))
+                }
+                Constant::Bytes(data) => format!("Value::Bytes({}.to_vec())", byte_string(data)),
+                Constant::Function(id) => format!("Value::Function({})", id),
+                Constant::Task(id) => format!("Value::Task({})", task_handle(self.program, *id)),
+            },
+        }
+    }
+
+    /// A slice of operands for a runtime function.
+    fn operands(&self, operands: &[Operand]) -> String {
+        let items: Vec<String> = operands
+            .iter()
+            .map(|operand| self.operand(operand))
+            .collect();
+        format!("&[{}]", items.join(", "))
+    }
+
+    fn variable(&self, variable: &Variable) -> Result<String, String> {
+        Ok(match *variable {
+            Variable::Local(local) => format!("v[{}]", local),
+            Variable::Stored(stored) => format!("self.stored[{}][{}]", self.id, stored),
+            Variable::Global(global) => format!("self.globals[{}]", global),
+            Variable::Record(_) => return Err("A record cannot be assigned as a whole".to_string()),
+        })
+    }
+
+    fn instruction(&self, instruction: &Instruction) -> Result<String, String> {
+        let operand = |operand: &Operand| self.operand(operand);
+        let operand_ref = |operand: &Operand| self.operand_ref(operand);
+        let set = |dest: usize, value: String| format!("{} = {};", self.slot(dest), value);
+
+        Ok(match instruction {
+            Instruction::Copy { dest, value } => set(*dest, operand(value)),
+            Instruction::Load { dest, variable } => match variable {
+                Variable::Record(record) => {
+                    set(*dest, format!("self.records[{}].to_value()", record))
+                }
+                _ => set(*dest, format!("{}.clone()", self.variable(variable)?)),
+            },
+            Instruction::Store { variable, value } => {
+                format!("{} = {};", self.variable(variable)?, operand(value))
+            }
+            Instruction::Not {
+                dest,
+                operand: value,
+            } => set(*dest, format!("not({})?", operand_ref(value))),
+            Instruction::Binary {
+                dest,
+                operator,
+                lhs,
+                rhs,
+            } => {
+                let function = match operator {
+                    BinaryOperator::Modulus => "rem",
+                    operator => operator.name(),
+                };
+                set(
+                    *dest,
+                    format!("{}({}, {})?", function, operand_ref(lhs), operand_ref(rhs)),
+                )
+            }
+            Instruction::List { dest, items } => {
+                let items: Vec<String> = items.iter().map(operand).collect();
+                set(*dest, format!("Value::List(vec![{}])", items.join(", ")))
+            }
+            Instruction::Pack {
+                dest,
+                value,
+                width,
+                endianness,
+            } => set(
+                *dest,
+                format!(
+                    "pack({}, {}, {})?",
+                    operand_ref(value),
+                    width,
+                    *endianness == Endianness::Big
+                ),
+            ),
+            Instruction::Unpack { fields, bytes } => {
+                let widths: Vec<String> = fields
+                    .iter()
+                    .map(|(_, width, endianness)| match endianness {
+                        Endianness::Big => format!("-{}", width),
+                        Endianness::Little => width.to_string(),
+                    })
+                    .collect();
+                let mut code = format!(
+                    "{{ let mut fields = unpack({}, &[{}])?.into_iter();",
+                    operand_ref(bytes),
+                    widths.join(", ")
+                );
+                for (temp, _, _) in fields.iter() {
+                    write!(code, " {} = fields.next().unwrap();", self.slot(*temp)).unwrap();
+                }
+                code.push_str(" }");
+                code
+            }
+            Instruction::Element {
+                dest,
+                record,
+                index,
+            } => set(
+                *dest,
+                format!("self.records[{}].get({})?", record, operand_ref(index)),
+            ),
+            Instruction::SetElement {
+                record,
+                index,
+                value,
+            } => format!(
+                "self.records[{}].set({}, {})?;",
+                record,
+                operand_ref(index),
+                operand_ref(value)
+            ),
+            Instruction::Items { dest, collection } => {
+                set(*dest, format!("items({})?", operand_ref(collection)))
+            }
+            Instruction::Length { dest, list } => {
+                set(*dest, format!("length({})?", operand_ref(list)))
+            }
+            Instruction::Index { dest, list, index } => set(
+                *dest,
+                format!("index({}, {})?", operand_ref(list), operand_ref(index)),
+            ),
+            Instruction::InRange {
+                dest,
+                value,
+                start,
+                end,
+            } => set(
+                *dest,
+                format!("in_range({}, {}, {})", operand_ref(value), start, end),
+            ),
+            Instruction::Call { dest, callee, args } => match callee {
+                Callee::Builtin(builtin) => {
+                    let call = match builtin {
+                        Builtin::Print => format!("self.print(io, {})?", self.operands(args)),
+                        Builtin::Send => format!("self.send(io, {})?", self.operands(args)),
+                        Builtin::Millis => format!("self.millis({})?", self.operands(args)),
+                        Builtin::Rand => format!("self.rand({})?", self.operands(args)),
+                        Builtin::Stop => format!("self.stop({})?", self.operands(args)),
+                    };
+                    set(*dest, call)
+                }
+                Callee::Function(id) => set(
+                    *dest,
+                    format!("self.call(io, {}, {})?", id, self.operands(args)),
+                ),
+            },
+            Instruction::Start {
+                dest,
+                target,
+                args,
+                interval,
+            } => {
+                let interval = match interval {
+                    Some(interval) => format!("Some({})", operand_ref(interval)),
+                    None => "None".to_string(),
+                };
+                set(
+                    *dest,
+                    format!(
+                        "self.start({}, {}, {})?",
+                        operand_ref(target),
+                        self.operands(args),
+                        interval
+                    ),
+                )
+            }
+        })
+    }
+}
+
+/// The `Handler` of a handler that takes `width` bytes at a time.
+fn fixed_width(id: usize, width: usize) -> String {
+    format!(
+        "Handler {{ body: {}, framing: Framing::Width({}), classes: &[], class_count: 0, \
+         transitions: &[], accepting: &[] }}",
+        id, width
+    )
+}
+
+/// A byte string literal.
+fn byte_string(data: &[u8]) -> String {
+    let mut literal = "b\"".to_string();
+    for &b in data {
+        match b {
+            0x20..=0x7e if b != b'"' && b != b'\\' => literal.push(b as char),
+            _ => literal.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::codegen::tests::TempDir;
+    use std::fs;
+    use std::process::Command;
+
+    /// Builds the module generated from `source` in a temporary crate whose
+    /// service polls it every millisecond from 0 to `ms`, gives `input` to
+    /// `when_uart` at 10 ms and writes what it sends. Returns its output.
+    pub(crate) fn run(name: &str, program: &Program, ms: u64, input: Option<&str>) -> String {
+        let generated = generate(program, "test.nx").unwrap();
+
+        let sends: String = sent_interfaces(program)
+            .iter()
+            .map(|(name, ident)| {
+                format!(
+                    "    fn send_{}(&mut self, data: &[u8]) {{\n        \
+                     let bytes: Vec<String> = data.iter().map(|b| format!(\"0x{{:02x}}\", b)).collect();\n        \
+                     println!(\"{} <- [{{}}]\", bytes.join(\", \"));\n    }}\n",
+                    ident, name
+                )
+            })
+            .collect();
+        let receive = match input {
+            Some(input) => format!(
+                "if now == 10 {{ program.when_uart({}); }}",
+                byte_string(input.as_bytes())
+            ),
+            None => String::new(),
+        };
+        let main = format!(
+            "mod program;\n\n\
+             use program::{{Interfaces, Program}};\n\n\
+             struct Service;\n\n\
+             impl Interfaces for Service {{\n{}}}\n\n\
+             fn main() {{\n    \
+                 let mut program = Program::new(7);\n    \
+                 for now in 0..={} {{\n        \
+                     {}\n        \
+                     if let Err(err) = program.poll(now, &mut Service) {{\n            \
+                         println!(\"failed: {{}}\", err);\n            \
+                         return;\n        \
+                     }}\n    \
+                 }}\n\
+             }}\n",
+            sends, ms, receive
+        );
+
+        let dir = TempDir::new("rust", name);
+        let dir = &dir.0;
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"service\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n[workspace]\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src/program.rs"), generated).unwrap();
+        fs::write(dir.join("src/main.rs"), main).unwrap();
+
+        let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+        let output = Command::new(cargo)
+            .args(["run", "--quiet", "--offline"])
+            .current_dir(dir)
+            .env("CARGO_TARGET_DIR", dir.join("target"))
+            .env("RUSTFLAGS", "-D warnings")
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
@@ -0,0 +1,951 @@
+// ---- Runtime ----
+
+/// The longest packet a `when` handler receives.
+pub const MAX_PACKET_LEN: usize = 256;
+
+/// How long, in milliseconds, a partial packet waits for its next byte
+/// before it is dropped.
+pub const FRAME_TIMEOUT_MS: u64 = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    String(Vec<u8>),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Task(usize),
+    /// A function, by body.
+    Function(usize),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) => "integer",
+            Value::String(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Task(_) => "task",
+            Value::Function(_) => "function",
+        }
+    }
+
+    fn integer(&self) -> Result<i64, String> {
+        match self {
+            Value::Integer(value) => Ok(*value),
+            other => Err(format!("Expected an integer, found {}", other.type_name())),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(value) => Ok(*value),
+            other => Err(format!("Expected a boolean, found {}", other.type_name())),
+        }
+    }
+
+    /// The bytes this value stands for when it is sent or unpacked.
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![];
+        self.write_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_bytes(&self, bytes: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Value::Integer(value) => {
+                if *value < 0 || *value > 255 {
+                    return Err(format!("The integer {} does not fit in a byte", value));
+                }
+                bytes.push(*value as u8);
+            }
+            Value::String(data) | Value::Bytes(data) => bytes.extend(data),
+            Value::List(items) => {
+                for item in items {
+                    item.write_bytes(bytes)?;
+                }
+            }
+            other => return Err(format!("Cannot convert {} to bytes", other.type_name())),
+        }
+        Ok(())
+    }
+
+    /// The items visited by a `for` loop.
+    fn items(&self) -> Result<Vec<Value>, String> {
+        match self {
+            Value::String(data) | Value::Bytes(data) => {
+                Ok(data.iter().map(|b| Value::Integer(*b as i64)).collect())
+            }
+            Value::List(items) => Ok(items.clone()),
+            other => Err(format!("Cannot iterate over {}", other.type_name())),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::String(data) => write!(f, "{}", String::from_utf8_lossy(data)),
+            Value::Bytes(data) => {
+                write!(f, "[")?;
+                for (index, b) in data.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "0x{:02x}", b)?;
+                }
+                write!(f, "]")
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Task(id) => write!(f, "<task {}>", id),
+            Value::Function(body) => write!(f, "<function {}>", BODIES[*body].name),
+        }
+    }
+}
+
+fn concat<T: Clone>(a: &[T], b: &[T]) -> Vec<T> {
+    let mut items = a.to_vec();
+    items.extend_from_slice(b);
+    items
+}
+
+fn add(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    match (lhs, rhs) {
+        (Value::String(a), Value::String(b)) => Ok(Value::String(concat(a, b))),
+        (Value::Bytes(a), Value::Bytes(b)) => Ok(Value::Bytes(concat(a, b))),
+        (Value::List(a), Value::List(b)) => Ok(Value::List(concat(a, b))),
+        _ => integer(lhs.integer()?.checked_add(rhs.integer()?)),
+    }
+}
+
+fn sub(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    integer(lhs.integer()?.checked_sub(rhs.integer()?))
+}
+
+fn mul(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    integer(lhs.integer()?.checked_mul(rhs.integer()?))
+}
+
+fn div(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let (lhs, rhs) = (lhs.integer()?, rhs.integer()?);
+    if rhs == 0 {
+        return Err("Division by zero".to_string());
+    }
+    integer(lhs.checked_div(rhs))
+}
+
+fn rem(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let (lhs, rhs) = (lhs.integer()?, rhs.integer()?);
+    if rhs == 0 {
+        return Err("Division by zero".to_string());
+    }
+    integer(lhs.checked_rem(rhs))
+}
+
+fn integer(result: Option<i64>) -> Result<Value, String> {
+    result
+        .map(Value::Integer)
+        .ok_or_else(|| "Integer overflow".to_string())
+}
+
+fn eq(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs == rhs))
+}
+
+fn ne(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs != rhs))
+}
+
+fn lt(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs.integer()? < rhs.integer()?))
+}
+
+fn gt(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs.integer()? > rhs.integer()?))
+}
+
+fn le(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs.integer()? <= rhs.integer()?))
+}
+
+fn ge(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs.integer()? >= rhs.integer()?))
+}
+
+fn xor(lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(lhs.boolean()? != rhs.boolean()?))
+}
+
+fn not(value: &Value) -> Result<Value, String> {
+    Ok(Value::Boolean(!value.boolean()?))
+}
+
+/// Whether `value` fits in `width` bytes as either an unsigned or a two's
+/// complement integer.
+fn fits(value: i64, width: usize) -> bool {
+    let bits = 8 * width as u32;
+    let value = value as i128;
+    value >= -(1i128 << (bits - 1)) && value < (1i128 << bits)
+}
+
+/// `value::width`, big-endian when `big`.
+fn pack(value: &Value, width: usize, big: bool) -> Result<Value, String> {
+    let value = value.integer()?;
+    if !fits(value, width) {
+        return Err(format!(
+            "The value {} does not fit in {} byte(s)",
+            value, width
+        ));
+    }
+    let mut bytes = value.to_le_bytes()[..width].to_vec();
+    if big {
+        bytes.reverse();
+    }
+    Ok(Value::Bytes(bytes))
+}
+
+/// `[a::2, b::-1] = value`: a negative width is big-endian.
+fn unpack(value: &Value, widths: &[i8]) -> Result<Vec<Value>, String> {
+    let bytes = value.to_bytes()?;
+    let expected: usize = widths.iter().map(|width| width.unsigned_abs() as usize).sum();
+    if expected != bytes.len() {
+        return Err(format!(
+            "Cannot unpack {} byte(s) into targets of {} byte(s)",
+            bytes.len(),
+            expected
+        ));
+    }
+
+    let mut fields = vec![];
+    let mut offset = 0;
+    for &width in widths {
+        let end = offset + width.unsigned_abs() as usize;
+        let field = &bytes[offset..end];
+        let fold = |acc: i64, b: &u8| (acc << 8) | *b as i64;
+        fields.push(Value::Integer(if width < 0 {
+            field.iter().fold(0, fold)
+        } else {
+            field.iter().rev().fold(0, fold)
+        }));
+        offset = end;
+    }
+    Ok(fields)
+}
+
+fn items(collection: &Value) -> Result<Value, String> {
+    Ok(Value::List(collection.items()?))
+}
+
+fn length(list: &Value) -> Result<Value, String> {
+    match list {
+        Value::List(items) => Ok(Value::Integer(items.len() as i64)),
+        other => Err(format!("Expected a list, found {}", other.type_name())),
+    }
+}
+
+fn index(list: &Value, index: &Value) -> Result<Value, String> {
+    let index = index.integer()?;
+    let items = list.items()?;
+    if index < 0 || index as usize >= items.len() {
+        return Err(format!("The index {} is out of bounds", index));
+    }
+    Ok(items[index as usize].clone())
+}
+
+fn in_range(value: &Value, start: i64, end: i64) -> Value {
+    Value::Boolean(matches!(value, Value::Integer(value) if start <= *value && *value <= end))
+}
+
+/// The elements of a `record`: unsigned integers of `size` bytes.
+struct Record {
+    name: &'static str,
+    size: usize,
+    elements: Vec<i64>,
+}
+
+impl Record {
+    fn get(&self, index: &Value) -> Result<Value, String> {
+        let index = self.position(index)?;
+        Ok(Value::Integer(self.elements[index]))
+    }
+
+    /// Stores an integer that fits the element as either an unsigned or a
+    /// two's complement value.
+    fn set(&mut self, index: &Value, value: &Value) -> Result<(), String> {
+        let index = self.position(index)?;
+        let value = value.integer()?;
+        if !fits(value, self.size) {
+            return Err(format!(
+                "The value {} does not fit in the {} byte(s) of an element of {}",
+                value, self.size, self.name
+            ));
+        }
+        self.elements[index] = match self.size {
+            8 => value,
+            size => value & ((1 << (8 * size)) - 1),
+        };
+        Ok(())
+    }
+
+    fn to_value(&self) -> Value {
+        Value::List(self.elements.iter().map(|e| Value::Integer(*e)).collect())
+    }
+
+    fn position(&self, index: &Value) -> Result<usize, String> {
+        let index = index.integer()?;
+        if index < 0 || index as usize >= self.elements.len() {
+            return Err(format!(
+                "The index {} is out of bounds for {} of length {}",
+                index,
+                self.name,
+                self.elements.len()
+            ));
+        }
+        Ok(index as usize)
+    }
+}
+
+struct BodyInfo {
+    name: &'static str,
+    parameters: usize,
+    interval_ms: u64,
+    /// The locals, then the temporaries.
+    slots: usize,
+    stored: usize,
+}
+
+struct RecordInfo {
+    name: &'static str,
+    length: usize,
+    size: usize,
+}
+
+enum Framing {
+    Width(usize),
+    End(&'static [u8]),
+    Continuation,
+    Delimited(&'static [u8], &'static [u8]),
+    Start(&'static [u8]),
+}
+
+/// A `when` handler, with how it frames packets and, for a pattern, the
+/// automaton that matches whole packets.
+struct Handler {
+    body: usize,
+    framing: Framing,
+    classes: &'static [u8],
+    class_count: usize,
+    transitions: &'static [u16],
+    accepting: &'static [bool],
+}
+
+impl Handler {
+    fn matches(&self, packet: &[u8]) -> bool {
+        if packet.len() > MAX_PACKET_LEN {
+            return false;
+        }
+        let mut state = 1;
+        for &b in packet {
+            state = self.transitions[state * self.class_count + self.classes[b as usize] as usize]
+                as usize;
+        }
+        self.accepting[state]
+    }
+}
+
+struct InterfaceInfo {
+    name: &'static str,
+    /// In dispatch order.
+    handlers: &'static [Handler],
+}
+
+/// The bytes received on an interface that are not a packet yet.
+#[derive(Default)]
+struct Buffer {
+    bytes: Vec<u8>,
+    last_byte_at: u64,
+}
+
+enum Candidate {
+    /// A packet at `start..end`, complete once `ready` bytes arrived.
+    Complete {
+        start: usize,
+        end: usize,
+        ready: usize,
+    },
+    /// No packet yet; the handler may still use the bytes from `from` on.
+    Waiting { from: usize },
+}
+
+/// The positions where `needle` appears in `haystack`, overlaps included.
+fn occurrences<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == needle)
+        .map(|(position, _)| position)
+}
+
+/// The length of the longest end of `buffer` that begins `delimiter`
+/// without completing it.
+fn partial_suffix(buffer: &[u8], delimiter: &[u8]) -> usize {
+    (1..delimiter.len().min(buffer.len() + 1))
+        .rev()
+        .find(|&len| buffer.ends_with(&delimiter[..len]))
+        .unwrap_or(0)
+}
+
+/// Frames packets the way the interpreter does.
+fn candidate(handler: &Handler, buffer: &[u8], idle: bool) -> Candidate {
+    let matches = |start: usize, end: usize| handler.matches(&buffer[start..end]);
+    let complete = |start, end, ready| Candidate::Complete { start, end, ready };
+
+    match handler.framing {
+        Framing::Width(width) if buffer.len() >= width => complete(0, width, width),
+        Framing::Width(_) => Candidate::Waiting { from: 0 },
+        Framing::End(end) => occurrences(buffer, end)
+            .map(|position| position + end.len())
+            .find(|&stop| matches(0, stop))
+            .map_or(Candidate::Waiting { from: 0 }, |stop| complete(0, stop, stop)),
+        Framing::Continuation => (1..=buffer.len())
+            .find(|&stop| matches(0, stop))
+            .map_or(Candidate::Waiting { from: 0 }, |stop| complete(0, stop, stop)),
+        Framing::Delimited(start, end) => {
+            let starts: Vec<usize> = occurrences(buffer, start).collect();
+            for position in occurrences(buffer, end) {
+                let stop = position + end.len();
+                let begin = starts
+                    .iter()
+                    .rev()
+                    .find(|&&begin| begin + start.len() <= position);
+                if let Some(&begin) = begin {
+                    if matches(begin, stop) {
+                        return complete(begin, stop, stop);
+                    }
+                }
+            }
+            Candidate::Waiting {
+                from: match starts.last() {
+                    Some(&begin) => begin,
+                    None => buffer.len() - partial_suffix(buffer, start),
+                },
+            }
+        }
+        Framing::Start(start) => {
+            let starts: Vec<usize> = occurrences(buffer, start).collect();
+            let mut begin = match starts.first() {
+                Some(&begin) => begin,
+                None => {
+                    return Candidate::Waiting {
+                        from: buffer.len() - partial_suffix(buffer, start),
+                    }
+                }
+            };
+            loop {
+                match starts.iter().find(|&&next| next >= begin + start.len()) {
+                    Some(&next) if matches(begin, next) => {
+                        return complete(begin, next, next + start.len())
+                    }
+                    Some(&next) => begin = next,
+                    None if idle && matches(begin, buffer.len()) => {
+                        return complete(begin, buffer.len(), buffer.len())
+                    }
+                    None => return Candidate::Waiting { from: begin },
+                }
+            }
+        }
+    }
+}
+
+/// A body in execution, which stops at `@` delays and goes on later.
+struct Frame {
+    /// The block to go on at: 0 runs the body from the start.
+    resume: usize,
+    delay_ms: u64,
+    result: Value,
+    slots: Vec<Value>,
+}
+
+impl Frame {
+    fn new(body: usize, args: &[Value]) -> Self {
+        let mut slots = args.to_vec();
+        slots.resize(BODIES[body].slots.max(args.len()), Value::Nil);
+        Frame {
+            resume: 0,
+            delay_ms: 0,
+            result: Value::Nil,
+            slots,
+        }
+    }
+}
+
+/// A declared task, a function started as a task or a running `when`
+/// handler.
+struct Task {
+    /// Whether the program has no handle to the task, so that its entry can
+    /// be reused once it stops.
+    detached: bool,
+    body: usize,
+    args: Vec<Value>,
+    interval_ms: u64,
+    state: TaskState,
+}
+
+enum TaskState {
+    /// Tasks due at the same time run in the order they were scheduled.
+    Waiting {
+        wake_at: u64,
+        sequence: u64,
+        /// The tick in progress and when it started, if any.
+        tick: Option<(Frame, u64)>,
+    },
+    Running {
+        tick_started: u64,
+    },
+    Stopped,
+}
+
+/// The program, with its variables, tasks and partial packets.
+pub struct Program {
+    now: u64,
+    random: u64,
+    globals: Vec<Value>,
+    /// The variables stored by each body.
+    stored: Vec<Vec<Value>>,
+    records: Vec<Record>,
+    /// The partial packets of each interface.
+    buffers: Vec<Buffer>,
+    tasks: Vec<Task>,
+    next_sequence: u64,
+}
+
+impl Program {
+    /// Starts the program at time 0, seeding `rand` with `seed`.
+    pub fn new(seed: u64) -> Self {
+        let mut program = Program {
+            now: 0,
+            random: seed,
+            globals: vec![Value::Nil; GLOBALS],
+            stored: BODIES
+                .iter()
+                .map(|body| vec![Value::Nil; body.stored])
+                .collect(),
+            records: RECORDS
+                .iter()
+                .map(|record| Record {
+                    name: record.name,
+                    size: record.size,
+                    elements: vec![0; record.length],
+                })
+                .collect(),
+            buffers: INTERFACES.iter().map(|_| Buffer::default()).collect(),
+            tasks: vec![],
+            next_sequence: 0,
+        };
+        for &body in DECLARED_TASKS {
+            program.spawn(body, vec![], BODIES[body].interval_ms, true);
+        }
+        program
+    }
+
+    /// Runs the handlers and tasks that are due at `now_ms`, the
+    /// milliseconds since the program started. A runtime error leaves the
+    /// program in the middle of a body: it cannot go on.
+    pub fn poll(&mut self, now_ms: u64, io: &mut dyn Interfaces) -> Result<(), String> {
+        self.now = self.now.max(now_ms);
+        while let Some(wake_at) = self.next_wake() {
+            if wake_at > self.now {
+                break;
+            }
+            self.step(io)?;
+        }
+        Ok(())
+    }
+
+    /// When something is next due: a task or a partial packet timing out.
+    pub fn next_wake(&self) -> Option<u64> {
+        let deadlines = self
+            .buffers
+            .iter()
+            .filter(|buffer| !buffer.bytes.is_empty())
+            .map(|buffer| buffer.last_byte_at + FRAME_TIMEOUT_MS);
+        deadlines
+            .chain(self.next_task().map(|(_, wake_at)| wake_at))
+            .min()
+    }
+
+    /// Frames the bytes received on an interface and starts a handler for
+    /// each packet they complete.
+    fn receive(&mut self, interface: usize, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.buffers[interface].bytes.extend_from_slice(bytes);
+        self.buffers[interface].last_byte_at = self.now;
+        self.frame(interface, false);
+    }
+
+    /// Starts a handler for each packet the buffer completes. When `idle`,
+    /// the buffered partial packet timed out.
+    fn frame(&mut self, interface: usize, idle: bool) {
+        let handlers = INTERFACES[interface].handlers;
+        let mut bytes = std::mem::take(&mut self.buffers[interface].bytes);
+
+        while !bytes.is_empty() {
+            let mut complete: Option<(usize, usize, usize, usize)> = None;
+            let mut from = bytes.len();
+            for handler in handlers {
+                match candidate(handler, &bytes, idle) {
+                    Candidate::Complete { start, end, ready } => match complete {
+                        Some((_, _, _, best)) if best <= ready => {}
+                        _ => complete = Some((handler.body, start, end, ready)),
+                    },
+                    Candidate::Waiting { from: handler_from } => from = from.min(handler_from),
+                }
+            }
+
+            if let Some((body, start, end, _)) = complete {
+                let packet = bytes[start..end].to_vec();
+                bytes.drain(..end);
+                self.spawn_detached(body, vec![Value::Bytes(packet)]);
+            } else if from > 0 {
+                bytes.drain(..from);
+            } else if bytes.len() > MAX_PACKET_LEN {
+                bytes.remove(0);
+            } else {
+                break;
+            }
+        }
+
+        if idle {
+            bytes.clear();
+        }
+        self.buffers[interface].bytes = bytes;
+    }
+
+    /// Handles what is due: partial packets that timed out, else the next
+    /// task.
+    fn step(&mut self, io: &mut dyn Interfaces) -> Result<(), String> {
+        let now = self.now;
+        let mut expired: Vec<usize> = (0..INTERFACES.len())
+            .filter(|&interface| {
+                let buffer = &self.buffers[interface];
+                !buffer.bytes.is_empty() && buffer.last_byte_at + FRAME_TIMEOUT_MS <= now
+            })
+            .collect();
+        if !expired.is_empty() {
+            expired.sort_by_key(|&interface| INTERFACES[interface].name);
+            for interface in expired {
+                self.frame(interface, true);
+            }
+            return Ok(());
+        }
+
+        match self.next_task() {
+            Some((id, _)) => self.resume(id, io),
+            None => Ok(()),
+        }
+    }
+
+    fn next_task(&self) -> Option<(usize, u64)> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .filter_map(|(id, task)| match task.state {
+                TaskState::Waiting {
+                    wake_at, sequence, ..
+                } => Some((wake_at, sequence, id)),
+                _ => None,
+            })
+            .min()
+            .map(|(wake_at, _, id)| (id, wake_at))
+    }
+
+    fn sequence(&mut self) -> u64 {
+        self.next_sequence += 1;
+        self.next_sequence
+    }
+
+    /// Runs the next tick, or the rest of the tick in progress, of a due
+    /// task.
+    fn resume(&mut self, id: usize, io: &mut dyn Interfaces) -> Result<(), String> {
+        let now = self.now;
+        let task = &mut self.tasks[id];
+        let (mut frame, tick_started) =
+            match std::mem::replace(&mut task.state, TaskState::Stopped) {
+                TaskState::Waiting {
+                    tick: Some(tick), ..
+                } => tick,
+                _ => (Frame::new(task.body, &task.args), now),
+            };
+        task.state = TaskState::Running { tick_started };
+
+        let body = task.body;
+        let finished = self.run(body, &mut frame, io)?;
+
+        let sequence = self.sequence();
+        let task = &mut self.tasks[id];
+        // A task restarted or stopped by its own tick abandons it.
+        if !matches!(task.state, TaskState::Running { .. }) {
+            return Ok(());
+        }
+        task.state = if !finished {
+            TaskState::Waiting {
+                wake_at: now + frame.delay_ms,
+                sequence,
+                tick: Some((frame, tick_started)),
+            }
+        } else if task.interval_ms > 0 {
+            TaskState::Waiting {
+                wake_at: now.max(tick_started + task.interval_ms),
+                sequence,
+                tick: None,
+            }
+        } else {
+            TaskState::Stopped
+        };
+        Ok(())
+    }
+
+    /// Adds a task whose first tick is due now, or after one interval when
+    /// it is not `immediate`.
+    fn spawn(&mut self, body: usize, args: Vec<Value>, interval_ms: u64, immediate: bool) -> usize {
+        self.tasks.push(Task {
+            detached: false,
+            body,
+            args,
+            interval_ms,
+            state: TaskState::Stopped,
+        });
+        let id = self.tasks.len() - 1;
+        self.restart(id, None, immediate);
+        id
+    }
+
+    /// Adds a task that runs once, now, and that the program has no handle
+    /// to, such as a `when` handler.
+    fn spawn_detached(&mut self, body: usize, args: Vec<Value>) {
+        let task = Task {
+            detached: true,
+            body,
+            args,
+            interval_ms: 0,
+            state: TaskState::Stopped,
+        };
+        let reusable = self
+            .tasks
+            .iter()
+            .position(|task| task.detached && matches!(task.state, TaskState::Stopped));
+        let id = match reusable {
+            Some(id) => {
+                self.tasks[id] = task;
+                id
+            }
+            None => {
+                self.tasks.push(task);
+                self.tasks.len() - 1
+            }
+        };
+        self.restart(id, None, true);
+    }
+
+    /// Starts a task over from the beginning of its body. A tick in
+    /// progress is abandoned.
+    fn restart(&mut self, id: usize, interval_ms: Option<u64>, immediate: bool) {
+        let now = self.now;
+        let sequence = self.sequence();
+        let task = &mut self.tasks[id];
+        if let Some(interval_ms) = interval_ms {
+            task.interval_ms = interval_ms;
+        }
+        let wake_at = if immediate {
+            now
+        } else {
+            now + task.interval_ms
+        };
+        task.state = TaskState::Waiting {
+            wake_at,
+            sequence,
+            tick: None,
+        };
+    }
+
+    /// Calls a function directly, which cannot wait with `@`.
+    fn call(
+        &mut self,
+        io: &mut dyn Interfaces,
+        body: usize,
+        args: &[Value],
+    ) -> Result<Value, String> {
+        let mut frame = Frame::new(body, args);
+        if !self.run(body, &mut frame, io)? {
+            return Err(format!(
+                "The function '{}' cannot wait with @ when it is called directly",
+                BODIES[body].name
+            ));
+        }
+        Ok(frame.result)
+    }
+
+    /// `print(format, args...)`: `%d`, `%x`, `%s` and `%a` take the next
+    /// argument and `%%` writes `%`.
+    fn print(&mut self, io: &mut dyn Interfaces, args: &[Value]) -> Result<Value, String> {
+        let (format, mut args) = match args.split_first() {
+            Some((Value::String(format), args)) => (format, args.iter()),
+            _ => return Err("print requires a format string".to_string()),
+        };
+
+        let mut text = vec![];
+        let mut chars = format.iter();
+        while let Some(&c) = chars.next() {
+            if c != b'%' {
+                text.push(c);
+                continue;
+            }
+            let directive = chars.next().ok_or("The format string cannot end with %")?;
+            if *directive == b'%' {
+                text.push(b'%');
+                continue;
+            }
+            let arg = args
+                .next()
+                .ok_or("Missing argument for the format string")?;
+            let formatted = match directive {
+                b'd' => arg.integer()?.to_string(),
+                b'x' => format!("{:x}", arg.integer()?),
+                b's' | b'a' => arg.to_string(),
+                other => return Err(format!("Unknown format directive %{}", *other as char)),
+            };
+            text.extend(formatted.as_bytes());
+        }
+        if args.next().is_some() {
+            return Err("Too many arguments for the format string".to_string());
+        }
+
+        io.print(&String::from_utf8_lossy(&text));
+        Ok(Value::Nil)
+    }
+
+    /// `send(interface, data...)` sends the bytes of every argument after
+    /// the interface name. Interfaces the program does not name are
+    /// printed.
+    fn send(&mut self, io: &mut dyn Interfaces, args: &[Value]) -> Result<Value, String> {
+        let (interface, data) = match args.split_first() {
+            Some((Value::String(interface), data)) => (interface, data),
+            _ => return Err("send requires an interface name".to_string()),
+        };
+        let bytes = Value::List(data.to_vec()).to_bytes()?;
+        if !send(io, interface, &bytes) {
+            io.print(&format!(
+                "{} <- {}\n",
+                String::from_utf8_lossy(interface),
+                Value::Bytes(bytes)
+            ));
+        }
+        Ok(Value::Nil)
+    }
+
+    fn millis(&mut self, args: &[Value]) -> Result<Value, String> {
+        if !args.is_empty() {
+            return Err("millis takes no arguments".to_string());
+        }
+        Ok(Value::Integer(self.now as i64))
+    }
+
+    /// `rand(min, max)` is a number from `min` included to `max` excluded,
+    /// from a SplitMix64 generator.
+    fn rand(&mut self, args: &[Value]) -> Result<Value, String> {
+        let (min, max) = match args {
+            [min, max] => (min.integer()?, max.integer()?),
+            _ => return Err("rand takes a minimum and a maximum".to_string()),
+        };
+        if max <= min {
+            return Err(format!("rand needs min < max, got {} and {}", min, max));
+        }
+
+        self.random = self.random.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        let span = max.wrapping_sub(min) as u64 as u128;
+        let offset = (z as u128 * span) >> 64;
+        Ok(Value::Integer(min.wrapping_add(offset as i64)))
+    }
+
+    /// `task.stop()` stops a task; stopping `nil` does nothing.
+    fn stop(&mut self, args: &[Value]) -> Result<Value, String> {
+        match args {
+            [Value::Task(id)] => self.tasks[*id].state = TaskState::Stopped,
+            [Value::Nil] => {}
+            [other] => return Err(format!("Cannot stop {}", other.type_name())),
+            _ => return Err("stop takes a single task".to_string()),
+        }
+        Ok(Value::Nil)
+    }
+
+    /// `function.start(args, immediate) @ interval` runs a function as a
+    /// task and returns its handle; `task.start() @ interval` starts a task
+    /// over.
+    fn start(
+        &mut self,
+        target: &Value,
+        args: &[Value],
+        interval: Option<&Value>,
+    ) -> Result<Value, String> {
+        let interval_ms = match interval {
+            Some(interval) => match interval.integer()? {
+                interval_ms if interval_ms < 0 => {
+                    return Err("The task interval cannot be negative".to_string())
+                }
+                interval_ms => Some(interval_ms as u64),
+            },
+            None => None,
+        };
+        let (task_args, immediate) = match args {
+            [] => (vec![], true),
+            [task_args] => (task_args.items()?, true),
+            [task_args, immediate] => (task_args.items()?, immediate.boolean()?),
+            _ => return Err("start takes an argument list and a boolean".to_string()),
+        };
+
+        match target {
+            Value::Function(body) => {
+                let function = &BODIES[*body];
+                if function.parameters != task_args.len() {
+                    return Err(format!(
+                        "The function '{}' takes {} argument(s) but {} were given",
+                        function.name,
+                        function.parameters,
+                        task_args.len()
+                    ));
+                }
+                let id = self.spawn(*body, task_args, interval_ms.unwrap_or(0), immediate);
+                Ok(Value::Task(id))
+            }
+            Value::Task(id) if task_args.is_empty() => {
+                self.restart(*id, interval_ms, immediate);
+                Ok(Value::Task(*id))
+            }
+            Value::Task(_) => Err("A task cannot be started with new arguments".to_string()),
+            other => Err(format!("Cannot start {}", other.type_name())),
+        }
+    }
+}
@@ -0,0 +1,1055 @@
+use crate::automaton::Dfa;
+use crate::codegen::{declared_tasks, framing_handlers, identifiers, task_handle};
+use crate::ir::{
+    Body, BodyKind, Builtin, Callee, Constant, Instruction, Operand, Program, Terminator, Variable,
+};
+use crate::parser::{Endianness, Framing, Guard};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const RUNTIME: &str = include_str!("wasm/runtime.wat");
+
+/// Where the data of the module starts, leaving address 0 unused.
+const DATA_START: usize = 16;
+
+/// The texts of the runtime errors, with the global that points at each.
+const MESSAGES: &[(&str, &str)] = &[
+    ("and", " and "),
+    ("arguments_but", " argument(s) but "),
+    ("bytes", " byte(s)"),
+    ("bytes_of_element", " byte(s) of an element of "),
+    ("cannot_convert", "Cannot convert "),
+    ("cannot_iterate", "Cannot iterate over "),
+    ("cannot_start", "Cannot start "),
+    ("cannot_stop", "Cannot stop "),
+    ("cannot_unpack", "Cannot unpack "),
+    (
+        "cannot_wait",
+        "' cannot wait with @ when it is called directly",
+    ),
+    ("division_by_zero", "Division by zero"),
+    ("does_not_fit", " does not fit in "),
+    ("does_not_fit_the", " does not fit in the "),
+    ("expected_boolean", "Expected a boolean, found "),
+    ("expected_integer", "Expected an integer, found "),
+    ("expected_list", "Expected a list, found "),
+    ("false", "false"),
+    ("format_end", "The format string cannot end with %"),
+    ("function", "<function "),
+    ("into_targets", " byte(s) into targets of "),
+    ("millis_arguments", "millis takes no arguments"),
+    ("missing_argument", "Missing argument for the format string"),
+    ("negative_interval", "The task interval cannot be negative"),
+    (
+        "new_arguments",
+        "A task cannot be started with new arguments",
+    ),
+    ("not_a_byte", " does not fit in a byte"),
+    ("of_length", " of length "),
+    ("out_of_bounds", " is out of bounds"),
+    ("out_of_bounds_for", " is out of bounds for "),
+    ("out_of_memory", "Out of memory"),
+    ("overflow", "Integer overflow"),
+    (
+        "packet_too_long",
+        "A packet cannot be longer than 256 bytes",
+    ),
+    ("print_format", "print requires a format string"),
+    ("rand_arguments", "rand takes a minimum and a maximum"),
+    ("rand_range", "rand needs min < max, got "),
+    ("send_interface", "send requires an interface name"),
+    ("separator", ", "),
+    (
+        "start_arguments",
+        "start takes an argument list and a boolean",
+    ),
+    ("stop_arguments", "stop takes a single task"),
+    ("takes", "' takes "),
+    ("task", "<task "),
+    ("the_function", "The function '"),
+    ("the_index", "The index "),
+    ("the_integer", "The integer "),
+    ("the_value", "The value "),
+    ("to_bytes", " to bytes"),
+    (
+        "too_many_arguments",
+        "Too many arguments for the format string",
+    ),
+    ("true", "true"),
+    ("unknown_directive", "Unknown format directive %"),
+    ("were_given", " were given"),
+];
+
+/// The names of the value kinds, in the order the runtime numbers them.
+const TYPE_NAMES: &[&str] = &[
+    "nil", "boolean", "integer", "string", "bytes", "list", "task", "function",
+];
+
+/// Translates a verified program to a WebAssembly module that runs it in a
+/// sandbox, for a service without a native compiler.
+///
+/// The module imports from "nx" `millis() -> i64`, `print(text, len)`,
+/// `send(interface, len, data, len)`, `read(interface, len, buffer,
+/// capacity) -> i32`, which returns how many bytes arrived on an interface
+/// with `when` handlers, and `fail(message, len)`, after which the module
+/// traps. It exports its `memory`, `init(seed)`, `tick()`, which runs the
+/// handlers and tasks that are due, and `next_wake() -> i64`. Every `when`
+/// handler also has a `when_<interface>_<n>(len)` export, numbered in
+/// dispatch order, that runs it on the `len` bytes the host wrote at the
+/// address of the `packet` global.
+pub fn generate(program: &Program, source_name: &str) -> Result<Vec<u8>, String> {
+    let text = generate_text(program, source_name)?;
+    wat::parse_str(&text).map_err(|err| format!("Invalid WebAssembly module: {}", err))
+}
+
+/// The module in the WebAssembly text format.
+fn generate_text(program: &Program, source_name: &str) -> Result<String, String> {
+    let mut generator = Generator {
+        program,
+        out: String::new(),
+        data: vec![],
+        texts: HashMap::new(),
+        constants: HashMap::new(),
+        globals: 0,
+        stored: vec![],
+    };
+    writeln!(
+        generator.out,
+        ";; Generated by nxc from {}. Call init, then tick whenever millis()\n\
+         ;; reaches next_wake or bytes arrive.\n\
+         (module\n  \
+           (import \"nx\" \"millis\" (func $host_millis (result i64)))\n  \
+           (import \"nx\" \"print\" (func $host_print (param i32 i32)))\n  \
+           (import \"nx\" \"send\" (func $host_send (param i32 i32 i32 i32)))\n  \
+           (import \"nx\" \"read\" (func $host_read (param i32 i32 i32 i32) (result i32)))\n  \
+           (import \"nx\" \"fail\" (func $host_fail (param i32 i32)))\n",
+        source_name.replace('\n', " ")
+    )
+    .unwrap();
+
+    generator.data()?;
+    let bodies = generator.bodies()?;
+    generator.memory();
+    generator.out.push('\n');
+    generator.out.push_str(RUNTIME);
+    generator.out.push_str(&bodies);
+    generator.out.push_str(")\n");
+    Ok(generator.out)
+}
+
+struct Generator<'a> {
+    program: &'a Program,
+    out: String,
+    /// The bytes of the data segment, from `DATA_START` on.
+    data: Vec<u8>,
+    /// The address of each text, which starts with its length.
+    texts: HashMap<Vec<u8>, usize>,
+    /// The address of the value of each constant.
+    constants: HashMap<String, usize>,
+    /// The address of the global variables.
+    globals: usize,
+    /// The address of the stored variables of each body.
+    stored: Vec<usize>,
+}
+
+impl Generator<'_> {
+    /// The address of `size` zeroed bytes in the data segment.
+    fn reserve(&mut self, size: usize, align: usize) -> usize {
+        while !(DATA_START + self.data.len()).is_multiple_of(align) {
+            self.data.push(0);
+        }
+        let address = DATA_START + self.data.len();
+        self.data.resize(self.data.len() + size, 0);
+        address
+    }
+
+    fn write_u32(&mut self, address: usize, value: usize) {
+        let offset = address - DATA_START;
+        self.data[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn write_u64(&mut self, address: usize, value: u64) {
+        let offset = address - DATA_START;
+        self.data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_bytes(&mut self, address: usize, bytes: &[u8]) {
+        let offset = address - DATA_START;
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// The address of bytes that start with their length.
+    fn text(&mut self, bytes: &[u8]) -> usize {
+        if let Some(&address) = self.texts.get(bytes) {
+            return address;
+        }
+        let address = self.reserve(4 + bytes.len(), 4);
+        self.write_u32(address, bytes.len());
+        self.write_bytes(address + 4, bytes);
+        self.texts.insert(bytes.to_vec(), address);
+        address
+    }
+
+    /// The address of an array of 32-bit words.
+    fn words(&mut self, words: &[usize]) -> usize {
+        let address = self.reserve(4 * words.len(), 4);
+        for (index, &word) in words.iter().enumerate() {
+            self.write_u32(address + 4 * index, word);
+        }
+        address
+    }
+
+    /// The address of the value of a constant. Strings point to an object
+    /// that counts no references.
+    fn constant(&mut self, constant: &Constant) -> usize {
+        let key = format!("{:?}", constant);
+        if let Some(&address) = self.constants.get(&key) {
+            return address;
+        }
+        let (kind, payload) = match constant {
+            Constant::Nil => (0, 0),
+            Constant::Boolean(value) => (1, *value as u64),
+            Constant::Integer(value) => (2, *value as u64),
+            Constant::String(data) | Constant::Bytes(data) => {
+                let object = self.reserve(8 + data.len(), 8);
+                self.write_u32(object + 4, data.len());
+                self.write_bytes(object + 8, data);
+                let kind = match constant {
+                    Constant::String(_) => 3,
+                    _ => 4,
+                };
+                (kind, object as u64)
+            }
+            Constant::Task(id) => (6, task_handle(self.program, *id) as u64),
+            Constant::Function(id) => (7, *id as u64),
+        };
+        let address = self.reserve(16, 16);
+        self.write_u32(address, kind);
+        self.write_u64(address + 8, payload);
+        self.constants.insert(key, address);
+        address
+    }
+
+    fn global(&mut self, name: &str, value: usize) {
+        writeln!(self.out, "  (global ${} i32 (i32.const {}))", name, value).unwrap();
+    }
+
+    fn data(&mut self) -> Result<(), String> {
+        let program = self.program;
+        self.out.push('\n');
+        for (name, message) in MESSAGES {
+            let address = self.text(message.as_bytes());
+            self.global(&format!("m_{}", name), address);
+        }
+        let type_names: Vec<usize> = TYPE_NAMES
+            .iter()
+            .map(|name| self.text(name.as_bytes()))
+            .collect();
+        let type_names = self.words(&type_names);
+        self.global("type_names", type_names);
+
+        // The bodies, with their name, parameters and interval.
+        let bodies = self.reserve(16 * program.bodies.len(), 8);
+        for (id, body) in program.bodies.iter().enumerate() {
+            let (name, parameters, interval_ms) = match &body.kind {
+                BodyKind::Function { name, parameters } => (name, *parameters, 0),
+                BodyKind::Task { name, interval_ms } => (name, 0, *interval_ms),
+                BodyKind::When { interface, .. } => (interface, 1, 0),
+            };
+            let name = self.text(name.as_bytes());
+            self.write_u32(bodies + 16 * id, name);
+            self.write_u32(bodies + 16 * id + 4, parameters);
+            self.write_u64(bodies + 16 * id + 8, interval_ms as u64);
+        }
+        self.global("bodies", bodies);
+        let max_args = program
+            .bodies
+            .iter()
+            .map(|body| match body.kind {
+                BodyKind::Function { parameters, .. } => parameters,
+                BodyKind::Task { .. } => 0,
+                BodyKind::When { .. } => 1,
+            })
+            .max()
+            .unwrap_or(0);
+        self.global("max_args", max_args);
+        let slots = program
+            .bodies
+            .iter()
+            .map(|body| body.locals.len() + body.temps)
+            .max()
+            .unwrap_or(0);
+        self.global("slots", slots);
+
+        let tasks = declared_tasks(program);
+        let declared_tasks = self.words(&tasks);
+        self.global("declared_tasks", declared_tasks);
+        self.global("declared_task_count", tasks.len());
+
+        if !program.globals.is_empty() {
+            writeln!(self.out, "  ;; {}", program.globals.join(", ")).unwrap();
+        }
+        self.globals = self.reserve(16 * program.globals.len(), 16);
+        for body in program.bodies.iter() {
+            let stored = self.reserve(16 * body.stored.len(), 16);
+            self.stored.push(stored);
+        }
+
+        let records = self.reserve(16 * program.records.len(), 4);
+        for (index, record) in program.records.iter().enumerate() {
+            let name = self.text(record.name.as_bytes());
+            let elements = self.reserve(8 * record.length, 8);
+            let entry = records + 16 * index;
+            self.write_u32(entry, name);
+            self.write_u32(entry + 4, elements);
+            self.write_u32(entry + 8, record.length);
+            self.write_u32(entry + 12, record.data_size);
+        }
+        self.global("records", records);
+
+        let interfaces = self.reserve(32 * program.interfaces.len(), 8);
+        for (index, interface) in program.interfaces.iter().enumerate() {
+            let name = self.text(interface.name.as_bytes());
+            let framing = framing_handlers(program, interface);
+            let count = framing.len();
+            let handlers = self.reserve(48 * count, 4);
+            for (position, (id, guard)) in framing.into_iter().enumerate() {
+                self.handler(handlers + 48 * position, id, guard)?;
+            }
+            let buffer = self.reserve(2 * 256, 1);
+            let entry = interfaces + 32 * index;
+            self.write_u32(entry, name);
+            self.write_u32(entry + 4, handlers);
+            self.write_u32(entry + 8, count);
+            self.write_u32(entry + 12, buffer);
+        }
+        self.global("interfaces", interfaces);
+        self.global("interface_count", program.interfaces.len());
+        Ok(())
+    }
+
+    /// Writes the entry of a handler at `entry`, with the tables of its
+    /// automaton.
+    fn handler(&mut self, entry: usize, id: usize, guard: &Guard) -> Result<(), String> {
+        self.write_u32(entry, id);
+        let (pattern, framing) = match guard {
+            Guard::Default => {
+                self.write_u32(entry + 8, 1);
+                return Ok(());
+            }
+            Guard::Numeric { width, .. } => {
+                self.write_u32(entry + 8, *width);
+                return Ok(());
+            }
+            Guard::Regex { pattern, framing } => (pattern, framing),
+        };
+
+        let dfa = Dfa::new(pattern)?;
+        let (kind, start, end): (usize, &[u8], &[u8]) = match framing {
+            Framing::End(end) => (1, &[], end),
+            Framing::Continuation => (2, &[], &[]),
+            Framing::Delimited { start, end } => (3, start, end),
+            Framing::Start(start) => (4, start, &[]),
+        };
+        let start_address = self.reserve(start.len(), 1);
+        self.write_bytes(start_address, start);
+        let end_address = self.reserve(end.len(), 1);
+        self.write_bytes(end_address, end);
+        let classes = self.reserve(dfa.classes.len(), 1);
+        self.write_bytes(classes, &dfa.classes);
+        let transitions = self.reserve(2 * dfa.transitions.len(), 2);
+        for (index, transition) in dfa.transitions.iter().enumerate() {
+            self.write_bytes(transitions + 2 * index, &transition.to_le_bytes());
+        }
+        let accepting: Vec<u8> = dfa.accepting.iter().map(|&a| a as u8).collect();
+        let accepting_address = self.reserve(accepting.len(), 1);
+        self.write_bytes(accepting_address, &accepting);
+
+        for (offset, value) in [
+            (4, kind),
+            (12, start_address),
+            (16, start.len()),
+            (20, end_address),
+            (24, end.len()),
+            (28, classes),
+            (32, dfa.class_count),
+            (36, transitions),
+            (40, accepting_address),
+        ] {
+            self.write_u32(entry + offset, value);
+        }
+        Ok(())
+    }
+
+    /// The functions of the bodies, the table `call_indirect` runs them
+    /// through and the exports of the `when` handlers.
+    fn bodies(&mut self) -> Result<String, String> {
+        let program = self.program;
+        let mut out = String::from("\n  ;; ---- Program ----\n\n");
+
+        let names: Vec<String> = program
+            .interfaces
+            .iter()
+            .map(|interface| interface.name.clone())
+            .collect();
+        for (interface, ident) in program.interfaces.iter().zip(identifiers(&names)) {
+            for (position, &id) in interface.handlers.iter().enumerate() {
+                writeln!(
+                    out,
+                    "  ;; {}\n  (func (export \"when_{}_{}\") (param $len i32)\n    \
+                     (call $deliver (i32.const {}) (local.get $len)))\n",
+                    program.bodies[id].name().replace('\n', " "),
+                    ident,
+                    position,
+                    id
+                )
+                .unwrap();
+            }
+        }
+
+        let functions: Vec<String> = (0..program.bodies.len())
+            .map(|id| format!("$body_{}", id))
+            .collect();
+        writeln!(out, "  (table $run funcref (elem {}))", functions.join(" ")).unwrap();
+
+        let mut scratch = 0;
+        let mut args = 1;
+        for (id, body) in program.bodies.iter().enumerate() {
+            let mut generator = BodyGenerator {
+                generator: self,
+                id,
+                body,
+            };
+            let code = generator
+                .generate()
+                .map_err(|err| format!("{} in {}", err, body.name()))?;
+            out.push('\n');
+            out.push_str(&code);
+            for instruction in body
+                .blocks
+                .iter()
+                .flat_map(|block| block.instructions.iter())
+            {
+                match instruction {
+                    Instruction::List { items, .. } => args = args.max(items.len()),
+                    Instruction::Call {
+                        callee: Callee::Builtin(_),
+                        args: call_args,
+                        ..
+                    } => args = args.max(call_args.len()),
+                    Instruction::Start {
+                        args: call_args, ..
+                    } => args = args.max(call_args.len()),
+                    Instruction::Unpack { fields, .. } => {
+                        scratch = scratch.max(fields.iter().map(|(_, width, _)| width).sum())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        self.out.push('\n');
+        let address = self.reserve(128, 8);
+        self.global("text", address);
+        let address = self.reserve(16 * args, 16);
+        self.global("args", address);
+        let address = self.reserve(scratch, 8);
+        self.global("scratch", address);
+        let address = self.reserve(256, 8);
+        writeln!(
+            self.out,
+            "  (global $packet (export \"packet\") i32 (i32.const {}))",
+            address
+        )
+        .unwrap();
+        let address = self.reserve(4 * 32, 4);
+        self.global("free_lists", address);
+        Ok(out)
+    }
+
+    /// The memory, with the data segment and the heap after it.
+    fn memory(&mut self) {
+        let heap = self.reserve(0, 16);
+        writeln!(
+            self.out,
+            "  (global $heap (mut i32) (i32.const {}))\n\n  \
+             (memory (export \"memory\") {})\n  \
+             (data (i32.const {}) \"{}\")",
+            heap,
+            heap.div_ceil(0x10000),
+            DATA_START,
+            escape(&self.data)
+        )
+        .unwrap();
+    }
+}
+
+struct BodyGenerator<'a, 'b> {
+    generator: &'b mut Generator<'a>,
+    id: usize,
+    body: &'a Body,
+}
+
+impl BodyGenerator<'_, '_> {
+    fn generate(&mut self) -> Result<String, String> {
+        let body = self.body;
+        let mut out = String::new();
+
+        writeln!(out, "  ;; {}", body.name().replace('\n', " ")).unwrap();
+        writeln!(
+            out,
+            "  (func $body_{} (type $body) (param $frame i32) (result i32)\n    \
+             (local $v i32) (local $block i32) (local $o i32)\n    \
+             (local.set $v (i32.add (local.get $frame) (i32.const 24)))",
+            self.id
+        )
+        .unwrap();
+
+        // A body of one block that does not loop runs straight through;
+        // the others dispatch on the block to go on at.
+        if let [block] = body.blocks.as_slice() {
+            if matches!(
+                block.terminator,
+                Terminator::Return(_) | Terminator::Suspend { .. }
+            ) {
+                for instruction in block.instructions.iter() {
+                    out.push_str(&self.instruction(instruction, "    ")?);
+                }
+                out.push_str(&self.terminator(&block.terminator, "    "));
+                out.push_str("  )\n");
+                return Ok(out);
+            }
+        }
+
+        out.push_str("    (local.set $block (i32.load (local.get $frame)))\n    (loop $dispatch\n");
+        for id in (0..body.blocks.len()).rev() {
+            writeln!(out, "    (block $b{}", id).unwrap();
+        }
+        let labels: Vec<String> = (0..body.blocks.len())
+            .map(|id| format!("$b{}", id))
+            .collect();
+        writeln!(
+            out,
+            "      (br_table {} (local.get $block)))",
+            labels.join(" ")
+        )
+        .unwrap();
+        for (id, block) in body.blocks.iter().enumerate() {
+            writeln!(out, "      ;; b{}", id).unwrap();
+            for instruction in block.instructions.iter() {
+                out.push_str(&self.instruction(instruction, "      ")?);
+            }
+            out.push_str(&self.terminator(&block.terminator, "      "));
+            out.push_str("    )\n");
+        }
+        out.push_str("    (unreachable))\n");
+        Ok(out)
+    }
+
+    fn terminator(&mut self, terminator: &Terminator, indent: &str) -> String {
+        let lines = match terminator {
+            Terminator::Jump(target) => vec![
+                format!("(local.set $block (i32.const {}))", target),
+                "(br $dispatch)".to_string(),
+            ],
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => vec![
+                format!(
+                    "(local.set $block (select (i32.const {}) (i32.const {}) (call $boolean {})))",
+                    then,
+                    otherwise,
+                    self.operand(condition)
+                ),
+                "(br $dispatch)".to_string(),
+            ],
+            Terminator::Suspend { delay_ms, resume } => vec![
+                format!("(i32.store (local.get $frame) (i32.const {}))", resume),
+                format!(
+                    "(i32.store offset=4 (local.get $frame) (i32.const {}))",
+                    delay_ms
+                ),
+                "(return (i32.const 0))".to_string(),
+            ],
+            Terminator::Return(value) => vec![
+                format!(
+                    "(call $assign (i32.add (local.get $frame) (i32.const 8)) {})",
+                    self.operand(value)
+                ),
+                "(return (i32.const 1))".to_string(),
+            ],
+        };
+        lines
+            .iter()
+            .map(|line| format!("{}{}\n", indent, line))
+            .collect()
+    }
+
+    fn slot(&self, index: usize) -> String {
+        format!("(i32.add (local.get $v) (i32.const {}))", 16 * index)
+    }
+
+    fn temp(&self, temp: usize) -> String {
+        self.slot(self.body.locals.len() + temp)
+    }
+
+    /// The address of the value of an operand.
+    fn operand(&mut self, operand: &Operand) -> String {
+        match operand {
+            Operand::Temp(temp) => self.temp(*temp),
+            Operand::Constant(constant) => {
+                format!("(i32.const {})", self.generator.constant(constant))
+            }
+        }
+    }
+
+    /// The address of a variable.
+    fn variable(&self, variable: &Variable) -> Result<String, String> {
+        Ok(match *variable {
+            Variable::Local(local) => self.slot(local),
+            Variable::Stored(stored) => {
+                format!(
+                    "(i32.const {})",
+                    self.generator.stored[self.id] + 16 * stored
+                )
+            }
+            Variable::Global(global) => {
+                format!("(i32.const {})", self.generator.globals + 16 * global)
+            }
+            Variable::Record(_) => return Err("A record cannot be assigned as a whole".to_string()),
+        })
+    }
+
+    /// Fills the arguments of a list or a builtin.
+    fn args(&mut self, args: &[Operand], indent: &str) -> String {
+        let mut out = String::new();
+        for (index, arg) in args.iter().enumerate() {
+            writeln!(
+                out,
+                "{}(call $arg (i32.const {}) {})",
+                indent,
+                index,
+                self.operand(arg)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    fn instruction(&mut self, instruction: &Instruction, indent: &str) -> Result<String, String> {
+        let set = |this: &Self, dest: usize, value: String| {
+            format!("{}(call $set {} {})\n", indent, this.temp(dest), value)
+        };
+
+        Ok(match instruction {
+            Instruction::Copy { dest, value } => format!(
+                "{}(call $assign {} {})\n",
+                indent,
+                self.temp(*dest),
+                self.operand(value)
+            ),
+            Instruction::Load { dest, variable } => match variable {
+                Variable::Record(record) => set(
+                    self,
+                    *dest,
+                    format!("(call $record_list (i32.const {}))", record),
+                ),
+                _ => format!(
+                    "{}(call $assign {} {})\n",
+                    indent,
+                    self.temp(*dest),
+                    self.variable(variable)?
+                ),
+            },
+            Instruction::Store { variable, value } => format!(
+                "{}(call $assign {} {})\n",
+                indent,
+                self.variable(variable)?,
+                self.operand(value)
+            ),
+            Instruction::Not {
+                dest,
+                operand: value,
+            } => {
+                let value = self.operand(value);
+                set(self, *dest, format!("(call $not {})", value))
+            }
+            Instruction::Binary {
+                dest,
+                operator,
+                lhs,
+                rhs,
+            } => {
+                let (lhs, rhs) = (self.operand(lhs), self.operand(rhs));
+                set(
+                    self,
+                    *dest,
+                    format!("(call ${} {} {})", operator.name(), lhs, rhs),
+                )
+            }
+            Instruction::List { dest, items } => {
+                let mut code = self.args(items, indent);
+                code.push_str(&set(
+                    self,
+                    *dest,
+                    format!("(call $list (i32.const {}))", items.len()),
+                ));
+                code
+            }
+            Instruction::Pack {
+                dest,
+                value,
+                width,
+                endianness,
+            } => {
+                let value = self.operand(value);
+                set(
+                    self,
+                    *dest,
+                    format!(
+                        "(call $pack {} (i32.const {}) (i32.const {}))",
+                        value,
+                        width,
+                        (*endianness == Endianness::Big) as u8
+                    ),
+                )
+            }
+            Instruction::Unpack { fields, bytes } => {
+                let total: usize = fields.iter().map(|(_, width, _)| width).sum();
+                let mut code = format!(
+                    "{}(local.set $o (call $unpack {} (i32.const {})))\n",
+                    indent,
+                    self.operand(bytes),
+                    total
+                );
+                let mut offset = 0;
+                for (temp, width, endianness) in fields.iter() {
+                    code.push_str(&set(
+                        self,
+                        *temp,
+                        format!(
+                            "(call $field (i32.add (local.get $o) (i32.const {})) \
+                             (i32.const {}) (i32.const {}))",
+                            offset,
+                            width,
+                            (*endianness == Endianness::Big) as u8
+                        ),
+                    ));
+                    offset += width;
+                }
+                code
+            }
+            Instruction::Element {
+                dest,
+                record,
+                index,
+            } => {
+                let index = self.operand(index);
+                set(
+                    self,
+                    *dest,
+                    format!("(call $element (i32.const {}) {})", record, index),
+                )
+            }
+            Instruction::SetElement {
+                record,
+                index,
+                value,
+            } => format!(
+                "{}(call $set_element (i32.const {}) {} {})\n",
+                indent,
+                record,
+                self.operand(index),
+                self.operand(value)
+            ),
+            Instruction::Items { dest, collection } => {
+                let collection = self.operand(collection);
+                set(self, *dest, format!("(call $items {})", collection))
+            }
+            Instruction::Length { dest, list } => {
+                let list = self.operand(list);
+                set(self, *dest, format!("(call $count {})", list))
+            }
+            Instruction::Index { dest, list, index } => {
+                let (list, index) = (self.operand(list), self.operand(index));
+                set(self, *dest, format!("(call $index {} {})", list, index))
+            }
+            Instruction::InRange {
+                dest,
+                value,
+                start,
+                end,
+            } => {
+                let value = self.operand(value);
+                set(
+                    self,
+                    *dest,
+                    format!(
+                        "(call $in_range {} (i64.const {}) (i64.const {}))",
+                        value, start, end
+                    ),
+                )
+            }
+            Instruction::Call { dest, callee, args } => match callee {
+                Callee::Builtin(builtin) => {
+                    let function = match builtin {
+                        Builtin::Print => "print",
+                        Builtin::Send => "send",
+                        Builtin::Millis => "millis",
+                        Builtin::Rand => "rand",
+                        Builtin::Stop => "stop",
+                    };
+                    let mut code = self.args(args, indent);
+                    code.push_str(&set(
+                        self,
+                        *dest,
+                        format!("(call $builtin_{} (i32.const {}))", function, args.len()),
+                    ));
+                    code
+                }
+                Callee::Function(id) => {
+                    let callee = &self.generator.program.bodies[*id];
+                    let slots = (callee.locals.len() + callee.temps).max(args.len());
+                    let mut code = format!(
+                        "{}(local.set $o (call $frame (i32.const {})))\n",
+                        indent, slots
+                    );
+                    for (index, arg) in args.iter().enumerate() {
+                        writeln!(
+                            code,
+                            "{}(call $assign (i32.add (local.get $o) (i32.const {})) {})",
+                            indent,
+                            24 + 16 * index,
+                            self.operand(arg)
+                        )
+                        .unwrap();
+                    }
+                    code.push_str(&set(
+                        self,
+                        *dest,
+                        format!(
+                            "(call $call (i32.const {}) (local.get $o) (i32.const {}))",
+                            id, slots
+                        ),
+                    ));
+                    code
+                }
+            },
+            Instruction::Start {
+                dest,
+                target,
+                args,
+                interval,
+            } => {
+                let mut code = self.args(args, indent);
+                let target = self.operand(target);
+                let (has_interval, interval) = match interval {
+                    Some(interval) => (1, self.operand(interval)),
+                    None => (0, "(i32.const 0)".to_string()),
+                };
+                code.push_str(&set(
+                    self,
+                    *dest,
+                    format!(
+                        "(call $start {} (i32.const {}) (i32.const {}) {})",
+                        target,
+                        args.len(),
+                        has_interval,
+                        interval
+                    ),
+                ));
+                code
+            }
+        })
+    }
+}
+
+/// The bytes of a data segment as a WebAssembly string.
+fn escape(data: &[u8]) -> String {
+    let mut literal = String::new();
+    for &b in data {
+        match b {
+            0x20..=0x7e if b != b'"' && b != b'\\' => literal.push(b as char),
+            _ => write!(literal, "\\{:02x}", b).unwrap(),
+        }
+    }
+    literal
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ir, parser};
+    use wasmi::{Caller, Engine, Extern, Linker, Memory, Module, Store, Val};
+
+    /// What the module gets from and gives to the test.
+    #[derive(Default)]
+    struct Host {
+        now: i64,
+        input: Vec<u8>,
+        output: String,
+        failure: Option<String>,
+    }
+
+    fn memory(caller: &Caller<'_, Host>) -> Memory {
+        caller
+            .get_export("memory")
+            .and_then(Extern::into_memory)
+            .unwrap()
+    }
+
+    fn read(caller: &Caller<'_, Host>, address: i32, len: i32) -> Vec<u8> {
+        let mut bytes = vec![0; len as usize];
+        memory(caller)
+            .read(caller, address as usize, &mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    /// Instantiates the module generated from `program` and ticks it every
+    /// millisecond from 0 to `ms`, with `input` arriving on "uart" at 10 ms.
+    /// Returns what it prints and sends.
+    pub(crate) fn run(_name: &str, program: &Program, ms: u64, input: Option<&str>) -> String {
+        run_with(program, ms, input.unwrap_or_default(), None)
+    }
+
+    /// Runs like `run`, with `deliver` also writing a packet and calling a
+    /// `when_*` export at 10 ms.
+    fn run_with(program: &Program, ms: u64, input: &str, deliver: Option<(&str, &str)>) -> String {
+        let wasm = generate(program, "test.nx").unwrap();
+
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm[..]).unwrap();
+        let mut store = Store::new(&engine, Host::default());
+        let mut linker = <Linker<Host>>::new(&engine);
+        linker
+            .func_wrap("nx", "millis", |caller: Caller<'_, Host>| caller.data().now)
+            .unwrap();
+        linker
+            .func_wrap(
+                "nx",
+                "print",
+                |mut caller: Caller<'_, Host>, text: i32, len: i32| {
+                    let text = read(&caller, text, len);
+                    caller
+                        .data_mut()
+                        .output
+                        .push_str(&String::from_utf8_lossy(&text));
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "nx",
+                "send",
+                |mut caller: Caller<'_, Host>, name: i32, name_len: i32, data: i32, len: i32| {
+                    let name = read(&caller, name, name_len);
+                    let data = read(&caller, data, len);
+                    let bytes: Vec<String> = data.iter().map(|b| format!("0x{:02x}", b)).collect();
+                    let line = format!(
+                        "{} <- [{}]\n",
+                        String::from_utf8_lossy(&name),
+                        bytes.join(", ")
+                    );
+                    caller.data_mut().output.push_str(&line);
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "nx",
+                "read",
+                |mut caller: Caller<'_, Host>,
+                 name: i32,
+                 name_len: i32,
+                 buffer: i32,
+                 capacity: i32| {
+                    if read(&caller, name, name_len) != b"uart" {
+                        return 0;
+                    }
+                    let len = caller.data().input.len().min(capacity as usize);
+                    let bytes: Vec<u8> = caller.data_mut().input.drain(..len).collect();
+                    let memory = memory(&caller);
+                    memory.write(&mut caller, buffer as usize, &bytes).unwrap();
+                    len as i32
+                },
+            )
+            .unwrap();
+        linker
+            .func_wrap(
+                "nx",
+                "fail",
+                |mut caller: Caller<'_, Host>, message: i32, len: i32| {
+                    let message = read(&caller, message, len);
+                    caller.data_mut().failure = Some(String::from_utf8_lossy(&message).into());
+                },
+            )
+            .unwrap();
+
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let init = instance.get_typed_func::<i64, ()>(&store, "init").unwrap();
+        let tick = instance.get_typed_func::<(), ()>(&store, "tick").unwrap();
+        init.call(&mut store, 7).unwrap();
+
+        for now in 0..=ms {
+            store.data_mut().now = now as i64;
+            let mut result = Ok(());
+            if now == 10 {
+                store.data_mut().input = input.as_bytes().to_vec();
+                if let Some((export, packet)) = deliver {
+                    let address = match instance.get_global(&store, "packet").unwrap().get(&store) {
+                        Val::I32(address) => address as usize,
+                        _ => unreachable!("the packet global is an address"),
+                    };
+                    let memory = instance.get_memory(&store, "memory").unwrap();
+                    memory
+                        .write(&mut store, address, packet.as_bytes())
+                        .unwrap();
+                    let handler = instance.get_typed_func::<i32, ()>(&store, export).unwrap();
+                    result = handler.call(&mut store, packet.len() as i32);
+                }
+            }
+            if result.is_ok() {
+                result = tick.call(&mut store, ());
+            }
+            if result.is_err() {
+                let failure = store.data_mut().failure.take().unwrap();
+                store.data_mut().output += &format!("failed: {}\n", failure);
+                break;
+            }
+        }
+        store.into_data().output
+    }
+
+    #[test]
+    fn runs_handlers_through_their_exports() {
+        let source = "
+            when \"uart\" => msg::':.*;'
+                for (b in msg)
+                    print(\"%x \", b);
+                end
+                print(\"from the pattern\\n\");
+            end
+
+            when \"uart\" => msg
+                [high::1, low::1] = msg;
+                print(\"%d\\n\", high * 256 + low);
+            end
+        ";
+        let program = ir::lower(&parser::parse_source(source)).unwrap();
+        assert_eq!(
+            run_with(&program, 20, "", Some(("when_uart_0", ":a;"))),
+            "3a 61 3b from the pattern\n"
+        );
+        assert_eq!(
+            run_with(&program, 20, "", Some(("when_uart_1", "\x01\x02"))),
+            "258\n"
+        );
+    }
+}
@@ -0,0 +1,1918 @@
+  ;; ---- Runtime ----
+  ;;
+  ;; A value takes 16 bytes: its kind as an i32, then at offset 8 the
+  ;; boolean, the integer, the task, the function body or, for strings,
+  ;; bytes and lists, the address of their object. An object holds its
+  ;; reference count, its length and then its bytes or its 16-byte items.
+  ;; Objects of the program's constants count no references and are never
+  ;; freed. Variables own a reference to their object; arguments are
+  ;; borrowed.
+
+  (type $body (func (param i32) (result i32)))
+
+  (global $NIL i32 (i32.const 0))
+  (global $BOOLEAN i32 (i32.const 1))
+  (global $INTEGER i32 (i32.const 2))
+  (global $STRING i32 (i32.const 3))
+  (global $BYTES i32 (i32.const 4))
+  (global $LIST i32 (i32.const 5))
+  (global $TASK i32 (i32.const 6))
+  (global $FUNCTION i32 (i32.const 7))
+
+  (global $MAX_PACKET i32 (i32.const 256))
+  (global $FRAME_TIMEOUT i64 (i64.const 100))
+  (global $TEXT_SIZE i32 (i32.const 128))
+
+  (global $now (mut i64) (i64.const 0))
+  (global $random_state (mut i64) (i64.const 0))
+
+  ;; ---- Memory ----
+
+  ;; Blocks of 2^class bytes, which start with their class and, while free,
+  ;; the next free block of the class.
+  (func $alloc (param $size i32) (result i32)
+    (local $class i32)
+    (local $head i32)
+    (local $block i32)
+    (local.set $class
+      (i32.sub (i32.const 32) (i32.clz (i32.add (local.get $size) (i32.const 7)))))
+    (if (i32.lt_u (local.get $class) (i32.const 4))
+      (then (local.set $class (i32.const 4))))
+    (if (i32.gt_u (local.get $class) (i32.const 30))
+      (then (call $fail (global.get $m_out_of_memory))))
+    (local.set $head
+      (i32.add (global.get $free_lists) (i32.shl (local.get $class) (i32.const 2))))
+    (local.set $block (i32.load (local.get $head)))
+    (if (local.get $block)
+      (then (i32.store (local.get $head) (i32.load offset=4 (local.get $block))))
+      (else
+        (local.set $block (global.get $heap))
+        (global.set $heap
+          (i32.add (local.get $block) (i32.shl (i32.const 1) (local.get $class))))
+        (if (i32.gt_u (global.get $heap) (i32.shl (memory.size) (i32.const 16)))
+          (then
+            (if (i32.eq
+                  (memory.grow
+                    (i32.sub
+                      (i32.shr_u (i32.add (global.get $heap) (i32.const 0xffff)) (i32.const 16))
+                      (memory.size)))
+                  (i32.const -1))
+              (then (call $fail (global.get $m_out_of_memory))))))))
+    (i32.store (local.get $block) (local.get $class))
+    (i32.add (local.get $block) (i32.const 8)))
+
+  (func $free (param $address i32)
+    (local $block i32)
+    (local $head i32)
+    (local.set $block (i32.sub (local.get $address) (i32.const 8)))
+    (local.set $head
+      (i32.add (global.get $free_lists) (i32.shl (i32.load (local.get $block)) (i32.const 2))))
+    (i32.store offset=4 (local.get $block) (i32.load (local.get $head)))
+    (i32.store (local.get $head) (local.get $block)))
+
+  ;; Zeroed memory, which holds nil values.
+  (func $alloc_zeroed (param $size i32) (result i32)
+    (local $address i32)
+    (local.set $address (call $alloc (local.get $size)))
+    (memory.fill (local.get $address) (i32.const 0) (local.get $size))
+    (local.get $address))
+
+  ;; An object of `len` items of `size` bytes, with one reference.
+  (func $new_object (param $len i32) (param $size i32) (result i32)
+    (local $object i32)
+    (local.set $object
+      (call $alloc (i32.add (i32.const 8) (i32.mul (local.get $len) (local.get $size)))))
+    (i32.store (local.get $object) (i32.const 1))
+    (i32.store offset=4 (local.get $object) (local.get $len))
+    (local.get $object))
+
+  ;; Whether `len` bytes at `a` and at `b` are the same.
+  (func $same (param $a i32) (param $b i32) (param $len i32) (result i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.ne
+              (i32.load8_u (i32.add (local.get $a) (local.get $i)))
+              (i32.load8_u (i32.add (local.get $b) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 1))
+
+  ;; ---- Text ----
+
+  ;; Collects text for print() and for error messages. Printed text goes
+  ;; out whenever the buffer fills up; a message is cut short instead.
+  (global $text_len (mut i32) (i32.const 0))
+  (global $text_print (mut i32) (i32.const 0))
+
+  (func $put_byte (param $byte i32)
+    (if (i32.eq (global.get $text_len) (global.get $TEXT_SIZE))
+      (then
+        (if (i32.eqz (global.get $text_print))
+          (then (return)))
+        (call $host_print (global.get $text) (global.get $text_len))
+        (global.set $text_len (i32.const 0))))
+    (i32.store8 (i32.add (global.get $text) (global.get $text_len)) (local.get $byte))
+    (global.set $text_len (i32.add (global.get $text_len) (i32.const 1))))
+
+  (func $put (param $data i32) (param $len i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (call $put_byte (i32.load8_u (i32.add (local.get $data) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+
+  ;; Text that starts with its length.
+  (func $put_text (param $text i32)
+    (call $put (i32.add (local.get $text) (i32.const 4)) (i32.load (local.get $text))))
+
+  (func $put_digits (param $value i64) (param $base i64)
+    (local $digit i32)
+    (if (i64.ge_u (local.get $value) (local.get $base))
+      (then (call $put_digits (i64.div_u (local.get $value) (local.get $base)) (local.get $base))))
+    (local.set $digit (i32.wrap_i64 (i64.rem_u (local.get $value) (local.get $base))))
+    (call $put_byte
+      (i32.add
+        (local.get $digit)
+        (select (i32.const 48) (i32.const 87) (i32.lt_u (local.get $digit) (i32.const 10))))))
+
+  (func $put_integer (param $value i64)
+    (if (i64.lt_s (local.get $value) (i64.const 0))
+      (then
+        (call $put_byte (i32.const 45))
+        (local.set $value (i64.sub (i64.const 0) (local.get $value)))))
+    (call $put_digits (local.get $value) (i64.const 10)))
+
+  ;; A byte as 0xNN.
+  (func $put_hex_byte (param $value i32)
+    (call $put_byte (i32.const 48))
+    (call $put_byte (i32.const 120))
+    (if (i32.lt_u (local.get $value) (i32.const 16))
+      (then (call $put_byte (i32.const 48))))
+    (call $put_digits (i64.extend_i32_u (local.get $value)) (i64.const 16)))
+
+  ;; Starts an error message.
+  (func $message
+    (global.set $text_len (i32.const 0))
+    (global.set $text_print (i32.const 0)))
+
+  ;; Reports a runtime error to the host, which cannot go on.
+  (func $fail_message
+    (call $host_fail (global.get $text) (global.get $text_len))
+    (unreachable))
+
+  (func $fail (param $text i32)
+    (call $message)
+    (call $put_text (local.get $text))
+    (call $fail_message))
+
+  (func $put_type (param $kind i32)
+    (call $put_text
+      (i32.load (i32.add (global.get $type_names) (i32.shl (local.get $kind) (i32.const 2))))))
+
+  ;; Fails with "<prefix><type name>".
+  (func $fail_type (param $prefix i32) (param $kind i32)
+    (call $message)
+    (call $put_text (local.get $prefix))
+    (call $put_type (local.get $kind))
+    (call $fail_message))
+
+  ;; Fails with "<prefix><integer><suffix>".
+  (func $fail_integer (param $prefix i32) (param $value i64) (param $suffix i32)
+    (call $message)
+    (call $put_text (local.get $prefix))
+    (call $put_integer (local.get $value))
+    (call $put_text (local.get $suffix))
+    (call $fail_message))
+
+  ;; ---- Values ----
+
+  (func $kind (param $value i32) (result i32)
+    (i32.load (local.get $value)))
+
+  (func $object (param $value i32) (result i32)
+    (i32.wrap_i64 (i64.load offset=8 (local.get $value))))
+
+  (func $has_object (param $kind i32) (result i32)
+    (i32.and
+      (i32.ge_u (local.get $kind) (global.get $STRING))
+      (i32.le_u (local.get $kind) (global.get $LIST))))
+
+  ;; The length of a string, bytes or list.
+  (func $length (param $value i32) (result i32)
+    (i32.load offset=4 (call $object (local.get $value))))
+
+  ;; The first byte of a string or bytes, or the first item of a list.
+  (func $data (param $value i32) (result i32)
+    (i32.add (call $object (local.get $value)) (i32.const 8)))
+
+  (func $retain (param $value i32)
+    (local $object i32)
+    (if (call $has_object (call $kind (local.get $value)))
+      (then
+        (local.set $object (call $object (local.get $value)))
+        (if (i32.load (local.get $object))
+          (then
+            (i32.store
+              (local.get $object)
+              (i32.add (i32.load (local.get $object)) (i32.const 1))))))))
+
+  (func $release (param $kind i32) (param $payload i64)
+    (local $object i32)
+    (local $refs i32)
+    (local $item i32)
+    (local $end i32)
+    (if (i32.eqz (call $has_object (local.get $kind)))
+      (then (return)))
+    (local.set $object (i32.wrap_i64 (local.get $payload)))
+    (local.set $refs (i32.load (local.get $object)))
+    (if (i32.eqz (local.get $refs))
+      (then (return)))
+    (local.set $refs (i32.sub (local.get $refs) (i32.const 1)))
+    (i32.store (local.get $object) (local.get $refs))
+    (if (local.get $refs)
+      (then (return)))
+    (if (i32.eq (local.get $kind) (global.get $LIST))
+      (then
+        (local.set $item (i32.add (local.get $object) (i32.const 8)))
+        (local.set $end
+          (i32.add
+            (local.get $item)
+            (i32.shl (i32.load offset=4 (local.get $object)) (i32.const 4))))
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $item) (local.get $end)))
+            (call $release (i32.load (local.get $item)) (i64.load offset=8 (local.get $item)))
+            (local.set $item (i32.add (local.get $item) (i32.const 16)))
+            (br $next)))))
+    (call $free (local.get $object)))
+
+  ;; Stores a value the caller owns.
+  (func $set (param $slot i32) (param $kind i32) (param $payload i64)
+    (local $old_kind i32)
+    (local $old_payload i64)
+    (local.set $old_kind (i32.load (local.get $slot)))
+    (local.set $old_payload (i64.load offset=8 (local.get $slot)))
+    (i32.store (local.get $slot) (local.get $kind))
+    (i64.store offset=8 (local.get $slot) (local.get $payload))
+    (call $release (local.get $old_kind) (local.get $old_payload)))
+
+  ;; Stores a borrowed value.
+  (func $assign (param $slot i32) (param $value i32)
+    (call $retain (local.get $value))
+    (call $set
+      (local.get $slot)
+      (i32.load (local.get $value))
+      (i64.load offset=8 (local.get $value))))
+
+  (func $clear (param $slots i32) (param $count i32)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $count)))
+        (call $set
+          (i32.add (local.get $slots) (i32.shl (local.get $i) (i32.const 4)))
+          (global.get $NIL)
+          (i64.const 0))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+
+  ;; Borrows a value as argument `index` of a list or a builtin.
+  (func $arg (param $index i32) (param $value i32)
+    (memory.copy
+      (i32.add (global.get $args) (i32.shl (local.get $index) (i32.const 4)))
+      (local.get $value)
+      (i32.const 16)))
+
+  ;; New bytes holding a copy of `data`.
+  (func $new_bytes (param $data i32) (param $len i32) (result i32)
+    (local $object i32)
+    (local.set $object (call $new_object (local.get $len) (i32.const 1)))
+    (memory.copy
+      (i32.add (local.get $object) (i32.const 8))
+      (local.get $data)
+      (local.get $len))
+    (local.get $object))
+
+  ;; A list of the first `count` arguments.
+  (func $list (param $count i32) (result i32 i64)
+    (local $object i32)
+    (local $i i32)
+    (local.set $object (call $new_object (local.get $count) (i32.const 16)))
+    (memory.copy
+      (i32.add (local.get $object) (i32.const 8))
+      (global.get $args)
+      (i32.shl (local.get $count) (i32.const 4)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $count)))
+        (call $retain
+          (i32.add (global.get $args) (i32.shl (local.get $i) (i32.const 4))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.get $LIST)
+    (i64.extend_i32_u (local.get $object)))
+
+  (func $integer (param $value i32) (result i64)
+    (if (i32.ne (call $kind (local.get $value)) (global.get $INTEGER))
+      (then (call $fail_type (global.get $m_expected_integer) (call $kind (local.get $value)))))
+    (i64.load offset=8 (local.get $value)))
+
+  (func $boolean (param $value i32) (result i32)
+    (if (i32.ne (call $kind (local.get $value)) (global.get $BOOLEAN))
+      (then (call $fail_type (global.get $m_expected_boolean) (call $kind (local.get $value)))))
+    (i32.wrap_i64 (i64.load offset=8 (local.get $value))))
+
+  ;; Fails unless a `for` loop can visit the items of the value.
+  (func $collection (param $value i32)
+    (local $kind i32)
+    (local.set $kind (call $kind (local.get $value)))
+    (if (i32.eqz (call $has_object (local.get $kind)))
+      (then (call $fail_type (global.get $m_cannot_iterate) (local.get $kind)))))
+
+  (func $equal (param $a i32) (param $b i32) (result i32)
+    (local $kind i32)
+    (local $len i32)
+    (local $i i32)
+    (local.set $kind (call $kind (local.get $a)))
+    (if (i32.ne (local.get $kind) (call $kind (local.get $b)))
+      (then (return (i32.const 0))))
+    (if (i32.eqz (call $has_object (local.get $kind)))
+      (then
+        (return
+          (i64.eq (i64.load offset=8 (local.get $a)) (i64.load offset=8 (local.get $b))))))
+    (local.set $len (call $length (local.get $a)))
+    (if (i32.ne (local.get $len) (call $length (local.get $b)))
+      (then (return (i32.const 0))))
+    (if (i32.ne (local.get $kind) (global.get $LIST))
+      (then
+        (return (call $same (call $data (local.get $a)) (call $data (local.get $b)) (local.get $len)))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (if (i32.eqz
+              (call $equal
+                (call $item_address (local.get $a) (local.get $i))
+                (call $item_address (local.get $b) (local.get $i))))
+          (then (return (i32.const 0))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.const 1))
+
+  (func $item_address (param $list i32) (param $position i32) (result i32)
+    (i32.add (call $data (local.get $list)) (i32.shl (local.get $position) (i32.const 4))))
+
+  (func $put_value (param $value i32)
+    (local $len i32)
+    (local $i i32)
+    (local $payload i64)
+    (local.set $payload (i64.load offset=8 (local.get $value)))
+    (block $function
+      (block $task
+        (block $list
+          (block $bytes
+            (block $string
+              (block $integer
+                (block $boolean
+                  (block $nil
+                    (br_table $nil $boolean $integer $string $bytes $list $task $function
+                      (call $kind (local.get $value))))
+                  (call $put_type (global.get $NIL))
+                  (return))
+                (call $put_text
+                  (select
+                    (global.get $m_true)
+                    (global.get $m_false)
+                    (i32.wrap_i64 (local.get $payload))))
+                (return))
+              (call $put_integer (local.get $payload))
+              (return))
+            (call $put (call $data (local.get $value)) (call $length (local.get $value)))
+            (return))
+          (local.set $len (call $length (local.get $value)))
+          (call $put_byte (i32.const 91))
+          (block $done
+            (loop $next
+              (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+              (if (local.get $i)
+                (then (call $put_text (global.get $m_separator))))
+              (call $put_hex_byte
+                (i32.load8_u (i32.add (call $data (local.get $value)) (local.get $i))))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br $next)))
+          (call $put_byte (i32.const 93))
+          (return))
+        (local.set $len (call $length (local.get $value)))
+        (call $put_byte (i32.const 91))
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+            (if (local.get $i)
+              (then (call $put_text (global.get $m_separator))))
+            (call $put_value (call $item_address (local.get $value) (local.get $i)))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))
+        (call $put_byte (i32.const 93))
+        (return))
+      (call $put_text (global.get $m_task))
+      (call $put_integer (local.get $payload))
+      (call $put_byte (i32.const 62))
+      (return))
+    (call $put_text (global.get $m_function))
+    (call $put_text (call $body_name (i32.wrap_i64 (local.get $payload))))
+    (call $put_byte (i32.const 62)))
+
+  ;; How many bytes a value stands for when it is sent or unpacked, failing
+  ;; when it cannot be.
+  (func $bytes_length (param $value i32) (result i32)
+    (local $kind i32)
+    (local $integer i64)
+    (local $len i32)
+    (local $i i32)
+    (local $total i32)
+    (local.set $kind (call $kind (local.get $value)))
+    (if (i32.eq (local.get $kind) (global.get $INTEGER))
+      (then
+        (local.set $integer (i64.load offset=8 (local.get $value)))
+        (if (i64.gt_u (local.get $integer) (i64.const 255))
+          (then
+            (call $fail_integer
+              (global.get $m_the_integer)
+              (local.get $integer)
+              (global.get $m_not_a_byte))))
+        (return (i32.const 1))))
+    (if (i32.or
+          (i32.eq (local.get $kind) (global.get $STRING))
+          (i32.eq (local.get $kind) (global.get $BYTES)))
+      (then (return (call $length (local.get $value)))))
+    (if (i32.ne (local.get $kind) (global.get $LIST))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_cannot_convert))
+        (call $put_type (local.get $kind))
+        (call $put_text (global.get $m_to_bytes))
+        (call $fail_message)))
+    (local.set $len (call $length (local.get $value)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $total
+          (i32.add
+            (local.get $total)
+            (call $bytes_length (call $item_address (local.get $value) (local.get $i)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.get $total))
+
+  ;; Writes the bytes of a value that $bytes_length accepted at `out`, and
+  ;; returns where they end.
+  (func $put_bytes (param $value i32) (param $out i32) (result i32)
+    (local $kind i32)
+    (local $len i32)
+    (local $i i32)
+    (local.set $kind (call $kind (local.get $value)))
+    (if (i32.eq (local.get $kind) (global.get $INTEGER))
+      (then
+        (i64.store8 (local.get $out) (i64.load offset=8 (local.get $value)))
+        (return (i32.add (local.get $out) (i32.const 1)))))
+    (local.set $len (call $length (local.get $value)))
+    (if (i32.ne (local.get $kind) (global.get $LIST))
+      (then
+        (memory.copy (local.get $out) (call $data (local.get $value)) (local.get $len))
+        (return (i32.add (local.get $out) (local.get $len)))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $out
+          (call $put_bytes (call $item_address (local.get $value) (local.get $i)) (local.get $out)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.get $out))
+
+  ;; ---- Operators ----
+
+  ;; A new string, bytes or list with the contents of `a`, then of `b`.
+  (func $concat (param $a i32) (param $b i32) (result i32 i64)
+    (local $kind i32)
+    (local $size i32)
+    (local $first i32)
+    (local $object i32)
+    (local $i i32)
+    (local $len i32)
+    (local.set $kind (call $kind (local.get $a)))
+    (local.set $size
+      (select (i32.const 16) (i32.const 1) (i32.eq (local.get $kind) (global.get $LIST))))
+    (local.set $first (i32.mul (call $length (local.get $a)) (local.get $size)))
+    (local.set $len (i32.add (call $length (local.get $a)) (call $length (local.get $b))))
+    (local.set $object (call $new_object (local.get $len) (local.get $size)))
+    (memory.copy
+      (i32.add (local.get $object) (i32.const 8))
+      (call $data (local.get $a))
+      (local.get $first))
+    (memory.copy
+      (i32.add (i32.add (local.get $object) (i32.const 8)) (local.get $first))
+      (call $data (local.get $b))
+      (i32.mul (call $length (local.get $b)) (local.get $size)))
+    (if (i32.eq (local.get $kind) (global.get $LIST))
+      (then
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+            (call $retain
+              (i32.add
+                (i32.add (local.get $object) (i32.const 8))
+                (i32.shl (local.get $i) (i32.const 4))))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))))
+    (local.get $kind)
+    (i64.extend_i32_u (local.get $object)))
+
+  (func $overflow
+    (call $fail (global.get $m_overflow)))
+
+  (func $add (param $a i32) (param $b i32) (result i32 i64)
+    (local $kind i32)
+    (local $x i64)
+    (local $y i64)
+    (local $result i64)
+    (local.set $kind (call $kind (local.get $a)))
+    (if (i32.and
+          (i32.eq (local.get $kind) (call $kind (local.get $b)))
+          (call $has_object (local.get $kind)))
+      (then (return (call $concat (local.get $a) (local.get $b)))))
+    (local.set $x (call $integer (local.get $a)))
+    (local.set $y (call $integer (local.get $b)))
+    (local.set $result (i64.add (local.get $x) (local.get $y)))
+    (if (i64.lt_s
+          (i64.and
+            (i64.xor (local.get $x) (local.get $result))
+            (i64.xor (local.get $y) (local.get $result)))
+          (i64.const 0))
+      (then (call $overflow)))
+    (global.get $INTEGER)
+    (local.get $result))
+
+  (func $sub (param $a i32) (param $b i32) (result i32 i64)
+    (local $x i64)
+    (local $y i64)
+    (local $result i64)
+    (local.set $x (call $integer (local.get $a)))
+    (local.set $y (call $integer (local.get $b)))
+    (local.set $result (i64.sub (local.get $x) (local.get $y)))
+    (if (i64.lt_s
+          (i64.and
+            (i64.xor (local.get $x) (local.get $y))
+            (i64.xor (local.get $x) (local.get $result)))
+          (i64.const 0))
+      (then (call $overflow)))
+    (global.get $INTEGER)
+    (local.get $result))
+
+  (func $mul (param $a i32) (param $b i32) (result i32 i64)
+    (local $x i64)
+    (local $y i64)
+    (local $result i64)
+    (local.set $x (call $integer (local.get $a)))
+    (local.set $y (call $integer (local.get $b)))
+    (local.set $result (i64.mul (local.get $x) (local.get $y)))
+    (if (i64.eq (local.get $x) (i64.const -1))
+      (then
+        (if (i64.eq (local.get $y) (i64.const 0x8000000000000000))
+          (then (call $overflow))))
+      (else
+        (if (i64.ne (local.get $x) (i64.const 0))
+          (then
+            (if (i64.ne (i64.div_s (local.get $result) (local.get $x)) (local.get $y))
+              (then (call $overflow)))))))
+    (global.get $INTEGER)
+    (local.get $result))
+
+  (func $check_division (param $x i64) (param $y i64)
+    (if (i64.eqz (local.get $y))
+      (then (call $fail (global.get $m_division_by_zero))))
+    (if (i32.and
+          (i64.eq (local.get $x) (i64.const 0x8000000000000000))
+          (i64.eq (local.get $y) (i64.const -1)))
+      (then (call $overflow))))
+
+  (func $div (param $a i32) (param $b i32) (result i32 i64)
+    (local $x i64)
+    (local $y i64)
+    (local.set $x (call $integer (local.get $a)))
+    (local.set $y (call $integer (local.get $b)))
+    (call $check_division (local.get $x) (local.get $y))
+    (global.get $INTEGER)
+    (i64.div_s (local.get $x) (local.get $y)))
+
+  (func $mod (param $a i32) (param $b i32) (result i32 i64)
+    (local $x i64)
+    (local $y i64)
+    (local.set $x (call $integer (local.get $a)))
+    (local.set $y (call $integer (local.get $b)))
+    (call $check_division (local.get $x) (local.get $y))
+    (global.get $INTEGER)
+    (i64.rem_s (local.get $x) (local.get $y)))
+
+  (func $bool (param $boolean i32) (result i32 i64)
+    (global.get $BOOLEAN)
+    (i64.extend_i32_u (local.get $boolean)))
+
+  (func $eq (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (call $equal (local.get $a) (local.get $b))))
+
+  (func $ne (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (i32.eqz (call $equal (local.get $a) (local.get $b)))))
+
+  (func $lt (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (i64.lt_s (call $integer (local.get $a)) (call $integer (local.get $b)))))
+
+  (func $gt (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (i64.gt_s (call $integer (local.get $a)) (call $integer (local.get $b)))))
+
+  (func $le (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (i64.le_s (call $integer (local.get $a)) (call $integer (local.get $b)))))
+
+  (func $ge (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (i64.ge_s (call $integer (local.get $a)) (call $integer (local.get $b)))))
+
+  (func $xor (param $a i32) (param $b i32) (result i32 i64)
+    (call $bool (i32.ne (call $boolean (local.get $a)) (call $boolean (local.get $b)))))
+
+  (func $not (param $a i32) (result i32 i64)
+    (call $bool (i32.eqz (call $boolean (local.get $a)))))
+
+  ;; Whether `value` fits in `width` bytes as either an unsigned or a two's
+  ;; complement integer.
+  (func $fits (param $value i64) (param $width i32) (result i32)
+    (local $limit i64)
+    (if (i32.ge_u (local.get $width) (i32.const 8))
+      (then (return (i32.const 1))))
+    (local.set $limit
+      (i64.shl (i64.const 1) (i64.extend_i32_u (i32.shl (local.get $width) (i32.const 3)))))
+    (i32.and
+      (i64.ge_s
+        (local.get $value)
+        (i64.sub (i64.const 0) (i64.shr_s (local.get $limit) (i64.const 1))))
+      (i64.lt_s (local.get $value) (local.get $limit))))
+
+  ;; `value::width`, big-endian when `big`.
+  (func $pack (param $value i32) (param $width i32) (param $big i32) (result i32 i64)
+    (local $integer i64)
+    (local $object i32)
+    (local $i i32)
+    (local.set $integer (call $integer (local.get $value)))
+    (if (i32.eqz (call $fits (local.get $integer) (local.get $width)))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_the_value))
+        (call $put_integer (local.get $integer))
+        (call $put_text (global.get $m_does_not_fit))
+        (call $put_integer (i64.extend_i32_u (local.get $width)))
+        (call $put_text (global.get $m_bytes))
+        (call $fail_message)))
+    (local.set $object (call $new_object (local.get $width) (i32.const 1)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $width)))
+        (i64.store8 offset=8
+          (i32.add
+            (local.get $object)
+            (select
+              (i32.sub (i32.sub (local.get $width) (i32.const 1)) (local.get $i))
+              (local.get $i)
+              (local.get $big)))
+          (i64.shr_u
+            (local.get $integer)
+            (i64.extend_i32_u (i32.shl (local.get $i) (i32.const 3)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.get $BYTES)
+    (i64.extend_i32_u (local.get $object)))
+
+  ;; The bytes that `[a::2, b::-1] = value` unpacks, which must be
+  ;; `expected` long, for $field to read.
+  (func $unpack (param $value i32) (param $expected i32) (result i32)
+    (local $len i32)
+    (local.set $len (call $bytes_length (local.get $value)))
+    (if (i32.ne (local.get $len) (local.get $expected))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_cannot_unpack))
+        (call $put_integer (i64.extend_i32_u (local.get $len)))
+        (call $put_text (global.get $m_into_targets))
+        (call $put_integer (i64.extend_i32_u (local.get $expected)))
+        (call $put_text (global.get $m_bytes))
+        (call $fail_message)))
+    (drop (call $put_bytes (local.get $value) (global.get $scratch)))
+    (global.get $scratch))
+
+  (func $field (param $bytes i32) (param $width i32) (param $big i32) (result i32 i64)
+    (local $integer i64)
+    (local $i i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $width)))
+        (local.set $integer
+          (i64.or
+            (i64.shl (local.get $integer) (i64.const 8))
+            (i64.load8_u
+              (i32.add
+                (local.get $bytes)
+                (select
+                  (local.get $i)
+                  (i32.sub (i32.sub (local.get $width) (i32.const 1)) (local.get $i))
+                  (local.get $big))))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.get $INTEGER)
+    (local.get $integer))
+
+  ;; The list of the items a `for` loop visits: the bytes of a string or
+  ;; bytes are integers.
+  (func $items (param $collection i32) (result i32 i64)
+    (local $len i32)
+    (local $object i32)
+    (local $item i32)
+    (local $i i32)
+    (call $collection (local.get $collection))
+    (if (i32.eq (call $kind (local.get $collection)) (global.get $LIST))
+      (then
+        (call $retain (local.get $collection))
+        (return (global.get $LIST) (i64.load offset=8 (local.get $collection)))))
+    (local.set $len (call $length (local.get $collection)))
+    (local.set $object (call $new_object (local.get $len) (i32.const 16)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $item
+          (i32.add
+            (i32.add (local.get $object) (i32.const 8))
+            (i32.shl (local.get $i) (i32.const 4))))
+        (i32.store (local.get $item) (global.get $INTEGER))
+        (i64.store offset=8
+          (local.get $item)
+          (i64.load8_u (i32.add (call $data (local.get $collection)) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.get $LIST)
+    (i64.extend_i32_u (local.get $object)))
+
+  (func $count (param $list i32) (result i32 i64)
+    (if (i32.ne (call $kind (local.get $list)) (global.get $LIST))
+      (then (call $fail_type (global.get $m_expected_list) (call $kind (local.get $list)))))
+    (global.get $INTEGER)
+    (i64.extend_i32_u (call $length (local.get $list))))
+
+  ;; The item at a position of a collection, which must hold it.
+  (func $item (param $collection i32) (param $position i32) (result i32 i64)
+    (local $item i32)
+    (if (i32.ne (call $kind (local.get $collection)) (global.get $LIST))
+      (then
+        (return
+          (global.get $INTEGER)
+          (i64.load8_u (i32.add (call $data (local.get $collection)) (local.get $position))))))
+    (local.set $item (call $item_address (local.get $collection) (local.get $position)))
+    (call $retain (local.get $item))
+    (i32.load (local.get $item))
+    (i64.load offset=8 (local.get $item)))
+
+  (func $index (param $collection i32) (param $index i32) (result i32 i64)
+    (local $position i64)
+    (local.set $position (call $integer (local.get $index)))
+    (call $collection (local.get $collection))
+    (if (i64.ge_u
+          (local.get $position)
+          (i64.extend_i32_u (call $length (local.get $collection))))
+      (then
+        (call $fail_integer
+          (global.get $m_the_index)
+          (local.get $position)
+          (global.get $m_out_of_bounds))))
+    (call $item (local.get $collection) (i32.wrap_i64 (local.get $position))))
+
+  (func $in_range (param $value i32) (param $start i64) (param $end i64) (result i32 i64)
+    (local $integer i64)
+    (local.set $integer (i64.load offset=8 (local.get $value)))
+    (call $bool
+      (i32.and
+        (i32.eq (call $kind (local.get $value)) (global.get $INTEGER))
+        (i32.and
+          (i64.ge_s (local.get $integer) (local.get $start))
+          (i64.le_s (local.get $integer) (local.get $end))))))
+
+  ;; ---- Records ----
+  ;;
+  ;; A record takes 16 bytes: its name, the address of its elements, its
+  ;; length and its element size. Elements take 8 bytes whatever their size
+  ;; and hold the bits of the element.
+
+  (func $record (param $index i32) (result i32)
+    (i32.add (global.get $records) (i32.shl (local.get $index) (i32.const 4))))
+
+  (func $position (param $record i32) (param $index i32) (result i32)
+    (local $position i64)
+    (local.set $position (call $integer (local.get $index)))
+    (if (i64.ge_u (local.get $position) (i64.load32_u offset=8 (local.get $record)))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_the_index))
+        (call $put_integer (local.get $position))
+        (call $put_text (global.get $m_out_of_bounds_for))
+        (call $put_text (i32.load (local.get $record)))
+        (call $put_text (global.get $m_of_length))
+        (call $put_integer (i64.load32_u offset=8 (local.get $record)))
+        (call $fail_message)))
+    (i32.wrap_i64 (local.get $position)))
+
+  (func $element_address (param $record i32) (param $position i32) (result i32)
+    (i32.add (i32.load offset=4 (local.get $record)) (i32.shl (local.get $position) (i32.const 3))))
+
+  (func $element (param $index i32) (param $position i32) (result i32 i64)
+    (local $record i32)
+    (local.set $record (call $record (local.get $index)))
+    (global.get $INTEGER)
+    (i64.load
+      (call $element_address
+        (local.get $record)
+        (call $position (local.get $record) (local.get $position)))))
+
+  ;; Stores an integer that fits the element as either an unsigned or a
+  ;; two's complement value.
+  (func $set_element (param $index i32) (param $position i32) (param $value i32)
+    (local $record i32)
+    (local $address i32)
+    (local $integer i64)
+    (local $size i32)
+    (local.set $record (call $record (local.get $index)))
+    (local.set $address
+      (call $element_address
+        (local.get $record)
+        (call $position (local.get $record) (local.get $position))))
+    (local.set $integer (call $integer (local.get $value)))
+    (local.set $size (i32.load offset=12 (local.get $record)))
+    (if (i32.eqz (call $fits (local.get $integer) (local.get $size)))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_the_value))
+        (call $put_integer (local.get $integer))
+        (call $put_text (global.get $m_does_not_fit_the))
+        (call $put_integer (i64.extend_i32_u (local.get $size)))
+        (call $put_text (global.get $m_bytes_of_element))
+        (call $put_text (i32.load (local.get $record)))
+        (call $fail_message)))
+    (if (i32.lt_u (local.get $size) (i32.const 8))
+      (then
+        (local.set $integer
+          (i64.and
+            (local.get $integer)
+            (i64.sub
+              (i64.shl (i64.const 1) (i64.extend_i32_u (i32.shl (local.get $size) (i32.const 3))))
+              (i64.const 1))))))
+    (i64.store (local.get $address) (local.get $integer)))
+
+  ;; A copy of the elements, as iterated by `for`.
+  (func $record_list (param $index i32) (result i32 i64)
+    (local $record i32)
+    (local $len i32)
+    (local $object i32)
+    (local $item i32)
+    (local $i i32)
+    (local.set $record (call $record (local.get $index)))
+    (local.set $len (i32.load offset=8 (local.get $record)))
+    (local.set $object (call $new_object (local.get $len) (i32.const 16)))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $item
+          (i32.add
+            (i32.add (local.get $object) (i32.const 8))
+            (i32.shl (local.get $i) (i32.const 4))))
+        (i32.store (local.get $item) (global.get $INTEGER))
+        (i64.store offset=8
+          (local.get $item)
+          (i64.load (call $element_address (local.get $record) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (global.get $LIST)
+    (i64.extend_i32_u (local.get $object)))
+
+  ;; ---- Tasks ----
+  ;;
+  ;; A body takes 16 bytes: its name, its parameters and its interval.
+  ;;
+  ;; A task is a declared task, a function started as a task or a running
+  ;; `when` handler. Its entry holds at offset
+  ;;   0  its state: stopped (0), waiting (1) or running (2)
+  ;;   4  whether the program has no handle to it, so that the entry can be
+  ;;      reused once it stops
+  ;;   8  whether a tick is in progress, waiting at a delay
+  ;;   12 its body
+  ;;   16 its interval
+  ;;   24 when it is due
+  ;;   32 its sequence number: tasks due at the same time run in the order
+  ;;      they were scheduled
+  ;;   40 when its tick started
+  ;;   48 how many arguments it has
+  ;;   56 its arguments, then its frame.
+  ;;
+  ;; A frame is the resume block, where a body goes on after a delay, then
+  ;; the delay, the result at offset 8 and the slots at offset 24: the
+  ;; locals, then the temporaries.
+
+  (global $STOPPED i32 (i32.const 0))
+  (global $WAITING i32 (i32.const 1))
+  (global $RUNNING i32 (i32.const 2))
+
+  (global $tasks (mut i32) (i32.const 0))
+  (global $task_count (mut i32) (i32.const 0))
+  (global $task_capacity (mut i32) (i32.const 0))
+  (global $sequence (mut i64) (i64.const 0))
+
+  (func $body_name (param $body i32) (result i32)
+    (i32.load (i32.add (global.get $bodies) (i32.shl (local.get $body) (i32.const 4)))))
+
+  (func $body_parameters (param $body i32) (result i32)
+    (i32.load offset=4 (i32.add (global.get $bodies) (i32.shl (local.get $body) (i32.const 4)))))
+
+  (func $body_interval (param $body i32) (result i64)
+    (i64.load offset=8
+      (i32.add (global.get $bodies) (i32.shl (local.get $body) (i32.const 4)))))
+
+  (func $task (param $id i32) (result i32)
+    (i32.load (i32.add (global.get $tasks) (i32.shl (local.get $id) (i32.const 2)))))
+
+  (func $task_arg (param $entry i32) (param $index i32) (result i32)
+    (i32.add
+      (i32.add (local.get $entry) (i32.const 56))
+      (i32.shl (local.get $index) (i32.const 4))))
+
+  (func $task_frame (param $entry i32) (result i32)
+    (call $task_arg (local.get $entry) (global.get $max_args)))
+
+  (func $task_slots (param $entry i32) (result i32)
+    (i32.add (call $task_frame (local.get $entry)) (i32.const 24)))
+
+  (func $next_sequence (result i64)
+    (global.set $sequence (i64.add (global.get $sequence) (i64.const 1)))
+    (global.get $sequence))
+
+  ;; Starts a task over from the beginning of its body. A tick in progress
+  ;; is abandoned.
+  (func $restart (param $id i32) (param $has_interval i32) (param $interval i64)
+    (param $immediate i32)
+    (local $entry i32)
+    (local.set $entry (call $task (local.get $id)))
+    (if (i32.ne (i32.load (local.get $entry)) (global.get $RUNNING))
+      (then (call $clear (call $task_slots (local.get $entry)) (global.get $slots))))
+    (if (local.get $has_interval)
+      (then (i64.store offset=16 (local.get $entry) (local.get $interval))))
+    (i32.store (local.get $entry) (global.get $WAITING))
+    (i32.store offset=8 (local.get $entry) (i32.const 0))
+    (i64.store offset=24
+      (local.get $entry)
+      (select
+        (global.get $now)
+        (i64.add (global.get $now) (i64.load offset=16 (local.get $entry)))
+        (local.get $immediate)))
+    (i64.store offset=32 (local.get $entry) (call $next_sequence)))
+
+  ;; A stopped task of `body` without arguments and its interval.
+  (func $new_task (param $body i32) (param $detached i32) (result i32)
+    (local $id i32)
+    (local $entry i32)
+    (local $tasks i32)
+    (local.set $id (i32.const -1))
+    (if (local.get $detached)
+      (then
+        (local.set $entry (i32.const 0))
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $entry) (global.get $task_count)))
+            (if (i32.and
+                  (i32.load offset=4 (call $task (local.get $entry)))
+                  (i32.eq (i32.load (call $task (local.get $entry))) (global.get $STOPPED)))
+              (then
+                (local.set $id (local.get $entry))
+                (br $done)))
+            (local.set $entry (i32.add (local.get $entry) (i32.const 1)))
+            (br $next)))))
+    (if (i32.lt_s (local.get $id) (i32.const 0))
+      (then
+        (if (i32.eq (global.get $task_count) (global.get $task_capacity))
+          (then
+            (global.set $task_capacity
+              (select
+                (i32.shl (global.get $task_capacity) (i32.const 1))
+                (i32.const 8)
+                (global.get $task_capacity)))
+            (local.set $tasks
+              (call $alloc (i32.shl (global.get $task_capacity) (i32.const 2))))
+            (if (global.get $tasks)
+              (then
+                (memory.copy
+                  (local.get $tasks)
+                  (global.get $tasks)
+                  (i32.shl (global.get $task_count) (i32.const 2)))
+                (call $free (global.get $tasks))))
+            (global.set $tasks (local.get $tasks))))
+        (local.set $id (global.get $task_count))
+        (i32.store
+          (i32.add (global.get $tasks) (i32.shl (local.get $id) (i32.const 2)))
+          (call $alloc_zeroed
+            (i32.add
+              (call $task_slots (i32.const 0))
+              (i32.shl (global.get $slots) (i32.const 4)))))
+        (global.set $task_count (i32.add (global.get $task_count) (i32.const 1)))))
+
+    (local.set $entry (call $task (local.get $id)))
+    (i32.store offset=4 (local.get $entry) (local.get $detached))
+    (i32.store offset=12 (local.get $entry) (local.get $body))
+    (i64.store offset=16 (local.get $entry) (i64.const 0))
+    (call $clear (call $task_arg (local.get $entry) (i32.const 0)) (global.get $max_args))
+    (i32.store offset=48 (local.get $entry) (i32.const 0))
+    (local.get $id))
+
+  (func $stop_task (param $id i32)
+    (local $entry i32)
+    (local.set $entry (call $task (local.get $id)))
+    (if (i32.ne (i32.load (local.get $entry)) (global.get $RUNNING))
+      (then (call $clear (call $task_slots (local.get $entry)) (global.get $slots))))
+    (i32.store (local.get $entry) (global.get $STOPPED))
+    (i32.store offset=8 (local.get $entry) (i32.const 0)))
+
+  ;; Runs the next tick, or the rest of the tick in progress, of a due
+  ;; task.
+  (func $resume (param $id i32)
+    (local $entry i32)
+    (local $frame i32)
+    (local $finished i32)
+    (local $i i32)
+    (local $wake_at i64)
+    (local.set $entry (call $task (local.get $id)))
+    (local.set $frame (call $task_frame (local.get $entry)))
+
+    (if (i32.eqz (i32.load offset=8 (local.get $entry)))
+      (then
+        (call $clear (call $task_slots (local.get $entry)) (global.get $slots))
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $i) (i32.load offset=48 (local.get $entry))))
+            (call $assign
+              (i32.add (call $task_slots (local.get $entry)) (i32.shl (local.get $i) (i32.const 4)))
+              (call $task_arg (local.get $entry) (local.get $i)))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))
+        (i32.store (local.get $frame) (i32.const 0))
+        (i64.store offset=40 (local.get $entry) (global.get $now))))
+    (i32.store (local.get $entry) (global.get $RUNNING))
+
+    (local.set $finished
+      (call_indirect $run (type $body)
+        (local.get $frame)
+        (i32.load offset=12 (local.get $entry))))
+    (call $set (i32.add (local.get $frame) (i32.const 8)) (global.get $NIL) (i64.const 0))
+
+    (if (i32.ne (i32.load (local.get $entry)) (global.get $RUNNING))
+      (then
+        (call $clear (call $task_slots (local.get $entry)) (global.get $slots))
+        (return)))
+    (i64.store offset=32 (local.get $entry) (call $next_sequence))
+    (if (i32.eqz (local.get $finished))
+      (then
+        (i32.store (local.get $entry) (global.get $WAITING))
+        (i32.store offset=8 (local.get $entry) (i32.const 1))
+        (i64.store offset=24
+          (local.get $entry)
+          (i64.add (global.get $now) (i64.load32_u offset=4 (local.get $frame))))
+        (return)))
+
+    (call $clear (call $task_slots (local.get $entry)) (global.get $slots))
+    (i32.store offset=8 (local.get $entry) (i32.const 0))
+    (if (i64.eqz (i64.load offset=16 (local.get $entry)))
+      (then
+        (i32.store (local.get $entry) (global.get $STOPPED))
+        (return)))
+    (i32.store (local.get $entry) (global.get $WAITING))
+    (local.set $wake_at
+      (i64.add (i64.load offset=40 (local.get $entry)) (i64.load offset=16 (local.get $entry))))
+    (i64.store offset=24
+      (local.get $entry)
+      (select
+        (global.get $now)
+        (local.get $wake_at)
+        (i64.lt_u (local.get $wake_at) (global.get $now)))))
+
+  ;; A zeroed frame with `slots` slots for a direct call.
+  (func $frame (param $slots i32) (result i32)
+    (call $alloc_zeroed (i32.add (i32.const 24) (i32.shl (local.get $slots) (i32.const 4)))))
+
+  ;; Calls a function directly, which cannot wait with `@`, with the frame
+  ;; that $frame made and the caller filled with the arguments.
+  (func $call (param $body i32) (param $frame i32) (param $slots i32) (result i32 i64)
+    (local $kind i32)
+    (local $payload i64)
+    (if (i32.eqz (call_indirect $run (type $body) (local.get $frame) (local.get $body)))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_the_function))
+        (call $put_text (call $body_name (local.get $body)))
+        (call $put_text (global.get $m_cannot_wait))
+        (call $fail_message)))
+    (local.set $kind (i32.load offset=8 (local.get $frame)))
+    (local.set $payload (i64.load offset=16 (local.get $frame)))
+    (call $clear (i32.add (local.get $frame) (i32.const 24)) (local.get $slots))
+    (call $free (local.get $frame))
+    (local.get $kind)
+    (local.get $payload))
+
+  ;; ---- Builtins ----
+  ;;
+  ;; Builtins take their arguments from $arg and return a value.
+
+  (func $builtin_arg (param $index i32) (result i32)
+    (i32.add (global.get $args) (i32.shl (local.get $index) (i32.const 4))))
+
+  ;; print(format, args...): %d, %x, %s and %a take the next argument and
+  ;; %% writes %.
+  (func $builtin_print (param $count i32) (result i32 i64)
+    (local $format i32)
+    (local $len i32)
+    (local $i i32)
+    (local $c i32)
+    (local $next i32)
+    (local $arg i32)
+    (if (i32.or
+          (i32.eqz (local.get $count))
+          (i32.ne (call $kind (global.get $args)) (global.get $STRING)))
+      (then (call $fail (global.get $m_print_format))))
+    (local.set $format (call $data (global.get $args)))
+    (local.set $len (call $length (global.get $args)))
+    (global.set $text_len (i32.const 0))
+    (global.set $text_print (i32.const 1))
+    (local.set $next (i32.const 1))
+
+    (block $done
+      (loop $continue
+        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+        (local.set $c (i32.load8_u (i32.add (local.get $format) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (if (i32.ne (local.get $c) (i32.const 37))
+          (then
+            (call $put_byte (local.get $c))
+            (br $continue)))
+        (if (i32.eq (local.get $i) (local.get $len))
+          (then (call $fail (global.get $m_format_end))))
+        (local.set $c (i32.load8_u (i32.add (local.get $format) (local.get $i))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (if (i32.eq (local.get $c) (i32.const 37))
+          (then
+            (call $put_byte (local.get $c))
+            (br $continue)))
+        (if (i32.eq (local.get $next) (local.get $count))
+          (then (call $fail (global.get $m_missing_argument))))
+        (local.set $arg (call $builtin_arg (local.get $next)))
+        (local.set $next (i32.add (local.get $next) (i32.const 1)))
+        (if (i32.eq (local.get $c) (i32.const 100))
+          (then
+            (call $put_integer (call $integer (local.get $arg)))
+            (br $continue)))
+        (if (i32.eq (local.get $c) (i32.const 120))
+          (then
+            (call $put_digits (call $integer (local.get $arg)) (i64.const 16))
+            (br $continue)))
+        (if (i32.or (i32.eq (local.get $c) (i32.const 115)) (i32.eq (local.get $c) (i32.const 97)))
+          (then
+            (call $put_value (local.get $arg))
+            (br $continue)))
+        (call $message)
+        (call $put_text (global.get $m_unknown_directive))
+        (call $put_byte (local.get $c))
+        (call $fail_message)))
+    (if (i32.ne (local.get $next) (local.get $count))
+      (then (call $fail (global.get $m_too_many_arguments))))
+
+    (call $host_print (global.get $text) (global.get $text_len))
+    (global.get $NIL)
+    (i64.const 0))
+
+  ;; send(interface, data...) sends the bytes of every argument after the
+  ;; interface name.
+  (func $builtin_send (param $count i32) (result i32 i64)
+    (local $len i32)
+    (local $i i32)
+    (local $buffer i32)
+    (local $out i32)
+    (if (i32.or
+          (i32.eqz (local.get $count))
+          (i32.ne (call $kind (global.get $args)) (global.get $STRING)))
+      (then (call $fail (global.get $m_send_interface))))
+    (local.set $i (i32.const 1))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $count)))
+        (local.set $len
+          (i32.add (local.get $len) (call $bytes_length (call $builtin_arg (local.get $i)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.set $buffer (call $alloc (local.get $len)))
+    (local.set $out (local.get $buffer))
+    (local.set $i (i32.const 1))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $count)))
+        (local.set $out (call $put_bytes (call $builtin_arg (local.get $i)) (local.get $out)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (call $host_send
+      (call $data (global.get $args))
+      (call $length (global.get $args))
+      (local.get $buffer)
+      (local.get $len))
+    (call $free (local.get $buffer))
+    (global.get $NIL)
+    (i64.const 0))
+
+  (func $builtin_millis (param $count i32) (result i32 i64)
+    (if (local.get $count)
+      (then (call $fail (global.get $m_millis_arguments))))
+    (global.get $INTEGER)
+    (global.get $now))
+
+  ;; The pseudo-random generator behind rand() (SplitMix64).
+  (func $random (result i64)
+    (local $z i64)
+    (global.set $random_state
+      (i64.add (global.get $random_state) (i64.const 0x9e3779b97f4a7c15)))
+    (local.set $z (global.get $random_state))
+    (local.set $z
+      (i64.mul
+        (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 30)))
+        (i64.const 0xbf58476d1ce4e5b9)))
+    (local.set $z
+      (i64.mul
+        (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 27)))
+        (i64.const 0x94d049bb133111eb)))
+    (i64.xor (local.get $z) (i64.shr_u (local.get $z) (i64.const 31))))
+
+  ;; The high 64 bits of a * b.
+  (func $multiply_high (param $a i64) (param $b i64) (result i64)
+    (local $low i64)
+    (local $middle1 i64)
+    (local $middle2 i64)
+    (local.set $low
+      (i64.mul
+        (i64.and (local.get $a) (i64.const 0xffffffff))
+        (i64.and (local.get $b) (i64.const 0xffffffff))))
+    (local.set $middle1
+      (i64.add
+        (i64.mul
+          (i64.shr_u (local.get $a) (i64.const 32))
+          (i64.and (local.get $b) (i64.const 0xffffffff)))
+        (i64.shr_u (local.get $low) (i64.const 32))))
+    (local.set $middle2
+      (i64.add
+        (i64.mul
+          (i64.and (local.get $a) (i64.const 0xffffffff))
+          (i64.shr_u (local.get $b) (i64.const 32)))
+        (i64.and (local.get $middle1) (i64.const 0xffffffff))))
+    (i64.add
+      (i64.add
+        (i64.mul (i64.shr_u (local.get $a) (i64.const 32)) (i64.shr_u (local.get $b) (i64.const 32)))
+        (i64.shr_u (local.get $middle1) (i64.const 32)))
+      (i64.shr_u (local.get $middle2) (i64.const 32))))
+
+  ;; rand(min, max) is a number from `min` included to `max` excluded.
+  (func $builtin_rand (param $count i32) (result i32 i64)
+    (local $min i64)
+    (local $max i64)
+    (if (i32.ne (local.get $count) (i32.const 2))
+      (then (call $fail (global.get $m_rand_arguments))))
+    (local.set $min (call $integer (call $builtin_arg (i32.const 0))))
+    (local.set $max (call $integer (call $builtin_arg (i32.const 1))))
+    (if (i64.le_s (local.get $max) (local.get $min))
+      (then
+        (call $message)
+        (call $put_text (global.get $m_rand_range))
+        (call $put_integer (local.get $min))
+        (call $put_text (global.get $m_and))
+        (call $put_integer (local.get $max))
+        (call $fail_message)))
+    (global.get $INTEGER)
+    (i64.add
+      (local.get $min)
+      (call $multiply_high (call $random) (i64.sub (local.get $max) (local.get $min)))))
+
+  ;; task.stop() stops a task; stopping nil does nothing.
+  (func $builtin_stop (param $count i32) (result i32 i64)
+    (local $kind i32)
+    (if (i32.ne (local.get $count) (i32.const 1))
+      (then (call $fail (global.get $m_stop_arguments))))
+    (local.set $kind (call $kind (global.get $args)))
+    (if (i32.eq (local.get $kind) (global.get $TASK))
+      (then (call $stop_task (i32.wrap_i64 (i64.load offset=8 (global.get $args)))))
+      (else
+        (if (i32.ne (local.get $kind) (global.get $NIL))
+          (then (call $fail_type (global.get $m_cannot_stop) (local.get $kind))))))
+    (global.get $NIL)
+    (i64.const 0))
+
+  ;; `function.start(args, immediate) @ interval` runs a function as a task
+  ;; and returns its handle; `task.start() @ interval` starts a task over.
+  ;; The arguments of start() come from $arg.
+  (func $start (param $target i32) (param $count i32) (param $has_interval i32)
+    (param $interval i32) (result i32 i64)
+    (local $interval_ms i64)
+    (local $immediate i32)
+    (local $items i32)
+    (local $argc i32)
+    (local $body i32)
+    (local $id i32)
+    (local $entry i32)
+    (local $i i32)
+    (if (local.get $has_interval)
+      (then
+        (local.set $interval_ms (call $integer (local.get $interval)))
+        (if (i64.lt_s (local.get $interval_ms) (i64.const 0))
+          (then (call $fail (global.get $m_negative_interval))))))
+    (if (i32.gt_u (local.get $count) (i32.const 2))
+      (then (call $fail (global.get $m_start_arguments))))
+    (local.set $items (call $alloc_zeroed (i32.const 16)))
+    (if (local.get $count)
+      (then
+        (call $set (local.get $items) (call $items (global.get $args)))
+        (local.set $argc (call $length (local.get $items)))))
+    (local.set $immediate (i32.const 1))
+    (if (i32.eq (local.get $count) (i32.const 2))
+      (then (local.set $immediate (call $boolean (call $builtin_arg (i32.const 1))))))
+
+    (if (i32.eq (call $kind (local.get $target)) (global.get $FUNCTION))
+      (then
+        (local.set $body (call $object (local.get $target)))
+        (if (i32.ne (local.get $argc) (call $body_parameters (local.get $body)))
+          (then
+            (call $message)
+            (call $put_text (global.get $m_the_function))
+            (call $put_text (call $body_name (local.get $body)))
+            (call $put_text (global.get $m_takes))
+            (call $put_integer (i64.extend_i32_u (call $body_parameters (local.get $body))))
+            (call $put_text (global.get $m_arguments_but))
+            (call $put_integer (i64.extend_i32_u (local.get $argc)))
+            (call $put_text (global.get $m_were_given))
+            (call $fail_message)))
+        (local.set $id (call $new_task (local.get $body) (i32.const 0)))
+        (local.set $entry (call $task (local.get $id)))
+        (block $done
+          (loop $next
+            (br_if $done (i32.ge_u (local.get $i) (local.get $argc)))
+            (call $assign
+              (call $task_arg (local.get $entry) (local.get $i))
+              (call $item_address (local.get $items) (local.get $i)))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))
+        (call $set (local.get $items) (global.get $NIL) (i64.const 0))
+        (call $free (local.get $items))
+        (i32.store offset=48 (local.get $entry) (local.get $argc))
+        (i64.store offset=16 (local.get $entry) (local.get $interval_ms))
+        (call $restart (local.get $id) (i32.const 0) (i64.const 0) (local.get $immediate))
+        (return (global.get $TASK) (i64.extend_i32_u (local.get $id)))))
+    (if (i32.eq (call $kind (local.get $target)) (global.get $TASK))
+      (then
+        (if (local.get $argc)
+          (then (call $fail (global.get $m_new_arguments))))
+        (call $set (local.get $items) (global.get $NIL) (i64.const 0))
+        (call $free (local.get $items))
+        (call $restart
+          (call $object (local.get $target))
+          (local.get $has_interval)
+          (local.get $interval_ms)
+          (local.get $immediate))
+        (return (global.get $TASK) (i64.load offset=8 (local.get $target)))))
+    (call $fail_type (global.get $m_cannot_start) (call $kind (local.get $target)))
+    (unreachable))
+
+  ;; ---- Framing ----
+  ;;
+  ;; An interface takes 32 bytes: its name, the address of its handlers,
+  ;; how many there are, the address of its buffer of twice the longest
+  ;; packet, how many bytes the buffer holds and, at offset 24, when the
+  ;; last of them arrived.
+  ;;
+  ;; A handler takes 48 bytes: its body, its framing (width 0, end 1,
+  ;; continuation 2, delimited 3 or start 4), its width, the address and
+  ;; length of its start and end delimiters and, for a pattern, the
+  ;; address of the class of every byte, the number of classes and the
+  ;; addresses of the u16 transitions and u8 accepting flags of the
+  ;; automaton that matches whole packets.
+
+  (global $WIDTH i32 (i32.const 0))
+  (global $END i32 (i32.const 1))
+  (global $CONTINUATION i32 (i32.const 2))
+  (global $DELIMITED i32 (i32.const 3))
+
+  (func $buffer (param $iface i32) (result i32)
+    (i32.load offset=12 (local.get $iface)))
+
+  (func $buffered (param $iface i32) (result i32)
+    (i32.load offset=16 (local.get $iface)))
+
+  ;; Where `needle` next appears in the buffer from `from` on, overlaps
+  ;; included, or -1.
+  (func $find (param $iface i32) (param $from i32) (param $needle i32) (param $len i32)
+    (result i32)
+    (local $position i32)
+    (local.set $position (local.get $from))
+    (block $done
+      (loop $next
+        (br_if $done
+          (i32.gt_u
+            (i32.add (local.get $position) (local.get $len))
+            (call $buffered (local.get $iface))))
+        (if (call $same
+              (i32.add (call $buffer (local.get $iface)) (local.get $position))
+              (local.get $needle)
+              (local.get $len))
+          (then (return (local.get $position))))
+        (local.set $position (i32.add (local.get $position) (i32.const 1)))
+        (br $next)))
+    (i32.const -1))
+
+  ;; The length of the longest end of the buffer that begins `delimiter`
+  ;; without completing it.
+  (func $partial_suffix (param $iface i32) (param $delimiter i32) (param $len i32)
+    (result i32)
+    (local $prefix i32)
+    (local.set $prefix (i32.sub (local.get $len) (i32.const 1)))
+    (if (i32.gt_u (local.get $prefix) (call $buffered (local.get $iface)))
+      (then (local.set $prefix (call $buffered (local.get $iface)))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.eqz (local.get $prefix)))
+        (if (call $same
+              (i32.sub
+                (i32.add (call $buffer (local.get $iface)) (call $buffered (local.get $iface)))
+                (local.get $prefix))
+              (local.get $delimiter)
+              (local.get $prefix))
+          (then (return (local.get $prefix))))
+        (local.set $prefix (i32.sub (local.get $prefix) (i32.const 1)))
+        (br $next)))
+    (i32.const 0))
+
+  (func $matches (param $iface i32) (param $handler i32) (param $start i32) (param $end i32)
+    (result i32)
+    (local $state i32)
+    (local $i i32)
+    (if (i32.gt_u (i32.sub (local.get $end) (local.get $start)) (global.get $MAX_PACKET))
+      (then (return (i32.const 0))))
+    (local.set $state (i32.const 1))
+    (local.set $i (local.get $start))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (local.get $end)))
+        (local.set $state
+          (i32.load16_u
+            (i32.add
+              (i32.load offset=36 (local.get $handler))
+              (i32.shl
+                (i32.add
+                  (i32.mul (local.get $state) (i32.load offset=32 (local.get $handler)))
+                  (i32.load8_u
+                    (i32.add
+                      (i32.load offset=28 (local.get $handler))
+                      (i32.load8_u
+                        (i32.add (call $buffer (local.get $iface)) (local.get $i))))))
+                (i32.const 1)))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (i32.load8_u (i32.add (i32.load offset=40 (local.get $handler)) (local.get $state))))
+
+  ;; The candidate packet of a handler: at start..end, complete once
+  ;; `ready` bytes arrived, or else where the handler may still find one.
+  (global $complete (mut i32) (i32.const 0))
+  (global $packet_start (mut i32) (i32.const 0))
+  (global $packet_end (mut i32) (i32.const 0))
+  (global $ready (mut i32) (i32.const 0))
+  (global $from (mut i32) (i32.const 0))
+
+  (func $found (param $start i32) (param $end i32) (param $ready i32)
+    (global.set $complete (i32.const 1))
+    (global.set $packet_start (local.get $start))
+    (global.set $packet_end (local.get $end))
+    (global.set $ready (local.get $ready)))
+
+  (func $waiting (param $from i32)
+    (global.set $complete (i32.const 0))
+    (global.set $from (local.get $from)))
+
+  ;; The last start delimiter in the buffer that begins before `before`, or
+  ;; -1.
+  (func $last_start (param $iface i32) (param $handler i32) (param $before i32) (result i32)
+    (local $begin i32)
+    (local $next i32)
+    (local.set $begin (i32.const -1))
+    (local.set $next
+      (call $find
+        (local.get $iface)
+        (i32.const 0)
+        (i32.load offset=12 (local.get $handler))
+        (i32.load offset=16 (local.get $handler))))
+    (block $done
+      (loop $again
+        (br_if $done (i32.lt_s (local.get $next) (i32.const 0)))
+        (br_if $done
+          (i32.gt_u
+            (i32.add (local.get $next) (i32.load offset=16 (local.get $handler)))
+            (local.get $before)))
+        (local.set $begin (local.get $next))
+        (local.set $next
+          (call $find
+            (local.get $iface)
+            (i32.add (local.get $next) (i32.const 1))
+            (i32.load offset=12 (local.get $handler))
+            (i32.load offset=16 (local.get $handler))))
+        (br $again)))
+    (local.get $begin))
+
+  ;; Frames packets the way the interpreter does.
+  (func $candidate (param $iface i32) (param $handler i32) (param $idle i32)
+    (local $len i32)
+    (local $framing i32)
+    (local $position i32)
+    (local $stop i32)
+    (local $begin i32)
+    (local $next i32)
+    (local.set $len (call $buffered (local.get $iface)))
+    (local.set $framing (i32.load offset=4 (local.get $handler)))
+
+    (if (i32.eq (local.get $framing) (global.get $WIDTH))
+      (then
+        (if (i32.ge_u (local.get $len) (i32.load offset=8 (local.get $handler)))
+          (then
+            (call $found
+              (i32.const 0)
+              (i32.load offset=8 (local.get $handler))
+              (i32.load offset=8 (local.get $handler))))
+          (else (call $waiting (i32.const 0))))
+        (return)))
+
+    (if (i32.eq (local.get $framing) (global.get $CONTINUATION))
+      (then
+        (local.set $stop (i32.const 1))
+        (block $done
+          (loop $next
+            (br_if $done (i32.gt_u (local.get $stop) (local.get $len)))
+            (if (call $matches (local.get $iface) (local.get $handler) (i32.const 0) (local.get $stop))
+              (then
+                (call $found (i32.const 0) (local.get $stop) (local.get $stop))
+                (return)))
+            (local.set $stop (i32.add (local.get $stop) (i32.const 1)))
+            (br $next)))
+        (call $waiting (i32.const 0))
+        (return)))
+
+    (if (i32.or
+          (i32.eq (local.get $framing) (global.get $END))
+          (i32.eq (local.get $framing) (global.get $DELIMITED)))
+      (then
+        (local.set $position
+          (call $find
+            (local.get $iface)
+            (i32.const 0)
+            (i32.load offset=20 (local.get $handler))
+            (i32.load offset=24 (local.get $handler))))
+        (block $done
+          (loop $again
+            (br_if $done (i32.lt_s (local.get $position) (i32.const 0)))
+            (local.set $stop
+              (i32.add (local.get $position) (i32.load offset=24 (local.get $handler))))
+            (local.set $begin (i32.const 0))
+            (if (i32.eq (local.get $framing) (global.get $DELIMITED))
+              (then
+                (local.set $begin
+                  (call $last_start (local.get $iface) (local.get $handler) (local.get $position)))))
+            (if (i32.ge_s (local.get $begin) (i32.const 0))
+              (then
+                (if (call $matches
+                      (local.get $iface)
+                      (local.get $handler)
+                      (local.get $begin)
+                      (local.get $stop))
+                  (then
+                    (call $found (local.get $begin) (local.get $stop) (local.get $stop))
+                    (return)))))
+            (local.set $position
+              (call $find
+                (local.get $iface)
+                (i32.add (local.get $position) (i32.const 1))
+                (i32.load offset=20 (local.get $handler))
+                (i32.load offset=24 (local.get $handler))))
+            (br $again)))
+        (if (i32.eq (local.get $framing) (global.get $END))
+          (then
+            (call $waiting (i32.const 0))
+            (return)))
+        (local.set $begin (call $last_start (local.get $iface) (local.get $handler) (local.get $len)))
+        (if (i32.ge_s (local.get $begin) (i32.const 0))
+          (then (call $waiting (local.get $begin)))
+          (else
+            (call $waiting
+              (i32.sub
+                (local.get $len)
+                (call $partial_suffix
+                  (local.get $iface)
+                  (i32.load offset=12 (local.get $handler))
+                  (i32.load offset=16 (local.get $handler)))))))
+        (return)))
+
+    (local.set $begin
+      (call $find
+        (local.get $iface)
+        (i32.const 0)
+        (i32.load offset=12 (local.get $handler))
+        (i32.load offset=16 (local.get $handler))))
+    (if (i32.lt_s (local.get $begin) (i32.const 0))
+      (then
+        (call $waiting
+          (i32.sub
+            (local.get $len)
+            (call $partial_suffix
+              (local.get $iface)
+              (i32.load offset=12 (local.get $handler))
+              (i32.load offset=16 (local.get $handler)))))
+        (return)))
+    (loop $again
+      (local.set $next
+        (call $find
+          (local.get $iface)
+          (i32.add (local.get $begin) (i32.load offset=16 (local.get $handler)))
+          (i32.load offset=12 (local.get $handler))
+          (i32.load offset=16 (local.get $handler))))
+      (if (i32.ge_s (local.get $next) (i32.const 0))
+        (then
+          (if (call $matches (local.get $iface) (local.get $handler) (local.get $begin) (local.get $next))
+            (then
+              (call $found
+                (local.get $begin)
+                (local.get $next)
+                (i32.add (local.get $next) (i32.load offset=16 (local.get $handler))))
+              (return)))
+          (local.set $begin (local.get $next))
+          (br $again))))
+    (if (i32.and
+          (local.get $idle)
+          (call $matches (local.get $iface) (local.get $handler) (local.get $begin) (local.get $len)))
+      (then (call $found (local.get $begin) (local.get $len) (local.get $len)))
+      (else (call $waiting (local.get $begin)))))
+
+  (func $drop (param $iface i32) (param $count i32)
+    (memory.copy
+      (call $buffer (local.get $iface))
+      (i32.add (call $buffer (local.get $iface)) (local.get $count))
+      (i32.sub (call $buffered (local.get $iface)) (local.get $count)))
+    (i32.store offset=16
+      (local.get $iface)
+      (i32.sub (call $buffered (local.get $iface)) (local.get $count))))
+
+  ;; Starts a `when` handler for a packet.
+  (func $spawn_handler (param $body i32) (param $data i32) (param $len i32)
+    (local $id i32)
+    (local.set $id (call $new_task (local.get $body) (i32.const 1)))
+    (call $set
+      (call $task_arg (call $task (local.get $id)) (i32.const 0))
+      (global.get $BYTES)
+      (i64.extend_i32_u (call $new_bytes (local.get $data) (local.get $len))))
+    (i32.store offset=48 (call $task (local.get $id)) (i32.const 1))
+    (call $restart (local.get $id) (i32.const 0) (i64.const 0) (i32.const 1)))
+
+  ;; Starts a handler for each packet the buffer completes. When `idle`, the
+  ;; buffered partial packet timed out.
+  (func $frame_packets (param $iface i32) (param $idle i32)
+    (local $best i32)
+    (local $start i32)
+    (local $end i32)
+    (local $ready i32)
+    (local $from i32)
+    (local $i i32)
+    (local $handler i32)
+    (block $done
+      (loop $again
+        (br_if $done (i32.eqz (call $buffered (local.get $iface))))
+        (local.set $best (i32.const 0))
+        (local.set $from (call $buffered (local.get $iface)))
+        (local.set $i (i32.const 0))
+        (block $handlers_done
+          (loop $next
+            (br_if $handlers_done
+              (i32.ge_u (local.get $i) (i32.load offset=8 (local.get $iface))))
+            (local.set $handler
+              (i32.add
+                (i32.load offset=4 (local.get $iface))
+                (i32.mul (local.get $i) (i32.const 48))))
+            (call $candidate (local.get $iface) (local.get $handler) (local.get $idle))
+            (if (global.get $complete)
+              (then
+                (if (i32.or
+                      (i32.eqz (local.get $best))
+                      (i32.lt_u (global.get $ready) (local.get $ready)))
+                  (then
+                    (local.set $best (local.get $handler))
+                    (local.set $start (global.get $packet_start))
+                    (local.set $end (global.get $packet_end))
+                    (local.set $ready (global.get $ready)))))
+              (else
+                (if (i32.lt_u (global.get $from) (local.get $from))
+                  (then (local.set $from (global.get $from))))))
+            (local.set $i (i32.add (local.get $i) (i32.const 1)))
+            (br $next)))
+
+        (if (local.get $best)
+          (then
+            (call $spawn_handler
+              (i32.load (local.get $best))
+              (i32.add (call $buffer (local.get $iface)) (local.get $start))
+              (i32.sub (local.get $end) (local.get $start)))
+            (call $drop (local.get $iface) (local.get $end))
+            (br $again)))
+        (if (local.get $from)
+          (then
+            (call $drop (local.get $iface) (local.get $from))
+            (br $again)))
+        (if (i32.gt_u (call $buffered (local.get $iface)) (global.get $MAX_PACKET))
+          (then
+            (call $drop (local.get $iface) (i32.const 1))
+            (br $again)))))
+    (if (local.get $idle)
+      (then (i32.store offset=16 (local.get $iface) (i32.const 0)))))
+
+  (func $interface (param $index i32) (result i32)
+    (i32.add (global.get $interfaces) (i32.shl (local.get $index) (i32.const 5))))
+
+  (func $receive
+    (local $i i32)
+    (local $iface i32)
+    (local $capacity i32)
+    (local $count i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (global.get $interface_count)))
+        (local.set $iface (call $interface (local.get $i)))
+        (block $drained
+          (loop $read
+            (local.set $capacity
+              (i32.sub
+                (i32.shl (global.get $MAX_PACKET) (i32.const 1))
+                (call $buffered (local.get $iface))))
+            (local.set $count
+              (call $host_read
+                (i32.add (i32.load (local.get $iface)) (i32.const 4))
+                (i32.load (i32.load (local.get $iface)))
+                (i32.add (call $buffer (local.get $iface)) (call $buffered (local.get $iface)))
+                (local.get $capacity)))
+            (br_if $drained (i32.le_s (local.get $count) (i32.const 0)))
+            (if (i32.gt_u (local.get $count) (local.get $capacity))
+              (then (local.set $count (local.get $capacity))))
+            (i32.store offset=16
+              (local.get $iface)
+              (i32.add (call $buffered (local.get $iface)) (local.get $count)))
+            (i64.store offset=24 (local.get $iface) (global.get $now))
+            (call $frame_packets (local.get $iface) (i32.const 0))
+            (br $read)))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+
+  ;; ---- Scheduling ----
+
+  ;; The waiting task due first, or -1.
+  (func $next_task (result i32)
+    (local $found i32)
+    (local $i i32)
+    (local $entry i32)
+    (local $best i32)
+    (local.set $found (i32.const -1))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (global.get $task_count)))
+        (local.set $entry (call $task (local.get $i)))
+        (if (i32.eq (i32.load (local.get $entry)) (global.get $WAITING))
+          (then
+            (if (i32.lt_s (local.get $found) (i32.const 0))
+              (then
+                (local.set $found (local.get $i))
+                (local.set $best (local.get $entry)))
+              (else
+                (if (i32.or
+                      (i64.lt_u
+                        (i64.load offset=24 (local.get $entry))
+                        (i64.load offset=24 (local.get $best)))
+                      (i32.and
+                        (i64.eq
+                          (i64.load offset=24 (local.get $entry))
+                          (i64.load offset=24 (local.get $best)))
+                        (i64.lt_u
+                          (i64.load offset=32 (local.get $entry))
+                          (i64.load offset=32 (local.get $best)))))
+                  (then
+                    (local.set $found (local.get $i))
+                    (local.set $best (local.get $entry))))))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.get $found))
+
+  ;; When something is next due, a task or a partial packet timing out, on
+  ;; the clock of millis(), or -1 when nothing is.
+  (func $next_wake (export "next_wake") (result i64)
+    (local $wake_at i64)
+    (local $id i32)
+    (local $i i32)
+    (local $iface i32)
+    (local $deadline i64)
+    (local.set $wake_at (i64.const -1))
+    (local.set $id (call $next_task))
+    (if (i32.ge_s (local.get $id) (i32.const 0))
+      (then (local.set $wake_at (i64.load offset=24 (call $task (local.get $id))))))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (global.get $interface_count)))
+        (local.set $iface (call $interface (local.get $i)))
+        (local.set $deadline
+          (i64.add (i64.load offset=24 (local.get $iface)) (global.get $FRAME_TIMEOUT)))
+        (if (i32.and
+              (i32.ne (call $buffered (local.get $iface)) (i32.const 0))
+              (i64.lt_u (local.get $deadline) (local.get $wake_at)))
+          (then (local.set $wake_at (local.get $deadline))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (local.get $wake_at))
+
+  ;; Handles what is due: partial packets that timed out, else the next
+  ;; task.
+  (func $step
+    (local $expired i32)
+    (local $i i32)
+    (local $iface i32)
+    (local $id i32)
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (global.get $interface_count)))
+        (local.set $iface (call $interface (local.get $i)))
+        (if (i32.and
+              (i32.ne (call $buffered (local.get $iface)) (i32.const 0))
+              (i64.le_u
+                (i64.add (i64.load offset=24 (local.get $iface)) (global.get $FRAME_TIMEOUT))
+                (global.get $now)))
+          (then
+            (call $frame_packets (local.get $iface) (i32.const 1))
+            (local.set $expired (i32.const 1))))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next)))
+    (if (local.get $expired)
+      (then (return)))
+    (local.set $id (call $next_task))
+    (if (i32.ge_s (local.get $id) (i32.const 0))
+      (then (call $resume (local.get $id)))))
+
+  ;; Starts the program, seeding rand() with `seed`.
+  (func (export "init") (param $seed i64)
+    (local $i i32)
+    (local $body i32)
+    (local $id i32)
+    (global.set $random_state (local.get $seed))
+    (block $done
+      (loop $next
+        (br_if $done (i32.ge_u (local.get $i) (global.get $declared_task_count)))
+        (local.set $body
+          (i32.load (i32.add (global.get $declared_tasks) (i32.shl (local.get $i) (i32.const 2)))))
+        (local.set $id (call $new_task (local.get $body) (i32.const 0)))
+        (i64.store offset=16 (call $task (local.get $id)) (call $body_interval (local.get $body)))
+        (call $restart (local.get $id) (i32.const 0) (i64.const 0) (i32.const 1))
+        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+        (br $next))))
+
+  ;; Receives bytes and runs the handlers and tasks that are due at the
+  ;; time millis() gives, the milliseconds since the program started.
+  (func (export "tick")
+    (local $millis i64)
+    (local $wake_at i64)
+    (local.set $millis (call $host_millis))
+    (if (i64.gt_u (local.get $millis) (global.get $now))
+      (then (global.set $now (local.get $millis))))
+    (loop $again
+      (call $receive)
+      (local.set $wake_at (call $next_wake))
+      (if (i32.and
+            (i64.ge_s (local.get $wake_at) (i64.const 0))
+            (i64.le_s (local.get $wake_at) (global.get $now)))
+        (then
+          (call $step)
+          (br $again)))))
+
+  ;; Runs the `when` handler `body` with the packet of `len` bytes the
+  ;; host wrote at `packet`.
+  (func $deliver (param $body i32) (param $len i32)
+    (if (i32.gt_u (local.get $len) (global.get $MAX_PACKET))
+      (then (call $fail (global.get $m_packet_too_long))))
+    (call $spawn_handler (local.get $body) (global.get $packet) (local.get $len)))
@@ -0,0 +1,52 @@
+use crate::token::Span;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    pub fn warning(message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            labels: vec![],
+        }
+    }
+
+    pub fn error(message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            labels: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, label: &str) -> Self {
+        self.labels.push((span, label.to_string()));
+        self
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}", severity, self.message)?;
+        for (span, label) in self.labels.iter() {
+            write!(f, "\n  --> {}: {}", span, label)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,871 @@
+use crate::analysis::{dispatch, walk_statements};
+use crate::ir::{
+    BinaryOperator, Block, BlockId, Body, BodyId, BodyKind, Builtin, Callee, Constant, Instruction,
+    Interface, Operand, Program, Record, Temp, Terminator, Variable,
+};
+use crate::parser::{Expression, FunctionCall, Guard, Literal, Pattern, Statement, AST};
+use std::collections::HashMap;
+
+/// Lowers a parsed program to its IR. Bodies are numbered in source order.
+///
+/// Names are resolved the way the interpreter looks them up: variables
+/// stored by the body, then its locals, then variables stored at the top
+/// level, records, functions and tasks.
+pub fn lower(program: &[AST]) -> Result<Program, String> {
+    let mut globals: Vec<String> = vec![];
+    let mut records = vec![];
+    let mut body_ids = HashMap::new();
+    let mut functions = HashMap::new();
+    let mut tasks = HashMap::new();
+    for (index, ast) in program.iter().enumerate() {
+        match ast {
+            AST::Store { var_list } => {
+                for name in var_list {
+                    if !globals.contains(name) {
+                        globals.push(name.clone());
+                    }
+                }
+            }
+            AST::Record {
+                name,
+                length,
+                data_size,
+            } => records.push(Record {
+                name: name.clone(),
+                length: *length,
+                data_size: *data_size,
+            }),
+            AST::Function {
+                name, arguments, ..
+            } => {
+                functions.insert(name.as_str(), (body_ids.len(), arguments.len()));
+                body_ids.insert(index, body_ids.len());
+            }
+            AST::Task { name, .. } => {
+                tasks.insert(name.as_str(), body_ids.len());
+                body_ids.insert(index, body_ids.len());
+            }
+            AST::When { .. } => {
+                body_ids.insert(index, body_ids.len());
+            }
+        }
+    }
+
+    let names = Names {
+        globals: &globals,
+        records: &records,
+        functions,
+        tasks,
+    };
+
+    let mut bodies = vec![];
+    for ast in program {
+        let (kind, fixed) = match ast {
+            AST::Function {
+                name, arguments, ..
+            } => (
+                BodyKind::Function {
+                    name: name.clone(),
+                    parameters: arguments.len(),
+                },
+                arguments.clone(),
+            ),
+            AST::Task {
+                name, interval_ms, ..
+            } => (
+                BodyKind::Task {
+                    name: name.clone(),
+                    interval_ms: *interval_ms,
+                },
+                vec![],
+            ),
+            AST::When {
+                interface,
+                packet,
+                guard,
+                ..
+            } => (
+                BodyKind::When {
+                    interface: interface.clone(),
+                    guard: guard.clone(),
+                },
+                vec![packet.clone()],
+            ),
+            AST::Record { .. } | AST::Store { .. } => continue,
+        };
+
+        let body = Builder::new(&names, fixed, ast.body(), ast.lines())
+            .lower(kind, ast.body())
+            .map_err(|err| format!("{} in {}", err, describe(ast)))?;
+        bodies.push(body);
+    }
+
+    let interfaces = dispatch::analyze(program)
+        .0
+        .into_iter()
+        .map(|dispatch| Interface {
+            name: dispatch.interface,
+            handlers: dispatch
+                .handlers
+                .iter()
+                .map(|index| body_ids[index])
+                .collect(),
+        })
+        .collect();
+
+    Ok(Program {
+        globals,
+        records,
+        bodies,
+        interfaces,
+    })
+}
+
+fn describe(ast: &AST) -> String {
+    match ast {
+        AST::Function { name, .. } => format!("function {}", name),
+        AST::Task { name, .. } => format!("task {}", name),
+        AST::When {
+            interface,
+            packet,
+            guard,
+            ..
+        } => format!("when \"{}\" => {}{}", interface, packet, guard),
+        AST::Record { name, .. } => format!("record {}", name),
+        AST::Store { .. } => "store".to_string(),
+    }
+}
+
+/// The program-wide names a body can refer to.
+struct Names<'a> {
+    globals: &'a [String],
+    records: &'a [Record],
+    /// Body and parameter count of each function.
+    functions: HashMap<&'a str, (BodyId, usize)>,
+    tasks: HashMap<&'a str, BodyId>,
+}
+
+/// A block being built: its instructions and terminator with their lines.
+type Pending = (Vec<(Instruction, usize)>, Option<(Terminator, usize)>);
+
+/// Builds the blocks of one body.
+struct Builder<'a> {
+    names: &'a Names<'a>,
+    locals: Vec<String>,
+    stored: Vec<String>,
+    temps: usize,
+    blocks: Vec<Pending>,
+    current: BlockId,
+    /// The line of each statement of the body, by address.
+    lines: HashMap<*const Statement, usize>,
+    /// The line of the statement being lowered.
+    line: usize,
+}
+
+impl<'a> Builder<'a> {
+    /// Collects the variables of a body: `fixed` names the parameters or the
+    /// packet, followed by the `for` variables and the assigned names that
+    /// are not stored.
+    fn new(names: &'a Names<'a>, fixed: Vec<String>, body: &[Statement], lines: &[usize]) -> Self {
+        let mut statements = vec![];
+        walk_statements(body, &mut |statement| {
+            statements.push(statement as *const Statement)
+        });
+        let lines = statements.into_iter().zip(lines.iter().copied()).collect();
+
+        let mut locals = fixed;
+        let mut stored = vec![];
+        let mut assigned = vec![];
+        walk_statements(body, &mut |statement| match statement {
+            Statement::Store { var_list } => stored.extend(var_list.iter().cloned()),
+            Statement::For { var, .. } => locals.push(var.clone()),
+            Statement::Assignment { variable, .. }
+            | Statement::AssignmentSum { variable, .. }
+            | Statement::AssignmentMinus { variable, .. }
+            | Statement::AssignmentMult { variable, .. }
+            | Statement::AssignmentDiv { variable, .. }
+            | Statement::AssignmentMod { variable, .. } => assigned.push(variable.clone()),
+            Statement::Unpack { targets, .. } => {
+                assigned.extend(targets.iter().map(|(name, _)| name.clone()))
+            }
+            _ => {}
+        });
+        locals.extend(
+            assigned
+                .into_iter()
+                .filter(|name| !names.globals.contains(name) && !stored.contains(name)),
+        );
+
+        Self {
+            names,
+            locals: dedup(locals),
+            stored: dedup(stored),
+            temps: 0,
+            blocks: vec![(vec![], None)],
+            current: 0,
+            lines,
+            line: 0,
+        }
+    }
+
+    fn lower(mut self, kind: BodyKind, body: &[Statement]) -> Result<Body, String> {
+        self.statements(body)?;
+        self.terminate(Terminator::Return(Operand::Constant(Constant::Nil)));
+
+        Ok(Body {
+            kind,
+            locals: self.locals,
+            stored: self.stored,
+            temps: self.temps,
+            blocks: reachable_blocks(self.blocks),
+        })
+    }
+
+    fn temp(&mut self) -> Temp {
+        self.temps += 1;
+        self.temps - 1
+    }
+
+    fn block(&mut self) -> BlockId {
+        self.blocks.push((vec![], None));
+        self.blocks.len() - 1
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let line = self.line;
+        self.blocks[self.current].0.push((instruction, line));
+    }
+
+    /// Ends the current block, unless a `return` already did.
+    fn terminate(&mut self, terminator: Terminator) {
+        let line = self.line;
+        let block = &mut self.blocks[self.current];
+        if block.1.is_none() {
+            block.1 = Some((terminator, line));
+        }
+    }
+
+    fn jump_to(&mut self, target: BlockId) {
+        self.terminate(Terminator::Jump(target));
+        self.current = target;
+    }
+
+    fn branch(&mut self, condition: Operand, then: BlockId, otherwise: BlockId) {
+        self.terminate(Terminator::Branch {
+            condition,
+            then,
+            otherwise,
+        });
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), String> {
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), String> {
+        if let Some(line) = self.lines.get(&(statement as *const Statement)) {
+            self.line = *line;
+        }
+
+        match statement {
+            Statement::Assignment {
+                variable,
+                expression,
+            } => {
+                let value = self.expression(expression)?;
+                let variable = self.write(variable)?;
+                self.emit(Instruction::Store { variable, value });
+            }
+            Statement::AssignmentSum {
+                variable,
+                expression,
+            } => self.update(variable, BinaryOperator::Sum, expression)?,
+            Statement::AssignmentMinus {
+                variable,
+                expression,
+            } => self.update(variable, BinaryOperator::Minus, expression)?,
+            Statement::AssignmentMult {
+                variable,
+                expression,
+            } => self.update(variable, BinaryOperator::Multiply, expression)?,
+            Statement::AssignmentDiv {
+                variable,
+                expression,
+            } => self.update(variable, BinaryOperator::Division, expression)?,
+            Statement::AssignmentMod {
+                variable,
+                expression,
+            } => self.update(variable, BinaryOperator::Modulus, expression)?,
+            Statement::Unpack {
+                targets,
+                expression,
+            } => {
+                let bytes = self.expression(expression)?;
+                let mut fields = vec![];
+                for (_, guard) in targets {
+                    match guard {
+                        Guard::Numeric { width, endianness } => {
+                            fields.push((self.temp(), *width, *endianness))
+                        }
+                        _ => unreachable!("matching assignments only hold numeric guards"),
+                    }
+                }
+                self.emit(Instruction::Unpack {
+                    fields: fields.clone(),
+                    bytes,
+                });
+                for ((name, _), (temp, _, _)) in targets.iter().zip(fields) {
+                    let variable = self.write(name)?;
+                    self.emit(Instruction::Store {
+                        variable,
+                        value: Operand::Temp(temp),
+                    });
+                }
+            }
+            Statement::Delay { time } => {
+                let resume = self.block();
+                self.terminate(Terminator::Suspend {
+                    delay_ms: *time,
+                    resume,
+                });
+                self.current = resume;
+            }
+            Statement::Store { .. } => {}
+            Statement::If {
+                condition,
+                body,
+                elif,
+                else_body,
+            } => {
+                let join = self.block();
+                let arms = std::iter::once((condition, body))
+                    .chain(elif.iter().map(|(condition, body)| (condition, body)));
+                for (condition, body) in arms {
+                    let condition = self.expression(condition)?;
+                    let then = self.block();
+                    let otherwise = self.block();
+                    self.branch(condition, then, otherwise);
+
+                    self.current = then;
+                    self.statements(body)?;
+                    self.terminate(Terminator::Jump(join));
+                    self.current = otherwise;
+                }
+                self.statements(else_body)?;
+                self.jump_to(join);
+            }
+            Statement::For {
+                var,
+                collection,
+                body,
+            } => {
+                let collection = self.read(collection)?;
+                let items = self.temp();
+                self.emit(Instruction::Items {
+                    dest: items,
+                    collection,
+                });
+                let length = self.temp();
+                self.emit(Instruction::Length {
+                    dest: length,
+                    list: Operand::Temp(items),
+                });
+                let position = self.temp();
+                self.emit(Instruction::Copy {
+                    dest: position,
+                    value: Operand::Constant(Constant::Integer(0)),
+                });
+
+                let header = self.block();
+                self.jump_to(header);
+                let more = self.temp();
+                self.emit(Instruction::Binary {
+                    dest: more,
+                    operator: BinaryOperator::Less,
+                    lhs: Operand::Temp(position),
+                    rhs: Operand::Temp(length),
+                });
+                let next = self.block();
+                let exit = self.block();
+                self.branch(Operand::Temp(more), next, exit);
+
+                self.current = next;
+                let item = self.temp();
+                self.emit(Instruction::Index {
+                    dest: item,
+                    list: Operand::Temp(items),
+                    index: Operand::Temp(position),
+                });
+                let local = self.locals.iter().position(|name| name == var).unwrap();
+                self.emit(Instruction::Store {
+                    variable: Variable::Local(local),
+                    value: Operand::Temp(item),
+                });
+                self.emit(Instruction::Binary {
+                    dest: position,
+                    operator: BinaryOperator::Sum,
+                    lhs: Operand::Temp(position),
+                    rhs: Operand::Constant(Constant::Integer(1)),
+                });
+                self.statements(body)?;
+                self.terminate(Terminator::Jump(header));
+                self.current = exit;
+            }
+            Statement::While { condition, body } => {
+                let header = self.block();
+                self.jump_to(header);
+                let condition = self.expression(condition)?;
+                let next = self.block();
+                let exit = self.block();
+                self.branch(condition, next, exit);
+
+                self.current = next;
+                self.statements(body)?;
+                self.terminate(Terminator::Jump(header));
+                self.current = exit;
+            }
+            Statement::Match {
+                target,
+                cases,
+                default,
+                ..
+            } => {
+                let target = self.expression(target)?;
+                let join = self.block();
+                for arm in cases {
+                    let body = self.block();
+                    for (pattern, _) in arm.patterns.iter() {
+                        let matched = self.temp();
+                        self.emit(match pattern {
+                            Pattern::Literal(literal) => Instruction::Binary {
+                                dest: matched,
+                                operator: BinaryOperator::Equal,
+                                lhs: target.clone(),
+                                rhs: Operand::Constant(constant(literal)),
+                            },
+                            Pattern::Range(start, end) => Instruction::InRange {
+                                dest: matched,
+                                value: target.clone(),
+                                start: *start as i64,
+                                end: *end as i64,
+                            },
+                        });
+                        let otherwise = self.block();
+                        self.branch(Operand::Temp(matched), body, otherwise);
+                        self.current = otherwise;
+                    }
+
+                    let next = self.current;
+                    self.current = body;
+                    self.statements(&arm.body)?;
+                    self.terminate(Terminator::Jump(join));
+                    self.current = next;
+                }
+                if let Some(default) = default {
+                    self.statements(default)?;
+                }
+                self.jump_to(join);
+            }
+            Statement::Return { expression } => {
+                let value = self.expression(expression)?;
+                self.terminate(Terminator::Return(value));
+                // Whatever follows a return is unreachable.
+                self.current = self.block();
+            }
+            Statement::FunctionCall(function_call) => {
+                self.function_call(function_call)?;
+            }
+            Statement::ElementAssignment {
+                record,
+                index,
+                expression,
+                ..
+            } => {
+                let record = self.record(record)?;
+                let index = self.expression(index)?;
+                let value = self.expression(expression)?;
+                self.emit(Instruction::SetElement {
+                    record,
+                    index,
+                    value,
+                });
+            }
+            Statement::Start {
+                function_call,
+                interval,
+            } => {
+                let (target, arguments) = function_call
+                    .arguments
+                    .split_first()
+                    .ok_or("start requires a function or a task")?;
+                let target = self.expression(target)?;
+                self.start(target, arguments, Some(interval))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn update(
+        &mut self,
+        variable: &str,
+        operator: BinaryOperator,
+        expression: &Expression,
+    ) -> Result<(), String> {
+        let lhs = self.read(variable)?;
+        let rhs = self.expression(expression)?;
+        let dest = self.temp();
+        self.emit(Instruction::Binary {
+            dest,
+            operator,
+            lhs,
+            rhs,
+        });
+        let variable = self.write(variable)?;
+        self.emit(Instruction::Store {
+            variable,
+            value: Operand::Temp(dest),
+        });
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<Operand, String> {
+        let (operator, lhs, rhs) = match expression {
+            Expression::Literal(literal) => return Ok(Operand::Constant(constant(literal))),
+            Expression::Variable(name) => return self.read(name),
+            Expression::List(items) => {
+                let items = self.expressions(items)?;
+                let dest = self.temp();
+                self.emit(Instruction::List { dest, items });
+                return Ok(Operand::Temp(dest));
+            }
+            Expression::Element { record, index, .. } => {
+                let record = self.record(record)?;
+                let index = self.expression(index)?;
+                let dest = self.temp();
+                self.emit(Instruction::Element {
+                    dest,
+                    record,
+                    index,
+                });
+                return Ok(Operand::Temp(dest));
+            }
+            Expression::And(lhs, rhs) => return self.logical(lhs, rhs, false),
+            Expression::Or(lhs, rhs) => return self.logical(lhs, rhs, true),
+            Expression::Not(operand) => {
+                let operand = self.expression(operand)?;
+                let dest = self.temp();
+                self.emit(Instruction::Not { dest, operand });
+                return Ok(Operand::Temp(dest));
+            }
+            Expression::Guard(operand, guard) => {
+                let value = self.expression(operand)?;
+                let (width, endianness) = match guard {
+                    Guard::Numeric { width, endianness } => (*width, *endianness),
+                    _ => return Err(format!("Cannot pack a value with the guard {}", guard)),
+                };
+                let dest = self.temp();
+                self.emit(Instruction::Pack {
+                    dest,
+                    value,
+                    width,
+                    endianness,
+                });
+                return Ok(Operand::Temp(dest));
+            }
+            Expression::Time(target, interval) => {
+                let (target, function_call) = match target.as_ref() {
+                    Expression::Pipe(target, function_call) => (Some(target), function_call),
+                    Expression::FunctionCall(function_call) => (None, function_call),
+                    _ => return Err("The @ operator can only follow a call to start".to_string()),
+                };
+                if function_call.name != "start" {
+                    return Err("The @ operator can only follow a call to start".to_string());
+                }
+                let (target, arguments) = match target {
+                    Some(target) => (target.as_ref(), function_call.arguments.as_slice()),
+                    None => function_call
+                        .arguments
+                        .split_first()
+                        .ok_or("start requires a function or a task")?,
+                };
+                let target = self.expression(target)?;
+                return self.start(target, arguments, Some(interval));
+            }
+            Expression::Pipe(target, function_call) => {
+                let target = self.expression(target)?;
+                if function_call.name == "start" {
+                    return self.start(target, &function_call.arguments, None);
+                }
+                return self.call(function_call, vec![target]);
+            }
+            Expression::FunctionCall(function_call) => return self.function_call(function_call),
+            Expression::Equal(lhs, rhs) => (BinaryOperator::Equal, lhs, rhs),
+            Expression::NotEqual(lhs, rhs) => (BinaryOperator::NotEqual, lhs, rhs),
+            Expression::Less(lhs, rhs) => (BinaryOperator::Less, lhs, rhs),
+            Expression::Greater(lhs, rhs) => (BinaryOperator::Greater, lhs, rhs),
+            Expression::LessOrEqual(lhs, rhs) => (BinaryOperator::LessOrEqual, lhs, rhs),
+            Expression::GreaterOrEqual(lhs, rhs) => (BinaryOperator::GreaterOrEqual, lhs, rhs),
+            Expression::Xor(lhs, rhs) => (BinaryOperator::Xor, lhs, rhs),
+            Expression::Sum(lhs, rhs) => (BinaryOperator::Sum, lhs, rhs),
+            Expression::Minus(lhs, rhs) => (BinaryOperator::Minus, lhs, rhs),
+            Expression::Multiply(lhs, rhs) => (BinaryOperator::Multiply, lhs, rhs),
+            Expression::Division(lhs, rhs) => (BinaryOperator::Division, lhs, rhs),
+            Expression::Modulus(lhs, rhs) => (BinaryOperator::Modulus, lhs, rhs),
+        };
+
+        let lhs = self.expression(lhs)?;
+        let rhs = self.expression(rhs)?;
+        let dest = self.temp();
+        self.emit(Instruction::Binary {
+            dest,
+            operator,
+            lhs,
+            rhs,
+        });
+        Ok(Operand::Temp(dest))
+    }
+
+    fn expressions(&mut self, expressions: &[Expression]) -> Result<Vec<Operand>, String> {
+        expressions
+            .iter()
+            .map(|expression| self.expression(expression))
+            .collect()
+    }
+
+    /// `and` (`or` when `is_or`) only evaluates its right side when the left
+    /// one does not decide the result. Both sides must be booleans.
+    fn logical(
+        &mut self,
+        lhs: &Expression,
+        rhs: &Expression,
+        is_or: bool,
+    ) -> Result<Operand, String> {
+        let dest = self.temp();
+        let lhs = self.expression(lhs)?;
+        let right = self.block();
+        let when_true = self.block();
+        let when_false = self.block();
+        let join = self.block();
+        if is_or {
+            self.branch(lhs, when_true, right);
+        } else {
+            self.branch(lhs, right, when_false);
+        }
+
+        self.current = right;
+        let rhs = self.expression(rhs)?;
+        self.branch(rhs, when_true, when_false);
+
+        for (block, value) in [(when_true, true), (when_false, false)] {
+            self.current = block;
+            self.emit(Instruction::Copy {
+                dest,
+                value: Operand::Constant(Constant::Boolean(value)),
+            });
+            self.terminate(Terminator::Jump(join));
+        }
+        self.current = join;
+        Ok(Operand::Temp(dest))
+    }
+
+    /// `f(args...)`, where `start(target, args...)` starts a task.
+    fn function_call(&mut self, function_call: &FunctionCall) -> Result<Operand, String> {
+        if function_call.name != "start" {
+            return self.call(function_call, vec![]);
+        }
+        let (target, arguments) = function_call
+            .arguments
+            .split_first()
+            .ok_or("start requires a function or a task")?;
+        let target = self.expression(target)?;
+        self.start(target, arguments, None)
+    }
+
+    /// Calls a builtin or a function of the program with `args` followed by
+    /// the arguments of the call.
+    fn call(
+        &mut self,
+        function_call: &FunctionCall,
+        mut args: Vec<Operand>,
+    ) -> Result<Operand, String> {
+        args.extend(self.expressions(&function_call.arguments)?);
+        let name = function_call.name.as_str();
+        let callee = match (Builtin::from_name(name), self.names.functions.get(name)) {
+            (Some(builtin), _) => Callee::Builtin(builtin),
+            (None, Some((id, parameters))) => {
+                if *parameters != args.len() {
+                    return Err(format!(
+                        "The function '{}' takes {} argument(s) but {} were given",
+                        name,
+                        parameters,
+                        args.len()
+                    ));
+                }
+                Callee::Function(*id)
+            }
+            (None, None) => return Err(format!("Undefined function '{}'", name)),
+        };
+
+        let dest = self.temp();
+        self.emit(Instruction::Call { dest, callee, args });
+        Ok(Operand::Temp(dest))
+    }
+
+    fn start(
+        &mut self,
+        target: Operand,
+        arguments: &[Expression],
+        interval: Option<&Expression>,
+    ) -> Result<Operand, String> {
+        let args = self.expressions(arguments)?;
+        let interval = interval
+            .map(|interval| self.expression(interval))
+            .transpose()?;
+        let dest = self.temp();
+        self.emit(Instruction::Start {
+            dest,
+            target,
+            args,
+            interval,
+        });
+        Ok(Operand::Temp(dest))
+    }
+
+    fn read(&mut self, name: &str) -> Result<Operand, String> {
+        let variable = self
+            .variable(name)
+            .or_else(|| self.record(name).ok().map(Variable::Record));
+        let variable = match variable {
+            Some(variable) => variable,
+            None => {
+                if let Some((id, _)) = self.names.functions.get(name) {
+                    return Ok(Operand::Constant(Constant::Function(*id)));
+                }
+                if let Some(id) = self.names.tasks.get(name) {
+                    return Ok(Operand::Constant(Constant::Task(*id)));
+                }
+                return Err(format!("Undefined variable '{}'", name));
+            }
+        };
+
+        let dest = self.temp();
+        self.emit(Instruction::Load { dest, variable });
+        Ok(Operand::Temp(dest))
+    }
+
+    fn write(&self, name: &str) -> Result<Variable, String> {
+        self.variable(name)
+            .ok_or_else(|| format!("Cannot assign to '{}'", name))
+    }
+
+    /// Resolves the name of a variable, leaving out records, functions and
+    /// tasks.
+    fn variable(&self, name: &str) -> Option<Variable> {
+        let position = |names: &[String]| names.iter().position(|n| n == name);
+        position(&self.stored)
+            .map(Variable::Stored)
+            .or_else(|| position(&self.locals).map(Variable::Local))
+            .or_else(|| position(self.names.globals).map(Variable::Global))
+    }
+
+    fn record(&self, name: &str) -> Result<usize, String> {
+        self.names
+            .records
+            .iter()
+            .position(|record| record.name == name)
+            .ok_or_else(|| format!("Undefined record '{}'", name))
+    }
+}
+
+fn constant(literal: &Literal) -> Constant {
+    match literal {
+        Literal::Integer(value) => Constant::Integer(*value as i64),
+        Literal::String(data) => Constant::String(data.clone()),
+        Literal::Bytes(data) => Constant::Bytes(data.clone()),
+        Literal::Boolean(value) => Constant::Boolean(*value),
+        Literal::Nil => Constant::Nil,
+    }
+}
+
+fn dedup(names: Vec<String>) -> Vec<String> {
+    let mut unique: Vec<String> = vec![];
+    for name in names {
+        if !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+/// Drops the blocks that cannot be reached from the entry and numbers the
+/// others in reverse postorder, so that a block comes before the blocks it
+/// leads to, except through loops, and the taken side of a branch comes
+/// first.
+fn reachable_blocks(blocks: Vec<Pending>) -> Vec<Block> {
+    let successors = |id: BlockId| match &blocks[id].1 {
+        Some((terminator, _)) => terminator.successors(),
+        None => vec![],
+    };
+
+    let mut visited = vec![false; blocks.len()];
+    let mut postorder = vec![];
+    let mut stack = vec![(0, successors(0))];
+    visited[0] = true;
+    while let Some((id, pending)) = stack.last_mut() {
+        match pending.pop() {
+            Some(next) if !visited[next] => {
+                visited[next] = true;
+                let next_successors = successors(next);
+                stack.push((next, next_successors));
+            }
+            Some(_) => {}
+            None => {
+                postorder.push(*id);
+                stack.pop();
+            }
+        }
+    }
+
+    let order: Vec<BlockId> = postorder.into_iter().rev().collect();
+    let mut numbers = vec![0; blocks.len()];
+    for (number, id) in order.iter().enumerate() {
+        numbers[*id] = number;
+    }
+
+    let mut blocks: Vec<Option<_>> = blocks.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|id| blocks[id].take().unwrap())
+        .map(|(instructions, terminator)| {
+            let (terminator, line) = terminator.expect("reachable blocks are terminated");
+            let (instructions, mut lines): (Vec<_>, Vec<_>) = instructions.into_iter().unzip();
+            lines.push(line);
+
+            let terminator = match terminator {
+                Terminator::Jump(target) => Terminator::Jump(numbers[target]),
+                Terminator::Branch {
+                    condition,
+                    then,
+                    otherwise,
+                } => Terminator::Branch {
+                    condition,
+                    then: numbers[then],
+                    otherwise: numbers[otherwise],
+                },
+                Terminator::Suspend { delay_ms, resume } => Terminator::Suspend {
+                    delay_ms,
+                    resume: numbers[resume],
+                },
+                Terminator::Return(value) => Terminator::Return(value),
+            };
+            Block {
+                instructions,
+                terminator,
+                lines,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,696 @@
+//! The lowered form of a program that code generation works from: every
+//! body is a list of basic blocks of simple instructions over numbered
+//! temporaries, ending in explicit jumps, returns and `@` suspensions.
+
+use crate::parser::{Endianness, Guard};
+use std::fmt::{Display, Formatter};
+
+mod lower;
+mod verify;
+
+pub use lower::lower;
+pub use verify::verify;
+
+pub type BodyId = usize;
+pub type BlockId = usize;
+pub type Temp = usize;
+
+#[derive(Debug)]
+pub struct Program {
+    /// Variables stored at the top level.
+    pub globals: Vec<String>,
+    pub records: Vec<Record>,
+    pub bodies: Vec<Body>,
+    /// The `when` handlers of each interface, in dispatch order.
+    pub interfaces: Vec<Interface>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub name: String,
+    pub length: usize,
+    pub data_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub handlers: Vec<BodyId>,
+}
+
+/// A function, task or `when` handler. Block 0 is the entry.
+#[derive(Debug)]
+pub struct Body {
+    pub kind: BodyKind,
+    /// Local variables, starting with the parameters of a function or the
+    /// packet of a handler. Locals start as `nil`.
+    pub locals: Vec<String>,
+    /// Variables stored by this body.
+    pub stored: Vec<String>,
+    /// How many temporaries the blocks use.
+    pub temps: usize,
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone)]
+pub enum BodyKind {
+    Function {
+        name: String,
+        parameters: usize,
+    },
+    Task {
+        name: String,
+        interval_ms: usize,
+    },
+    /// Receives its packet in local 0.
+    When {
+        interface: String,
+        guard: Guard,
+    },
+}
+
+#[derive(Debug)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+    /// The source line of each instruction, then of the terminator.
+    pub lines: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Constant(Constant),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    String(Vec<u8>),
+    Bytes(Vec<u8>),
+    Function(BodyId),
+    /// The handle of a declared task.
+    Task(BodyId),
+}
+
+/// Where a named value lives, by index into the list that holds its name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variable {
+    Local(usize),
+    Stored(usize),
+    Global(usize),
+    /// A record, read as a list of its elements.
+    Record(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Sum,
+    Minus,
+    Multiply,
+    Division,
+    Modulus,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+    Xor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Builtin {
+    Print,
+    Send,
+    Millis,
+    Rand,
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Builtin(Builtin),
+    Function(BodyId),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Copy {
+        dest: Temp,
+        value: Operand,
+    },
+    Load {
+        dest: Temp,
+        variable: Variable,
+    },
+    Store {
+        variable: Variable,
+        value: Operand,
+    },
+    Not {
+        dest: Temp,
+        operand: Operand,
+    },
+    Binary {
+        dest: Temp,
+        operator: BinaryOperator,
+        lhs: Operand,
+        rhs: Operand,
+    },
+    List {
+        dest: Temp,
+        items: Vec<Operand>,
+    },
+    /// `value::N`: the integer as `width` bytes.
+    Pack {
+        dest: Temp,
+        value: Operand,
+        width: usize,
+        endianness: Endianness,
+    },
+    /// `[a, b::2] = bytes`: fails unless `bytes` is exactly as long as the
+    /// fields together.
+    Unpack {
+        fields: Vec<(Temp, usize, Endianness)>,
+        bytes: Operand,
+    },
+    Element {
+        dest: Temp,
+        record: usize,
+        index: Operand,
+    },
+    SetElement {
+        record: usize,
+        index: Operand,
+        value: Operand,
+    },
+    /// The items a `for` loop visits, as a list.
+    Items {
+        dest: Temp,
+        collection: Operand,
+    },
+    Length {
+        dest: Temp,
+        list: Operand,
+    },
+    Index {
+        dest: Temp,
+        list: Operand,
+        index: Operand,
+    },
+    /// Whether `value` is an integer in `start..=end`.
+    InRange {
+        dest: Temp,
+        value: Operand,
+        start: i64,
+        end: i64,
+    },
+    Call {
+        dest: Temp,
+        callee: Callee,
+        args: Vec<Operand>,
+    },
+    /// `target.start(args...) @ interval`, as the `start` builtin.
+    Start {
+        dest: Temp,
+        target: Operand,
+        args: Vec<Operand>,
+        interval: Option<Operand>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockId),
+    /// Goes to `then` when `condition` is `true` and to `otherwise` when it
+    /// is `false`; fails on anything else.
+    Branch {
+        condition: Operand,
+        then: BlockId,
+        otherwise: BlockId,
+    },
+    /// An `@` delay: the body waits and then goes on at `resume`.
+    Suspend {
+        delay_ms: usize,
+        resume: BlockId,
+    },
+    Return(Operand),
+}
+
+impl Instruction {
+    /// The temporaries this instruction writes.
+    pub fn defined(&self) -> Vec<Temp> {
+        match self {
+            Instruction::Copy { dest, .. }
+            | Instruction::Load { dest, .. }
+            | Instruction::Not { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::List { dest, .. }
+            | Instruction::Pack { dest, .. }
+            | Instruction::Element { dest, .. }
+            | Instruction::Items { dest, .. }
+            | Instruction::Length { dest, .. }
+            | Instruction::Index { dest, .. }
+            | Instruction::InRange { dest, .. }
+            | Instruction::Call { dest, .. }
+            | Instruction::Start { dest, .. } => vec![*dest],
+            Instruction::Unpack { fields, .. } => fields.iter().map(|field| field.0).collect(),
+            Instruction::Store { .. } | Instruction::SetElement { .. } => vec![],
+        }
+    }
+
+    /// The operands this instruction reads.
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::Copy { value, .. }
+            | Instruction::Store { value, .. }
+            | Instruction::Pack { value, .. }
+            | Instruction::InRange { value, .. } => vec![value],
+            Instruction::Not { operand, .. } => vec![operand],
+            Instruction::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Instruction::List { items, .. } => items.iter().collect(),
+            Instruction::Unpack { bytes, .. } => vec![bytes],
+            Instruction::Element { index, .. } => vec![index],
+            Instruction::SetElement { index, value, .. } => vec![index, value],
+            Instruction::Items { collection, .. } => vec![collection],
+            Instruction::Length { list, .. } => vec![list],
+            Instruction::Index { list, index, .. } => vec![list, index],
+            Instruction::Call { args, .. } => args.iter().collect(),
+            Instruction::Start {
+                target,
+                args,
+                interval,
+                ..
+            } => std::iter::once(target)
+                .chain(args)
+                .chain(interval.as_ref())
+                .collect(),
+            Instruction::Load { .. } => vec![],
+        }
+    }
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch {
+                then, otherwise, ..
+            } => vec![*then, *otherwise],
+            Terminator::Suspend { resume, .. } => vec![*resume],
+            Terminator::Return(_) => vec![],
+        }
+    }
+}
+
+impl Program {
+    /// The bodies the runtime runs on its own: the `when` handlers, the
+    /// tasks and the functions a `start` names.
+    pub fn run_bodies(&self) -> Vec<BodyId> {
+        let mut bodies: Vec<BodyId> = (0..self.bodies.len())
+            .filter(|&id| !matches!(self.bodies[id].kind, BodyKind::Function { .. }))
+            .collect();
+        for body in self.bodies.iter() {
+            for instruction in body.blocks.iter().flat_map(|block| &block.instructions) {
+                if let Instruction::Start {
+                    target: Operand::Constant(Constant::Function(id)),
+                    ..
+                } = instruction
+                {
+                    if !bodies.contains(id) {
+                        bodies.push(*id);
+                    }
+                }
+            }
+        }
+        bodies
+    }
+}
+
+impl Body {
+    pub fn name(&self) -> String {
+        match &self.kind {
+            BodyKind::Function { name, .. } | BodyKind::Task { name, .. } => name.clone(),
+            BodyKind::When { interface, guard } => {
+                format!("when \"{}\" => {}{}", interface, self.locals[0], guard)
+            }
+        }
+    }
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        Some(match name {
+            "print" => Builtin::Print,
+            "send" => Builtin::Send,
+            "millis" => Builtin::Millis,
+            "rand" => Builtin::Rand,
+            "stop" => Builtin::Stop,
+            _ => return None,
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Print => "print",
+            Builtin::Send => "send",
+            Builtin::Millis => "millis",
+            Builtin::Rand => "rand",
+            Builtin::Stop => "stop",
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BinaryOperator::Sum => "add",
+            BinaryOperator::Minus => "sub",
+            BinaryOperator::Multiply => "mul",
+            BinaryOperator::Division => "div",
+            BinaryOperator::Modulus => "mod",
+            BinaryOperator::Equal => "eq",
+            BinaryOperator::NotEqual => "ne",
+            BinaryOperator::Less => "lt",
+            BinaryOperator::Greater => "gt",
+            BinaryOperator::LessOrEqual => "le",
+            BinaryOperator::GreaterOrEqual => "ge",
+            BinaryOperator::Xor => "xor",
+        }
+    }
+}
+
+/// Writes a string constant between double quotes, with the bytes that are
+/// not printable as `\xNN` escapes.
+pub fn quote(data: &[u8]) -> String {
+    let mut quoted = "\"".to_string();
+    for &b in data {
+        match b {
+            0x20..=0x7e if b != b'"' && b != b'\\' => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes a body's view of the program, which names its variables.
+struct Named<'a, T> {
+    program: &'a Program,
+    body: &'a Body,
+    item: &'a T,
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for record in self.records.iter() {
+            writeln!(
+                f,
+                "record {}[{}, {}]",
+                record.name, record.length, record.data_size
+            )?;
+        }
+        if !self.globals.is_empty() {
+            writeln!(f, "store {}", self.globals.join(", "))?;
+        }
+        for interface in self.interfaces.iter() {
+            let handlers: Vec<String> = interface
+                .handlers
+                .iter()
+                .map(|&id| self.bodies[id].name())
+                .collect();
+            writeln!(
+                f,
+                "dispatch \"{}\": {}",
+                interface.name,
+                handlers.join(", ")
+            )?;
+        }
+
+        for body in self.bodies.iter() {
+            writeln!(f)?;
+            match &body.kind {
+                BodyKind::Function { name, parameters } => writeln!(
+                    f,
+                    "function {}({})",
+                    name,
+                    body.locals[..*parameters].join(", ")
+                )?,
+                BodyKind::Task { name, interval_ms } => {
+                    writeln!(f, "task {} @ {}", name, interval_ms)?
+                }
+                BodyKind::When { .. } => writeln!(f, "{}", body.name())?,
+            }
+            if !body.locals.is_empty() {
+                writeln!(f, "  locals {}", body.locals.join(", "))?;
+            }
+            if !body.stored.is_empty() {
+                writeln!(f, "  store {}", body.stored.join(", "))?;
+            }
+            for (id, block) in body.blocks.iter().enumerate() {
+                writeln!(f, "b{}:", id)?;
+                for instruction in block.instructions.iter() {
+                    let named = Named {
+                        program: self,
+                        body,
+                        item: instruction,
+                    };
+                    writeln!(f, "  {}", named)?;
+                }
+                let named = Named {
+                    program: self,
+                    body,
+                    item: &block.terminator,
+                };
+                writeln!(f, "  {}", named)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Named<'_, Operand> {
+    fn new<'a>(program: &'a Program, body: &'a Body, item: &'a Operand) -> Named<'a, Operand> {
+        Named {
+            program,
+            body,
+            item,
+        }
+    }
+}
+
+impl Display for Named<'_, Operand> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.item {
+            Operand::Temp(temp) => write!(f, "t{}", temp),
+            Operand::Constant(Constant::Nil) => write!(f, "nil"),
+            Operand::Constant(Constant::Boolean(value)) => write!(f, "{}", value),
+            Operand::Constant(Constant::Integer(value)) => write!(f, "{}", value),
+            Operand::Constant(Constant::String(data)) => write!(f, "{}", quote(data)),
+            Operand::Constant(Constant::Bytes(data)) => write!(f, "b{}", quote(data)),
+            Operand::Constant(Constant::Function(id)) | Operand::Constant(Constant::Task(id)) => {
+                write!(f, "&{}", self.program.bodies[*id].name())
+            }
+        }
+    }
+}
+
+impl Named<'_, Instruction> {
+    fn operand<'a>(&'a self, operand: &'a Operand) -> Named<'a, Operand> {
+        Named::new(self.program, self.body, operand)
+    }
+
+    fn list(&self, operands: &[Operand]) -> String {
+        operands
+            .iter()
+            .map(|operand| self.operand(operand).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn variable(&self, variable: &Variable) -> String {
+        match variable {
+            Variable::Local(index) => self.body.locals[*index].clone(),
+            Variable::Stored(index) => format!("stored:{}", self.body.stored[*index]),
+            Variable::Global(index) => format!("global:{}", self.program.globals[*index]),
+            Variable::Record(index) => format!("record:{}", self.program.records[*index].name),
+        }
+    }
+}
+
+impl Display for Named<'_, Instruction> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let records = &self.program.records;
+        match self.item {
+            Instruction::Copy { dest, value } => write!(f, "t{} = {}", dest, self.operand(value)),
+            Instruction::Load { dest, variable } => {
+                write!(f, "t{} = load {}", dest, self.variable(variable))
+            }
+            Instruction::Store { variable, value } => {
+                write!(
+                    f,
+                    "store {}, {}",
+                    self.variable(variable),
+                    self.operand(value)
+                )
+            }
+            Instruction::Not { dest, operand } => {
+                write!(f, "t{} = not {}", dest, self.operand(operand))
+            }
+            Instruction::Binary {
+                dest,
+                operator,
+                lhs,
+                rhs,
+            } => write!(
+                f,
+                "t{} = {} {}, {}",
+                dest,
+                operator.name(),
+                self.operand(lhs),
+                self.operand(rhs)
+            ),
+            Instruction::List { dest, items } => write!(f, "t{} = [{}]", dest, self.list(items)),
+            Instruction::Pack {
+                dest,
+                value,
+                width,
+                endianness,
+            } => {
+                let sign = if *endianness == Endianness::Big {
+                    "-"
+                } else {
+                    ""
+                };
+                write!(
+                    f,
+                    "t{} = pack {}::{}{}",
+                    dest,
+                    self.operand(value),
+                    sign,
+                    width
+                )
+            }
+            Instruction::Unpack { fields, bytes } => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(temp, width, endianness)| {
+                        let sign = if *endianness == Endianness::Big {
+                            "-"
+                        } else {
+                            ""
+                        };
+                        format!("t{}::{}{}", temp, sign, width)
+                    })
+                    .collect();
+                write!(
+                    f,
+                    "[{}] = unpack {}",
+                    fields.join(", "),
+                    self.operand(bytes)
+                )
+            }
+            Instruction::Element {
+                dest,
+                record,
+                index,
+            } => write!(
+                f,
+                "t{} = {}[{}]",
+                dest,
+                records[*record].name,
+                self.operand(index)
+            ),
+            Instruction::SetElement {
+                record,
+                index,
+                value,
+            } => write!(
+                f,
+                "{}[{}] = {}",
+                records[*record].name,
+                self.operand(index),
+                self.operand(value)
+            ),
+            Instruction::Items { dest, collection } => {
+                write!(f, "t{} = items {}", dest, self.operand(collection))
+            }
+            Instruction::Length { dest, list } => {
+                write!(f, "t{} = length {}", dest, self.operand(list))
+            }
+            Instruction::Index { dest, list, index } => write!(
+                f,
+                "t{} = index {}, {}",
+                dest,
+                self.operand(list),
+                self.operand(index)
+            ),
+            Instruction::InRange {
+                dest,
+                value,
+                start,
+                end,
+            } => write!(
+                f,
+                "t{} = in {}, {}..={}",
+                dest,
+                self.operand(value),
+                start,
+                end
+            ),
+            Instruction::Call { dest, callee, args } => {
+                let name = match callee {
+                    Callee::Builtin(builtin) => builtin.name().to_string(),
+                    Callee::Function(id) => self.program.bodies[*id].name(),
+                };
+                write!(f, "t{} = call {}({})", dest, name, self.list(args))
+            }
+            Instruction::Start {
+                dest,
+                target,
+                args,
+                interval,
+            } => {
+                write!(
+                    f,
+                    "t{} = start {}({})",
+                    dest,
+                    self.operand(target),
+                    self.list(args)
+                )?;
+                match interval {
+                    Some(interval) => write!(f, " @ {}", self.operand(interval)),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Display for Named<'_, Terminator> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.item {
+            Terminator::Jump(target) => write!(f, "jump b{}", target),
+            Terminator::Branch {
+                condition,
+                then,
+                otherwise,
+            } => write!(
+                f,
+                "branch {}, b{}, b{}",
+                Named::new(self.program, self.body, condition),
+                then,
+                otherwise
+            ),
+            Terminator::Suspend { delay_ms, resume } => {
+                write!(f, "suspend {} -> b{}", delay_ms, resume)
+            }
+            Terminator::Return(value) => {
+                write!(f, "return {}", Named::new(self.program, self.body, value))
+            }
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use crate::ir::{
+    Body, BodyKind, Callee, Constant, Instruction, Operand, Program, Temp, Terminator, Variable,
+};
+use crate::parser::MAX_GUARD_WIDTH;
+
+/// Checks that a program is well formed: every jump lands on a block of its
+/// body, every block can be reached from the entry, every temporary is
+/// written on all paths before it is read, and every variable, record,
+/// function and task it refers to exists. Fails with one line per problem.
+pub fn verify(program: &Program) -> Result<(), String> {
+    let mut errors = vec![];
+
+    for (id, body) in program.bodies.iter().enumerate() {
+        let mut error = |message: String| {
+            errors.push(format!("{} (body {}): {}", body.name(), id, message));
+        };
+        verify_body(program, body, &mut error);
+    }
+
+    for interface in program.interfaces.iter() {
+        for &id in interface.handlers.iter() {
+            match program.bodies.get(id).map(|body| &body.kind) {
+                Some(BodyKind::When {
+                    interface: name, ..
+                }) if *name == interface.name => {}
+                _ => errors.push(format!(
+                    "dispatch \"{}\": body {} is not one of its handlers",
+                    interface.name, id
+                )),
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+fn verify_body(program: &Program, body: &Body, error: &mut impl FnMut(String)) {
+    if body.blocks.is_empty() {
+        return error("no entry block".to_string());
+    }
+
+    match &body.kind {
+        BodyKind::Function { parameters, .. } if *parameters > body.locals.len() => {
+            error("more parameters than locals".to_string())
+        }
+        BodyKind::When { .. } if body.locals.is_empty() => {
+            error("no local for the packet".to_string())
+        }
+        _ => {}
+    }
+
+    for (id, block) in body.blocks.iter().enumerate() {
+        let mut error = |message: String| error(format!("b{}: {}", id, message));
+
+        for instruction in block.instructions.iter() {
+            for temp in instruction.defined() {
+                check_temp(body, temp, &mut error);
+            }
+            for operand in instruction.operands() {
+                check_operand(program, body, operand, &mut error);
+            }
+            check_instruction(program, body, instruction, &mut error);
+        }
+
+        if let Terminator::Branch { condition, .. } | Terminator::Return(condition) =
+            &block.terminator
+        {
+            check_operand(program, body, condition, &mut error);
+        }
+        if block.lines.len() != block.instructions.len() + 1 {
+            error("has no line for every instruction".to_string());
+        }
+        for successor in block.terminator.successors() {
+            if successor >= body.blocks.len() {
+                error(format!("jumps to the missing block b{}", successor));
+            }
+        }
+    }
+
+    let reachable = reachable(body);
+    for (id, reachable) in reachable.iter().enumerate() {
+        if !reachable {
+            error(format!("b{} cannot be reached", id));
+        }
+    }
+
+    check_definitions(body, &reachable, error);
+}
+
+fn check_temp(body: &Body, temp: Temp, error: &mut impl FnMut(String)) {
+    if temp >= body.temps {
+        error(format!(
+            "t{} is beyond the {} temporaries",
+            temp, body.temps
+        ));
+    }
+}
+
+fn check_operand(
+    program: &Program,
+    body: &Body,
+    operand: &Operand,
+    error: &mut impl FnMut(String),
+) {
+    match operand {
+        Operand::Temp(temp) => check_temp(body, *temp, error),
+        Operand::Constant(Constant::Function(id)) => match program.bodies.get(*id) {
+            Some(Body {
+                kind: BodyKind::Function { .. },
+                ..
+            }) => {}
+            _ => error(format!("body {} is not a function", id)),
+        },
+        Operand::Constant(Constant::Task(id)) => match program.bodies.get(*id) {
+            Some(Body {
+                kind: BodyKind::Task { .. },
+                ..
+            }) => {}
+            _ => error(format!("body {} is not a task", id)),
+        },
+        Operand::Constant(_) => {}
+    }
+}
+
+fn check_instruction(
+    program: &Program,
+    body: &Body,
+    instruction: &Instruction,
+    error: &mut impl FnMut(String),
+) {
+    match instruction {
+        Instruction::Load { variable, .. } => check_variable(program, body, variable, error),
+        Instruction::Store { variable, .. } => {
+            if let Variable::Record(_) = variable {
+                error("a record cannot be assigned as a whole".to_string());
+            }
+            check_variable(program, body, variable, error);
+        }
+        Instruction::Element { record, .. } | Instruction::SetElement { record, .. }
+            if *record >= program.records.len() =>
+        {
+            error(format!("record {} does not exist", record))
+        }
+        Instruction::Pack { width, .. } => check_width(*width, error),
+        Instruction::Unpack { fields, .. } => {
+            for (_, width, _) in fields {
+                check_width(*width, error);
+            }
+        }
+        Instruction::InRange { start, end, .. } if start > end => {
+            error(format!("the range {}..={} is empty", start, end))
+        }
+        Instruction::Call {
+            callee: Callee::Function(id),
+            args,
+            ..
+        } => match program.bodies.get(*id).map(|body| &body.kind) {
+            Some(BodyKind::Function { parameters, .. }) if *parameters == args.len() => {}
+            Some(BodyKind::Function { parameters, .. }) => error(format!(
+                "calls body {} with {} argument(s) instead of {}",
+                id,
+                args.len(),
+                parameters
+            )),
+            _ => error(format!("calls body {}, which is not a function", id)),
+        },
+        _ => {}
+    }
+}
+
+fn check_variable(
+    program: &Program,
+    body: &Body,
+    variable: &Variable,
+    error: &mut impl FnMut(String),
+) {
+    let (index, count, kind) = match variable {
+        Variable::Local(index) => (*index, body.locals.len(), "local"),
+        Variable::Stored(index) => (*index, body.stored.len(), "stored variable"),
+        Variable::Global(index) => (*index, program.globals.len(), "global"),
+        Variable::Record(index) => (*index, program.records.len(), "record"),
+    };
+    if index >= count {
+        error(format!("{} {} does not exist", kind, index));
+    }
+}
+
+fn check_width(width: usize, error: &mut impl FnMut(String)) {
+    if !(1..=MAX_GUARD_WIDTH).contains(&width) {
+        error(format!("{} is not a valid byte width", width));
+    }
+}
+
+fn reachable(body: &Body) -> Vec<bool> {
+    let mut reachable = vec![false; body.blocks.len()];
+    let mut pending = vec![0];
+    while let Some(id) = pending.pop() {
+        if id >= reachable.len() || reachable[id] {
+            continue;
+        }
+        reachable[id] = true;
+        pending.extend(body.blocks[id].terminator.successors());
+    }
+    reachable
+}
+
+/// Finds the temporaries written on every path to the start of each block,
+/// then checks each read against them.
+fn check_definitions(body: &Body, reachable: &[bool], error: &mut impl FnMut(String)) {
+    let blocks = &body.blocks;
+    let mut predecessors = vec![vec![]; blocks.len()];
+    for (id, block) in blocks.iter().enumerate() {
+        for successor in block.terminator.successors() {
+            if successor < blocks.len() && reachable[id] {
+                predecessors[successor].push(id);
+            }
+        }
+    }
+
+    let mut defined_in = vec![vec![true; body.temps]; blocks.len()];
+    defined_in[0] = vec![false; body.temps];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in 1..blocks.len() {
+            let mut defined = vec![!predecessors[id].is_empty(); body.temps];
+            for &predecessor in predecessors[id].iter() {
+                let out = defined_out(body, predecessor, &defined_in[predecessor]);
+                for (defined, out) in defined.iter_mut().zip(out) {
+                    *defined &= out;
+                }
+            }
+            if defined != defined_in[id] {
+                defined_in[id] = defined;
+                changed = true;
+            }
+        }
+    }
+
+    for (id, block) in blocks.iter().enumerate() {
+        let mut defined = defined_in[id].clone();
+        let mut check = |operand: &Operand, defined: &[bool]| {
+            if let Operand::Temp(temp) = operand {
+                if *temp < defined.len() && !defined[*temp] {
+                    error(format!(
+                        "b{}: t{} may be read before it is written",
+                        id, temp
+                    ));
+                }
+            }
+        };
+
+        for instruction in block.instructions.iter() {
+            for operand in instruction.operands() {
+                check(operand, &defined);
+            }
+            define(instruction, &mut defined);
+        }
+        if let Terminator::Branch { condition, .. } | Terminator::Return(condition) =
+            &block.terminator
+        {
+            check(condition, &defined);
+        }
+    }
+}
+
+fn defined_out(body: &Body, id: usize, defined_in: &[bool]) -> Vec<bool> {
+    let mut defined = defined_in.to_vec();
+    for instruction in body.blocks[id].instructions.iter() {
+        define(instruction, &mut defined);
+    }
+    defined
+}
+
+fn define(instruction: &Instruction, defined: &mut [bool]) {
+    for temp in instruction.defined() {
+        if let Some(defined) = defined.get_mut(temp) {
+            *defined = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{Block, Interface};
+    use crate::parser::Guard;
+
+    fn block(instructions: Vec<Instruction>, terminator: Terminator) -> Block {
+        let lines = vec![1; instructions.len() + 1];
+        Block {
+            instructions,
+            terminator,
+            lines,
+        }
+    }
+
+    fn body(kind: BodyKind, temps: usize, blocks: Vec<Block>) -> Body {
+        Body {
+            kind,
+            locals: vec!["msg".to_string()],
+            stored: vec![],
+            temps,
+            blocks,
+        }
+    }
+
+    fn task(temps: usize, blocks: Vec<Block>) -> Body {
+        let kind = BodyKind::Task {
+            name: "main".to_string(),
+            interval_ms: 0,
+        };
+        body(kind, temps, blocks)
+    }
+
+    fn program(bodies: Vec<Body>, interfaces: Vec<Interface>) -> Program {
+        Program {
+            globals: vec![],
+            records: vec![],
+            bodies,
+            interfaces,
+        }
+    }
+
+    fn nil() -> Operand {
+        Operand::Constant(Constant::Nil)
+    }
+
+    #[test]
+    fn accepts_a_well_formed_program() {
+        let blocks = vec![
+            block(
+                vec![Instruction::Copy {
+                    dest: 0,
+                    value: nil(),
+                }],
+                Terminator::Jump(1),
+            ),
+            block(vec![], Terminator::Return(Operand::Temp(0))),
+        ];
+        assert_eq!(verify(&program(vec![task(1, blocks)], vec![])), Ok(()));
+    }
+
+    #[test]
+    fn reports_jumps_to_missing_blocks() {
+        let blocks = vec![block(vec![], Terminator::Jump(3))];
+        assert_eq!(
+            verify(&program(vec![task(0, blocks)], vec![])),
+            Err("main (body 0): b0: jumps to the missing block b3".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_temps_read_before_written() {
+        let blocks = vec![
+            block(
+                vec![],
+                Terminator::Branch {
+                    condition: Operand::Constant(Constant::Boolean(true)),
+                    then: 1,
+                    otherwise: 2,
+                },
+            ),
+            block(
+                vec![Instruction::Copy {
+                    dest: 0,
+                    value: nil(),
+                }],
+                Terminator::Jump(2),
+            ),
+            block(vec![], Terminator::Return(Operand::Temp(0))),
+        ];
+        assert_eq!(
+            verify(&program(vec![task(1, blocks)], vec![])),
+            Err("main (body 0): b2: t0 may be read before it is written".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_unknown_records() {
+        let blocks = vec![block(
+            vec![Instruction::Element {
+                dest: 0,
+                record: 2,
+                index: Operand::Constant(Constant::Integer(0)),
+            }],
+            Terminator::Return(nil()),
+        )];
+        assert_eq!(
+            verify(&program(vec![task(1, blocks)], vec![])),
+            Err("main (body 0): b0: record 2 does not exist".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_dispatch_entries_that_are_not_handlers() {
+        let handler = body(
+            BodyKind::When {
+                interface: "uart".to_string(),
+                guard: Guard::Default,
+            },
+            0,
+            vec![block(vec![], Terminator::Return(nil()))],
+        );
+        let main = task(0, vec![block(vec![], Terminator::Return(nil()))]);
+        let interfaces = vec![
+            Interface {
+                name: "uart".to_string(),
+                handlers: vec![0, 1],
+            },
+            Interface {
+                name: "spi".to_string(),
+                handlers: vec![0],
+            },
+        ];
+        assert_eq!(
+            verify(&program(vec![handler, main], interfaces)),
+            Err([
+                "dispatch \"uart\": body 1 is not one of its handlers",
+                "dispatch \"spi\": body 0 is not one of its handlers",
+            ]
+            .join("\n"))
+        );
+    }
+}
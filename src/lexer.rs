@@ -29,7 +29,7 @@ pub fn tokenizer(content: String) -> Result<Vec<Token>, String> {
             }
 
             for (tk, re) in rules.iter() {
-                if let Some(mut tok) = parse_regex(re, &mut line_content, &tk) {
+                if let Some(mut tok) = parse_regex(re, &mut line_content, tk) {
                     tok.set_line(line_idx);
                     tok.set_column(col);
                     col += tok.value_len();
@@ -79,7 +79,7 @@ fn get_rules() -> Vec<(TokenKind, Regex)> {
         (TokenKind::RightArrow, Regex::new(r"^=>").unwrap()),
         (
             TokenKind::CompareOperator,
-            Regex::new(r"^([=><!]=)|(><)").unwrap(),
+            Regex::new(r"^(([=><!]=)|(><)|[<>])").unwrap(),
         ),
         (
             TokenKind::AssignOperator,
@@ -91,7 +91,9 @@ fn get_rules() -> Vec<(TokenKind, Regex)> {
         ),
         (TokenKind::GuardOperator, Regex::new(r"^::").unwrap()),
         (TokenKind::TimeOperator, Regex::new(r"^@").unwrap()),
+        (TokenKind::RangeOperator, Regex::new(r"^\.\.").unwrap()),
         (TokenKind::PipeOperator, Regex::new(r"^\.").unwrap()),
+        (TokenKind::AlternativeOperator, Regex::new(r"^\|").unwrap()),
         (TokenKind::Keyword, Regex::new(keyword_regex).unwrap()),
         (
             TokenKind::Identifier,
@@ -117,7 +119,7 @@ fn parse_regex(re: &Regex, line: &mut String, token_kind: &TokenKind) -> Option<
     let mut value = String::new();
 
     if let Some(mat) = re.find(line) {
-        let end = get_end_pos(&line, &mat, &token_kind);
+        let end = get_end_pos(line, &mat, token_kind);
 
         value.extend(line.get(0..end));
         line.drain(0..end);
@@ -0,0 +1,11 @@
+pub mod analysis;
+pub(crate) mod automaton;
+pub mod bytecode;
+pub mod codegen;
+pub mod diagnostic;
+pub mod ir;
+pub mod lexer;
+pub mod optimize;
+pub mod parser;
+pub mod runtime;
+pub mod token;
@@ -1,20 +1,512 @@
-use std::fs::File;
-use std::io::prelude::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 
-mod lexer;
-mod parser;
-mod token;
+use nxc::analysis::cost;
+use nxc::analysis::memory::{self, Budget};
+use nxc::analysis::{dispatch, matching, records};
+use nxc::diagnostic::{Diagnostic, Severity};
+use nxc::optimize::{constants, inline, unused};
+use nxc::parser::AST;
+use nxc::{bytecode, codegen, ir, lexer, parser, runtime};
 
-fn main() -> std::io::Result<()> {
-    let mut file = File::open("example.nx")?;
-    let mut file_content = String::new();
-    file.read_to_string(&mut file_content)?;
+const USAGE: &str = "Usage:
+    nxc build <file.nx> [options]    Check the program and print it
+    nxc run <file.nx> [options]      Run the program
+    nxc run <file.nxb> [options]     Run a bytecode file
+    nxc disasm <file.nxb>            Print the contents of a bytecode file
 
-    let toks = lexer::tokenizer(file_content).unwrap();
+Build options:
+    --emit <kind>            What to print: 'ast' for the syntax tree and
+                             the dispatch order (the default) or 'ir' for
+                             the lowered program; 'bytecode' writes a
+                             bytecode file instead, 'c' a C99 source
+                             file with the nx_hal.h header it runs on,
+                             'rust' a Rust module to embed in a service
+                             and 'wasm' a WebAssembly module to sandbox
+    -o <file>                Where the bytecode, source or module goes, by
+                             default next to the program
+    --memory-report          Print the static RAM of the records and
+                             stored variables, the stack of every handler
+                             and task and the longest packet of every
+                             interface
+    --ram-budget <bytes>     Fail when the records and stored variables
+                             take more RAM
+    --stack-budget <bytes>   Fail when a handler or task may need more
+                             stack
+    --cost-report            Print the most IR instructions every handler
+                             and task may run, which needs a bound on
+                             its loops: 'for' over a record or a packet
+                             of known length
+    --cost-limit <instructions>
+                             Fail when a handler or task may run more
+                             instructions, or has no bound
 
-    let list_ast = parser::parse(toks).unwrap();
+Build and run options:
+    -O0                      Keep the program as written (the default)
+    -O1                      Fold constant expressions and remove the
+                             branches and loops they rule out, warning
+                             about the code that never runs
+    -O2                      Also inline small functions and remove the
+                             functions and records that no handler or
+                             task reaches, reporting what was inlined,
+                             why other calls were kept, what was removed
+                             and the size of the bytecode file before
+                             and after
+
+Run options:
+    --sim-time <duration>    Run in simulated time for <duration>, such as
+                             500ms, 10s or 2m, instead of the wall clock.
+                             The run ends once nothing is due before
+                             <duration>, even while interfaces are open
+    --iface <name>=<spec>    Connect the interface <name> to a local stand-in:
+                               stdio                 standard input and output
+                               file:<input>,<output> files, either may be empty
+                               pipe:<path>           named pipes <path>.in and <path>.out
+                               pty                   a new pseudo-terminal
+                               tcp:<address>         a TCP server
+                               udp:<address>[,<peer>] a UDP socket
+                             Interfaces left out print what is sent. In
+                             simulated time, stdio and file inputs are read
+                             to their end and received at 0ms; use --replay
+                             for bytes that arrive at set times
+    --max-packet <bytes>     Longest packet a 'when' handler receives (256)
+    --frame-timeout <duration>
+                             How long a partial packet waits for its next
+                             byte before it is dropped (100ms)
+    --store <file>           Keep the 'store' variables in <file>, so that
+                             they survive a restart
+    --trace <file>           Log packets, sends, prints, delays and task
+                             starts, stops and ticks to <file> as JSON Lines,
+                             stamped with the virtual time
+    --record <file.nxcap>    Capture the bytes received on every interface,
+                             with the virtual time they arrived at
+    --replay <file.nxcap>    Receive the bytes of a capture at the times
+                             they were recorded
+    --seed <number>          Seed of 'rand', so that runs repeat exactly;
+                             taken from the clock by default and written
+                             to the trace
+
+Bytecode files run without --store, --record and --replay, and keep their
+'store' variables in memory.";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.as_slice() {
+        [command, path, options @ ..] if command == "build" => {
+            parse_build_options(options).and_then(|options| build(path, &options))
+        }
+        [command, path, options @ ..] if command == "run" => {
+            parse_run_options(options).and_then(|options| run(path, &options))
+        }
+        [command, path] if command == "disasm" => disasm(path),
+        _ => Err(USAGE.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+/// What `nxc build` prints.
+enum Emit {
+    Ast,
+    Ir,
+    Bytecode,
+    C,
+    Rust,
+    Wasm,
+}
+
+struct BuildOptions {
+    emit: Emit,
+    /// 0, 1 or 2, from `-O0`, `-O1` or `-O2`.
+    opt_level: u8,
+    memory_report: bool,
+    budget: Budget,
+    cost_report: bool,
+    cost_limit: Option<u64>,
+    /// Where `--emit bytecode` writes the bytecode, `--emit c` and
+    /// `--emit rust` the source and `--emit wasm` the module.
+    output_path: Option<String>,
+}
+
+fn parse_build_options(args: &[String]) -> Result<BuildOptions, String> {
+    let mut options = BuildOptions {
+        emit: Emit::Ast,
+        opt_level: 0,
+        memory_report: false,
+        budget: Budget::default(),
+        cost_report: false,
+        cost_limit: None,
+        output_path: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", flag))
+        };
+        match flag.as_str() {
+            "--emit" => {
+                options.emit = match value()?.as_str() {
+                    "ast" => Emit::Ast,
+                    "ir" => Emit::Ir,
+                    "bytecode" => Emit::Bytecode,
+                    "c" => Emit::C,
+                    "rust" => Emit::Rust,
+                    "wasm" => Emit::Wasm,
+                    other => return Err(format!("Unknown output kind {}", other)),
+                }
+            }
+            "-o" => options.output_path = Some(value()?.clone()),
+            "-O0" => options.opt_level = 0,
+            "-O1" => options.opt_level = 1,
+            "-O2" => options.opt_level = 2,
+            "--memory-report" => options.memory_report = true,
+            "--ram-budget" => options.budget.ram = Some(parse_bytes(value()?)?),
+            "--stack-budget" => options.budget.stack = Some(parse_bytes(value()?)?),
+            "--cost-report" => options.cost_report = true,
+            "--cost-limit" => {
+                let text = value()?;
+                let limit = text
+                    .parse()
+                    .map_err(|_| format!("Invalid instruction count {}", text))?;
+                options.cost_limit = Some(limit);
+            }
+            _ => return Err(format!("Unknown option {}\n\n{}", flag, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_bytes(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("Invalid size {}", text))
+}
+
+struct RunOptions {
+    runtime: runtime::Options,
+    /// 0, 1 or 2, from `-O0`, `-O1` or `-O2`.
+    opt_level: u8,
+}
+
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut opt_level = 0;
+    let mut options = runtime::Options::default();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for {}", flag))
+        };
+        match flag.as_str() {
+            "--sim-time" => options.sim_time_ms = Some(parse_duration(value()?)?),
+            "--iface" => {
+                let value = value()?;
+                let (name, spec) = value.split_once('=').ok_or_else(|| {
+                    format!("Invalid interface {}, expected <name>=<spec>", value)
+                })?;
+                options
+                    .interfaces
+                    .push((name.to_string(), spec.to_string()));
+            }
+            "--max-packet" => {
+                let value = value()?;
+                options.framing.max_packet_len = match value.parse() {
+                    Ok(len) if len > 0 => len,
+                    _ => return Err(format!("Invalid packet length {}", value)),
+                };
+            }
+            "--frame-timeout" => options.framing.timeout_ms = parse_duration(value()?)?,
+            "--store" => options.store_path = Some(value()?.into()),
+            "--trace" => options.trace_path = Some(value()?.into()),
+            "--replay" => options.replay_path = Some(value()?.into()),
+            "--record" => options.record_path = Some(value()?.into()),
+            "--seed" => {
+                let value = value()?;
+                let seed = value
+                    .parse()
+                    .map_err(|_| format!("Invalid seed {}", value))?;
+                options.seed = Some(seed);
+            }
+            "-O0" => opt_level = 0,
+            "-O1" => opt_level = 1,
+            "-O2" => opt_level = 2,
+            _ => return Err(format!("Unknown option {}\n\n{}", flag, USAGE)),
+        }
+    }
+
+    Ok(RunOptions {
+        runtime: options,
+        opt_level,
+    })
+}
+
+/// Parses durations such as `250ms`, `10s`, `2m` or `1h`; plain numbers are
+/// milliseconds.
+fn parse_duration(text: &str) -> Result<u64, String> {
+    let (number, scale) = if let Some(number) = text.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = text.strip_suffix('s') {
+        (number, 1_000)
+    } else if let Some(number) = text.strip_suffix('m') {
+        (number, 60_000)
+    } else if let Some(number) = text.strip_suffix('h') {
+        (number, 3_600_000)
+    } else {
+        (text, 1)
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or_else(|| format!("Invalid duration {}", text))
+}
+
+fn compile(path: &str) -> Result<Vec<AST>, String> {
+    let file_content =
+        fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+
+    let toks = lexer::tokenizer(file_content)?;
+
+    parser::parse(toks)
+}
+
+/// Prints the diagnostics, failing when any of them is an error.
+fn report(diagnostics: &[Diagnostic]) -> Result<(), String> {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    match errors {
+        0 => Ok(()),
+        1 => Err("Compilation failed with 1 error".to_string()),
+        _ => Err(format!("Compilation failed with {} errors", errors)),
+    }
+}
+
+/// Checks the program, optimizing it as far as `opt_level` asks: constants
+/// are folded before the checks, and functions inlined and unreachable
+/// definitions removed after them, so that they are checked all the same.
+fn check(list_ast: &mut Vec<AST>, opt_level: u8) -> Result<(), String> {
+    let mut diagnostics = vec![];
+    if opt_level >= 1 {
+        diagnostics.extend(constants::fold(list_ast));
+    }
+    diagnostics.extend(dispatch::analyze(list_ast).1);
+    diagnostics.extend(matching::check(list_ast));
+    diagnostics.extend(records::check(list_ast));
+    report(&diagnostics)?;
+
+    if opt_level >= 2 {
+        prune(list_ast, &diagnostics)?;
+    }
+    Ok(())
+}
+
+/// Inlines small functions, removes the definitions nothing reaches and
+/// folds the constants inlining brought together, then prints what was done,
+/// why calls were kept and the bytecode size it saved.
+fn prune(list_ast: &mut Vec<AST>, reported: &[Diagnostic]) -> Result<(), String> {
+    let size_before = bytecode_size(list_ast);
+    let inlining = inline::inline(list_ast);
+    let removed = unused::remove(list_ast);
+    let diagnostics: Vec<Diagnostic> = constants::fold(list_ast)
+        .into_iter()
+        .filter(|diagnostic| {
+            !reported
+                .iter()
+                .any(|reported| reported.message == diagnostic.message)
+        })
+        .collect();
+    report(&diagnostics)?;
+    let size_after = bytecode_size(list_ast);
+
+    eprintln!("Optimization report:");
+    if inlining.inlined.is_empty() && inlining.kept.is_empty() && removed.is_empty() {
+        eprintln!("  nothing to inline or remove");
+    }
+    for (name, count) in inlining.inlined {
+        eprintln!("  inlined {} {} time(s)", name, count);
+    }
+    for (name, reason, count) in inlining.kept {
+        eprintln!("  kept {} call(s) to {}: {}", count, name, reason);
+    }
+    for removed in removed.iter() {
+        eprintln!("  removed {}", removed.definition);
+    }
+    if let (Some(before), Some(after)) = (size_before, size_after) {
+        eprintln!("  bytecode: {} byte(s), {} before", after, before);
+    }
+    let ram: usize = removed.iter().map(|removed| removed.ram).sum();
+    eprintln!("  record RAM saved: {} byte(s)", ram);
+    Ok(())
+}
+
+/// The size of the bytecode file of the program, to estimate what
+/// optimizations save.
+fn bytecode_size(list_ast: &[AST]) -> Option<usize> {
+    let module = bytecode::compile(&lower(list_ast).ok()?).ok()?;
+    Some(bytecode::write(&module).len())
+}
+
+fn build(path: &str, options: &BuildOptions) -> Result<(), String> {
+    let mut list_ast = compile(path)?;
+    check(&mut list_ast, options.opt_level)?;
+
+    let budget = &options.budget;
+    if options.memory_report || budget.ram.is_some() || budget.stack.is_some() {
+        let memory = memory::analyze(&lower(&list_ast)?);
+        if options.memory_report {
+            print!("{}", memory);
+        }
+        report(&memory.check(budget))?;
+    }
+
+    if options.cost_report || options.cost_limit.is_some() {
+        let cost = cost::analyze(&lower(&list_ast)?, options.cost_limit);
+        if options.cost_report {
+            print!("{}", cost);
+        }
+        report(&cost.check())?;
+    }
+
+    match options.emit {
+        Emit::Ast => {}
+        Emit::Ir => {
+            print!("{}", lower(&list_ast)?);
+            return Ok(());
+        }
+        Emit::Bytecode => {
+            let module = bytecode::compile(&lower(&list_ast)?)?;
+            let output_path = output_path(path, options, "nxb");
+            return write(&output_path, bytecode::write(&module));
+        }
+        Emit::C => {
+            let source_name = Path::new(path).file_name().unwrap_or_default();
+            let source = codegen::c::generate(&lower(&list_ast)?, &source_name.to_string_lossy())?;
+            let output_path = output_path(path, options, "c");
+            let header_path = output_path.with_file_name("nx_hal.h");
+            write(&output_path, source.into_bytes())?;
+            return write(&header_path, codegen::c::HAL_HEADER.as_bytes().to_vec());
+        }
+        Emit::Rust => {
+            let source_name = Path::new(path).file_name().unwrap_or_default();
+            let source =
+                codegen::rust::generate(&lower(&list_ast)?, &source_name.to_string_lossy())?;
+            return write(&output_path(path, options, "rs"), source.into_bytes());
+        }
+        Emit::Wasm => {
+            let source_name = Path::new(path).file_name().unwrap_or_default();
+            let module =
+                codegen::wasm::generate(&lower(&list_ast)?, &source_name.to_string_lossy())?;
+            return write(&output_path(path, options, "wasm"), module);
+        }
+    }
 
     println!("List AST: {:#?}", list_ast);
 
+    println!("Dispatch order:");
+    for dispatch in dispatch::analyze(&list_ast).0 {
+        println!("  \"{}\":", dispatch.interface);
+        for index in dispatch.handlers {
+            if let AST::When {
+                packet,
+                guard,
+                span,
+                ..
+            } = &list_ast[index]
+            {
+                println!("    {}{} ({})", packet, guard, span);
+            }
+        }
+    }
+
     Ok(())
 }
+
+/// Where `--emit` writes its file: the `-o` path or the program with
+/// another extension.
+fn output_path(path: &str, options: &BuildOptions, extension: &str) -> PathBuf {
+    match &options.output_path {
+        Some(output_path) => PathBuf::from(output_path),
+        None => Path::new(path).with_extension(extension),
+    }
+}
+
+fn write(path: &Path, contents: Vec<u8>) -> Result<(), String> {
+    fs::write(path, contents).map_err(|err| format!("Cannot write {}: {}", path.display(), err))
+}
+
+fn lower(list_ast: &[AST]) -> Result<ir::Program, String> {
+    let program = ir::lower(list_ast)?;
+    ir::verify(&program).map_err(|err| format!("Invalid IR:\n{}", err))?;
+    Ok(program)
+}
+
+fn run(path: &str, options: &RunOptions) -> Result<(), String> {
+    if path.ends_with(".nxb") {
+        return run_bytecode(path, &options.runtime);
+    }
+
+    let mut list_ast = compile(path)?;
+    check(&mut list_ast, options.opt_level)?;
+
+    runtime::run(&list_ast, Box::new(io::stdout()), &options.runtime)
+}
+
+fn run_bytecode(path: &str, options: &runtime::Options) -> Result<(), String> {
+    if options.store_path.is_some()
+        || options.record_path.is_some()
+        || options.replay_path.is_some()
+    {
+        return Err("Bytecode files run without --store, --record and --replay".to_string());
+    }
+
+    let bytes = fs::read(path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    let module = bytecode::read(&bytes)?;
+
+    let interfaces = runtime::open_interfaces(options)?;
+    let mut vm = bytecode::vm::Vm::new(module, Box::new(io::stdout()), interfaces, options)?;
+    runtime::drive(&mut vm, options.sim_time_ms)
+}
+
+fn disasm(path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|err| format!("Cannot read {}: {}", path, err))?;
+    let module = bytecode::read(&bytes)?;
+    print!("{}", bytecode::disassemble(&module)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250ms"), Ok(250));
+        assert_eq!(parse_duration("10s"), Ok(10_000));
+        assert_eq!(parse_duration("2m"), Ok(120_000));
+        assert_eq!(parse_duration("1h"), Ok(3_600_000));
+        assert_eq!(parse_duration("42"), Ok(42));
+        assert_eq!(
+            parse_duration("99999999999999999h"),
+            Err("Invalid duration 99999999999999999h".to_string())
+        );
+        assert_eq!(
+            parse_duration("-1s"),
+            Err("Invalid duration -1s".to_string())
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Constant folding: expressions whose operands are all literals become the
+//! literal they evaluate to, and the branches and loops their conditions
+//! rule out are removed.
+//!
+//! Folding follows the interpreter. Expressions that would fail at runtime,
+//! such as a division by zero or a `::N` packing of a value that does not
+//! fit, are left as they are so that they still fail, and are reported.
+
+use crate::analysis::{statement_expressions_mut, walk_statements};
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Expression, Literal, Statement, AST};
+use crate::runtime::interpreter::{arithmetic, Operator};
+use crate::runtime::value::Value;
+
+/// Folds the bodies of `program`, keeping their statement lines in step, and
+/// reports the code that was removed because it can never run.
+pub fn fold(program: &mut [AST]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for ast in program.iter_mut() {
+        let (body, lines) = match ast {
+            AST::Function { body, lines, .. }
+            | AST::Task { body, lines, .. }
+            | AST::When { body, lines, .. } => (body, lines),
+            AST::Record { .. } | AST::Store { .. } => continue,
+        };
+        let mut folder = Folder {
+            lines: std::mem::take(lines).into_iter(),
+            kept: vec![],
+            diagnostics: &mut diagnostics,
+        };
+        *body = folder.block(std::mem::take(body));
+        *lines = folder.kept;
+    }
+    diagnostics
+}
+
+struct Folder<'a> {
+    /// The lines of the statements not visited yet, in `walk_statements`
+    /// order.
+    lines: std::vec::IntoIter<usize>,
+    /// The lines of the statements kept so far, in the same order.
+    kept: Vec<usize>,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl Folder<'_> {
+    fn block(&mut self, body: Vec<Statement>) -> Vec<Statement> {
+        let mut folded = vec![];
+        for statement in body {
+            self.statement(statement, &mut folded);
+        }
+        folded
+    }
+
+    /// Folds `statement` into the statements that replace it, pushed to `out`.
+    fn statement(&mut self, mut statement: Statement, out: &mut Vec<Statement>) {
+        let line = self.lines.next().unwrap_or_default();
+        match statement {
+            Statement::If {
+                condition,
+                body,
+                elif,
+                else_body,
+            } => self.branches(line, condition, body, elif, else_body, out),
+            Statement::While {
+                mut condition,
+                body,
+            } => {
+                self.expression(&mut condition, line);
+                if let Some(Value::Boolean(false)) = constant(&condition) {
+                    let skipped = self.skip(&body);
+                    self.unreachable(
+                        format!("'while' condition at line {}", line),
+                        false,
+                        skipped,
+                    );
+                    return;
+                }
+                self.kept.push(line);
+                let body = self.block(body);
+                out.push(Statement::While { condition, body });
+            }
+            Statement::For {
+                var,
+                collection,
+                body,
+            } => {
+                self.kept.push(line);
+                let body = self.block(body);
+                out.push(Statement::For {
+                    var,
+                    collection,
+                    body,
+                });
+            }
+            Statement::Match {
+                mut target,
+                cases,
+                default,
+                span,
+            } => {
+                self.kept.push(line);
+                self.expression(&mut target, line);
+                let mut folded_cases = vec![];
+                for mut arm in cases {
+                    arm.body = self.block(arm.body);
+                    folded_cases.push(arm);
+                }
+                let default = default.map(|default| self.block(default));
+                out.push(Statement::Match {
+                    target,
+                    cases: folded_cases,
+                    default,
+                    span,
+                });
+            }
+            _ => {
+                self.kept.push(line);
+                for expression in statement_expressions_mut(&mut statement) {
+                    self.expression(expression, line);
+                }
+                out.push(statement);
+            }
+        }
+    }
+
+    /// Folds an `if` written at `line`: branches whose condition is always
+    /// false are dropped, the first branch whose condition is always true
+    /// becomes the `else` and the branches after it are dropped. An `if`
+    /// left with no condition is replaced by its `else` body.
+    fn branches(
+        &mut self,
+        line: usize,
+        condition: Expression,
+        body: Vec<Statement>,
+        elif: Vec<(Expression, Vec<Statement>)>,
+        else_body: Vec<Statement>,
+        out: &mut Vec<Statement>,
+    ) {
+        let position = self.kept.len();
+        self.kept.push(line);
+
+        let mut kept = vec![];
+        // The always true condition and the body it makes the `else`.
+        let mut taken: Option<(String, Vec<Statement>)> = None;
+        let mut skipped = None;
+        let branches = std::iter::once((condition, body)).chain(elif);
+        for (index, (mut condition, body)) in branches.enumerate() {
+            if taken.is_some() {
+                skipped = skipped.or(self.skip(&body));
+                continue;
+            }
+
+            self.expression(&mut condition, line);
+            let name = match index {
+                0 => format!("'if' condition at line {}", line),
+                _ => format!("'elif' condition of the 'if' at line {}", line),
+            };
+            match constant(&condition) {
+                Some(Value::Boolean(false)) => {
+                    let skipped = self.skip(&body);
+                    self.unreachable(name, false, skipped);
+                }
+                Some(Value::Boolean(true)) => taken = Some((name, self.block(body))),
+                _ => {
+                    let body = self.block(body);
+                    kept.push((condition, body));
+                }
+            }
+        }
+
+        let else_body = match taken {
+            Some((name, body)) => {
+                skipped = skipped.or(self.skip(&else_body));
+                if skipped.is_some() {
+                    self.unreachable(name, true, skipped);
+                }
+                body
+            }
+            None => self.block(else_body),
+        };
+
+        if kept.is_empty() {
+            self.kept.remove(position);
+            out.extend(else_body);
+            return;
+        }
+        let mut kept = kept.into_iter();
+        let (condition, body) = kept.next().unwrap();
+        out.push(Statement::If {
+            condition,
+            body,
+            elif: kept.collect(),
+            else_body,
+        });
+    }
+
+    /// Drops the lines of the statements of `body`, returning the first.
+    fn skip(&mut self, body: &[Statement]) -> Option<usize> {
+        let mut count = 0;
+        walk_statements(body, &mut |_| count += 1);
+        let mut skipped = self.lines.by_ref().take(count);
+        let first = skipped.next();
+        skipped.for_each(drop);
+        first
+    }
+
+    fn unreachable(&mut self, condition: String, value: bool, skipped: Option<usize>) {
+        let mut message = format!("{} is always {}", condition, value);
+        if let Some(skipped) = skipped {
+            message.push_str(&format!(", so the code at line {} never runs", skipped));
+        }
+        self.diagnostics.push(Diagnostic::warning(message));
+    }
+
+    /// Folds `expression`, written in the statement at `line`, in place.
+    fn expression(&mut self, expression: &mut Expression, line: usize) {
+        let folded = match expression {
+            Expression::Literal(_) | Expression::Variable(_) => None,
+            Expression::List(items) => {
+                for item in items.iter_mut() {
+                    self.expression(item, line);
+                }
+                None
+            }
+            Expression::Element { index, .. } => {
+                self.expression(index, line);
+                None
+            }
+            Expression::Equal(lhs, rhs) => {
+                self.binary(lhs, rhs, line, |lhs, rhs| Ok(Value::Boolean(lhs == rhs)))
+            }
+            Expression::NotEqual(lhs, rhs) => {
+                self.binary(lhs, rhs, line, |lhs, rhs| Ok(Value::Boolean(lhs != rhs)))
+            }
+            Expression::Less(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                Ok(Value::Boolean(lhs.as_integer()? < rhs.as_integer()?))
+            }),
+            Expression::Greater(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                Ok(Value::Boolean(lhs.as_integer()? > rhs.as_integer()?))
+            }),
+            Expression::LessOrEqual(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                Ok(Value::Boolean(lhs.as_integer()? <= rhs.as_integer()?))
+            }),
+            Expression::GreaterOrEqual(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                Ok(Value::Boolean(lhs.as_integer()? >= rhs.as_integer()?))
+            }),
+            Expression::And(lhs, rhs) => self.logical(lhs, rhs, false, line),
+            Expression::Or(lhs, rhs) => self.logical(lhs, rhs, true, line),
+            Expression::Xor(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                Ok(Value::Boolean(lhs.as_boolean()? ^ rhs.as_boolean()?))
+            }),
+            Expression::Not(operand) => {
+                self.expression(operand, line);
+                constant(operand).map(|value| Ok(Value::Boolean(!value.as_boolean()?)))
+            }
+            Expression::Sum(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                arithmetic(Operator::Sum, lhs, rhs)
+            }),
+            Expression::Minus(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                arithmetic(Operator::Minus, lhs, rhs)
+            }),
+            Expression::Multiply(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                arithmetic(Operator::Multiply, lhs, rhs)
+            }),
+            Expression::Division(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                arithmetic(Operator::Division, lhs, rhs)
+            }),
+            Expression::Modulus(lhs, rhs) => self.binary(lhs, rhs, line, |lhs, rhs| {
+                arithmetic(Operator::Modulus, lhs, rhs)
+            }),
+            Expression::Guard(operand, guard) => {
+                self.expression(operand, line);
+                constant(operand).map(|value| value.pack(guard))
+            }
+            Expression::Time(target, interval) => {
+                self.expression(target, line);
+                self.expression(interval, line);
+                None
+            }
+            Expression::Pipe(receiver, call) => {
+                self.expression(receiver, line);
+                for argument in call.arguments.iter_mut() {
+                    self.expression(argument, line);
+                }
+                None
+            }
+            Expression::FunctionCall(call) => {
+                for argument in call.arguments.iter_mut() {
+                    self.expression(argument, line);
+                }
+                None
+            }
+        };
+
+        match folded {
+            Some(Ok(value)) => {
+                if let Some(value) = literal(value) {
+                    *expression = Expression::Literal(value);
+                }
+            }
+            Some(Err(err)) => self.diagnostics.push(Diagnostic::warning(format!(
+                "expression at line {} always fails at runtime: {}",
+                line, err
+            ))),
+            None => {}
+        }
+    }
+
+    /// Folds both operands and, when both are literals, applies `operation`.
+    fn binary(
+        &mut self,
+        lhs: &mut Expression,
+        rhs: &mut Expression,
+        line: usize,
+        operation: impl FnOnce(Value, Value) -> Result<Value, String>,
+    ) -> Option<Result<Value, String>> {
+        self.expression(lhs, line);
+        self.expression(rhs, line);
+        Some(operation(constant(lhs)?, constant(rhs)?))
+    }
+
+    /// Folds `and` (`decides` is false) or `or` (`decides` is true): a
+    /// literal left operand equal to `decides` is the result without
+    /// evaluating the right one, as in the interpreter.
+    fn logical(
+        &mut self,
+        lhs: &mut Expression,
+        rhs: &mut Expression,
+        decides: bool,
+        line: usize,
+    ) -> Option<Result<Value, String>> {
+        self.expression(lhs, line);
+        if constant(lhs) == Some(Value::Boolean(decides)) {
+            return Some(Ok(Value::Boolean(decides)));
+        }
+        self.binary(lhs, rhs, line, |lhs, rhs| {
+            let (lhs, rhs) = (lhs.as_boolean()?, rhs.as_boolean()?);
+            Ok(Value::Boolean(if decides {
+                lhs || rhs
+            } else {
+                lhs && rhs
+            }))
+        })
+    }
+}
+
+/// The value of `expression` when it is a literal.
+fn constant(expression: &Expression) -> Option<Value> {
+    match expression {
+        Expression::Literal(literal) => Some(Value::from(literal)),
+        _ => None,
+    }
+}
+
+/// The literal written for `value`, when there is one.
+fn literal(value: Value) -> Option<Literal> {
+    Some(match value {
+        Value::Nil => Literal::Nil,
+        Value::Boolean(value) => Literal::Boolean(value),
+        Value::Integer(value) => Literal::Integer(value as isize),
+        Value::String(data) => Literal::String(data),
+        Value::Bytes(data) => Literal::Bytes(data),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parser};
+
+    /// The IR of `source` after folding, with what folding reported.
+    fn folded(source: &str) -> (String, Vec<String>) {
+        let mut program = parser::parse_source(source);
+        let diagnostics = fold(&mut program);
+        let lowered = ir::lower(&program).unwrap();
+        ir::verify(&lowered).unwrap();
+        let messages = diagnostics.iter().map(|d| d.message.clone()).collect();
+        (lowered.to_string(), messages)
+    }
+
+    #[test]
+    fn folds_constant_expressions() {
+        let (ir, messages) = folded(
+            "
+            task main @ 100
+                x = 2 + 3 * 4;
+                b = 258::-2 + 1::1;
+                s = \"ab\" + \"cd\";
+                ok = not (1 != 1 and 2 == 0) xor false;
+                y = x + 1;
+                z = 1 / 0;
+            end
+            ",
+        );
+        assert!(ir.contains("store x, 14"), "{}", ir);
+        assert!(ir.contains("store b, b\"\\x01\\x02\\x01\""), "{}", ir);
+        assert!(ir.contains("store s, \"abcd\""), "{}", ir);
+        assert!(ir.contains("store ok, true"), "{}", ir);
+        assert!(ir.contains("add t"), "{}", ir);
+        assert!(ir.contains("div 1, 0"), "{}", ir);
+        assert_eq!(
+            messages,
+            ["expression at line 8 always fails at runtime: Division by zero"]
+        );
+    }
+
+    #[test]
+    fn removes_branches_that_never_run() {
+        let (ir, messages) = folded(
+            "
+            task main @ 100
+                if (1 > 2)
+                    print(\"a\");
+                elif (millis() > 5)
+                    print(\"b\");
+                elif (true)
+                    print(\"c\");
+                else
+                    print(\"d\");
+                end
+                while (false)
+                    print(\"e\");
+                end
+                if (false or true)
+                    print(\"f\");
+                end
+            end
+            ",
+        );
+        for kept in ["\"b\"", "\"c\"", "\"f\""] {
+            assert!(ir.contains(kept), "{}", ir);
+        }
+        for removed in ["\"a\"", "\"d\"", "\"e\""] {
+            assert!(!ir.contains(removed), "{}", ir);
+        }
+        assert_eq!(
+            messages,
+            [
+                "'if' condition at line 3 is always false, so the code at line 4 never runs",
+                "'elif' condition of the 'if' at line 3 is always true, so the code at line 10 never runs",
+                "'while' condition at line 12 is always false, so the code at line 13 never runs",
+            ]
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! Inlining of small functions: a call to a function whose body is a single
+//! `return` of a short expression over its parameters becomes that
+//! expression, with the arguments written in place of the parameters.
+//!
+//! Only literals and variables are substituted, so that no argument is
+//! evaluated more or fewer times than the call would. Variables are kept
+//! out of expressions that call functions, which could change them before
+//! they are read. Every call that is kept is reported with the reason.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::analysis::{
+    statement_expressions, statement_expressions_mut, walk_expression, walk_statements,
+    walk_statements_mut,
+};
+use crate::ir::Builtin;
+use crate::parser::{Expression, Statement, AST};
+
+/// How many expression nodes a function may return to be inlined.
+const SIZE_LIMIT: usize = 12;
+
+struct Candidate {
+    parameters: Vec<String>,
+    expression: Expression,
+    /// Whether `expression` calls functions.
+    calls: bool,
+}
+
+/// What `inline` did, by function in declaration order.
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// Each inlined function with how many calls to it were inlined.
+    pub inlined: Vec<(String, usize)>,
+    /// Each function with calls that were kept, why, and how many.
+    pub kept: Vec<(String, &'static str, usize)>,
+}
+
+/// Counts the inlined and kept calls by function name.
+#[derive(Default)]
+struct Counts {
+    inlined: HashMap<String, usize>,
+    kept: Vec<(String, &'static str, usize)>,
+}
+
+impl Counts {
+    fn keep(&mut self, name: &str, reason: &'static str) {
+        match self
+            .kept
+            .iter_mut()
+            .find(|(kept, kept_reason, _)| kept == name && *kept_reason == reason)
+        {
+            Some((.., count)) => *count += 1,
+            None => self.kept.push((name.to_string(), reason, 1)),
+        }
+    }
+}
+
+/// Inlines the calls to small non-recursive functions in every body of
+/// `program`, reporting the calls it inlined and those it kept.
+pub fn inline(program: &mut [AST]) -> Report {
+    let candidates = candidates(program);
+    let mut counts = Counts::default();
+    for ast in program.iter_mut() {
+        let body = match ast {
+            AST::Function { body, .. } | AST::Task { body, .. } | AST::When { body, .. } => body,
+            AST::Record { .. } | AST::Store { .. } => continue,
+        };
+        walk_statements_mut(body, &mut |statement| {
+            if let Statement::FunctionCall(call) = statement {
+                match candidates.get(&call.name) {
+                    Some(Ok(_)) => counts.keep(&call.name, "its value is not used"),
+                    Some(Err(reason)) => counts.keep(&call.name, reason),
+                    None => {}
+                }
+            }
+            for expression in statement_expressions_mut(statement) {
+                inline_calls(expression, &candidates, &mut counts);
+            }
+        });
+    }
+
+    let mut report = Report::default();
+    for ast in program.iter() {
+        if let AST::Function { name, .. } = ast {
+            if let Some(&count) = counts.inlined.get(name) {
+                report.inlined.push((name.clone(), count));
+            }
+            let kept = counts.kept.iter().filter(|(kept, ..)| kept == name);
+            report.kept.extend(kept.cloned());
+        }
+    }
+    report
+}
+
+/// Every function of the program by name, with what inlining a call to it
+/// takes or why it cannot be inlined.
+fn candidates(program: &[AST]) -> HashMap<String, Result<Candidate, &'static str>> {
+    let mut declared = HashMap::new();
+    let mut callees: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for ast in program {
+        if let AST::Function { name, body, .. } = ast {
+            *declared.entry(name.as_str()).or_insert(0) += 1;
+            let names = callees.entry(name.as_str()).or_default();
+            walk_statements(body, &mut |statement| {
+                for expression in statement_expressions(statement) {
+                    walk_expression(expression, &mut |expression| {
+                        if let Some(name) = called(expression) {
+                            names.insert(name);
+                        }
+                    });
+                }
+                if let Statement::FunctionCall(call) = statement {
+                    names.insert(call.name.as_str());
+                }
+                if let Statement::Start { function_call, .. } = statement {
+                    names.insert(function_call.name.as_str());
+                }
+            });
+        }
+    }
+
+    let mut candidates = HashMap::new();
+    for ast in program {
+        let (name, arguments, body) = match ast {
+            AST::Function {
+                name,
+                arguments,
+                body,
+                ..
+            } => (name, arguments, body),
+            _ => continue,
+        };
+        if is_builtin(name) {
+            continue;
+        }
+        let candidate = candidate(name, arguments, body, &declared, &callees);
+        candidates.insert(name.clone(), candidate);
+    }
+    candidates
+}
+
+/// What inlining a call to the function `name` takes, or why it cannot be
+/// inlined.
+fn candidate(
+    name: &str,
+    arguments: &[String],
+    body: &[Statement],
+    declared: &HashMap<&str, usize>,
+    callees: &HashMap<&str, HashSet<&str>>,
+) -> Result<Candidate, &'static str> {
+    let expression = match body {
+        [Statement::Return { expression }] => expression,
+        _ => return Err("its body is not a single return"),
+    };
+    if declared[name] > 1 {
+        return Err("it is declared more than once");
+    }
+    if arguments.iter().collect::<HashSet<_>>().len() != arguments.len() {
+        return Err("it repeats a parameter");
+    }
+    if reaches(callees, name, name) {
+        return Err("it is recursive");
+    }
+
+    let mut size = 0;
+    let mut other_variables = false;
+    let mut starts = false;
+    let mut calls = false;
+    walk_expression(expression, &mut |expression| {
+        size += 1;
+        match expression {
+            Expression::Variable(variable) => other_variables |= !arguments.contains(variable),
+            Expression::Time(..) => starts = true,
+            Expression::FunctionCall(_) | Expression::Pipe(..) => calls = true,
+            _ => {}
+        }
+    });
+    if other_variables {
+        return Err("it reads variables other than its parameters");
+    }
+    if starts {
+        return Err("it starts a task");
+    }
+    if size > SIZE_LIMIT {
+        return Err("its expression is too large");
+    }
+    Ok(Candidate {
+        parameters: arguments.to_vec(),
+        expression: expression.clone(),
+        calls,
+    })
+}
+
+/// The name `expression` calls, when it is a call.
+fn called(expression: &Expression) -> Option<&str> {
+    match expression {
+        Expression::FunctionCall(call) | Expression::Pipe(_, call) => Some(call.name.as_str()),
+        _ => None,
+    }
+}
+
+/// Builtins are called in place of the functions that share their name.
+fn is_builtin(name: &str) -> bool {
+    name == "start" || Builtin::from_name(name).is_some()
+}
+
+/// Whether a chain of calls leads from the function `from` to `to`.
+fn reaches(callees: &HashMap<&str, HashSet<&str>>, from: &str, to: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut pending = vec![from];
+    while let Some(name) = pending.pop() {
+        for &callee in callees.get(name).into_iter().flatten() {
+            if callee == to {
+                return true;
+            }
+            if seen.insert(callee) {
+                pending.push(callee);
+            }
+        }
+    }
+    false
+}
+
+/// Inlines the calls in `expression`, innermost first, and then the calls
+/// that inlining brought in.
+fn inline_calls(
+    expression: &mut Expression,
+    candidates: &HashMap<String, Result<Candidate, &'static str>>,
+    counts: &mut Counts,
+) {
+    for child in children(expression) {
+        inline_calls(child, candidates, counts);
+    }
+
+    let (name, arguments): (_, Vec<&Expression>) = match &*expression {
+        Expression::FunctionCall(call) => (&call.name, call.arguments.iter().collect()),
+        Expression::Pipe(receiver, call) => (
+            &call.name,
+            std::iter::once(&**receiver)
+                .chain(call.arguments.iter())
+                .collect(),
+        ),
+        _ => return,
+    };
+    let candidate = match candidates.get(name) {
+        Some(Ok(candidate)) => candidate,
+        Some(Err(reason)) => return counts.keep(name, reason),
+        None => return,
+    };
+    if arguments.len() != candidate.parameters.len() {
+        return counts.keep(name, "the arguments do not match its parameters");
+    }
+    for argument in arguments.iter() {
+        match argument {
+            Expression::Literal(_) => {}
+            Expression::Variable(_) if !candidate.calls => {}
+            Expression::Variable(_) => {
+                return counts.keep(name, "it calls functions that could change an argument")
+            }
+            _ => return counts.keep(name, "an argument is not a literal or a variable"),
+        }
+    }
+
+    *counts.inlined.entry(name.clone()).or_insert(0) += 1;
+    let arguments: HashMap<&String, Expression> = candidate
+        .parameters
+        .iter()
+        .zip(arguments.into_iter().cloned())
+        .collect();
+    let mut inlined = candidate.expression.clone();
+    substitute(&mut inlined, &arguments);
+    *expression = inlined;
+    inline_calls(expression, candidates, counts);
+}
+
+/// Writes the argument of each parameter in place of its variable.
+fn substitute(expression: &mut Expression, arguments: &HashMap<&String, Expression>) {
+    if let Expression::Variable(name) = expression {
+        if let Some(argument) = arguments.get(name) {
+            *expression = argument.clone();
+        }
+        return;
+    }
+    for child in children(expression) {
+        substitute(child, arguments);
+    }
+}
+
+/// The expressions nested directly in `expression`.
+fn children(expression: &mut Expression) -> Vec<&mut Expression> {
+    match expression {
+        Expression::Literal(_) | Expression::Variable(_) => vec![],
+        Expression::List(items) => items.iter_mut().collect(),
+        Expression::Element { index, .. } => vec![index],
+        Expression::Equal(lhs, rhs)
+        | Expression::NotEqual(lhs, rhs)
+        | Expression::Less(lhs, rhs)
+        | Expression::Greater(lhs, rhs)
+        | Expression::LessOrEqual(lhs, rhs)
+        | Expression::GreaterOrEqual(lhs, rhs)
+        | Expression::And(lhs, rhs)
+        | Expression::Or(lhs, rhs)
+        | Expression::Xor(lhs, rhs)
+        | Expression::Sum(lhs, rhs)
+        | Expression::Minus(lhs, rhs)
+        | Expression::Multiply(lhs, rhs)
+        | Expression::Division(lhs, rhs)
+        | Expression::Modulus(lhs, rhs)
+        | Expression::Time(lhs, rhs) => vec![lhs, rhs],
+        Expression::Not(operand) | Expression::Guard(operand, _) => vec![operand],
+        Expression::Pipe(receiver, call) => std::iter::once(receiver.as_mut())
+            .chain(call.arguments.iter_mut())
+            .collect(),
+        Expression::FunctionCall(call) => call.arguments.iter_mut().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ir, parser};
+
+    fn inlined(source: &str) -> (String, Report) {
+        let mut program = parser::parse_source(source);
+        let inlined = inline(&mut program);
+        let lowered = ir::lower(&program).unwrap();
+        ir::verify(&lowered).unwrap();
+        (lowered.to_string(), inlined)
+    }
+
+    #[test]
+    fn inlines_small_functions() {
+        let (ir, inlined) = inlined(
+            "
+            function sq(x)
+                return x * x;
+            end
+
+            function area(w, h)
+                return sq(w) + h;
+            end
+
+            task main @ 100
+                v = 3;
+                print(\"%d %d\", sq(v), 2.area(5));
+            end
+            ",
+        );
+        let main = &ir[ir.find("task main").unwrap()..];
+        assert!(!main.contains("call sq"), "{}", ir);
+        assert!(!main.contains("call area"), "{}", ir);
+        assert!(main.contains("mul 2, 2"), "{}", ir);
+        assert_eq!(
+            inlined.inlined,
+            [("sq".to_string(), 3), ("area".to_string(), 1)]
+        );
+        assert_eq!(inlined.kept, []);
+    }
+
+    #[test]
+    fn keeps_calls_it_cannot_inline() {
+        let (ir, inlined) = inlined(
+            "
+            function down(n)
+                return down(n - 1);
+            end
+
+            function sq(x)
+                return x * x;
+            end
+
+            function both(x)
+                return sq(x) + millis();
+            end
+
+            function twice(x)
+                y = x * 2;
+                return y;
+            end
+
+            task main @ 100
+                v = 3;
+                print(\"%d %d %d\", down(1), sq(v + 1), both(v));
+                sq(twice(v));
+            end
+            ",
+        );
+        let main = &ir[ir.find("task main").unwrap()..];
+        assert!(main.contains("call down"), "{}", ir);
+        assert!(main.contains("call sq"), "{}", ir);
+        assert!(main.contains("call both"), "{}", ir);
+        assert_eq!(inlined.inlined, [("sq".to_string(), 1)]);
+        let reason = |name: &str, reason, count| (name.to_string(), reason, count);
+        assert_eq!(
+            inlined.kept,
+            [
+                reason("down", "it is recursive", 2),
+                reason("sq", "an argument is not a literal or a variable", 1),
+                reason("sq", "its value is not used", 1),
+                reason(
+                    "both",
+                    "it calls functions that could change an argument",
+                    1
+                ),
+                reason("twice", "its body is not a single return", 1),
+            ]
+        );
+    }
+}
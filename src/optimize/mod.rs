@@ -0,0 +1,6 @@
+//! Rewrites of the syntax tree that keep what the program does, asked for
+//! with `-O1` and `-O2`.
+
+pub mod constants;
+pub mod inline;
+pub mod unused;
@@ -0,0 +1,139 @@
+//! Removal of the functions and records that no `when` handler or task can
+//! reach, through calls, `start` or by naming them. Every task starts with
+//! the program, so tasks are always reached and never removed.
+
+use std::collections::HashSet;
+
+use crate::analysis::{statement_expressions, walk_expression, walk_statements};
+use crate::parser::{Expression, Statement, AST};
+
+/// A definition removed from the program.
+pub struct Removed {
+    /// How it was declared, such as `function f` or `record r[8, 1]`.
+    pub definition: String,
+    /// The bytes of RAM it took, for records.
+    pub ram: usize,
+}
+
+/// Removes the unreachable functions and records of `program`, returning
+/// them in declaration order.
+pub fn remove(program: &mut Vec<AST>) -> Vec<Removed> {
+    let mut reached: HashSet<String> = HashSet::new();
+    let mut pending: Vec<&AST> = program
+        .iter()
+        .filter(|ast| matches!(ast, AST::Task { .. } | AST::When { .. }))
+        .collect();
+    while let Some(ast) = pending.pop() {
+        for name in references(ast.body()) {
+            if reached.insert(name.clone()) {
+                pending.extend(program.iter().filter(|ast| match ast {
+                    AST::Function { name: function, .. } => *function == name,
+                    _ => false,
+                }));
+            }
+        }
+    }
+
+    let mut removed = vec![];
+    program.retain(|ast| {
+        let (definition, ram) = match ast {
+            AST::Function { name, .. } if !reached.contains(name) => {
+                (format!("function {}", name), 0)
+            }
+            AST::Record {
+                name,
+                length,
+                data_size,
+            } if !reached.contains(name) => (
+                format!("record {}[{}, {}]", name, length, data_size),
+                length * data_size,
+            ),
+            _ => return true,
+        };
+        removed.push(Removed { definition, ram });
+        false
+    });
+    removed
+}
+
+/// The names of functions and records `body` may use: those it calls,
+/// indexes or reads as variables.
+fn references(body: &[Statement]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    walk_statements(body, &mut |statement| {
+        match statement {
+            Statement::FunctionCall(call) => {
+                names.insert(call.name.clone());
+            }
+            Statement::Start { function_call, .. } => {
+                names.insert(function_call.name.clone());
+            }
+            Statement::ElementAssignment { record, .. } => {
+                names.insert(record.clone());
+            }
+            Statement::For { collection, .. } => {
+                names.insert(collection.clone());
+            }
+            _ => {}
+        }
+        for expression in statement_expressions(statement) {
+            walk_expression(expression, &mut |expression| match expression {
+                Expression::Variable(name) | Expression::Element { record: name, .. } => {
+                    names.insert(name.clone());
+                }
+                Expression::FunctionCall(call) | Expression::Pipe(_, call) => {
+                    names.insert(call.name.clone());
+                }
+                _ => {}
+            });
+        }
+    });
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    #[test]
+    fn removes_what_nothing_reaches() {
+        let mut program = parser::parse_source(
+            "
+                function helper()
+                    return table[0];
+                end
+
+                function spawned(n)
+                    print(\"%d\", n);
+                end
+
+                function unused()
+                    scratch[0] = helper();
+                end
+
+                record table[4, 2];
+                record scratch[8, 4];
+
+                task main @ 100
+                    t = spawned.start([1]) @ 10;
+                end
+
+                when \"uart\" => msg
+                    print(\"%d\", helper());
+                end
+            ",
+        );
+
+        let removed = remove(&mut program);
+        let removed: Vec<_> = removed
+            .iter()
+            .map(|removed| (removed.definition.as_str(), removed.ram))
+            .collect();
+        assert_eq!(
+            removed,
+            [("function unused", 0), ("record scratch[8, 4]", 32)]
+        );
+        assert_eq!(program.len(), 5);
+    }
+}
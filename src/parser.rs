@@ -1,12 +1,25 @@
-use crate::token::{Token, TokenKind};
+use crate::token::{Span, Token, TokenKind};
+use regex::bytes::{Regex, RegexBuilder};
+use regex_syntax::hir::{self, Hir, HirKind};
+use regex_syntax::ParserBuilder;
+use std::fmt::{Display, Formatter};
 
+/// Widest value, in bytes, that a numeric guard can pack or unpack.
+pub const MAX_GUARD_WIDTH: usize = 8;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub enum AST {
     Function {
         name: String,
         arguments: Vec<String>,
         body: Vec<Statement>,
+        /// The line of each statement of the body, in the order
+        /// `walk_statements` visits them.
+        lines: Vec<usize>,
     },
+    /// `record name[length, data_size];`: a program-wide array of `length`
+    /// unsigned integers of `data_size` bytes, all starting at 0.
     Record {
         name: String,
         length: usize,
@@ -16,19 +29,200 @@ pub enum AST {
         name: String,
         interval_ms: usize,
         body: Vec<Statement>,
+        lines: Vec<usize>,
     },
     When {
         interface: String,
         packet: String,
         guard: Guard,
         body: Vec<Statement>,
+        lines: Vec<usize>,
+        span: Span,
     },
+    /// `store` at the top level: variables shared by the whole program.
+    Store { var_list: Vec<String> },
 }
 
-#[derive(Debug)]
+impl AST {
+    pub fn body(&self) -> &[Statement] {
+        match self {
+            AST::Function { body, .. } | AST::Task { body, .. } | AST::When { body, .. } => body,
+            AST::Record { .. } | AST::Store { .. } => &[],
+        }
+    }
+
+    pub fn lines(&self) -> &[usize] {
+        match self {
+            AST::Function { lines, .. } | AST::Task { lines, .. } | AST::When { lines, .. } => {
+                lines
+            }
+            AST::Record { .. } | AST::Store { .. } => &[],
+        }
+    }
+}
+
+/// The `::` annotation used by `when` packets and by byte packing.
+///
+/// A numeric guard `::N` is `|N|` bytes wide, with `|N|` in `1..=8`. Its sign
+/// selects the byte order: `msg::4` is little-endian, `value::-4` is
+/// big-endian.
+///
+/// A regex guard `::"..."` holds the pattern without its quotes. Escapes such
+/// as `\x3f` are left for the regex engine, which matches raw bytes, so they
+/// always stand for the literal byte. Anchors such as `^` are rejected:
+/// packets are cut from a stream of bytes, which has no start or end of text
+/// for them to match.
+///
+/// A `when` handler written without a guard gets `Guard::Default`, which
+/// delivers one byte per packet, just like `::1`.
+#[derive(Debug, Clone)]
 pub enum Guard {
-    Numeric(isize),
-    Regex(String),
+    Default,
+    Numeric {
+        width: usize,
+        endianness: Endianness,
+    },
+    Regex {
+        pattern: String,
+        framing: Framing,
+    },
+}
+
+/// How a regex guard splits the interface byte stream into packets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Framing {
+    /// `"\x3a.*"`: a packet begins at the delimiter and lasts until the next
+    /// one.
+    Start(Vec<u8>),
+    /// `'.*\x3f'`: a packet begins where the previous one ended and lasts
+    /// until the delimiter.
+    End(Vec<u8>),
+    /// `"\x3a.*\x3f"`: a packet begins at the start delimiter and lasts until
+    /// the end delimiter.
+    Delimited { start: Vec<u8>, end: Vec<u8> },
+    /// No literal delimiter: a packet is the shortest match of the whole
+    /// pattern, beginning where the previous one ended.
+    Continuation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Guard {
+    /// Whether `value` can be packed into this guard without losing bits,
+    /// either as an unsigned or as a two's complement integer.
+    pub fn fits(&self, value: isize) -> bool {
+        match self {
+            Guard::Default => fits_width(value, 1),
+            Guard::Numeric { width, .. } => fits_width(value, *width),
+            Guard::Regex { .. } => true,
+        }
+    }
+
+    /// Compiles a regex guard pattern the same way for every stage that
+    /// matches packets against it.
+    pub fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+        RegexBuilder::new(pattern)
+            .unicode(false)
+            .dot_matches_new_line(true)
+            .build()
+    }
+
+    /// Parses a guard pattern into the syntax tree that framing and the
+    /// code generators work from, with the settings of `build_regex`.
+    pub fn parse_pattern(pattern: &str) -> Result<Hir, String> {
+        ParserBuilder::new()
+            .unicode(false)
+            .allow_invalid_utf8(true)
+            .dot_matches_new_line(true)
+            .build()
+            .parse(pattern)
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Whether `value` fits in `width` bytes as either an unsigned or a two's
+/// complement integer.
+pub fn fits_width(value: isize, width: usize) -> bool {
+    let bits = 8 * width as u32;
+    let value = value as i128;
+    value >= -(1i128 << (bits - 1)) && value < (1i128 << bits)
+}
+
+impl Display for Guard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Guard::Default => Ok(()),
+            Guard::Numeric {
+                width,
+                endianness: Endianness::Little,
+            } => write!(f, "::{}", width),
+            Guard::Numeric {
+                width,
+                endianness: Endianness::Big,
+            } => write!(f, "::-{}", width),
+            Guard::Regex { pattern, .. } => write!(f, "::\"{}\"", pattern),
+        }
+    }
+}
+
+impl Framing {
+    fn classify(hir: &Hir) -> Framing {
+        let items = match hir.kind() {
+            HirKind::Concat(items) => items.as_slice(),
+            _ => std::slice::from_ref(hir),
+        };
+
+        let prefix_len = items.iter().take_while(|item| is_literal(item)).count();
+        if prefix_len == items.len() {
+            return Framing::End(literal_bytes(items));
+        }
+        let suffix_len = items[prefix_len..]
+            .iter()
+            .rev()
+            .take_while(|item| is_literal(item))
+            .count();
+
+        let start = literal_bytes(&items[..prefix_len]);
+        let end = literal_bytes(&items[items.len() - suffix_len..]);
+        match (start.is_empty(), end.is_empty()) {
+            (false, false) => Framing::Delimited { start, end },
+            (false, true) => Framing::Start(start),
+            (true, false) => Framing::End(end),
+            (true, true) => Framing::Continuation,
+        }
+    }
+}
+
+fn uses_anchor(hir: &Hir) -> bool {
+    match hir.kind() {
+        HirKind::Anchor(_) | HirKind::WordBoundary(_) => true,
+        HirKind::Group(group) => uses_anchor(&group.hir),
+        HirKind::Repetition(repetition) => uses_anchor(&repetition.hir),
+        HirKind::Concat(items) | HirKind::Alternation(items) => items.iter().any(uses_anchor),
+        HirKind::Empty | HirKind::Literal(_) | HirKind::Class(_) => false,
+    }
+}
+
+fn is_literal(hir: &Hir) -> bool {
+    matches!(hir.kind(), HirKind::Literal(_))
+}
+
+fn literal_bytes(items: &[Hir]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for item in items {
+        match item.kind() {
+            HirKind::Literal(hir::Literal::Unicode(c)) => {
+                bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes())
+            }
+            HirKind::Literal(hir::Literal::Byte(b)) => bytes.push(*b),
+            _ => {}
+        }
+    }
+    bytes
 }
 
 #[derive(Debug)]
@@ -57,6 +251,10 @@ pub enum Statement {
         variable: String,
         expression: Expression,
     },
+    Unpack {
+        targets: Vec<(String, Guard)>,
+        expression: Expression,
+    },
     Delay {
         time: usize,
     },
@@ -80,24 +278,63 @@ pub enum Statement {
     },
     Match {
         target: Expression,
-        cases: Vec<(Literal, Vec<Statement>)>,
-        default: Vec<Statement>,
+        cases: Vec<MatchArm>,
+        default: Option<Vec<Statement>>,
+        span: Span,
     },
     Return {
         expression: Expression,
     },
     FunctionCall(FunctionCall),
+    /// `record[index] = expression`
+    ElementAssignment {
+        record: String,
+        index: Expression,
+        expression: Expression,
+        span: Span,
+    },
+    /// `f.start(...) @ interval` whose task handle is not kept.
+    Start {
+        function_call: FunctionCall,
+        interval: Expression,
+    },
+}
+
+/// A `match` arm such as `0x00 | 0x10 => ...` or `0x20..0x2f => ...`.
+#[derive(Debug)]
+pub struct MatchArm {
+    pub patterns: Vec<(Pattern, Span)>,
+    pub body: Vec<Statement>,
 }
 
 #[derive(Debug)]
+pub enum Pattern {
+    Literal(Literal),
+    /// Inclusive on both ends.
+    Range(isize, isize),
+}
+
+#[derive(Debug, Clone)]
 pub enum Expression {
     Literal(Literal),
+    Variable(String),
+    List(Vec<Expression>),
+    /// `record[index]`
+    Element {
+        record: String,
+        index: Box<Expression>,
+        span: Span,
+    },
     Equal(Box<Expression>, Box<Expression>),
     NotEqual(Box<Expression>, Box<Expression>),
     Less(Box<Expression>, Box<Expression>),
     Greater(Box<Expression>, Box<Expression>),
     LessOrEqual(Box<Expression>, Box<Expression>),
     GreaterOrEqual(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Xor(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
     Sum(Box<Expression>, Box<Expression>),
     Minus(Box<Expression>, Box<Expression>),
     Multiply(Box<Expression>, Box<Expression>),
@@ -109,16 +346,19 @@ pub enum Expression {
     FunctionCall(FunctionCall),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FunctionCall {
-    name: String,
-    arguments: Vec<Box<Expression>>,
+    pub name: String,
+    pub arguments: Vec<Expression>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Literal {
     Integer(isize),
-    String(String),
+    /// The bytes of the string with its escapes decoded.
+    String(Vec<u8>),
+    /// Packed bytes, which only constant folding writes.
+    Bytes(Vec<u8>),
     Boolean(bool),
     Nil,
 }
@@ -147,6 +387,11 @@ pub fn parse(mut tokens: Vec<Token>) -> Result<Vec<AST>, String> {
             continue;
         }
 
+        if let Some(Statement::Store { var_list }) = parse_store(&mut tokens)? {
+            ast_list.push(AST::Store { var_list });
+            continue;
+        }
+
         return Err("Statement isn't valid".to_string());
     }
 
@@ -158,7 +403,7 @@ macro_rules! check_first_keyword {
         if $tokens.is_empty() {
             return Ok(None);
         }
-        if $tokens.get(0).unwrap() != &Token::new(TokenKind::Keyword, $kw.to_string()) {
+        if $tokens.first().unwrap() != &Token::new(TokenKind::Keyword, $kw.to_string()) {
             return Ok(None);
         }
         $tokens.remove(0);
@@ -167,7 +412,7 @@ macro_rules! check_first_keyword {
 
 macro_rules! retrieve_tokenkind {
     ($tokens: expr, $tk_kind: expr, $err_msg: expr) => {{
-        let tk = $tokens.get(0).ok_or($err_msg.to_string())?;
+        let tk = $tokens.first().ok_or($err_msg.to_string())?;
         if tk.kind() != &$tk_kind {
             return Err($err_msg.to_string());
         }
@@ -177,7 +422,7 @@ macro_rules! retrieve_tokenkind {
 
 macro_rules! retrieve_tokenkind_or_none {
     ($tokens: expr, $tk_kind: expr, $err_msg: expr) => {{
-        let tk = $tokens.get(0).ok_or($err_msg.to_string())?;
+        let tk = $tokens.first().ok_or($err_msg.to_string())?;
         if tk.kind() != &$tk_kind {
             None
         } else {
@@ -188,7 +433,7 @@ macro_rules! retrieve_tokenkind_or_none {
 
 macro_rules! retrieve_token {
     ($tokens: expr, $tk: expr, $err_msg: expr) => {{
-        let tk = $tokens.get(0).ok_or($err_msg.to_string())?;
+        let tk = $tokens.first().ok_or($err_msg.to_string())?;
         if tk != &$tk {
             return Err($err_msg.to_string());
         }
@@ -198,7 +443,7 @@ macro_rules! retrieve_token {
 
 macro_rules! retrieve_token_or_none {
     ($tokens: expr, $tk: expr, $err_msg: expr) => {{
-        let tk = $tokens.get(0).ok_or($err_msg.to_string())?;
+        let tk = $tokens.first().ok_or($err_msg.to_string())?;
         if tk != &$tk {
             None
         } else {
@@ -219,12 +464,14 @@ fn parse_function(tokens: &mut Vec<Token>) -> Result<Option<AST>, String> {
 
     let arguments = parse_argument_name_list(tokens)?;
 
-    let body = parse_block_statement(tokens)?;
+    let mut lines = vec![];
+    let body = parse_block_statement(tokens, &mut lines)?;
 
     Ok(Some(AST::Function {
         name,
         arguments,
         body,
+        lines,
     }))
 }
 
@@ -259,24 +506,28 @@ fn parse_task(tokens: &mut Vec<Token>) -> Result<Option<AST>, String> {
 
     let interval_ms = parse_task_interval(tokens)?;
 
-    let body = parse_block_statement(tokens)?;
+    let mut lines = vec![];
+    let body = parse_block_statement(tokens, &mut lines)?;
 
     Ok(Some(AST::Task {
         name,
         interval_ms,
         body,
+        lines,
     }))
 }
 
 fn parse_when(tokens: &mut Vec<Token>) -> Result<Option<AST>, String> {
+    let span = tokens.first().map(Token::span).unwrap_or_default();
     check_first_keyword!(tokens, "when");
 
-    let interface = retrieve_tokenkind!(
-        tokens,
-        TokenKind::StringLiteral,
-        "The 'when' keyword requires a string literal"
-    )
-    .to_string();
+    let interface = tokens
+        .first()
+        .ok_or("The 'when' keyword requires a string literal")?;
+    if interface.kind() != &TokenKind::StringLiteral {
+        return Err("The 'when' keyword requires a string literal".to_string());
+    }
+    let interface = String::from_utf8_lossy(&decode_string(&tokens.remove(0))?).to_string();
 
     retrieve_tokenkind!(
         tokens,
@@ -291,33 +542,35 @@ fn parse_when(tokens: &mut Vec<Token>) -> Result<Option<AST>, String> {
     )
     .to_string();
 
-    retrieve_tokenkind!(
-        tokens,
-        TokenKind::GuardOperator,
-        "Not found :: after 'when' packet variable"
-    );
-
-    let guard = if let Some(numeric_guard) =
-        retrieve_tokenkind_or_none!(tokens, TokenKind::IntegerLiteral, "Not find guard")
-    {
-        Guard::Numeric(numeric_guard.value().parse::<isize>().unwrap())
-    } else if let Some(regex_guard) =
-        retrieve_tokenkind_or_none!(tokens, TokenKind::StringLiteral, "Not find guard")
-    {
-        Guard::Regex(regex_guard.value().to_string())
-    } else {
-        return Err(
-            "The next token after :: must be a integer literal or a string literal".to_string(),
-        );
-    };
+    let guard =
+        if retrieve_tokenkind_or_none!(tokens, TokenKind::GuardOperator, "Not found 'end' keyword")
+            .is_none()
+        {
+            Guard::Default
+        } else if let Some(numeric_guard) =
+            retrieve_tokenkind_or_none!(tokens, TokenKind::IntegerLiteral, "Not find guard")
+        {
+            parse_numeric_guard(&numeric_guard)?
+        } else if let Some(regex_guard) =
+            retrieve_tokenkind_or_none!(tokens, TokenKind::StringLiteral, "Not find guard")
+        {
+            parse_regex_guard(&regex_guard)?
+        } else {
+            return Err(
+                "The next token after :: must be a integer literal or a string literal".to_string(),
+            );
+        };
 
-    let body = parse_block_statement(tokens)?;
+    let mut lines = vec![];
+    let body = parse_block_statement(tokens, &mut lines)?;
 
     Ok(Some(AST::When {
         interface,
         packet,
         guard,
         body,
+        lines,
+        span,
     }))
 }
 
@@ -336,11 +589,13 @@ fn parse_argument_name_list(tokens: &mut Vec<Token>) -> Result<Vec<String>, Stri
         arg_name_list.push(first_arg.value().to_string());
 
         loop {
-            if let Some(_) = retrieve_token_or_none!(
+            if retrieve_token_or_none!(
                 tokens,
                 Token::new(TokenKind::Delimiter, ")".to_string()),
                 "Missing a close bracket"
-            ) {
+            )
+            .is_some()
+            {
                 break;
             }
 
@@ -371,55 +626,52 @@ fn parse_argument_name_list(tokens: &mut Vec<Token>) -> Result<Vec<String>, Stri
 }
 
 fn parse_record_info(tokens: &mut Vec<Token>) -> Result<(usize, usize), String> {
-    let open_brace = tokens.get(0).ok_or("Missing a open brace")?;
-    if open_brace != &Token::new(TokenKind::Delimiter, "[".to_string()) {
-        return Err("Missing a open brace".to_string());
-    }
-    tokens.remove(0);
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, "[".to_string()),
+        "Missing a open brace"
+    );
 
-    let length = tokens.get(0).ok_or("Missing record length".to_string())?;
-    if length.kind() != &TokenKind::IntegerLiteral {
-        return Err("Missing record length".to_string());
-    }
-    let length = tokens.remove(0).value().parse::<isize>().unwrap();
-    if length < 0 {
-        return Err("The record length cannot be negative".to_string());
+    let length =
+        consume_tokenkind(tokens, TokenKind::IntegerLiteral).ok_or("Missing record length")?;
+    let length = parse_integer(&length)?;
+    if length < 1 {
+        return Err("The record length must be at least 1".to_string());
     }
 
-    let next_tok = tokens.get(0).ok_or("Missing close brace".to_string())?;
-    let data_size = if next_tok == &Token::new(TokenKind::Delimiter, ",".to_string()) {
-        let data_size = tokens
-            .get(0)
-            .ok_or("Missing record data size".to_string())?;
-        if data_size.kind() != &TokenKind::IntegerLiteral {
-            return Err("Missing record data size".to_string());
-        }
-        let data_size = tokens.remove(0).value().parse::<isize>().unwrap();
-        if data_size < 0 {
-            return Err("The record data size cannot be negative".to_string());
-        }
-        data_size
-    } else if next_tok == &Token::new(TokenKind::Delimiter, "]".to_string()) {
-        tokens.remove(0);
-        1
-    } else {
-        return Err("Missing a close brace".to_string());
-    };
+    let data_size =
+        if consume_token(tokens, Token::new(TokenKind::Delimiter, ",".to_string())).is_some() {
+            let data_size = consume_tokenkind(tokens, TokenKind::IntegerLiteral)
+                .ok_or("Missing record data size")?;
+            let data_size = parse_integer(&data_size)?;
+            if !(1..=MAX_GUARD_WIDTH as isize).contains(&data_size) {
+                return Err(format!(
+                    "The record data size must be between 1 and {} bytes",
+                    MAX_GUARD_WIDTH
+                ));
+            }
+            data_size
+        } else {
+            1
+        };
 
-    let semicolon = tokens
-        .get(0)
-        .ok_or("Missing ; at end of record statement")?;
-    if semicolon != &Token::new(TokenKind::Delimiter, ";".to_string()) {
-        return Err("Missing ; at end of record statement".to_string());
-    }
-    tokens.remove(0);
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, "]".to_string()),
+        "Missing close brace"
+    );
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, ";".to_string()),
+        "Missing ; at end of record statement"
+    );
 
     Ok((length as usize, data_size as usize))
 }
 
 fn parse_task_interval(tokens: &mut Vec<Token>) -> Result<usize, String> {
-    if let Some(_) =
-        retrieve_tokenkind_or_none!(tokens, TokenKind::TimeOperator, "Missing time operator")
+    if retrieve_tokenkind_or_none!(tokens, TokenKind::TimeOperator, "Missing time operator")
+        .is_some()
     {
         let interval_ms = retrieve_tokenkind!(
             tokens,
@@ -438,25 +690,34 @@ fn parse_task_interval(tokens: &mut Vec<Token>) -> Result<usize, String> {
     }
 }
 
-fn parse_block_statement(tokens: &mut Vec<Token>) -> Result<Vec<Statement>, String> {
+fn parse_block_statement(
+    tokens: &mut Vec<Token>,
+    lines: &mut Vec<usize>,
+) -> Result<Vec<Statement>, String> {
     let mut statements = vec![];
 
     loop {
-        if let Some(_) = retrieve_token_or_none!(
+        if retrieve_token_or_none!(
             tokens,
             Token::new(TokenKind::Keyword, "end".to_string()),
             "Not found 'end' keyword"
-        ) {
+        )
+        .is_some()
+        {
             break;
         }
 
-        statements.push(parse_statement(tokens)?);
+        statements.push(parse_statement(tokens, lines)?);
     }
 
     Ok(statements)
 }
 
-fn parse_statement(tokens: &mut Vec<Token>) -> Result<Statement, String> {
+/// Parses a statement, adding its line to `lines` before the lines of the
+/// statements nested in it.
+fn parse_statement(tokens: &mut Vec<Token>, lines: &mut Vec<usize>) -> Result<Statement, String> {
+    lines.push(tokens.first().map(Token::line).unwrap_or_default());
+
     if let Some(statement) = parse_assignment(tokens)? {
         return Ok(statement);
     }
@@ -481,6 +742,14 @@ fn parse_statement(tokens: &mut Vec<Token>) -> Result<Statement, String> {
         return Ok(statement);
     }
 
+    if let Some(statement) = parse_unpack(tokens)? {
+        return Ok(statement);
+    }
+
+    if let Some(statement) = parse_element_assignment(tokens)? {
+        return Ok(statement);
+    }
+
     if let Some(statement) = parse_delay(tokens)? {
         return Ok(statement);
     }
@@ -489,19 +758,19 @@ fn parse_statement(tokens: &mut Vec<Token>) -> Result<Statement, String> {
         return Ok(statement);
     }
 
-    if let Some(statement) = parse_if(tokens)? {
+    if let Some(statement) = parse_if(tokens, lines)? {
         return Ok(statement);
     }
 
-    if let Some(statement) = parse_for(tokens)? {
+    if let Some(statement) = parse_for(tokens, lines)? {
         return Ok(statement);
     }
 
-    if let Some(statement) = parse_while(tokens)? {
+    if let Some(statement) = parse_while(tokens, lines)? {
         return Ok(statement);
     }
 
-    if let Some(statement) = parse_match(tokens)? {
+    if let Some(statement) = parse_match(tokens, lines)? {
         return Ok(statement);
     }
 
@@ -513,61 +782,1139 @@ fn parse_statement(tokens: &mut Vec<Token>) -> Result<Statement, String> {
         return Ok(statement);
     }
 
-    return Err("Statement isn't valid".to_string());
+    Err("Statement isn't valid".to_string())
+}
+
+fn parse_statements_until(
+    tokens: &mut Vec<Token>,
+    terminators: &[&str],
+    lines: &mut Vec<usize>,
+) -> Result<Vec<Statement>, String> {
+    let mut statements = vec![];
+
+    loop {
+        let tk = tokens.first().ok_or("Not found 'end' keyword")?;
+        if tk.kind() == &TokenKind::Keyword && terminators.contains(&tk.value()) {
+            break;
+        }
+
+        statements.push(parse_statement(tokens, lines)?);
+    }
+
+    Ok(statements)
+}
+
+fn consume_semicolon(tokens: &mut Vec<Token>) {
+    if tokens.first() == Some(&Token::new(TokenKind::Delimiter, ";".to_string())) {
+        tokens.remove(0);
+    }
+}
+
+fn consume_token(tokens: &mut Vec<Token>, token: Token) -> Option<Token> {
+    if tokens.first() == Some(&token) {
+        Some(tokens.remove(0))
+    } else {
+        None
+    }
+}
+
+fn consume_tokenkind(tokens: &mut Vec<Token>, tk_kind: TokenKind) -> Option<Token> {
+    if tokens.first().map(Token::kind) == Some(&tk_kind) {
+        Some(tokens.remove(0))
+    } else {
+        None
+    }
+}
+
+fn parse_assignment_operator(
+    tokens: &mut Vec<Token>,
+    operator: &str,
+) -> Result<Option<(String, Expression)>, String> {
+    if tokens.len() < 2
+        || tokens[0].kind() != &TokenKind::Identifier
+        || tokens[1] != Token::new(TokenKind::AssignOperator, operator.to_string())
+    {
+        return Ok(None);
+    }
+
+    let variable = tokens.remove(0).value().to_string();
+    tokens.remove(0);
+
+    let expression = parse_expression(tokens)?;
+    consume_semicolon(tokens);
+
+    Ok(Some((variable, expression)))
+}
+
+fn parse_element_assignment(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
+    match tokens.first() {
+        Some(tk) if tk.kind() == &TokenKind::Identifier && starts_index(tk, &tokens[1..]) => {}
+        _ => return Ok(None),
+    }
+
+    // `record[index]` may also begin an expression statement, such as
+    // `registers[0].print()`, so look for the `=` after the closing brace.
+    let mut depth = 0;
+    let close = tokens[1..].iter().position(|tk| {
+        if tk.kind() == &TokenKind::Delimiter && tk.value() == "[" {
+            depth += 1;
+        } else if tk.kind() == &TokenKind::Delimiter && tk.value() == "]" {
+            depth -= 1;
+        }
+        depth == 0
+    });
+    match close.and_then(|close| tokens.get(close + 2)) {
+        Some(tk) if tk == &Token::new(TokenKind::AssignOperator, "=".to_string()) => {}
+        _ => return Ok(None),
+    }
+
+    let identifier = tokens.remove(0);
+    let index = parse_index(tokens)?;
+    tokens.remove(0);
+    let expression = parse_expression(tokens)?;
+    consume_semicolon(tokens);
+
+    Ok(Some(Statement::ElementAssignment {
+        record: identifier.value().to_string(),
+        index,
+        expression,
+        span: identifier.span(),
+    }))
 }
 
 fn parse_assignment(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    Ok(
+        parse_assignment_operator(tokens, "=")?.map(|(variable, expression)| {
+            Statement::Assignment {
+                variable,
+                expression,
+            }
+        }),
+    )
 }
 
 fn parse_assignment_sum(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    Ok(
+        parse_assignment_operator(tokens, "+=")?.map(|(variable, expression)| {
+            Statement::AssignmentSum {
+                variable,
+                expression,
+            }
+        }),
+    )
 }
 
 fn parse_assignment_minus(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    Ok(
+        parse_assignment_operator(tokens, "-=")?.map(|(variable, expression)| {
+            Statement::AssignmentMinus {
+                variable,
+                expression,
+            }
+        }),
+    )
 }
 
 fn parse_assignment_mult(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    Ok(
+        parse_assignment_operator(tokens, "*=")?.map(|(variable, expression)| {
+            Statement::AssignmentMult {
+                variable,
+                expression,
+            }
+        }),
+    )
 }
 
 fn parse_assignment_div(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    Ok(
+        parse_assignment_operator(tokens, "/=")?.map(|(variable, expression)| {
+            Statement::AssignmentDiv {
+                variable,
+                expression,
+            }
+        }),
+    )
 }
 
 fn parse_assignment_mod(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    Ok(
+        parse_assignment_operator(tokens, "%=")?.map(|(variable, expression)| {
+            Statement::AssignmentMod {
+                variable,
+                expression,
+            }
+        }),
+    )
+}
+
+fn parse_unpack(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
+    if consume_token(tokens, Token::new(TokenKind::Delimiter, "[".to_string())).is_none() {
+        return Ok(None);
+    }
+
+    let mut targets = vec![];
+    loop {
+        let variable = retrieve_tokenkind!(
+            tokens,
+            TokenKind::Identifier,
+            "The targets of a matching assignment must be identifiers"
+        )
+        .to_string();
+
+        let guard = if consume_tokenkind(tokens, TokenKind::GuardOperator).is_some() {
+            let width = tokens
+                .first()
+                .ok_or("Not found the target width after ::")?;
+            if width.kind() != &TokenKind::IntegerLiteral {
+                return Err("The target width after :: must be an integer literal".to_string());
+            }
+            parse_numeric_guard(&tokens.remove(0))?
+        } else {
+            Guard::Numeric {
+                width: 1,
+                endianness: Endianness::Little,
+            }
+        };
+        targets.push((variable, guard));
+
+        if consume_token(tokens, Token::new(TokenKind::Delimiter, "]".to_string())).is_some() {
+            break;
+        }
+
+        retrieve_token!(
+            tokens,
+            Token::new(TokenKind::Delimiter, ",".to_string()),
+            "Missing comma after matching assignment target"
+        );
+    }
+
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::AssignOperator, "=".to_string()),
+        "Not found = after matching assignment targets"
+    );
+
+    let expression = parse_expression(tokens)?;
+    consume_semicolon(tokens);
+
+    Ok(Some(Statement::Unpack {
+        targets,
+        expression,
+    }))
 }
 
 fn parse_delay(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    if consume_tokenkind(tokens, TokenKind::TimeOperator).is_none() {
+        return Ok(None);
+    }
+
+    let time = tokens.first().ok_or("Not found delay time after @")?;
+    if time.kind() != &TokenKind::IntegerLiteral {
+        return Err("Not found delay time after @".to_string());
+    }
+    let time = parse_integer(&tokens.remove(0))?;
+    if time < 0 {
+        return Err("The delay time cannot be a negative integer".to_string());
+    }
+    consume_semicolon(tokens);
+
+    Ok(Some(Statement::Delay {
+        time: time as usize,
+    }))
 }
 
 fn parse_store(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    check_first_keyword!(tokens, "store");
+
+    let mut var_list = vec![retrieve_tokenkind!(
+        tokens,
+        TokenKind::Identifier,
+        "The 'store' keyword requires an identifier"
+    )
+    .to_string()];
+
+    while consume_token(tokens, Token::new(TokenKind::Delimiter, ",".to_string())).is_some() {
+        var_list.push(
+            retrieve_tokenkind!(
+                tokens,
+                TokenKind::Identifier,
+                "The 'store' variables must be identifiers"
+            )
+            .to_string(),
+        );
+    }
+    consume_semicolon(tokens);
+
+    Ok(Some(Statement::Store { var_list }))
 }
 
-fn parse_if(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+fn parse_if(tokens: &mut Vec<Token>, lines: &mut Vec<usize>) -> Result<Option<Statement>, String> {
+    check_first_keyword!(tokens, "if");
+
+    let condition = parse_expression(tokens)?;
+    let body = parse_statements_until(tokens, &["elif", "else", "end"], lines)?;
+
+    let mut elif = vec![];
+    let mut else_body = vec![];
+    loop {
+        if consume_token(tokens, Token::new(TokenKind::Keyword, "elif".to_string())).is_some() {
+            let elif_condition = parse_expression(tokens)?;
+            let elif_body = parse_statements_until(tokens, &["elif", "else", "end"], lines)?;
+            elif.push((elif_condition, elif_body));
+        } else if consume_token(tokens, Token::new(TokenKind::Keyword, "else".to_string()))
+            .is_some()
+        {
+            else_body = parse_block_statement(tokens, lines)?;
+            break;
+        } else {
+            retrieve_token!(
+                tokens,
+                Token::new(TokenKind::Keyword, "end".to_string()),
+                "Not found 'end' keyword"
+            );
+            break;
+        }
+    }
+
+    Ok(Some(Statement::If {
+        condition,
+        body,
+        elif,
+        else_body,
+    }))
 }
 
-fn parse_for(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+fn parse_for(tokens: &mut Vec<Token>, lines: &mut Vec<usize>) -> Result<Option<Statement>, String> {
+    check_first_keyword!(tokens, "for");
+
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, "(".to_string()),
+        "Missing an open bracket after 'for' keyword"
+    );
+
+    let var = retrieve_tokenkind!(
+        tokens,
+        TokenKind::Identifier,
+        "The 'for' keyword requires an identifier"
+    )
+    .to_string();
+
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Keyword, "in".to_string()),
+        "Not found 'in' keyword after 'for' variable"
+    );
+
+    let collection = retrieve_tokenkind!(
+        tokens,
+        TokenKind::Identifier,
+        "The 'for' collection must be an identifier"
+    )
+    .to_string();
+
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, ")".to_string()),
+        "Missing a close bracket"
+    );
+
+    let body = parse_block_statement(tokens, lines)?;
+
+    Ok(Some(Statement::For {
+        var,
+        collection,
+        body,
+    }))
 }
 
-fn parse_while(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+fn parse_while(
+    tokens: &mut Vec<Token>,
+    lines: &mut Vec<usize>,
+) -> Result<Option<Statement>, String> {
+    check_first_keyword!(tokens, "while");
+
+    let condition = parse_expression(tokens)?;
+    let body = parse_block_statement(tokens, lines)?;
+
+    Ok(Some(Statement::While { condition, body }))
 }
 
-fn parse_match(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+fn parse_match(
+    tokens: &mut Vec<Token>,
+    lines: &mut Vec<usize>,
+) -> Result<Option<Statement>, String> {
+    let span = tokens.first().map(Token::span).unwrap_or_default();
+    check_first_keyword!(tokens, "match");
+
+    let target = parse_expression(tokens)?;
+
+    let mut cases = vec![];
+    let mut default = None;
+    // The default arm is visited after the others, wherever it is written.
+    let mut default_lines = vec![];
+    loop {
+        if consume_token(tokens, Token::new(TokenKind::Keyword, "end".to_string())).is_some() {
+            break;
+        }
+
+        if let Some(default_operator) = consume_tokenkind(tokens, TokenKind::MatchDefaultOperator) {
+            if default.is_some() {
+                return Err(format!(
+                    "The match statement already has a default arm at line {}, column {}",
+                    default_operator.line(),
+                    default_operator.column()
+                ));
+            }
+            retrieve_tokenkind!(
+                tokens,
+                TokenKind::RightArrow,
+                "Not found right arrow (=>) after match default operator"
+            );
+            default = Some(parse_match_arm_body(tokens, &mut default_lines)?);
+            continue;
+        }
+
+        let mut patterns = vec![parse_match_pattern(tokens)?];
+        while consume_tokenkind(tokens, TokenKind::AlternativeOperator).is_some() {
+            patterns.push(parse_match_pattern(tokens)?);
+        }
+        retrieve_tokenkind!(
+            tokens,
+            TokenKind::RightArrow,
+            "Not found right arrow (=>) after match pattern"
+        );
+        cases.push(MatchArm {
+            patterns,
+            body: parse_match_arm_body(tokens, lines)?,
+        });
+    }
+    lines.extend(default_lines);
+
+    Ok(Some(Statement::Match {
+        target,
+        cases,
+        default,
+        span,
+    }))
+}
+
+fn parse_match_pattern(tokens: &mut Vec<Token>) -> Result<(Pattern, Span), String> {
+    let span = tokens.first().map(Token::span).unwrap_or_default();
+    let literal = parse_literal(tokens)?
+        .ok_or("The match arms must start with a literal or the default operator (_)")?;
+
+    if consume_tokenkind(tokens, TokenKind::RangeOperator).is_none() {
+        return Ok((Pattern::Literal(literal), span));
+    }
+
+    let start = match literal {
+        Literal::Integer(start) => start,
+        _ => return Err(format!("The range bounds must be integers at {}", span)),
+    };
+    let end = match parse_literal(tokens)? {
+        Some(Literal::Integer(end)) => end,
+        _ => return Err(format!("The range bounds must be integers at {}", span)),
+    };
+    if start > end {
+        return Err(format!("The range {}..{} is empty at {}", start, end, span));
+    }
+
+    Ok((Pattern::Range(start, end), span))
+}
+
+fn parse_match_arm_body(
+    tokens: &mut Vec<Token>,
+    lines: &mut Vec<usize>,
+) -> Result<Vec<Statement>, String> {
+    if consume_token(tokens, Token::new(TokenKind::Keyword, "do".to_string())).is_some() {
+        parse_block_statement(tokens, lines)
+    } else {
+        Ok(vec![parse_statement(tokens, lines)?])
+    }
 }
 
 fn parse_return(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    check_first_keyword!(tokens, "return");
+
+    let tk = tokens.first().ok_or("Not found 'end' keyword")?;
+    let expression = if tk == &Token::new(TokenKind::Delimiter, ";".to_string())
+        || tk == &Token::new(TokenKind::Keyword, "end".to_string())
+    {
+        Expression::Literal(Literal::Nil)
+    } else {
+        parse_expression(tokens)?
+    };
+    consume_semicolon(tokens);
+
+    Ok(Some(Statement::Return { expression }))
 }
 
 fn parse_statement_function_call(tokens: &mut Vec<Token>) -> Result<Option<Statement>, String> {
-    todo!()
+    let statement = match parse_expression(tokens)? {
+        Expression::Time(target, interval) => match into_function_call(*target) {
+            Some(function_call) if function_call.name == "start" => Statement::Start {
+                function_call,
+                interval: *interval,
+            },
+            _ => return Ok(None),
+        },
+        expression => match into_function_call(expression) {
+            Some(function_call) => Statement::FunctionCall(function_call),
+            None => return Ok(None),
+        },
+    };
+    consume_semicolon(tokens);
+
+    Ok(Some(statement))
+}
+
+/// Turns `a.f(b)` into `f(a, b)`.
+fn into_function_call(expression: Expression) -> Option<FunctionCall> {
+    match expression {
+        Expression::FunctionCall(function_call) => Some(function_call),
+        Expression::Pipe(target, mut function_call) => {
+            function_call.arguments.insert(0, *target);
+            Some(function_call)
+        }
+        _ => None,
+    }
+}
+
+fn parse_expression(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let expression = parse_or(tokens)?;
+
+    if consume_tokenkind(tokens, TokenKind::TimeOperator).is_some() {
+        let time = parse_or(tokens)?;
+        return Ok(Expression::Time(Box::new(expression), Box::new(time)));
+    }
+
+    Ok(expression)
+}
+
+fn parse_or(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let mut lhs = parse_and(tokens)?;
+
+    loop {
+        if consume_token(tokens, Token::new(TokenKind::Keyword, "or".to_string())).is_some() {
+            lhs = Expression::Or(Box::new(lhs), Box::new(parse_and(tokens)?));
+        } else if consume_token(tokens, Token::new(TokenKind::Keyword, "xor".to_string())).is_some()
+        {
+            lhs = Expression::Xor(Box::new(lhs), Box::new(parse_and(tokens)?));
+        } else {
+            return Ok(lhs);
+        }
+    }
+}
+
+fn parse_and(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let mut lhs = parse_not(tokens)?;
+
+    while consume_token(tokens, Token::new(TokenKind::Keyword, "and".to_string())).is_some() {
+        lhs = Expression::And(Box::new(lhs), Box::new(parse_not(tokens)?));
+    }
+
+    Ok(lhs)
+}
+
+fn parse_not(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    if consume_token(tokens, Token::new(TokenKind::Keyword, "not".to_string())).is_some() {
+        return Ok(Expression::Not(Box::new(parse_not(tokens)?)));
+    }
+
+    parse_comparison(tokens)
+}
+
+fn parse_comparison(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let lhs = parse_additive(tokens)?;
+
+    let operator = match consume_tokenkind(tokens, TokenKind::CompareOperator) {
+        Some(operator) => operator,
+        None => return Ok(lhs),
+    };
+
+    let lhs = Box::new(lhs);
+    let rhs = Box::new(parse_additive(tokens)?);
+    Ok(match operator.value() {
+        "==" => Expression::Equal(lhs, rhs),
+        "!=" => Expression::NotEqual(lhs, rhs),
+        "<" => Expression::Less(lhs, rhs),
+        ">" => Expression::Greater(lhs, rhs),
+        "<=" => Expression::LessOrEqual(lhs, rhs),
+        ">=" => Expression::GreaterOrEqual(lhs, rhs),
+        other => {
+            return Err(format!(
+                "Unknown compare operator '{}' at line {}, column {}",
+                other,
+                operator.line(),
+                operator.column()
+            ))
+        }
+    })
+}
+
+fn parse_additive(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let mut lhs = parse_multiplicative(tokens)?;
+
+    loop {
+        let tk = match tokens.first() {
+            Some(tk) => tk,
+            None => return Ok(lhs),
+        };
+
+        if tk.kind() == &TokenKind::IntegerLiteral && tk.value().starts_with('-') {
+            // `a -1` is lexed as an identifier followed by a negative literal
+            let tk = tokens.remove(0);
+            let mut magnitude = Token::new(TokenKind::IntegerLiteral, tk.value()[1..].to_string());
+            magnitude.set_line(tk.line());
+            magnitude.set_column(tk.column() + 1);
+            tokens.insert(0, magnitude);
+
+            lhs = Expression::Minus(Box::new(lhs), Box::new(parse_multiplicative(tokens)?));
+        } else if tk == &Token::new(TokenKind::ArithmeticOperator, "+".to_string()) {
+            tokens.remove(0);
+            lhs = Expression::Sum(Box::new(lhs), Box::new(parse_multiplicative(tokens)?));
+        } else if tk == &Token::new(TokenKind::ArithmeticOperator, "-".to_string()) {
+            tokens.remove(0);
+            lhs = Expression::Minus(Box::new(lhs), Box::new(parse_multiplicative(tokens)?));
+        } else {
+            return Ok(lhs);
+        }
+    }
+}
+
+fn parse_multiplicative(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let mut lhs = parse_postfix(tokens)?;
+
+    loop {
+        let operator = match consume_tokenkind(tokens, TokenKind::ArithmeticOperator) {
+            Some(operator) => operator,
+            None => return Ok(lhs),
+        };
+
+        let lhs_box = Box::new(lhs);
+        lhs = match operator.value() {
+            "*" => Expression::Multiply(lhs_box, Box::new(parse_postfix(tokens)?)),
+            "/" => Expression::Division(lhs_box, Box::new(parse_postfix(tokens)?)),
+            "%" => Expression::Modulus(lhs_box, Box::new(parse_postfix(tokens)?)),
+            _ => {
+                tokens.insert(0, operator);
+                return Ok(*lhs_box);
+            }
+        };
+    }
+}
+
+fn parse_postfix(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    let mut expression = parse_primary(tokens)?;
+
+    loop {
+        if consume_tokenkind(tokens, TokenKind::PipeOperator).is_some() {
+            let name = retrieve_tokenkind!(
+                tokens,
+                TokenKind::Identifier,
+                "The pipe operator (.) requires a function name"
+            )
+            .to_string();
+            let arguments = parse_argument_list(tokens)?;
+
+            expression = Expression::Pipe(Box::new(expression), FunctionCall { name, arguments });
+        } else if let Some(operator) = consume_tokenkind(tokens, TokenKind::GuardOperator) {
+            let width = tokens.first().ok_or("Not found the width after ::")?;
+            if width.kind() != &TokenKind::IntegerLiteral {
+                return Err(format!(
+                    "The width after :: must be an integer literal at line {}, column {}",
+                    operator.line(),
+                    operator.column()
+                ));
+            }
+            let width = tokens.remove(0);
+            let guard = parse_numeric_guard(&width)?;
+
+            if let Expression::Literal(Literal::Integer(value)) = expression {
+                if !guard.fits(value) {
+                    return Err(format!(
+                        "The value {} does not fit in {} byte(s) at line {}, column {}",
+                        value,
+                        width.value().trim_start_matches('-'),
+                        width.line(),
+                        width.column()
+                    ));
+                }
+            }
+
+            expression = Expression::Guard(Box::new(expression), guard);
+        } else {
+            return Ok(expression);
+        }
+    }
+}
+
+fn parse_primary(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    if let Some(literal) = parse_literal(tokens)? {
+        return Ok(Expression::Literal(literal));
+    }
+
+    let tk = tokens.first().ok_or("Expected an expression")?;
+    match tk.kind() {
+        TokenKind::Identifier => {
+            let identifier = tokens.remove(0);
+            let name = identifier.value().to_string();
+            if tokens.first() == Some(&Token::new(TokenKind::Delimiter, "(".to_string())) {
+                let arguments = parse_argument_list(tokens)?;
+                Ok(Expression::FunctionCall(FunctionCall { name, arguments }))
+            } else if starts_index(&identifier, tokens) {
+                Ok(Expression::Element {
+                    record: name,
+                    index: Box::new(parse_index(tokens)?),
+                    span: identifier.span(),
+                })
+            } else {
+                Ok(Expression::Variable(name))
+            }
+        }
+        TokenKind::Delimiter if tk.value() == "[" => {
+            tokens.remove(0);
+            let mut items = vec![];
+            if consume_token(tokens, Token::new(TokenKind::Delimiter, "]".to_string())).is_none() {
+                loop {
+                    items.push(parse_expression(tokens)?);
+                    if consume_token(tokens, Token::new(TokenKind::Delimiter, "]".to_string()))
+                        .is_some()
+                    {
+                        break;
+                    }
+                    retrieve_token!(
+                        tokens,
+                        Token::new(TokenKind::Delimiter, ",".to_string()),
+                        "Missing comma after list item"
+                    );
+                }
+            }
+            Ok(Expression::List(items))
+        }
+        TokenKind::Delimiter if tk.value() == "(" => {
+            tokens.remove(0);
+            let expression = parse_expression(tokens)?;
+            retrieve_token!(
+                tokens,
+                Token::new(TokenKind::Delimiter, ")".to_string()),
+                "Missing a close bracket"
+            );
+            Ok(expression)
+        }
+        _ => Err(format!(
+            "Unexpected '{}' at line {}, column {}",
+            tk.value(),
+            tk.line(),
+            tk.column()
+        )),
+    }
+}
+
+/// Whether `[` follows `identifier` on the same line. On another line it
+/// begins a new statement, such as `[a, b] = msg`.
+fn starts_index(identifier: &Token, tokens: &[Token]) -> bool {
+    tokens.first().is_some_and(|tk| {
+        tk == &Token::new(TokenKind::Delimiter, "[".to_string()) && tk.line() == identifier.line()
+    })
+}
+
+fn parse_index(tokens: &mut Vec<Token>) -> Result<Expression, String> {
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, "[".to_string()),
+        "Missing an open brace"
+    );
+    let index = parse_expression(tokens)?;
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, "]".to_string()),
+        "Missing a close brace after the index"
+    );
+    Ok(index)
+}
+
+fn parse_argument_list(tokens: &mut Vec<Token>) -> Result<Vec<Expression>, String> {
+    let mut arguments = vec![];
+
+    retrieve_token!(
+        tokens,
+        Token::new(TokenKind::Delimiter, "(".to_string()),
+        "Missing an open bracket"
+    );
+
+    if consume_token(tokens, Token::new(TokenKind::Delimiter, ")".to_string())).is_some() {
+        return Ok(arguments);
+    }
+
+    loop {
+        arguments.push(parse_expression(tokens)?);
+
+        if consume_token(tokens, Token::new(TokenKind::Delimiter, ")".to_string())).is_some() {
+            return Ok(arguments);
+        }
+
+        retrieve_token!(
+            tokens,
+            Token::new(TokenKind::Delimiter, ",".to_string()),
+            "Missing comma after argument"
+        );
+    }
+}
+
+fn parse_literal(tokens: &mut Vec<Token>) -> Result<Option<Literal>, String> {
+    let tk = match tokens.first() {
+        Some(tk) => tk,
+        None => return Ok(None),
+    };
+
+    let literal = match tk.kind() {
+        TokenKind::IntegerLiteral => Literal::Integer(parse_integer(tk)?),
+        TokenKind::StringLiteral => Literal::String(decode_string(tk)?),
+        TokenKind::BooleanLiteral => Literal::Boolean(tk.value() == "true"),
+        TokenKind::NilLiteral => Literal::Nil,
+        _ => return Ok(None),
+    };
+    tokens.remove(0);
+
+    Ok(Some(literal))
+}
+
+fn decode_string(tk: &Token) -> Result<Vec<u8>, String> {
+    let value = tk.value();
+    let mut bytes = vec![];
+
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('\'') => b'\'',
+            Some('"') => b'"',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).map_err(|_| {
+                    format!(
+                        "Invalid escape \\x{} in {} at line {}, column {}",
+                        hex,
+                        value,
+                        tk.line(),
+                        tk.column()
+                    )
+                })?
+            }
+            other => {
+                return Err(format!(
+                    "Invalid escape \\{} in {} at line {}, column {}",
+                    other.map(String::from).unwrap_or_default(),
+                    value,
+                    tk.line(),
+                    tk.column()
+                ))
+            }
+        };
+        bytes.push(escaped);
+    }
+
+    Ok(bytes)
+}
+
+fn parse_integer(tk: &Token) -> Result<isize, String> {
+    let value = tk.value();
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+
+    let magnitude = match digits.strip_prefix("0x") {
+        Some(hex) => isize::from_str_radix(hex, 16),
+        None => digits.parse::<isize>(),
+    }
+    .map_err(|_| {
+        format!(
+            "The integer {} is out of range at line {}, column {}",
+            value,
+            tk.line(),
+            tk.column()
+        )
+    })?;
+
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+fn parse_numeric_guard(tk: &Token) -> Result<Guard, String> {
+    let value = parse_integer(tk)?;
+
+    let width = value.unsigned_abs();
+    if width == 0 || width > MAX_GUARD_WIDTH {
+        return Err(format!(
+            "The guard width must be between 1 and {} bytes, found {} at line {}, column {}",
+            MAX_GUARD_WIDTH,
+            value,
+            tk.line(),
+            tk.column()
+        ));
+    }
+
+    Ok(Guard::Numeric {
+        width,
+        endianness: if value < 0 {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        },
+    })
+}
+
+fn parse_regex_guard(tk: &Token) -> Result<Guard, String> {
+    let value = tk.value();
+    let pattern = value[1..value.len() - 1].to_string();
+
+    let regex = Guard::build_regex(&pattern).map_err(|err| {
+        format!(
+            "Invalid guard pattern {} at line {}, column {}:\n{}",
+            value,
+            tk.line(),
+            tk.column(),
+            err
+        )
+    })?;
+    if regex.is_match(b"") {
+        return Err(format!(
+            "The guard pattern {} matches an empty packet at line {}, column {}",
+            value,
+            tk.line(),
+            tk.column()
+        ));
+    }
+
+    let hir = Guard::parse_pattern(&pattern)?;
+    if uses_anchor(&hir) {
+        return Err(format!(
+            "The guard pattern {} uses an anchor (^, $, \\b or \\B) at line {}, column {}",
+            value,
+            tk.line(),
+            tk.column()
+        ));
+    }
+
+    Ok(Guard::Regex {
+        framing: Framing::classify(&hir),
+        pattern,
+    })
+}
+
+/// Parses `source`, which a test expects to be a valid program.
+#[cfg(test)]
+pub(crate) fn parse_source(source: &str) -> Vec<AST> {
+    parse(crate::lexer::tokenizer(source.to_string()).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+
+    fn try_parse(source: &str) -> Result<Vec<AST>, String> {
+        parse(lexer::tokenizer(source.to_string())?)
+    }
+
+    /// The statements of the only task of `source`.
+    fn task_body(source: &str) -> Vec<Statement> {
+        match parse_source(source).pop() {
+            Some(AST::Task { body, .. }) => body,
+            other => panic!("expected a task, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_guard_widths_out_of_range() {
+        for width in ["0", "9", "-9"] {
+            let source = format!("when \"uart\" => msg::{}\nend", width);
+            let err = try_parse(&source).unwrap_err();
+            assert!(
+                err.starts_with("The guard width must be between 1 and 8 bytes"),
+                "{}",
+                err
+            );
+        }
+        assert!(try_parse("when \"uart\" => msg::8\nend").is_ok());
+    }
+
+    #[test]
+    fn rejects_literals_wider_than_their_guard() {
+        let err = try_parse("task main @ 10\n    x = 300::1;\nend").unwrap_err();
+        assert_eq!(
+            err,
+            "The value 300 does not fit in 1 byte(s) at line 2, column 14"
+        );
+        assert!(try_parse("task main @ 10\n    x = 255::1;\n    y = -128::1;\nend").is_ok());
+    }
+
+    #[test]
+    fn parses_record_sizes() {
+        let sizes = |source: &str| match parse_source(source).pop() {
+            Some(AST::Record {
+                length, data_size, ..
+            }) => (length, data_size),
+            other => panic!("expected a record, found {:?}", other),
+        };
+        assert_eq!(sizes("record r[2,4];"), (2, 4));
+        assert_eq!(sizes("record r[2, 4];"), (2, 4));
+        assert_eq!(sizes("record r[2];"), (2, 1));
+        assert_eq!(
+            try_parse("record r[2, 9];").unwrap_err(),
+            "The record data size must be between 1 and 8 bytes"
+        );
+    }
+
+    #[test]
+    fn handlers_without_a_guard_get_the_default() {
+        match parse_source("when 'uart' => msg\nend").pop() {
+            Some(AST::When {
+                interface,
+                packet,
+                guard: Guard::Default,
+                ..
+            }) => assert_eq!((interface.as_str(), packet.as_str()), ("uart", "msg")),
+            other => panic!("expected a handler without a guard, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_widths_are_big_endian() {
+        let ast = parse_source("when \"spi\" => word::-4\nend");
+        match &ast[0] {
+            AST::When {
+                guard:
+                    Guard::Numeric {
+                        width: 4,
+                        endianness: Endianness::Big,
+                    },
+                ..
+            } => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    /// The guard of the only handler of a program with `guard` after its
+    /// packet.
+    fn guard(guard: &str) -> Result<Guard, String> {
+        match try_parse(&format!("when \"uart\" => msg::{}\nend", guard))?.pop() {
+            Some(AST::When { guard, .. }) => Ok(guard),
+            other => panic!("expected a handler, found {:?}", other),
+        }
+    }
+
+    fn framing(pattern: &str) -> Framing {
+        match guard(pattern).unwrap() {
+            Guard::Regex { framing, .. } => framing,
+            other => panic!("expected a regex guard, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn classifies_regex_framing() {
+        assert_eq!(
+            framing(r#""\x3a.*\x3f""#),
+            Framing::Delimited {
+                start: b":".to_vec(),
+                end: b"?".to_vec()
+            }
+        );
+        assert_eq!(framing(r#""\x3a.*""#), Framing::Start(b":".to_vec()));
+        assert_eq!(framing(r"'.*\x3f'"), Framing::End(b"?".to_vec()));
+        assert_eq!(framing(r#""[0-9]+""#), Framing::Continuation);
+    }
+
+    #[test]
+    fn rejects_invalid_regex_guards() {
+        let err = guard(r#""(ab""#).unwrap_err();
+        assert!(
+            err.starts_with("Invalid guard pattern \"(ab\" at line 1"),
+            "{}",
+            err
+        );
+
+        let err = guard(r#""a*""#).unwrap_err();
+        assert_eq!(
+            err,
+            "The guard pattern \"a*\" matches an empty packet at line 1, column 21"
+        );
+
+        for pattern in [r#""^:.*""#, r#""a.*$""#, r#""\bab""#] {
+            let err = guard(pattern).unwrap_err();
+            assert!(err.contains("uses an anchor"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn parses_unpack_targets_with_widths() {
+        let body = task_body("task main @ 10\n    msg = 0;\n    [a, b::2] = msg;\nend");
+        match &body[1] {
+            Statement::Unpack {
+                targets,
+                expression: Expression::Variable(source),
+            } => {
+                let targets: Vec<_> = targets
+                    .iter()
+                    .map(|(name, guard)| (name.as_str(), guard.to_string()))
+                    .collect();
+                assert_eq!(
+                    targets,
+                    [("a", "::1".to_string()), ("b", "::2".to_string())]
+                );
+                assert_eq!(source, "msg");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_logic_operators_by_precedence() {
+        let body = task_body("task main @ 10\n    x = not a and b or [c, 1] == d;\nend");
+        match &body[0] {
+            Statement::Assignment {
+                expression: Expression::Or(lhs, rhs),
+                ..
+            } => {
+                match &**lhs {
+                    Expression::And(not, _) => assert!(matches!(&**not, Expression::Not(_))),
+                    other => panic!("unexpected {:?}", other),
+                }
+                match &**rhs {
+                    Expression::Equal(list, _) => {
+                        assert!(matches!(&**list, Expression::List(items) if items.len() == 2))
+                    }
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn negative_literal_after_operand_is_subtraction() {
+        let body = task_body("task main @ 10\n    b = a -1;\nend");
+        match &body[0] {
+            Statement::Assignment {
+                expression: Expression::Minus(lhs, rhs),
+                ..
+            } => {
+                assert!(matches!(&**lhs, Expression::Variable(name) if name == "a"));
+                assert!(matches!(&**rhs, Expression::Literal(Literal::Integer(1))));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
 }
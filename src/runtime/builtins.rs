@@ -0,0 +1,297 @@
+use crate::runtime::random::Random;
+use crate::runtime::trace::Event;
+use crate::runtime::value::{TaskId, Value};
+use std::io::Write;
+
+/// What the builtins need from the interpreter or the bytecode VM running
+/// the program.
+pub trait Host {
+    /// The virtual time, in milliseconds, since the program started.
+    fn now(&self) -> u64;
+
+    fn output(&mut self) -> &mut dyn Write;
+
+    fn random(&mut self) -> &mut Random;
+
+    /// Logs an event at the current virtual time when tracing.
+    fn trace(&mut self, event: Event) -> Result<(), String>;
+
+    /// Sends bytes on an interface. Interfaces that were not configured
+    /// write what is sent to the program output.
+    fn send(&mut self, interface: &str, bytes: Vec<u8>) -> Result<(), String>;
+
+    /// Turns a function into a task that runs it with `args` on every tick.
+    fn spawn_function(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        interval_ms: usize,
+        immediate: bool,
+    ) -> Result<TaskId, String>;
+
+    /// Starts a task over, as `Scheduler::restart` does.
+    fn restart_task(
+        &mut self,
+        id: TaskId,
+        interval_ms: Option<usize>,
+        immediate: bool,
+    ) -> Result<(), String>;
+
+    fn stop_task(&mut self, id: TaskId) -> Result<(), String>;
+}
+
+/// Runs the builtin called `name`, or returns `None` when there is no such
+/// builtin.
+pub fn call(host: &mut impl Host, name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    Some(match name {
+        "print" => print(host, args),
+        "send" => send(host, args),
+        "millis" => millis(host, args),
+        "rand" => rand(host, args),
+        "start" => start(host, args, None),
+        "stop" => stop(host, args),
+        _ => return None,
+    })
+}
+
+/// `print(format, args...)` writes `format` replacing `%d` (decimal
+/// integer), `%x` (hexadecimal integer), `%s` and `%a` (any value) by the
+/// next argument, and `%%` by `%`.
+fn print(host: &mut impl Host, args: &[Value]) -> Result<Value, String> {
+    let text = format(args)?;
+    host.trace(Event::Print { text: &text })?;
+    host.output()
+        .write_all(&text)
+        .and_then(|_| host.output().flush())
+        .map_err(|err| err.to_string())?;
+    Ok(Value::Nil)
+}
+
+fn format(args: &[Value]) -> Result<Vec<u8>, String> {
+    let (format, mut args) = match args.split_first() {
+        Some((Value::String(format), args)) => (format, args.iter()),
+        _ => return Err("print requires a format string".to_string()),
+    };
+
+    let mut text = vec![];
+    let mut chars = format.iter();
+    while let Some(&c) = chars.next() {
+        if c != b'%' {
+            text.push(c);
+            continue;
+        }
+
+        let directive = chars.next().ok_or("The format string cannot end with %")?;
+        if *directive == b'%' {
+            text.push(b'%');
+            continue;
+        }
+
+        let arg = args
+            .next()
+            .ok_or("Missing argument for the format string")?;
+        let formatted = match directive {
+            b'd' => arg.as_integer()?.to_string(),
+            b'x' => format!("{:x}", arg.as_integer()?),
+            b's' | b'a' => arg.to_string(),
+            other => return Err(format!("Unknown format directive %{}", *other as char)),
+        };
+        text.extend(formatted.as_bytes());
+    }
+
+    if args.next().is_some() {
+        return Err("Too many arguments for the format string".to_string());
+    }
+
+    Ok(text)
+}
+
+/// `send(interface, data...)` sends the bytes of every argument after the
+/// interface name.
+fn send(host: &mut impl Host, args: &[Value]) -> Result<Value, String> {
+    let (interface, data) = match args.split_first() {
+        Some((Value::String(interface), data)) => (String::from_utf8_lossy(interface), data),
+        _ => return Err("send requires an interface name".to_string()),
+    };
+    let bytes = Value::List(data.to_vec()).to_bytes()?;
+
+    host.send(&interface, bytes)?;
+    Ok(Value::Nil)
+}
+
+/// `millis()` is the virtual time, in milliseconds, since the program started.
+fn millis(host: &mut impl Host, args: &[Value]) -> Result<Value, String> {
+    if !args.is_empty() {
+        return Err("millis takes no arguments".to_string());
+    }
+    Ok(Value::Integer(host.now() as i64))
+}
+
+/// `rand(min, max)` is a pseudo-random integer from `min` included to `max`
+/// excluded. The numbers depend only on the seed of the run, set with
+/// `--seed`.
+fn rand(host: &mut impl Host, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [min, max] => {
+            let value = host.random().range(min.as_integer()?, max.as_integer()?)?;
+            Ok(Value::Integer(value))
+        }
+        _ => Err("rand takes a minimum and a maximum".to_string()),
+    }
+}
+
+/// `function.start(args, immediate) @ interval` runs `function(args...)` as
+/// a task every `interval` milliseconds and returns its handle. `args`
+/// defaults to `[]`. When `immediate` is `false` the first tick waits one
+/// interval; it defaults to `true`. Without `@` the task runs once.
+///
+/// `task.start() @ interval` starts a declared or previously started task
+/// over, keeping its interval unless a new one is given.
+pub fn start(
+    host: &mut impl Host,
+    args: &[Value],
+    interval_ms: Option<usize>,
+) -> Result<Value, String> {
+    let (target, args) = args
+        .split_first()
+        .ok_or("start requires a function or a task")?;
+
+    let (task_args, immediate) = match args {
+        [] => (vec![], true),
+        [task_args] => (task_args.items()?, true),
+        [task_args, immediate] => (task_args.items()?, immediate.as_boolean()?),
+        _ => return Err("start takes an argument list and a boolean".to_string()),
+    };
+
+    match target {
+        Value::Function(name) => {
+            let id = host.spawn_function(name, task_args, interval_ms.unwrap_or(0), immediate)?;
+            Ok(Value::Task(id))
+        }
+        Value::Task(id) if task_args.is_empty() => {
+            host.restart_task(*id, interval_ms, immediate)?;
+            Ok(Value::Task(*id))
+        }
+        Value::Task(_) => Err("A task cannot be started with new arguments".to_string()),
+        other => Err(format!("Cannot start {}", other.type_name())),
+    }
+}
+
+/// `task.stop()` stops a task. Stopping a stopped task, or `nil` for a task
+/// that was never started, does nothing.
+fn stop(host: &mut impl Host, args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::Task(id)] => host.stop_task(*id)?,
+        [Value::Nil] => {}
+        [other] => return Err(format!("Cannot stop {}", other.type_name())),
+        _ => return Err("stop takes a single task".to_string()),
+    }
+    Ok(Value::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+    use crate::runtime::{run_captured, Options};
+    use std::fs;
+
+    /// What `source` prints in its first `ms` simulated milliseconds, with
+    /// how the run ended, after receiving the chunks of `capture`, if any.
+    fn run(source: &str, ms: u64, capture: Option<&str>) -> (String, Result<(), String>) {
+        let program = parser::parse_source(source);
+        let path = capture.map(|capture| {
+            let path =
+                std::env::temp_dir().join(format!("nxc-builtins-{}.nxcap", std::process::id()));
+            fs::write(&path, format!("# nxcap 1\n{}", capture)).unwrap();
+            path
+        });
+        let options = Options {
+            sim_time_ms: Some(ms),
+            seed: Some(1),
+            replay_path: path.clone(),
+            ..Options::default()
+        };
+        let result = run_captured(&program, &options);
+        if let Some(path) = path {
+            fs::remove_file(path).unwrap();
+        }
+        result
+    }
+
+    const BEAT: &str = "
+        function beat()
+            print(\"%d \", millis());
+        end
+    ";
+
+    #[test]
+    fn restarts_a_stopped_task() {
+        let source = format!(
+            "{}
+            task main
+                t = beat.start() @ 100;
+                @250;
+                t.stop();
+                t.stop();
+                @200;
+                t.start() @ 100;
+            end",
+            BEAT
+        );
+        assert_eq!(
+            run(&source, 600, None),
+            ("0 100 200 450 550 ".to_string(), Ok(()))
+        );
+    }
+
+    #[test]
+    fn stopping_nil_does_nothing() {
+        let source = "
+            task main
+                t = nil;
+                t.stop();
+                print(\"stopped\");
+            end
+        ";
+        assert_eq!(run(source, 100, None), ("stopped".to_string(), Ok(())));
+    }
+
+    #[test]
+    fn rejects_new_arguments_for_a_task() {
+        let source = format!(
+            "{}
+            task main
+                t = beat.start() @ 100;
+                t.start([1]);
+            end",
+            BEAT
+        );
+        let (output, result) = run(&source, 100, None);
+        assert_eq!(output, "");
+        assert!(result
+            .unwrap_err()
+            .contains("A task cannot be started with new arguments"));
+    }
+
+    #[test]
+    fn keeps_a_stored_handle_across_packets() {
+        let source = format!(
+            "{}
+            when \"uart\" => msg::1
+                store t;
+                [op] = msg;
+                match (op)
+                    1 => t = beat.start([], false) @ 100;
+                    2 => t.stop();
+                    _ => print(\"unknown \");
+                end
+            end",
+            BEAT
+        );
+        let capture = "50 uart 01\n330 uart 02\n";
+        assert_eq!(
+            run(&source, 700, Some(capture)),
+            ("150 250 ".to_string(), Ok(()))
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::runtime::trace::hex;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The first line of every capture file.
+const HEADER: &str = "# nxcap 1";
+
+/// Bytes received on an interface at a virtual time.
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    pub time_ms: u64,
+    pub interface: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Writes the traffic received during a run to a capture file: a header
+/// line, then one `<time> <interface> <hex bytes>` line per chunk, such as
+/// `150 uart 3a61623f`, in the order the chunks arrived.
+pub struct Recorder {
+    output: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let mut output = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|err| format!("Cannot create {}: {}", path.display(), err))?;
+        writeln!(output, "{}", HEADER).map_err(|err| err.to_string())?;
+        Ok(Self { output })
+    }
+
+    pub fn record(&mut self, time_ms: u64, interface: &str, bytes: &[u8]) -> Result<(), String> {
+        writeln!(self.output, "{} {} {}", time_ms, interface, hex(bytes))
+            .and_then(|_| self.output.flush())
+            .map_err(|err| format!("Cannot write the capture: {}", err))
+    }
+}
+
+/// Reads the chunks of a capture file written by a `Recorder`, sorted by
+/// time. The first line that is not empty must be the header of this
+/// version; after it, empty lines and lines starting with `#` are skipped.
+pub fn load(path: &Path) -> Result<Vec<Chunk>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    match lines.next() {
+        Some((_, HEADER)) => {}
+        Some((_, line)) if line.starts_with("# nxcap ") => {
+            return Err(format!(
+                "Unsupported capture version {} in {}",
+                &line["# nxcap ".len()..],
+                path.display()
+            ))
+        }
+        _ => {
+            return Err(format!(
+                "{} is not a capture file: it must start with '{}'",
+                path.display(),
+                HEADER
+            ))
+        }
+    }
+
+    let mut chunks = vec![];
+    for (line_number, line) in lines {
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let chunk = parse_chunk(line).ok_or_else(|| {
+            format!(
+                "Invalid capture file {} at line {}",
+                path.display(),
+                line_number + 1
+            )
+        })?;
+        chunks.push(chunk);
+    }
+
+    chunks.sort_by_key(|chunk| chunk.time_ms);
+    Ok(chunks)
+}
+
+/// Parses `<time> <interface> <hex bytes>`; the interface name may hold
+/// spaces.
+fn parse_chunk(line: &str) -> Option<Chunk> {
+    let (time_ms, rest) = line.split_once(' ')?;
+    let (interface, bytes) = rest.rsplit_once(' ')?;
+    if interface.is_empty() || bytes.len() % 2 != 0 {
+        return None;
+    }
+
+    let bytes = (0..bytes.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(bytes.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    Some(Chunk {
+        time_ms: time_ms.parse().ok()?,
+        interface: interface.to_string(),
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_chunks() {
+        assert_eq!(
+            parse_chunk("150 uart 3a61623f"),
+            Some(Chunk {
+                time_ms: 150,
+                interface: "uart".to_string(),
+                bytes: vec![0x3a, 0x61, 0x62, 0x3f],
+            })
+        );
+        assert_eq!(
+            parse_chunk("7 debug port 00FF"),
+            Some(Chunk {
+                time_ms: 7,
+                interface: "debug port".to_string(),
+                bytes: vec![0x00, 0xff],
+            })
+        );
+        assert_eq!(parse_chunk("150 uart 3a6"), None);
+        assert_eq!(parse_chunk("150 uart 3g"), None);
+        assert_eq!(parse_chunk("150  3a"), None);
+        assert_eq!(parse_chunk("soon uart 3a"), None);
+    }
+
+    #[test]
+    fn loads_chunks_sorted_by_time() {
+        let path = std::env::temp_dir().join(format!("nxc-capture-{}.nxcap", std::process::id()));
+        fs::write(
+            &path,
+            "# nxcap 1\n200 uart 02\n\n100 debug port 01\n# comment\n200 uart 03\n",
+        )
+        .unwrap();
+        let chunks = load(&path).unwrap();
+        let order: Vec<_> = chunks
+            .iter()
+            .map(|chunk| (chunk.time_ms, chunk.interface.as_str(), chunk.bytes[0]))
+            .collect();
+        assert_eq!(
+            order,
+            [(100, "debug port", 1), (200, "uart", 2), (200, "uart", 3)]
+        );
+
+        fs::write(&path, "# nxcap 1\n100 uart 01\n200 uart 012\n").unwrap();
+        let result = load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result,
+            Err(format!("Invalid capture file {} at line 3", path.display()))
+        );
+    }
+
+    #[test]
+    fn requires_the_header_of_this_version() {
+        let path =
+            std::env::temp_dir().join(format!("nxc-capture-header-{}.nxcap", std::process::id()));
+        let load_text = |text: &str| {
+            fs::write(&path, text).unwrap();
+            load(&path)
+        };
+
+        let accepted = load_text("\n# nxcap 1\n100 uart 01\n");
+        let headerless = load_text("100 uart 01\n");
+        let commented = load_text("# recorded on the bench\n# nxcap 1\n100 uart 01\n");
+        let newer = load_text("# nxcap 2\n100 uart 01\n");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(accepted.map(|chunks| chunks.len()), Ok(1));
+        let not_a_capture = Err(format!(
+            "{} is not a capture file: it must start with '# nxcap 1'",
+            path.display()
+        ));
+        assert_eq!(headerless, not_a_capture);
+        assert_eq!(commented, not_a_capture);
+        assert_eq!(
+            newer,
+            Err(format!(
+                "Unsupported capture version 2 in {}",
+                path.display()
+            ))
+        );
+    }
+}
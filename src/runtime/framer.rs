@@ -0,0 +1,598 @@
+use crate::parser::{Framing, Guard};
+use regex::bytes::Regex;
+
+/// Limits that keep a broken byte stream from stalling an interface.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The longest packet a handler can receive.
+    pub max_packet_len: usize,
+    /// How long a partial packet waits for its next byte.
+    pub timeout_ms: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_packet_len: 256,
+            timeout_ms: 100,
+        }
+    }
+}
+
+/// What the framer makes of incoming bytes.
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    /// A packet for the handler with the given id.
+    Packet { handler: usize, bytes: Vec<u8> },
+    /// Bytes that no handler can use.
+    Garbage(Vec<u8>),
+}
+
+/// Splits the byte stream of one interface into packets for its `when`
+/// handlers, which are given in dispatch order.
+///
+/// A packet is emitted as soon as its last byte arrives. When several
+/// handlers complete a packet on the same byte, the first one in dispatch
+/// order gets it, and the bytes before the start of its packet are garbage.
+/// The packets therefore do not depend on how the stream is split into reads.
+///
+/// Each handler frames packets as follows:
+///
+/// - Fixed width: the next `width` bytes.
+/// - `Framing::End`: from the beginning of the buffer to the first end
+///   delimiter where the pattern matches.
+/// - `Framing::Delimited`: from the last start delimiter before an end
+///   delimiter to that end delimiter, so a new start delimiter restarts an
+///   unfinished packet.
+/// - `Framing::Start`: from a start delimiter to the next one, which stays in
+///   the buffer, or to the timeout.
+/// - `Framing::Continuation`: the shortest beginning of the buffer that the
+///   pattern matches.
+///
+/// Only the first fixed-width handler frames packets: the ones after it are
+/// shadowed, as the dispatch analysis warns.
+///
+/// While no packet is complete, the bytes before the first one any handler
+/// can still use are garbage. A partial packet longer than the maximum
+/// length loses its first byte, so that the stream resynchronizes, and one
+/// that receives no byte for the timeout is dropped.
+pub struct Framer {
+    handlers: Vec<Handler>,
+    limits: Limits,
+    buffer: Vec<u8>,
+    last_byte_at: u64,
+}
+
+struct Handler {
+    id: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Width(usize),
+    /// `regex` only matches whole packets.
+    Regex {
+        regex: Regex,
+        framing: Framing,
+    },
+}
+
+enum Candidate {
+    /// A packet at `start..end`, complete once the first `ready` bytes of
+    /// the buffer arrived.
+    Complete {
+        start: usize,
+        end: usize,
+        ready: usize,
+    },
+    /// No packet yet; the handler may still use the bytes from `from` on.
+    Waiting { from: usize },
+}
+
+impl Framer {
+    pub fn new(handlers: &[(usize, &Guard)], limits: Limits) -> Self {
+        let first_fixed_width = handlers
+            .iter()
+            .position(|(_, guard)| !matches!(guard, Guard::Regex { .. }));
+        let handlers = handlers
+            .iter()
+            .enumerate()
+            .filter(|(position, (_, guard))| {
+                matches!(guard, Guard::Regex { .. }) || Some(*position) == first_fixed_width
+            })
+            .map(|(_, &(id, guard))| {
+                let kind = match guard {
+                    Guard::Default => Kind::Width(1),
+                    Guard::Numeric { width, .. } => Kind::Width(*width),
+                    Guard::Regex { pattern, framing } => Kind::Regex {
+                        regex: Guard::build_regex(&format!("^(?:{})$", pattern))
+                            .expect("guard patterns are checked by the parser"),
+                        framing: framing.clone(),
+                    },
+                };
+                Handler { id, kind }
+            })
+            .collect();
+
+        Self {
+            handlers,
+            limits,
+            buffer: vec![],
+            last_byte_at: 0,
+        }
+    }
+
+    /// Adds bytes received at the virtual time `now` and frames what they
+    /// complete.
+    pub fn push(&mut self, bytes: &[u8], now: u64) -> Vec<Frame> {
+        if bytes.is_empty() {
+            return vec![];
+        }
+        self.buffer.extend(bytes);
+        self.last_byte_at = now;
+        self.frame(false)
+    }
+
+    /// When the buffered partial packet times out, if any.
+    pub fn deadline(&self) -> Option<u64> {
+        if self.buffer.is_empty() {
+            None
+        } else {
+            Some(self.last_byte_at + self.limits.timeout_ms)
+        }
+    }
+
+    /// Ends the buffered partial packet once it timed out: a start-delimited
+    /// packet is complete, anything else is garbage.
+    pub fn expire(&mut self, now: u64) -> Vec<Frame> {
+        match self.deadline() {
+            Some(deadline) if deadline <= now => self.frame(true),
+            _ => vec![],
+        }
+    }
+
+    fn frame(&mut self, idle: bool) -> Vec<Frame> {
+        let mut frames = vec![];
+        let mut garbage = vec![];
+
+        while !self.buffer.is_empty() {
+            let mut complete: Option<(usize, usize, usize, usize)> = None;
+            let mut from = self.buffer.len();
+            for handler in &self.handlers {
+                match self.candidate(handler, idle) {
+                    Candidate::Complete { start, end, ready } => {
+                        if complete.is_none_or(|(_, _, _, best)| ready < best) {
+                            complete = Some((handler.id, start, end, ready));
+                        }
+                    }
+                    Candidate::Waiting { from: handler_from } => from = from.min(handler_from),
+                }
+            }
+
+            if let Some((handler, start, end, _)) = complete {
+                garbage.extend(self.buffer.drain(..start));
+                if !garbage.is_empty() {
+                    frames.push(Frame::Garbage(std::mem::take(&mut garbage)));
+                }
+                let bytes = self.buffer.drain(..end - start).collect();
+                frames.push(Frame::Packet { handler, bytes });
+            } else if from > 0 {
+                garbage.extend(self.buffer.drain(..from));
+            } else if self.buffer.len() > self.limits.max_packet_len {
+                garbage.push(self.buffer.remove(0));
+            } else {
+                break;
+            }
+        }
+
+        if idle {
+            garbage.append(&mut self.buffer);
+        }
+        if !garbage.is_empty() {
+            frames.push(Frame::Garbage(garbage));
+        }
+        frames
+    }
+
+    fn candidate(&self, handler: &Handler, idle: bool) -> Candidate {
+        let buffer = &self.buffer;
+        let max = self.limits.max_packet_len;
+
+        let (regex, framing) = match &handler.kind {
+            Kind::Width(width) if buffer.len() >= *width => {
+                return Candidate::Complete {
+                    start: 0,
+                    end: *width,
+                    ready: *width,
+                }
+            }
+            Kind::Width(_) => return Candidate::Waiting { from: 0 },
+            Kind::Regex { regex, framing } => (regex, framing),
+        };
+        let matches =
+            |start: usize, end: usize| end - start <= max && regex.is_match(&buffer[start..end]);
+
+        match framing {
+            Framing::End(end) => occurrences(buffer, end)
+                .map(|position| position + end.len())
+                .find(|&stop| matches(0, stop))
+                .map_or(Candidate::Waiting { from: 0 }, |stop| Candidate::Complete {
+                    start: 0,
+                    end: stop,
+                    ready: stop,
+                }),
+            Framing::Continuation => (1..=buffer.len()).find(|&stop| matches(0, stop)).map_or(
+                Candidate::Waiting { from: 0 },
+                |stop| Candidate::Complete {
+                    start: 0,
+                    end: stop,
+                    ready: stop,
+                },
+            ),
+            Framing::Delimited { start, end } => {
+                let starts: Vec<usize> = occurrences(buffer, start).collect();
+                for position in occurrences(buffer, end) {
+                    let stop = position + end.len();
+                    let begin = starts
+                        .iter()
+                        .rev()
+                        .find(|&&begin| begin + start.len() <= position);
+                    if let Some(&begin) = begin {
+                        if matches(begin, stop) {
+                            return Candidate::Complete {
+                                start: begin,
+                                end: stop,
+                                ready: stop,
+                            };
+                        }
+                    }
+                }
+                Candidate::Waiting {
+                    from: match starts.last() {
+                        Some(&begin) => begin,
+                        None => buffer.len() - partial_suffix(buffer, start),
+                    },
+                }
+            }
+            Framing::Start(start) => {
+                let starts: Vec<usize> = occurrences(buffer, start).collect();
+                let mut begin = match starts.first() {
+                    Some(&begin) => begin,
+                    None => {
+                        return Candidate::Waiting {
+                            from: buffer.len() - partial_suffix(buffer, start),
+                        }
+                    }
+                };
+                loop {
+                    match starts.iter().find(|&&next| next >= begin + start.len()) {
+                        Some(&next) if matches(begin, next) => {
+                            return Candidate::Complete {
+                                start: begin,
+                                end: next,
+                                ready: next + start.len(),
+                            }
+                        }
+                        Some(&next) => begin = next,
+                        None if idle && matches(begin, buffer.len()) => {
+                            return Candidate::Complete {
+                                start: begin,
+                                end: buffer.len(),
+                                ready: buffer.len(),
+                            }
+                        }
+                        None => return Candidate::Waiting { from: begin },
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The positions where `needle` appears in `haystack`, overlaps included.
+fn occurrences<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == needle)
+        .map(|(position, _)| position)
+}
+
+/// The length of the longest end of `buffer` that begins `delimiter`
+/// without completing it.
+fn partial_suffix(buffer: &[u8], delimiter: &[u8]) -> usize {
+    (1..delimiter.len().min(buffer.len() + 1))
+        .rev()
+        .find(|&len| buffer.ends_with(&delimiter[..len]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::dispatch;
+    use crate::parser::{self, AST};
+
+    const LIMITS: Limits = Limits {
+        max_packet_len: 16,
+        timeout_ms: 100,
+    };
+
+    /// Parses one `when "u" => p<guard>` handler per guard, so handler ids
+    /// are positions in `guards`, and frames with them in dispatch order.
+    fn framer(guards: &[&str]) -> Framer {
+        framer_with(guards, LIMITS)
+    }
+
+    fn framer_with(guards: &[&str], limits: Limits) -> Framer {
+        let source: String = guards
+            .iter()
+            .map(|guard| format!("when \"u\" => p{}\nend\n", guard))
+            .collect();
+        let program = parser::parse_source(&source);
+        let (dispatches, _) = dispatch::analyze(&program);
+
+        let handlers: Vec<(usize, &Guard)> = dispatches[0]
+            .handlers
+            .iter()
+            .map(|&index| match &program[index] {
+                AST::When { guard, .. } => (index, guard),
+                _ => unreachable!(),
+            })
+            .collect();
+        Framer::new(&handlers, limits)
+    }
+
+    fn packet(handler: usize, bytes: &[u8]) -> Frame {
+        Frame::Packet {
+            handler,
+            bytes: bytes.to_vec(),
+        }
+    }
+
+    fn garbage(bytes: &[u8]) -> Frame {
+        Frame::Garbage(bytes.to_vec())
+    }
+
+    /// Feeds `stream` one byte at a time.
+    fn push_bytes(framer: &mut Framer, stream: &[u8]) -> Vec<Frame> {
+        stream
+            .iter()
+            .flat_map(|b| framer.push(std::slice::from_ref(b), 0))
+            .collect()
+    }
+
+    /// Frames `stream` in every way of splitting it in two reads and one
+    /// byte at a time, and checks that they all agree.
+    fn frame_all_ways(guards: &[&str], stream: &[u8]) -> Vec<Frame> {
+        let expected = framer(guards).push(stream, 0);
+        for split in 0..=stream.len() {
+            let mut framer = framer(guards);
+            let mut frames = framer.push(&stream[..split], 0);
+            frames.extend(framer.push(&stream[split..], 0));
+            assert_eq!(merge_garbage(frames), expected, "split at {}", split);
+        }
+        assert_eq!(
+            merge_garbage(push_bytes(&mut framer(guards), stream)),
+            expected
+        );
+        expected
+    }
+
+    fn merge_garbage(frames: Vec<Frame>) -> Vec<Frame> {
+        let mut merged: Vec<Frame> = vec![];
+        for frame in frames {
+            match (merged.last_mut(), frame) {
+                (Some(Frame::Garbage(last)), Frame::Garbage(bytes)) => last.extend(bytes),
+                (_, frame) => merged.push(frame),
+            }
+        }
+        merged
+    }
+
+    #[test]
+    fn numeric_packets() {
+        let mut framer = framer(&["::4"]);
+        assert_eq!(framer.push(b"abc", 0), vec![]);
+        assert_eq!(framer.push(b"de", 0), vec![packet(0, b"abcd")]);
+        assert_eq!(
+            framer.push(b"fghijklm", 0),
+            vec![packet(0, b"efgh"), packet(0, b"ijkl")]
+        );
+        assert_eq!(framer.buffer, b"m");
+    }
+
+    #[test]
+    fn big_endian_width_is_the_same() {
+        assert_eq!(
+            frame_all_ways(&["::-2"], b"abcde"),
+            vec![packet(0, b"ab"), packet(0, b"cd")]
+        );
+    }
+
+    #[test]
+    fn default_guard_takes_single_bytes() {
+        assert_eq!(
+            frame_all_ways(&[""], b"xyz"),
+            vec![packet(0, b"x"), packet(0, b"y"), packet(0, b"z")]
+        );
+    }
+
+    #[test]
+    fn end_delimited_packets() {
+        assert_eq!(
+            frame_all_ways(&[r"::'.*\x3f'"], b"ab?c??d"),
+            vec![packet(0, b"ab?"), packet(0, b"c?"), packet(0, b"?")]
+        );
+    }
+
+    #[test]
+    fn end_delimited_waits_for_a_matching_packet() {
+        let mut framer = framer(&[r"::'[0-9]+\x3f'"]);
+        assert_eq!(framer.push(b"ab?", 0), vec![]);
+        assert_eq!(framer.buffer, b"ab?");
+        assert_eq!(framer.expire(100), vec![garbage(b"ab?")]);
+        assert_eq!(framer.push(b"12?", 200), vec![packet(0, b"12?")]);
+    }
+
+    #[test]
+    fn delimited_packets() {
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a.*\x3f""#], b":a?:bc?"),
+            vec![packet(0, b":a?"), packet(0, b":bc?")]
+        );
+    }
+
+    #[test]
+    fn delimited_drops_garbage_before_the_start() {
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a.*\x3f""#], b"xy?:a?z"),
+            vec![garbage(b"xy?"), packet(0, b":a?"), garbage(b"z")]
+        );
+    }
+
+    #[test]
+    fn delimited_restarts_on_a_new_start() {
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a.*\x3f""#], b":ab:cd?"),
+            vec![garbage(b":ab"), packet(0, b":cd?")]
+        );
+    }
+
+    #[test]
+    fn delimited_keeps_a_partial_start_delimiter() {
+        let mut framer = framer(&[r#"::"AB.*\x3f""#]);
+        assert_eq!(framer.push(b"xxA", 0), vec![garbage(b"xx")]);
+        assert_eq!(framer.push(b"Bz?", 0), vec![packet(0, b"ABz?")]);
+    }
+
+    #[test]
+    fn delimited_with_equal_delimiters() {
+        assert_eq!(
+            frame_all_ways(&[r#"::"~[a-z]*~""#], b"~ab~~c~"),
+            vec![packet(0, b"~ab~"), packet(0, b"~c~")]
+        );
+    }
+
+    #[test]
+    fn start_delimited_packets_end_at_the_next_start() {
+        let mut framer = framer(&[r#"::"\x3a.*""#]);
+        assert_eq!(framer.push(b"x:ab", 0), vec![garbage(b"x")]);
+        assert_eq!(framer.push(b":c", 0), vec![packet(0, b":ab")]);
+        assert_eq!(framer.buffer, b":c");
+    }
+
+    #[test]
+    fn start_delimited_packet_ends_on_timeout() {
+        let mut framer = framer(&[r#"::"\x3a.*""#]);
+        assert_eq!(framer.push(b":ab", 10), vec![]);
+        assert_eq!(framer.deadline(), Some(110));
+        assert_eq!(framer.expire(109), vec![]);
+        assert_eq!(framer.expire(110), vec![packet(0, b":ab")]);
+        assert_eq!(framer.deadline(), None);
+    }
+
+    #[test]
+    fn start_delimited_skips_packets_that_do_not_match() {
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a[0-9]+""#], b":a:1:"),
+            vec![garbage(b":a"), packet(0, b":1")]
+        );
+    }
+
+    #[test]
+    fn continuation_takes_the_shortest_match() {
+        assert_eq!(
+            frame_all_ways(&[r"::'[a-z]+[0-9]'"], b"ab1c23"),
+            vec![packet(0, b"ab1"), packet(0, b"c2")]
+        );
+    }
+
+    #[test]
+    fn earliest_complete_packet_wins() {
+        // The fixed-width handler completes on the second byte, before the
+        // end delimiter arrives.
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a.*\x3f""#, "::2"], b":ab?"),
+            vec![packet(1, b":a"), packet(1, b"b?")]
+        );
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a.*\x3f""#, "::4"], b":a?bc"),
+            vec![packet(0, b":a?")]
+        );
+    }
+
+    #[test]
+    fn dispatch_order_breaks_ties() {
+        // Both complete on the same byte; the delimited handler comes first
+        // in dispatch order even though it is declared second.
+        assert_eq!(
+            frame_all_ways(&[r"::'.*\x3f'", r#"::"\x3a.*\x3f""#], b":a?"),
+            vec![packet(1, b":a?")]
+        );
+        assert_eq!(
+            frame_all_ways(&[r"::'.*\x3f'", r#"::"\x3a.*\x3f""#], b"a?"),
+            vec![packet(0, b"a?")]
+        );
+    }
+
+    #[test]
+    fn handlers_keep_bytes_for_each_other() {
+        // The end-delimited handler may still use the bytes before the
+        // start delimiter, so they are not garbage.
+        assert_eq!(
+            frame_all_ways(&[r#"::"\x3a.*\x3f""#, r"::'[xy]+!'"], b"xy!:z?"),
+            vec![packet(1, b"xy!"), packet(0, b":z?")]
+        );
+    }
+
+    #[test]
+    fn overlong_packets_resynchronize() {
+        let limits = Limits {
+            max_packet_len: 4,
+            timeout_ms: 100,
+        };
+        let mut framer = framer_with(&[r#"::"\x3a.*\x3f""#], limits);
+        assert_eq!(framer.push(b":abcd", 0), vec![garbage(b":abcd")]);
+        assert_eq!(
+            framer.push(b"?:ab?", 0),
+            vec![garbage(b"?"), packet(0, b":ab?")]
+        );
+
+        let mut framer = framer_with(&[r"::'.*\x3f'"], limits);
+        assert_eq!(framer.push(b"abcde", 0), vec![garbage(b"a")]);
+        assert_eq!(
+            framer.push(b"?", 0),
+            vec![garbage(b"b"), packet(0, b"cde?")]
+        );
+    }
+
+    #[test]
+    fn partial_packets_time_out() {
+        let mut framer = framer(&["::4"]);
+        assert_eq!(framer.push(b"ab", 0), vec![]);
+        assert_eq!(framer.push(b"c", 50), vec![]);
+        assert_eq!(framer.deadline(), Some(150));
+        assert_eq!(framer.expire(149), vec![]);
+        assert_eq!(framer.expire(150), vec![garbage(b"abc")]);
+        assert_eq!(framer.push(b"defg", 200), vec![packet(0, b"defg")]);
+    }
+
+    #[test]
+    fn example_program_stream() {
+        let guards = [
+            "::4",
+            "",
+            r"::'.*\x3f'",
+            r#"::"\x3a.*\x3f""#,
+            r#"::"\x3a.*""#,
+        ];
+        // The one-byte default handler is shadowed by `::4`. The last four
+        // bytes complete a packet for `::4` and both delimiter handlers at
+        // once, and the delimited one comes first in dispatch order.
+        assert_eq!(
+            frame_all_ways(&guards, b"\x00\x01\xe8\x03:ab?"),
+            vec![packet(0, b"\x00\x01\xe8\x03"), packet(3, b":ab?")]
+        );
+    }
+}
@@ -0,0 +1,320 @@
+use std::ffi::{CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A local stand-in for the hardware behind an interface name such as
+/// `"uart"`: `send` writes to it and its incoming bytes feed the `when`
+/// handlers of that name.
+pub trait Interface {
+    /// Writes the bytes of a `send` call.
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// The bytes received since the last call, without waiting for more, or
+    /// `None` once the interface is closed and nothing else can arrive.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+}
+
+/// Opens the interface described by `spec`:
+///
+/// - `stdio`: reads standard input and writes standard output.
+/// - `file:<input>,<output>`: reads the input file once and appends what is
+///   sent to the output file. Either path may be left empty.
+/// - `pipe:<path>`: reads the named pipe `<path>.in` and writes the named
+///   pipe `<path>.out`, creating them when they do not exist.
+/// - `pty`: opens a pseudo-terminal in raw mode and reports the path of the
+///   terminal to connect to, such as `/dev/pts/3`.
+/// - `tcp:<address>`: listens on `<address>` and talks to the last client
+///   that connected.
+/// - `udp:<address>[,<peer>]`: binds `<address>` and sends to `<peer>`, or
+///   to the last address a datagram came from.
+///
+/// In a `simulated` run, the input of `stdio` and `file` is read to its end
+/// here, so that all of it is received before the clock moves.
+pub fn open(name: &str, spec: &str, simulated: bool) -> Result<Box<dyn Interface>, String> {
+    let (kind, args) = spec.split_once(':').unwrap_or((spec, ""));
+    let stream = match kind {
+        "stdio" => Incoming::open(io::stdin(), simulated)
+            .and_then(|incoming| Stream::new(incoming, io::stdout())),
+        "file" => open_file(args, simulated),
+        "pipe" => open_pipe(args),
+        "pty" => open_pty(name),
+        "tcp" => open_tcp(args),
+        "udp" => open_udp(args),
+        _ => return Err(format!("Unknown interface kind '{}' for {}", kind, name)),
+    };
+    stream
+        .map(|stream| Box::new(stream) as Box<dyn Interface>)
+        .map_err(|err| format!("Cannot open {} as {}: {}", name, spec, err))
+}
+
+/// An interface made of a byte source and a byte sink.
+struct Stream {
+    incoming: Incoming,
+    outgoing: Box<dyn Write>,
+}
+
+impl Stream {
+    fn new(incoming: Incoming, outgoing: impl Write + 'static) -> io::Result<Self> {
+        Ok(Self {
+            incoming,
+            outgoing: Box::new(outgoing),
+        })
+    }
+}
+
+impl Interface for Stream {
+    fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.outgoing.write_all(bytes)?;
+        self.outgoing.flush()
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.incoming.receive()
+    }
+}
+
+/// Bytes read by a background thread, so that receiving never blocks.
+struct Incoming(Receiver<Vec<u8>>);
+
+impl Incoming {
+    fn spawn(mut reader: impl Read + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(count) => {
+                        if sender.send(buffer[..count].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+        });
+        Self(receiver)
+    }
+
+    /// Reads `reader` to its end right away when `finite`, and on a
+    /// background thread otherwise.
+    fn open(mut reader: impl Read + Send + 'static, finite: bool) -> io::Result<Self> {
+        if !finite {
+            return Ok(Self::spawn(reader));
+        }
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let (sender, receiver) = mpsc::channel();
+        sender.send(bytes).unwrap();
+        Ok(Self(receiver))
+    }
+
+    fn closed() -> Self {
+        Self(mpsc::channel().1)
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let mut bytes = vec![];
+        loop {
+            match self.0.try_recv() {
+                Ok(chunk) => bytes.extend(chunk),
+                Err(TryRecvError::Empty) => return Some(bytes),
+                Err(TryRecvError::Disconnected) if bytes.is_empty() => return None,
+                Err(TryRecvError::Disconnected) => return Some(bytes),
+            }
+        }
+    }
+}
+
+fn open_file(args: &str, simulated: bool) -> io::Result<Stream> {
+    let (input, output) = args.split_once(',').unwrap_or((args, ""));
+
+    let incoming = match input {
+        "" => Incoming::closed(),
+        path => Incoming::open(File::open(path)?, simulated)?,
+    };
+    match output {
+        "" => Stream::new(incoming, io::sink()),
+        path => Stream::new(
+            incoming,
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ),
+    }
+}
+
+fn open_pipe(path: &str) -> io::Result<Stream> {
+    let input = open_fifo(&format!("{}.in", path))?;
+    let output = open_fifo(&format!("{}.out", path))?;
+    Stream::new(Incoming::spawn(input), output)
+}
+
+/// Opens a named pipe for reading and writing, which neither waits for the
+/// other end nor sees an end of file when the other end goes away.
+fn open_fifo(path: &str) -> io::Result<File> {
+    if !Path::new(path).exists() {
+        let c_path = CString::new(path).map_err(io::Error::other)?;
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    OpenOptions::new().read(true).write(true).open(path)
+}
+
+fn open_pty(name: &str) -> io::Result<Stream> {
+    let master = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        master
+    };
+
+    let mut path = [0 as libc::c_char; 128];
+    if unsafe { libc::ptsname_r(master.as_raw_fd(), path.as_mut_ptr(), path.len()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let path = unsafe { CStr::from_ptr(path.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+
+    // Holding the terminal open keeps the interface alive between clients.
+    let terminal = OpenOptions::new().read(true).write(true).open(&path)?;
+    unsafe {
+        let mut termios = std::mem::zeroed();
+        if libc::tcgetattr(terminal.as_raw_fd(), &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(terminal.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    eprintln!("{} is connected to {}", name, path);
+
+    let incoming = Incoming::spawn(master.try_clone()?);
+    Stream::new(
+        incoming,
+        Pty {
+            master,
+            _terminal: terminal,
+        },
+    )
+}
+
+struct Pty {
+    master: File,
+    _terminal: File,
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.master.flush()
+    }
+}
+
+fn open_tcp(address: &str) -> io::Result<Stream> {
+    let listener = TcpListener::bind(address)?;
+    let client = Client::default();
+
+    let (sender, receiver) = mpsc::channel();
+    let accepted = client.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut stream: TcpStream = stream;
+            *accepted.0.lock().unwrap() = stream.try_clone().ok();
+
+            let mut buffer = [0; 1024];
+            while let Ok(count) = stream.read(&mut buffer) {
+                if count == 0 {
+                    break;
+                }
+                if sender.send(buffer[..count].to_vec()).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    Stream::new(Incoming(receiver), client)
+}
+
+/// The TCP client bytes are sent to; they are dropped while none is
+/// connected.
+#[derive(Clone, Default)]
+struct Client(Arc<Mutex<Option<TcpStream>>>);
+
+impl Write for Client {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.lock().unwrap().as_mut() {
+            Some(stream) => stream.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn open_udp(args: &str) -> io::Result<Stream> {
+    let (address, peer) = args.split_once(',').unwrap_or((args, ""));
+    let socket = UdpSocket::bind(address)?;
+    let peer = match peer {
+        "" => None,
+        peer => Some(
+            peer.parse::<SocketAddr>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?,
+        ),
+    };
+    let peer = Arc::new(Mutex::new(peer));
+
+    let (sender, receiver) = mpsc::channel();
+    let reader = socket.try_clone()?;
+    let last_peer = peer.clone();
+    thread::spawn(move || {
+        let mut buffer = [0; 65536];
+        while let Ok((count, from)) = reader.recv_from(&mut buffer) {
+            *last_peer.lock().unwrap() = Some(from);
+            if sender.send(buffer[..count].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    Stream::new(Incoming(receiver), Datagrams { socket, peer })
+}
+
+/// Sends each write as one datagram; they are dropped while the peer is
+/// unknown.
+struct Datagrams {
+    socket: UdpSocket,
+    peer: Arc<Mutex<Option<SocketAddr>>>,
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self.peer.lock().unwrap() {
+            Some(peer) => self.socket.send_to(buf, peer),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
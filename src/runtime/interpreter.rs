@@ -0,0 +1,1283 @@
+use crate::analysis::{dispatch, walk_statements};
+use crate::parser::{Expression, Guard, Pattern, Statement, AST};
+use crate::runtime::builtins::{self, Host};
+use crate::runtime::capture::{self, Chunk, Recorder};
+use crate::runtime::framer::{Frame, Framer};
+use crate::runtime::interface::Interface;
+use crate::runtime::random::Random;
+use crate::runtime::record::Record;
+use crate::runtime::scheduler::Scheduler;
+use crate::runtime::store::{self, Storage};
+use crate::runtime::trace::{Event, Trace};
+use crate::runtime::value::{TaskId, Value};
+use crate::runtime::{Machine, Options};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+/// Program-wide state shared by every running body.
+pub struct Interpreter<'p> {
+    program: &'p [AST],
+    functions: HashMap<&'p str, (&'p [String], &'p [Statement])>,
+    tasks: Vec<&'p str>,
+    records: HashMap<&'p str, Record>,
+    framers: HashMap<String, Framer>,
+    /// Variables stored at the top level of the program.
+    globals: Storage,
+    /// Variables stored inside a body, by the address of the body.
+    storages: HashMap<usize, Storage>,
+    store_path: Option<PathBuf>,
+    output: Box<dyn Write>,
+    trace: Option<Trace>,
+    /// Captured traffic still to be received, by time.
+    replay: VecDeque<Chunk>,
+    recorder: Option<Recorder>,
+    random: Random,
+    interfaces: HashMap<String, Attached>,
+    scheduler: Scheduler<Fiber<'p>>,
+}
+
+/// An interface of the run, with whether it can still receive bytes.
+pub(crate) struct Attached {
+    pub(crate) interface: Box<dyn Interface>,
+    pub(crate) open: bool,
+}
+
+/// The outcome of resuming a fiber.
+#[derive(Debug, PartialEq)]
+pub enum Step {
+    /// The fiber reached an `@` delay of the given milliseconds.
+    Suspended(usize),
+    Finished(Value),
+}
+
+/// A body in execution, which can stop at `@` delays and be resumed later.
+///
+/// Only the body a fiber was created with can wait: functions it calls run
+/// to completion, and fail if they reach a delay.
+#[derive(Clone)]
+pub struct Fiber<'p> {
+    body: &'p [Statement],
+    locals: HashMap<String, Value>,
+    blocks: Vec<Block<'p>>,
+}
+
+#[derive(Clone)]
+struct Block<'p> {
+    statements: &'p [Statement],
+    pc: usize,
+    kind: BlockKind<'p>,
+}
+
+#[derive(Clone)]
+enum BlockKind<'p> {
+    Plain,
+    While(&'p Expression),
+    For {
+        var: &'p str,
+        items: Vec<Value>,
+        next: usize,
+    },
+}
+
+enum Flow {
+    Next,
+    Suspend(usize),
+    Return(Value),
+}
+
+enum BlockEnd<'p> {
+    Pop,
+    Repeat,
+    Bind(&'p str, Value),
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Operator {
+    Sum,
+    Minus,
+    Multiply,
+    Division,
+    Modulus,
+}
+
+impl<'p> Interpreter<'p> {
+    pub fn new(
+        program: &'p [AST],
+        output: Box<dyn Write>,
+        interfaces: HashMap<String, Box<dyn Interface>>,
+        options: &Options,
+    ) -> Result<Self, String> {
+        let mut functions = HashMap::new();
+        let mut tasks = vec![];
+        let mut records = HashMap::new();
+        let mut scheduler = Scheduler::default();
+        for ast in program {
+            match ast {
+                AST::Function {
+                    name,
+                    arguments,
+                    body,
+                    ..
+                } => {
+                    functions.insert(name.as_str(), (arguments.as_slice(), body.as_slice()));
+                }
+                AST::Task {
+                    name,
+                    interval_ms,
+                    body,
+                    ..
+                } => {
+                    tasks.push(name.as_str());
+                    scheduler.spawn(name, Fiber::new(body, HashMap::new()), *interval_ms, true);
+                }
+                AST::Record {
+                    name,
+                    length,
+                    data_size,
+                } => {
+                    records.insert(name.as_str(), Record::new(name, *length, *data_size));
+                }
+                _ => {}
+            }
+        }
+
+        let framers = dispatch::analyze(program)
+            .0
+            .into_iter()
+            .map(|dispatch| {
+                let handlers: Vec<(usize, &Guard)> = dispatch
+                    .handlers
+                    .iter()
+                    .map(|&index| match &program[index] {
+                        AST::When { guard, .. } => (index, guard),
+                        _ => unreachable!("dispatch tables only hold 'when' handlers"),
+                    })
+                    .collect();
+                (dispatch.interface, Framer::new(&handlers, options.framing))
+            })
+            .collect();
+
+        let interfaces = interfaces
+            .into_iter()
+            .map(|(name, interface)| {
+                let attached = Attached {
+                    interface,
+                    open: true,
+                };
+                (name, attached)
+            })
+            .collect();
+
+        let mut global_names = vec![];
+        let mut storages = HashMap::new();
+        for ast in program {
+            let scope = match ast {
+                AST::Store { var_list } => {
+                    global_names.extend(var_list.iter().map(String::as_str));
+                    continue;
+                }
+                AST::Function { name, .. } => format!("function {}", name),
+                AST::Task { name, .. } => format!("task {}", name),
+                AST::When {
+                    interface,
+                    packet,
+                    guard,
+                    ..
+                } => format!("when \"{}\" => {}{}", interface, packet, guard),
+                AST::Record { .. } => continue,
+            };
+
+            let mut names = vec![];
+            walk_statements(ast.body(), &mut |statement| {
+                if let Statement::Store { var_list } = statement {
+                    names.extend(var_list.iter().map(String::as_str));
+                }
+            });
+            if !names.is_empty() {
+                storages.insert(address(ast.body()), Storage::new(scope, &names));
+            }
+        }
+
+        let trace = match &options.trace_path {
+            Some(path) => {
+                let file = File::create(path)
+                    .map_err(|err| format!("Cannot create {}: {}", path.display(), err))?;
+                Some(Trace::new(Box::new(BufWriter::new(file))))
+            }
+            None => None,
+        };
+
+        let replay = match &options.replay_path {
+            Some(path) => capture::load(path)?.into(),
+            None => VecDeque::new(),
+        };
+        let recorder = match &options.record_path {
+            Some(path) => Some(Recorder::create(path)?),
+            None => None,
+        };
+
+        let seed = options.seed.unwrap_or_else(Random::clock_seed);
+
+        let mut interpreter = Self {
+            program,
+            functions,
+            tasks,
+            records,
+            framers,
+            globals: Storage::new("global".to_string(), &global_names),
+            storages,
+            store_path: options.store_path.clone(),
+            output,
+            trace,
+            replay,
+            recorder,
+            random: Random::new(seed),
+            interfaces,
+            scheduler,
+        };
+
+        interpreter.trace(Event::Seed { seed })?;
+        for id in 0..interpreter.tasks.len() {
+            interpreter.trace_start(id)?;
+        }
+
+        if let Some(path) = &options.store_path {
+            let mut saved = store::load(path)?;
+            let storages =
+                std::iter::once(&mut interpreter.globals).chain(interpreter.storages.values_mut());
+            for storage in storages {
+                if let Some(values) = saved.remove(&storage.scope) {
+                    for (name, value) in values {
+                        if let Some(stored) = storage.values.get_mut(&name) {
+                            *stored = value;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(interpreter)
+    }
+
+    pub fn scheduler(&self) -> &Scheduler<Fiber<'p>> {
+        &self.scheduler
+    }
+
+    pub fn scheduler_mut(&mut self) -> &mut Scheduler<Fiber<'p>> {
+        &mut self.scheduler
+    }
+
+    fn trace_start(&mut self, id: TaskId) -> Result<(), String> {
+        match &mut self.trace {
+            Some(trace) => {
+                let task = self.scheduler.name(id);
+                let interval_ms = self.scheduler.interval(id);
+                trace.log(
+                    self.scheduler.now(),
+                    Event::Start {
+                        id,
+                        task,
+                        interval_ms,
+                    },
+                )
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Frames the bytes received on `interface` and starts a handler for
+    /// each packet they complete.
+    pub fn receive(&mut self, interface: &str, bytes: Vec<u8>) -> Result<(), String> {
+        let now = self.scheduler.now();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(now, interface, &bytes)?;
+        }
+        match self.framers.get_mut(interface) {
+            Some(framer) => {
+                let frames = framer.push(&bytes, now);
+                self.handle_frames(interface, frames)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn handle_frames(&mut self, interface: &str, frames: Vec<Frame>) -> Result<(), String> {
+        for frame in frames {
+            match frame {
+                Frame::Packet { handler, bytes } => {
+                    if let AST::When {
+                        packet,
+                        guard,
+                        body,
+                        ..
+                    } = &self.program[handler]
+                    {
+                        self.trace(Event::Packet {
+                            interface,
+                            handler: &format!("{}{}", packet, guard),
+                            bytes: &bytes,
+                        })?;
+                        let name = format!("when \"{}\" => {}{}", interface, packet, guard);
+                        let locals = HashMap::from([(packet.clone(), Value::Bytes(bytes))]);
+                        self.scheduler
+                            .spawn_detached(&name, Fiber::new(body, locals));
+                    }
+                }
+                Frame::Garbage(bytes) => self.trace(Event::Garbage {
+                    interface,
+                    bytes: &bytes,
+                })?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls a builtin or a function of the program.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, String> {
+        if let Some(result) = builtins::call(self, name, &args) {
+            return result;
+        }
+
+        let body = self.function_body(name)?;
+
+        let locals = self.bind_arguments(name, args)?;
+        match Fiber::new(body, locals).resume(self)? {
+            Step::Finished(value) => Ok(value),
+            Step::Suspended(_) => Err(format!(
+                "The function '{}' cannot wait with @ when it is called directly",
+                name
+            )),
+        }
+    }
+
+    fn function_body(&self, name: &str) -> Result<&'p [Statement], String> {
+        self.functions
+            .get(name)
+            .map(|(_, body)| *body)
+            .ok_or_else(|| format!("Undefined function '{}'", name))
+    }
+
+    fn bind_arguments(
+        &self,
+        name: &str,
+        args: Vec<Value>,
+    ) -> Result<HashMap<String, Value>, String> {
+        let (parameters, _) = self.functions[name];
+        if parameters.len() != args.len() {
+            return Err(format!(
+                "The function '{}' takes {} argument(s) but {} were given",
+                name,
+                parameters.len(),
+                args.len()
+            ));
+        }
+        Ok(parameters.iter().cloned().zip(args).collect())
+    }
+
+    /// The slot of a variable stored by `body`, or stored at the top level
+    /// when `body` is `None`.
+    fn stored_mut(&mut self, body: Option<&[Statement]>, name: &str) -> Option<&mut Value> {
+        match body {
+            Some(body) => self.storages.get_mut(&address(body))?.values.get_mut(name),
+            None => self.globals.values.get_mut(name),
+        }
+    }
+
+    /// Writes a stored variable and saves the store file when it changed.
+    fn write_stored(
+        &mut self,
+        body: Option<&[Statement]>,
+        name: &str,
+        value: Value,
+    ) -> Result<(), String> {
+        let slot = self.stored_mut(body, name).expect("the variable is stored");
+        if *slot == value {
+            return Ok(());
+        }
+        *slot = value;
+
+        match &self.store_path {
+            Some(path) => store::save(
+                path,
+                std::iter::once(&self.globals).chain(self.storages.values()),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn record(&mut self, name: &str) -> Result<&mut Record, String> {
+        self.records
+            .get_mut(name)
+            .ok_or_else(|| format!("Undefined record '{}'", name))
+    }
+
+    fn global(&self, name: &str) -> Result<Value, String> {
+        if let Some(value) = self.globals.values.get(name) {
+            return Ok(value.clone());
+        }
+        if let Some(record) = self.records.get(name) {
+            return Ok(record.to_value());
+        }
+        if self.functions.contains_key(name) {
+            return Ok(Value::Function(name.to_string()));
+        }
+        if let Some(id) = self.tasks.iter().position(|task| *task == name) {
+            return Ok(Value::Task(id as TaskId));
+        }
+        Err(format!("Undefined variable '{}'", name))
+    }
+}
+
+impl<'p> Host for Interpreter<'p> {
+    fn now(&self) -> u64 {
+        self.scheduler.now()
+    }
+
+    fn output(&mut self) -> &mut dyn Write {
+        self.output.as_mut()
+    }
+
+    fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    fn trace(&mut self, event: Event) -> Result<(), String> {
+        match &mut self.trace {
+            Some(trace) => trace.log(self.scheduler.now(), event),
+            None => Ok(()),
+        }
+    }
+
+    fn send(&mut self, interface: &str, bytes: Vec<u8>) -> Result<(), String> {
+        self.trace(Event::Send {
+            interface,
+            bytes: &bytes,
+        })?;
+        match self.interfaces.get_mut(interface) {
+            Some(attached) => attached
+                .interface
+                .send(&bytes)
+                .map_err(|err| format!("Cannot send on {}: {}", interface, err)),
+            None => writeln!(self.output, "{} <- {}", interface, Value::Bytes(bytes))
+                .map_err(|err| err.to_string()),
+        }
+    }
+
+    fn spawn_function(
+        &mut self,
+        name: &str,
+        args: Vec<Value>,
+        interval_ms: usize,
+        immediate: bool,
+    ) -> Result<TaskId, String> {
+        let body = self.function_body(name)?;
+        let locals = self.bind_arguments(name, args)?;
+        let id = self
+            .scheduler
+            .spawn(name, Fiber::new(body, locals), interval_ms, immediate);
+        self.trace_start(id)?;
+        Ok(id)
+    }
+
+    fn restart_task(
+        &mut self,
+        id: TaskId,
+        interval_ms: Option<usize>,
+        immediate: bool,
+    ) -> Result<(), String> {
+        self.scheduler.restart(id, interval_ms, immediate);
+        self.trace_start(id)
+    }
+
+    fn stop_task(&mut self, id: TaskId) -> Result<(), String> {
+        self.scheduler.stop(id);
+        match &mut self.trace {
+            Some(trace) => {
+                let task = self.scheduler.name(id);
+                trace.log(self.scheduler.now(), Event::Stop { id, task })
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Machine for Interpreter<'_> {
+    fn advance_to(&mut self, time_ms: u64) {
+        self.scheduler.advance_to(time_ms);
+    }
+
+    /// Collects the bytes received by every interface and starts the
+    /// handlers they match. Returns whether any interface is still open.
+    fn poll_interfaces(&mut self) -> Result<bool, String> {
+        let mut received = vec![];
+        for (name, attached) in self.interfaces.iter_mut().filter(|(_, a)| a.open) {
+            match attached.interface.receive() {
+                Some(bytes) if bytes.is_empty() => {}
+                Some(bytes) => received.push((name.clone(), bytes)),
+                None => attached.open = false,
+            }
+        }
+
+        for (name, bytes) in received {
+            self.receive(&name, bytes)?;
+        }
+        Ok(self.interfaces.values().any(|attached| attached.open))
+    }
+
+    /// The virtual time of the next task tick, partial packet timeout or
+    /// replayed chunk.
+    fn next_wake(&self) -> Option<u64> {
+        self.framers
+            .values()
+            .filter_map(Framer::deadline)
+            .chain(self.scheduler.next_wake())
+            .chain(self.replay.front().map(|chunk| chunk.time_ms))
+            .min()
+    }
+
+    /// Moves the clock to `next_wake` and handles what is due then: partial
+    /// packets that timed out are ended first, then replayed chunks are
+    /// received, otherwise the next due task runs until it finishes its tick
+    /// or waits.
+    fn step(&mut self) -> Result<(), String> {
+        let wake_at = match self.next_wake() {
+            Some(wake_at) => wake_at,
+            None => return Ok(()),
+        };
+        self.scheduler.advance_to(wake_at);
+
+        let now = self.scheduler.now();
+        let mut expired = vec![];
+        for (interface, framer) in self.framers.iter_mut() {
+            let frames = framer.expire(now);
+            if !frames.is_empty() {
+                expired.push((interface.clone(), frames));
+            }
+        }
+        if !expired.is_empty() {
+            expired.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (interface, frames) in expired {
+                self.handle_frames(&interface, frames)?;
+            }
+            return Ok(());
+        }
+
+        if self
+            .replay
+            .front()
+            .is_some_and(|chunk| chunk.time_ms <= now)
+        {
+            while let Some(chunk) = self.replay.front() {
+                if chunk.time_ms > now {
+                    break;
+                }
+                let chunk = self.replay.pop_front().unwrap();
+                self.receive(&chunk.interface, chunk.bytes)?;
+            }
+            return Ok(());
+        }
+
+        if let Some((id, mut fiber, new_tick)) = self.scheduler.take_due() {
+            if let (Some(trace), true) = (&mut self.trace, new_tick) {
+                let task = self.scheduler.name(id);
+                trace.log(self.scheduler.now(), Event::Tick { id, task })?;
+            }
+            let step = fiber.resume(self)?;
+            if let (Some(trace), Step::Suspended(delay_ms)) = (&mut self.trace, &step) {
+                let task = self.scheduler.name(id);
+                let delay_ms = *delay_ms;
+                trace.log(self.scheduler.now(), Event::Delay { id, task, delay_ms })?;
+            }
+            self.scheduler.park(id, fiber, step);
+        }
+        Ok(())
+    }
+}
+
+fn address(body: &[Statement]) -> usize {
+    body.as_ptr() as usize
+}
+
+impl<'p> Fiber<'p> {
+    pub fn new(body: &'p [Statement], locals: HashMap<String, Value>) -> Self {
+        Self {
+            body,
+            locals,
+            blocks: vec![Block {
+                statements: body,
+                pc: 0,
+                kind: BlockKind::Plain,
+            }],
+        }
+    }
+
+    /// Runs until the body finishes or reaches an `@` delay.
+    pub fn resume(&mut self, interpreter: &mut Interpreter<'p>) -> Result<Step, String> {
+        while let Some(block) = self.blocks.last_mut() {
+            if block.pc < block.statements.len() {
+                let statements = block.statements;
+                let statement = &statements[block.pc];
+                block.pc += 1;
+
+                match self.execute(statement, interpreter)? {
+                    Flow::Next => {}
+                    Flow::Suspend(delay_ms) => return Ok(Step::Suspended(delay_ms)),
+                    Flow::Return(value) => {
+                        self.blocks.clear();
+                        return Ok(Step::Finished(value));
+                    }
+                }
+                continue;
+            }
+
+            let end = match &self.blocks.last().unwrap().kind {
+                BlockKind::Plain => BlockEnd::Pop,
+                BlockKind::While(condition) => {
+                    let condition = *condition;
+                    if self.evaluate(condition, interpreter)?.as_boolean()? {
+                        BlockEnd::Repeat
+                    } else {
+                        BlockEnd::Pop
+                    }
+                }
+                BlockKind::For { var, items, next } => match items.get(*next) {
+                    Some(item) => BlockEnd::Bind(var, item.clone()),
+                    None => BlockEnd::Pop,
+                },
+            };
+
+            match end {
+                BlockEnd::Pop => {
+                    self.blocks.pop();
+                }
+                BlockEnd::Repeat => self.blocks.last_mut().unwrap().pc = 0,
+                BlockEnd::Bind(var, item) => {
+                    self.locals.insert(var.to_string(), item);
+                    let block = self.blocks.last_mut().unwrap();
+                    block.pc = 0;
+                    if let BlockKind::For { next, .. } = &mut block.kind {
+                        *next += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(Step::Finished(Value::Nil))
+    }
+
+    fn execute(
+        &mut self,
+        statement: &'p Statement,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<Flow, String> {
+        match statement {
+            Statement::Assignment {
+                variable,
+                expression,
+            } => {
+                let value = self.evaluate(expression, interpreter)?;
+                self.assign(variable, value, interpreter)?;
+            }
+            Statement::AssignmentSum {
+                variable,
+                expression,
+            } => self.update(variable, Operator::Sum, expression, interpreter)?,
+            Statement::AssignmentMinus {
+                variable,
+                expression,
+            } => self.update(variable, Operator::Minus, expression, interpreter)?,
+            Statement::AssignmentMult {
+                variable,
+                expression,
+            } => self.update(variable, Operator::Multiply, expression, interpreter)?,
+            Statement::AssignmentDiv {
+                variable,
+                expression,
+            } => self.update(variable, Operator::Division, expression, interpreter)?,
+            Statement::AssignmentMod {
+                variable,
+                expression,
+            } => self.update(variable, Operator::Modulus, expression, interpreter)?,
+            Statement::Unpack {
+                targets,
+                expression,
+            } => {
+                let bytes = self.evaluate(expression, interpreter)?.to_bytes()?;
+                let widths: Vec<_> = targets
+                    .iter()
+                    .map(|(_, guard)| match guard {
+                        Guard::Numeric { width, endianness } => (*width, *endianness),
+                        _ => unreachable!("matching assignments only hold numeric guards"),
+                    })
+                    .collect();
+
+                let expected: usize = widths.iter().map(|(width, _)| width).sum();
+                if expected != bytes.len() {
+                    return Err(format!(
+                        "Cannot unpack {} byte(s) into targets of {} byte(s)",
+                        bytes.len(),
+                        expected
+                    ));
+                }
+
+                let mut offset = 0;
+                for ((variable, _), (width, endianness)) in targets.iter().zip(widths) {
+                    let value = Value::unpack(&bytes[offset..offset + width], endianness);
+                    offset += width;
+                    self.assign(variable, value, interpreter)?;
+                }
+            }
+            Statement::Delay { time } => return Ok(Flow::Suspend(*time)),
+            // Stored variables are set up with the interpreter.
+            Statement::Store { .. } => {}
+            Statement::If {
+                condition,
+                body,
+                elif,
+                else_body,
+            } => {
+                let mut chosen = else_body;
+                if self.evaluate(condition, interpreter)?.as_boolean()? {
+                    chosen = body;
+                } else {
+                    for (elif_condition, elif_body) in elif {
+                        if self.evaluate(elif_condition, interpreter)?.as_boolean()? {
+                            chosen = elif_body;
+                            break;
+                        }
+                    }
+                }
+                self.push(chosen, BlockKind::Plain);
+            }
+            Statement::For {
+                var,
+                collection,
+                body,
+            } => {
+                let items = self.lookup(collection, interpreter)?.items()?;
+                self.push_loop(
+                    body,
+                    BlockKind::For {
+                        var,
+                        items,
+                        next: 0,
+                    },
+                );
+            }
+            Statement::While { condition, body } => {
+                self.push_loop(body, BlockKind::While(condition));
+            }
+            Statement::Match {
+                target,
+                cases,
+                default,
+                ..
+            } => {
+                let value = self.evaluate(target, interpreter)?;
+                let arm = cases.iter().find(|arm| {
+                    arm.patterns
+                        .iter()
+                        .any(|(pattern, _)| pattern_matches(pattern, &value))
+                });
+                match (arm, default) {
+                    (Some(arm), _) => self.push(&arm.body, BlockKind::Plain),
+                    (None, Some(default)) => self.push(default, BlockKind::Plain),
+                    (None, None) => {}
+                }
+            }
+            Statement::Return { expression } => {
+                return Ok(Flow::Return(self.evaluate(expression, interpreter)?));
+            }
+            Statement::FunctionCall(function_call) => {
+                let args = self.evaluate_all(&function_call.arguments, interpreter)?;
+                interpreter.call(&function_call.name, args)?;
+            }
+            Statement::ElementAssignment {
+                record,
+                index,
+                expression,
+                ..
+            } => {
+                let index = self.evaluate(index, interpreter)?.as_integer()?;
+                let value = self.evaluate(expression, interpreter)?;
+                interpreter.record(record)?.set(index, &value)?;
+            }
+            Statement::Start {
+                function_call,
+                interval,
+            } => {
+                let args = self.evaluate_all(&function_call.arguments, interpreter)?;
+                self.start(args, interval, interpreter)?;
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn push(&mut self, statements: &'p [Statement], kind: BlockKind<'p>) {
+        self.blocks.push(Block {
+            statements,
+            pc: 0,
+            kind,
+        });
+    }
+
+    /// Loops start at the end of their body so that the condition is checked
+    /// before the first iteration.
+    fn push_loop(&mut self, statements: &'p [Statement], kind: BlockKind<'p>) {
+        self.blocks.push(Block {
+            statements,
+            pc: statements.len(),
+            kind,
+        });
+    }
+
+    /// Finds a variable among the ones stored by this body, its locals and
+    /// then the program-wide names.
+    fn lookup(&self, name: &str, interpreter: &Interpreter<'p>) -> Result<Value, String> {
+        let stored = interpreter
+            .storages
+            .get(&address(self.body))
+            .and_then(|storage| storage.values.get(name));
+        match stored.or_else(|| self.locals.get(name)) {
+            Some(value) => Ok(value.clone()),
+            None => interpreter.global(name),
+        }
+    }
+
+    /// Writes a variable stored by this body, an existing local, a variable
+    /// stored at the top level or else a new local, in this order.
+    fn assign(
+        &mut self,
+        variable: &str,
+        value: Value,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<(), String> {
+        if interpreter.stored_mut(Some(self.body), variable).is_some() {
+            return interpreter.write_stored(Some(self.body), variable, value);
+        }
+        if !self.locals.contains_key(variable) && interpreter.stored_mut(None, variable).is_some() {
+            return interpreter.write_stored(None, variable, value);
+        }
+        self.locals.insert(variable.to_string(), value);
+        Ok(())
+    }
+
+    fn update(
+        &mut self,
+        variable: &str,
+        operator: Operator,
+        expression: &'p Expression,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<(), String> {
+        let lhs = self.lookup(variable, interpreter)?;
+        let rhs = self.evaluate(expression, interpreter)?;
+        let value = arithmetic(operator, lhs, rhs)?;
+        self.assign(variable, value, interpreter)?;
+        Ok(())
+    }
+
+    /// Runs `start(args...) @ interval`.
+    fn start(
+        &self,
+        args: Vec<Value>,
+        interval: &'p Expression,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<Value, String> {
+        let interval_ms = self.evaluate(interval, interpreter)?.as_integer()?;
+        if interval_ms < 0 {
+            return Err("The task interval cannot be negative".to_string());
+        }
+        builtins::start(interpreter, &args, Some(interval_ms as usize))
+    }
+
+    fn evaluate_all(
+        &self,
+        expressions: &'p [Expression],
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<Vec<Value>, String> {
+        expressions
+            .iter()
+            .map(|expression| self.evaluate(expression, interpreter))
+            .collect()
+    }
+
+    fn evaluate(
+        &self,
+        expression: &'p Expression,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<Value, String> {
+        let value = match expression {
+            Expression::Literal(literal) => Value::from(literal),
+            Expression::Variable(name) => self.lookup(name, interpreter)?,
+            Expression::List(items) => Value::List(self.evaluate_all(items, interpreter)?),
+            Expression::Element { record, index, .. } => {
+                let index = self.evaluate(index, interpreter)?.as_integer()?;
+                interpreter.record(record)?.get(index)?
+            }
+            Expression::Equal(lhs, rhs) => {
+                Value::Boolean(self.evaluate(lhs, interpreter)? == self.evaluate(rhs, interpreter)?)
+            }
+            Expression::NotEqual(lhs, rhs) => {
+                Value::Boolean(self.evaluate(lhs, interpreter)? != self.evaluate(rhs, interpreter)?)
+            }
+            Expression::Less(lhs, rhs) => {
+                Value::Boolean(self.compare(lhs, rhs, interpreter)?.is_lt())
+            }
+            Expression::Greater(lhs, rhs) => {
+                Value::Boolean(self.compare(lhs, rhs, interpreter)?.is_gt())
+            }
+            Expression::LessOrEqual(lhs, rhs) => {
+                Value::Boolean(self.compare(lhs, rhs, interpreter)?.is_le())
+            }
+            Expression::GreaterOrEqual(lhs, rhs) => {
+                Value::Boolean(self.compare(lhs, rhs, interpreter)?.is_ge())
+            }
+            Expression::And(lhs, rhs) => Value::Boolean(
+                self.evaluate(lhs, interpreter)?.as_boolean()?
+                    && self.evaluate(rhs, interpreter)?.as_boolean()?,
+            ),
+            Expression::Or(lhs, rhs) => Value::Boolean(
+                self.evaluate(lhs, interpreter)?.as_boolean()?
+                    || self.evaluate(rhs, interpreter)?.as_boolean()?,
+            ),
+            Expression::Xor(lhs, rhs) => Value::Boolean(
+                self.evaluate(lhs, interpreter)?.as_boolean()?
+                    ^ self.evaluate(rhs, interpreter)?.as_boolean()?,
+            ),
+            Expression::Not(operand) => {
+                Value::Boolean(!self.evaluate(operand, interpreter)?.as_boolean()?)
+            }
+            Expression::Sum(lhs, rhs) => self.arithmetic(Operator::Sum, lhs, rhs, interpreter)?,
+            Expression::Minus(lhs, rhs) => {
+                self.arithmetic(Operator::Minus, lhs, rhs, interpreter)?
+            }
+            Expression::Multiply(lhs, rhs) => {
+                self.arithmetic(Operator::Multiply, lhs, rhs, interpreter)?
+            }
+            Expression::Division(lhs, rhs) => {
+                self.arithmetic(Operator::Division, lhs, rhs, interpreter)?
+            }
+            Expression::Modulus(lhs, rhs) => {
+                self.arithmetic(Operator::Modulus, lhs, rhs, interpreter)?
+            }
+            Expression::Guard(operand, guard) => {
+                self.evaluate(operand, interpreter)?.pack(guard)?
+            }
+            Expression::Time(target, interval) => {
+                let (receiver, function_call) = match target.as_ref() {
+                    Expression::FunctionCall(function_call) => (None, function_call),
+                    Expression::Pipe(receiver, function_call) => (Some(receiver), function_call),
+                    _ => return Err("The @ operator can only follow a call to start".to_string()),
+                };
+                if function_call.name != "start" {
+                    return Err("The @ operator can only follow a call to start".to_string());
+                }
+
+                let mut args = vec![];
+                if let Some(receiver) = receiver {
+                    args.push(self.evaluate(receiver, interpreter)?);
+                }
+                args.extend(self.evaluate_all(&function_call.arguments, interpreter)?);
+                self.start(args, interval, interpreter)?
+            }
+            Expression::Pipe(target, function_call) => {
+                let mut args = vec![self.evaluate(target, interpreter)?];
+                args.extend(self.evaluate_all(&function_call.arguments, interpreter)?);
+                interpreter.call(&function_call.name, args)?
+            }
+            Expression::FunctionCall(function_call) => {
+                let args = self.evaluate_all(&function_call.arguments, interpreter)?;
+                interpreter.call(&function_call.name, args)?
+            }
+        };
+
+        Ok(value)
+    }
+
+    fn compare(
+        &self,
+        lhs: &'p Expression,
+        rhs: &'p Expression,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<std::cmp::Ordering, String> {
+        let lhs = self.evaluate(lhs, interpreter)?.as_integer()?;
+        let rhs = self.evaluate(rhs, interpreter)?.as_integer()?;
+        Ok(lhs.cmp(&rhs))
+    }
+
+    fn arithmetic(
+        &self,
+        operator: Operator,
+        lhs: &'p Expression,
+        rhs: &'p Expression,
+        interpreter: &mut Interpreter<'p>,
+    ) -> Result<Value, String> {
+        let lhs = self.evaluate(lhs, interpreter)?;
+        let rhs = self.evaluate(rhs, interpreter)?;
+        arithmetic(operator, lhs, rhs)
+    }
+}
+
+pub(crate) fn arithmetic(operator: Operator, lhs: Value, rhs: Value) -> Result<Value, String> {
+    if let Operator::Sum = operator {
+        match (&lhs, &rhs) {
+            (Value::String(a), Value::String(b)) => return Ok(Value::String([&a[..], b].concat())),
+            (Value::Bytes(a), Value::Bytes(b)) => return Ok(Value::Bytes([&a[..], b].concat())),
+            (Value::List(a), Value::List(b)) => return Ok(Value::List([&a[..], b].concat())),
+            _ => {}
+        }
+    }
+
+    let lhs = lhs.as_integer()?;
+    let rhs = rhs.as_integer()?;
+    let result = match operator {
+        Operator::Sum => lhs.checked_add(rhs),
+        Operator::Minus => lhs.checked_sub(rhs),
+        Operator::Multiply => lhs.checked_mul(rhs),
+        Operator::Division | Operator::Modulus if rhs == 0 => {
+            return Err("Division by zero".to_string())
+        }
+        Operator::Division => lhs.checked_div(rhs),
+        Operator::Modulus => lhs.checked_rem(rhs),
+    };
+
+    result
+        .map(Value::Integer)
+        .ok_or_else(|| "Integer overflow".to_string())
+}
+
+fn pattern_matches(pattern: &Pattern, value: &Value) -> bool {
+    match (pattern, value) {
+        (Pattern::Literal(literal), value) => &Value::from(literal) == value,
+        (Pattern::Range(start, end), Value::Integer(value)) => {
+            (*start as i64..=*end as i64).contains(value)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+    use crate::runtime::{run_captured, Options};
+    use std::fs;
+
+    /// What `source` prints in its first `ms` simulated milliseconds, with
+    /// how the run ended.
+    fn run(source: &str, ms: u64) -> (String, Result<(), String>) {
+        let program = parser::parse_source(source);
+        let options = Options {
+            sim_time_ms: Some(ms),
+            seed: Some(1),
+            ..Options::default()
+        };
+        run_captured(&program, &options)
+    }
+
+    #[test]
+    fn selects_the_first_true_branch() {
+        let source = "
+            function pick(n)
+                if (n == 0)
+                    return \"if\";
+                elif (n < 10)
+                    return \"first elif\";
+                elif (n < 100)
+                    return \"second elif\";
+                else
+                    return \"else\";
+                end
+            end
+
+            task main
+                print(\"%s, %s, %s, %s\\n\", pick(0), pick(5), pick(50), pick(500));
+            end
+        ";
+        assert_eq!(
+            run(source, 10),
+            ("if, first elif, second elif, else\n".to_string(), Ok(()))
+        );
+    }
+
+    #[test]
+    fn fails_on_integer_overflow() {
+        let source = "
+            task main
+                big = 0x7fffffffffffffff;
+                print(\"%d\\n\", big - 1);
+                print(\"%d\\n\", big + 1);
+            end
+        ";
+        let (output, result) = run(source, 10);
+        assert_eq!(output, "9223372036854775806\n");
+        assert!(result.unwrap_err().contains("Integer overflow"));
+    }
+
+    #[test]
+    fn unpacks_only_bytes_of_the_targets_length() {
+        let source = "
+            task main
+                [a, b::-2] = [1, 2, 3];
+                print(\"%d %d\\n\", a, b);
+                [c, d::2] = [1, 2];
+            end
+        ";
+        let (output, result) = run(source, 10);
+        assert_eq!(output, "1 515\n");
+        assert!(result
+            .unwrap_err()
+            .contains("Cannot unpack 2 byte(s) into targets of 3 byte(s)"));
+    }
+
+    #[test]
+    fn iterates_over_a_copy_of_a_record() {
+        let source = "
+            record r[3, 1];
+
+            task main
+                r[0] = -1;
+                r[2] = 7;
+                for (e in r)
+                    r[1] = 9;
+                    print(\"%d \", e);
+                end
+                print(\"%d\", r[1]);
+                r[3] = 0;
+            end
+        ";
+        assert_eq!(
+            run(source, 10),
+            (
+                "255 0 7 9".to_string(),
+                Err("The index 3 is out of bounds for r of length 3".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn delivers_one_byte_per_packet_without_a_guard() {
+        let program = parser::parse_source("when 'uart' => msg print(\"%s \", msg); end");
+        let input =
+            std::env::temp_dir().join(format!("nxc-default-guard-{}.in", std::process::id()));
+        fs::write(&input, "xyz").unwrap();
+        let options = Options {
+            sim_time_ms: Some(10),
+            interfaces: vec![("uart".to_string(), format!("file:{},", input.display()))],
+            ..Options::default()
+        };
+
+        let result = run_captured(&program, &options);
+        fs::remove_file(input).unwrap();
+        assert_eq!(result, ("[0x78] [0x79] [0x7a] ".to_string(), Ok(())));
+    }
+
+    #[test]
+    fn receives_a_file_before_the_clock_moves() {
+        let program = parser::parse_source(include_str!("../../example.nx"));
+        let dir = std::env::temp_dir();
+        let input = dir.join(format!("nxc-interpreter-{}.in", std::process::id()));
+        let output = dir.join(format!("nxc-interpreter-{}.out", std::process::id()));
+        // Starts `voltage_r` every 300ms for register 7.
+        fs::write(&input, [0x10, 0x07, 0x2c, 0x01]).unwrap();
+        let options = Options {
+            sim_time_ms: Some(700),
+            seed: Some(1),
+            interfaces: vec![(
+                "uart".to_string(),
+                format!("file:{},{}", input.display(), output.display()),
+            )],
+            ..Options::default()
+        };
+
+        let result = run_captured(&program, &options);
+        let sent = fs::read(&output).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+
+        assert_eq!(result, ("Diff\n167441".to_string(), Ok(())));
+        let mut expected = b"heatbeat\xc8heatbeat".to_vec();
+        expected.extend([0xf0, 0x07, 0x00, 0x00, 0x00, 0xef]);
+        expected.extend([0xf0, 0x07, 0x00, 0x00, 0x00, 0xfc]);
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn ends_at_the_time_limit_while_an_interface_is_open() {
+        let (done, finished) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let program = parser::parse_source("when 'uart' => msg print(msg); end");
+            let options = Options {
+                sim_time_ms: Some(1000),
+                interfaces: vec![("uart".to_string(), "tcp:127.0.0.1:0".to_string())],
+                ..Options::default()
+            };
+            done.send(run_captured(&program, &options)).unwrap();
+        });
+
+        let result = finished
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .expect("the run did not end at the time limit");
+        assert_eq!(result, (String::new(), Ok(())));
+    }
+
+    #[test]
+    fn traces_events_as_json_lines() {
+        let source = "
+            function beat(n)
+                @10;
+            end
+
+            task main
+                beat.start([1]);
+                b = beat.start([2]);
+                print(\"quote \\x22 slash \\x5c tab \\t bell \\x07 \\xc3\\xa9\\n\");
+                @20;
+                b.stop();
+            end
+
+            when \"uart\" => msg::2
+                @1;
+            end
+        ";
+        let program = parser::parse_source(source);
+        let dir = std::env::temp_dir();
+        let capture = dir.join(format!("nxc-interpreter-{}.nxcap", std::process::id()));
+        let trace = dir.join(format!("nxc-interpreter-{}.jsonl", std::process::id()));
+        fs::write(&capture, "# nxcap 1\n5 uart 0102\n").unwrap();
+        let options = Options {
+            sim_time_ms: Some(50),
+            seed: Some(7),
+            replay_path: Some(capture.clone()),
+            trace_path: Some(trace.clone()),
+            ..Options::default()
+        };
+
+        let (_, result) = run_captured(&program, &options);
+        let lines = fs::read_to_string(&trace).unwrap();
+        fs::remove_file(capture).unwrap();
+        fs::remove_file(trace).unwrap();
+
+        assert_eq!(result, Ok(()));
+        let expected = [
+            r#"{"time":0,"event":"seed","seed":7}"#,
+            r#"{"time":0,"event":"start","id":0,"task":"main","interval":0}"#,
+            r#"{"time":0,"event":"tick","id":0,"task":"main"}"#,
+            r#"{"time":0,"event":"start","id":1,"task":"beat","interval":0}"#,
+            r#"{"time":0,"event":"start","id":2,"task":"beat","interval":0}"#,
+            r#"{"time":0,"event":"print","text":"quote \" slash \\ tab \t bell \u0007 é\n"}"#,
+            r#"{"time":0,"event":"delay","id":0,"task":"main","delay":20}"#,
+            r#"{"time":0,"event":"tick","id":1,"task":"beat"}"#,
+            r#"{"time":0,"event":"delay","id":1,"task":"beat","delay":10}"#,
+            r#"{"time":0,"event":"tick","id":2,"task":"beat"}"#,
+            r#"{"time":0,"event":"delay","id":2,"task":"beat","delay":10}"#,
+            r#"{"time":5,"event":"packet","interface":"uart","handler":"msg::2","bytes":"0102"}"#,
+            r#"{"time":5,"event":"tick","id":3,"task":"when \"uart\" => msg::2"}"#,
+            r#"{"time":5,"event":"delay","id":3,"task":"when \"uart\" => msg::2","delay":1}"#,
+            r#"{"time":20,"event":"stop","id":2,"task":"beat"}"#,
+        ];
+        assert_eq!(lines.lines().collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::parser::AST;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod builtins;
+pub mod capture;
+pub mod framer;
+pub mod interface;
+pub mod interpreter;
+pub mod random;
+pub mod record;
+pub mod scheduler;
+pub mod store;
+pub mod trace;
+pub mod value;
+
+use interpreter::Interpreter;
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Run in simulated time up to this many milliseconds instead of
+    /// following the wall clock.
+    pub sim_time_ms: Option<u64>,
+    /// Interface names with the spec of the stand-in opened for them, as
+    /// accepted by `interface::open`.
+    pub interfaces: Vec<(String, String)>,
+    /// How incoming byte streams are split into packets.
+    pub framing: framer::Limits,
+    /// File that keeps the `store` variables, so that they survive a
+    /// restart of the program.
+    pub store_path: Option<PathBuf>,
+    /// File the events of the run are logged to, as written by
+    /// `trace::Trace`.
+    pub trace_path: Option<PathBuf>,
+    /// Capture file whose traffic is received at its recorded times.
+    pub replay_path: Option<PathBuf>,
+    /// Capture file the traffic received during the run is written to.
+    pub record_path: Option<PathBuf>,
+    /// Seed of the numbers returned by `rand`, taken from the clock when
+    /// missing.
+    pub seed: Option<u64>,
+}
+
+/// How long the wall clock run waits for incoming bytes between checks.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// What `drive` runs: the interpreter or the bytecode VM.
+pub trait Machine {
+    /// Moves the virtual clock forward to `time_ms` without running
+    /// anything.
+    fn advance_to(&mut self, time_ms: u64);
+
+    /// Collects the bytes received by every interface and starts the
+    /// handlers they match. Returns whether any interface is still open.
+    fn poll_interfaces(&mut self) -> Result<bool, String>;
+
+    /// The virtual time at which something is next due.
+    fn next_wake(&self) -> Option<u64>;
+
+    /// Moves the clock to `next_wake` and handles what is due then.
+    fn step(&mut self) -> Result<(), String>;
+}
+
+/// Opens the interfaces given in the options, by name.
+pub fn open_interfaces(
+    options: &Options,
+) -> Result<HashMap<String, Box<dyn interface::Interface>>, String> {
+    let mut interfaces = HashMap::new();
+    for (name, spec) in &options.interfaces {
+        interfaces.insert(
+            name.clone(),
+            interface::open(name, spec, options.sim_time_ms.is_some())?,
+        );
+    }
+    Ok(interfaces)
+}
+
+/// Runs the tasks of the program and the handlers of incoming packets until
+/// no task or partial packet is left and every interface is closed or, in simulated time,
+/// until the time limit.
+pub fn run(program: &[AST], output: Box<dyn Write>, options: &Options) -> Result<(), String> {
+    let interfaces = open_interfaces(options)?;
+    let mut interpreter = Interpreter::new(program, output, interfaces, options)?;
+    drive(&mut interpreter, options.sim_time_ms)
+}
+
+/// Runs a machine until nothing is due and every interface is closed or,
+/// in simulated time, until `sim_time_ms`.
+///
+/// In simulated time, interfaces are checked between steps and bytes are
+/// received at the current virtual time; when nothing is due the clock moves
+/// to `sim_time_ms` and the run ends, even while interfaces are open. Only
+/// files and standard input, read whole when they are opened, and replayed
+/// captures arrive at the same virtual time on every run.
+pub fn drive(machine: &mut impl Machine, sim_time_ms: Option<u64>) -> Result<(), String> {
+    let started = Instant::now();
+
+    loop {
+        if sim_time_ms.is_none() {
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            machine.advance_to(elapsed_ms);
+        }
+        let listening = machine.poll_interfaces()?;
+
+        match (machine.next_wake(), sim_time_ms) {
+            (None, None) if !listening => break,
+            (None, Some(limit_ms)) => {
+                machine.advance_to(limit_ms);
+                break;
+            }
+            (None, None) => {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            }
+            (Some(wake_at), Some(limit_ms)) if wake_at > limit_ms => {
+                machine.advance_to(limit_ms);
+                break;
+            }
+            (Some(_), Some(_)) => {}
+            (Some(wake_at), None) => {
+                let due = started + Duration::from_millis(wake_at);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep((due - now).min(POLL_INTERVAL));
+                    continue;
+                }
+            }
+        }
+
+        machine.step()?;
+    }
+
+    Ok(())
+}
+
+/// Runs `program` like `run`, returning what it printed with how the run
+/// ended.
+#[cfg(test)]
+pub(crate) fn run_captured(program: &[AST], options: &Options) -> (String, Result<(), String>) {
+    let output = Captured::default();
+    let result = run(program, Box::new(output.clone()), options);
+    (output.text(), result)
+}
+
+/// Output that tests read back after the run that writes it.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct Captured(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
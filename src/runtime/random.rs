@@ -0,0 +1,72 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The pseudo-random generator behind `rand` (SplitMix64): the same seed
+/// always gives the same numbers, on every platform.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A seed taken from the wall clock, for runs without `--seed`.
+    pub fn clock_seed() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or(0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `min..max`: `min` included, `max` excluded.
+    pub fn range(&mut self, min: i64, max: i64) -> Result<i64, String> {
+        if max <= min {
+            return Err(format!("rand needs min < max, got {} and {}", min, max));
+        }
+        let span = max.wrapping_sub(min) as u64 as u128;
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        Ok(min.wrapping_add(offset as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_splitmix64() {
+        let mut random = Random::new(0);
+        assert_eq!(random.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(random.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+    }
+
+    #[test]
+    fn pins_the_range_of_a_seed() {
+        let mut random = Random::new(1);
+        let values: Vec<i64> = (0..6).map(|_| random.range(-5, 5).unwrap()).collect();
+        assert_eq!(values, [0, 2, 4, -1, -1, 2]);
+    }
+
+    #[test]
+    fn rejects_empty_ranges() {
+        let mut random = Random::new(1);
+        assert_eq!(
+            random.range(3, 3),
+            Err("rand needs min < max, got 3 and 3".to_string())
+        );
+        assert_eq!(
+            random.range(4, -4),
+            Err("rand needs min < max, got 4 and -4".to_string())
+        );
+        assert!(random.range(i64::MIN, i64::MAX).is_ok());
+    }
+}
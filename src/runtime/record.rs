@@ -0,0 +1,120 @@
+use crate::parser::fits_width;
+use crate::runtime::value::Value;
+
+/// The elements of a `record`: unsigned integers of `data_size` bytes. An
+/// 8-byte element that does not fit an integer reads back negative.
+#[derive(Debug)]
+pub struct Record {
+    name: String,
+    data_size: usize,
+    elements: Vec<i64>,
+}
+
+impl Record {
+    pub fn new(name: &str, length: usize, data_size: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            data_size,
+            elements: vec![0; length],
+        }
+    }
+
+    pub fn get(&self, index: i64) -> Result<Value, String> {
+        let index = self.position(index)?;
+        Ok(Value::Integer(self.elements[index]))
+    }
+
+    /// Stores an integer that fits the element as either an unsigned or a
+    /// two's complement value.
+    pub fn set(&mut self, index: i64, value: &Value) -> Result<(), String> {
+        let index = self.position(index)?;
+        let value = value.as_integer()?;
+        if !fits_width(value as isize, self.data_size) {
+            return Err(format!(
+                "The value {} does not fit in the {} byte(s) of an element of {}",
+                value, self.data_size, self.name
+            ));
+        }
+
+        self.elements[index] = match self.data_size {
+            8 => value,
+            size => value & ((1 << (8 * size)) - 1),
+        };
+        Ok(())
+    }
+
+    /// A copy of the elements, as iterated by `for`.
+    pub fn to_value(&self) -> Value {
+        Value::List(self.elements.iter().map(|e| Value::Integer(*e)).collect())
+    }
+
+    fn position(&self, index: i64) -> Result<usize, String> {
+        if index < 0 || index as usize >= self.elements.len() {
+            return Err(format!(
+                "The index {} is out of bounds for {} of length {}",
+                index,
+                self.name,
+                self.elements.len()
+            ));
+        }
+        Ok(index as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_indices_out_of_bounds() {
+        let mut record = Record::new("r", 2, 1);
+        assert_eq!(
+            record.get(2),
+            Err("The index 2 is out of bounds for r of length 2".to_string())
+        );
+        assert_eq!(
+            record.set(-1, &Value::Integer(0)),
+            Err("The index -1 is out of bounds for r of length 2".to_string())
+        );
+        assert_eq!(record.get(1), Ok(Value::Integer(0)));
+    }
+
+    #[test]
+    fn masks_negative_values_into_unsigned_elements() {
+        let mut record = Record::new("r", 3, 2);
+        record.set(0, &Value::Integer(-1)).unwrap();
+        record.set(1, &Value::Integer(-32768)).unwrap();
+        record.set(2, &Value::Integer(65535)).unwrap();
+        assert_eq!(
+            record.to_value(),
+            Value::List(vec![
+                Value::Integer(65535),
+                Value::Integer(32768),
+                Value::Integer(65535)
+            ])
+        );
+
+        let mut wide = Record::new("w", 1, 8);
+        wide.set(0, &Value::Integer(-1)).unwrap();
+        assert_eq!(wide.get(0), Ok(Value::Integer(-1)));
+    }
+
+    #[test]
+    fn rejects_values_that_do_not_fit() {
+        let mut record = Record::new("r", 1, 1);
+        for value in [256, -129] {
+            assert_eq!(
+                record.set(0, &Value::Integer(value)),
+                Err(format!(
+                    "The value {} does not fit in the 1 byte(s) of an element of r",
+                    value
+                ))
+            );
+        }
+        assert_eq!(
+            record.set(0, &Value::Nil),
+            Err("Expected an integer, found nil".to_string())
+        );
+        assert_eq!(record.get(0), Ok(Value::Integer(0)));
+    }
+}
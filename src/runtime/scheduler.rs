@@ -0,0 +1,314 @@
+use crate::runtime::interpreter::Step;
+use crate::runtime::value::TaskId;
+
+/// Cooperative scheduler driven by a virtual millisecond clock.
+///
+/// A task with an interval is ticked every `interval_ms` from the time its
+/// previous tick started; a tick that runs late, because of its own delays,
+/// is followed right away by the next one. A task without an interval runs
+/// once. Tasks due at the same time run in the order they were scheduled, so
+/// a program always runs the same way.
+///
+/// A `TaskId` stays valid for the whole run: a stopped or finished task can
+/// be started again through it.
+///
+/// Each tick runs a copy of the fiber a task was spawned with, which is a
+/// `Fiber` of the interpreter or of the bytecode VM.
+pub struct Scheduler<F> {
+    now_ms: u64,
+    next_sequence: u64,
+    tasks: Vec<Task<F>>,
+}
+
+struct Task<F> {
+    name: String,
+    /// Whether the program has no handle to the task, so its slot can be
+    /// reused once it stops.
+    detached: bool,
+    fiber: F,
+    interval_ms: usize,
+    state: TaskState<F>,
+}
+
+enum TaskState<F> {
+    Waiting {
+        wake_at: u64,
+        sequence: u64,
+        /// The tick in progress and when it started, if any.
+        tick: Option<(F, u64)>,
+    },
+    /// The task is running its current tick.
+    Running {
+        tick_started: u64,
+    },
+    Stopped,
+}
+
+impl<F> Default for Scheduler<F> {
+    fn default() -> Self {
+        Self {
+            now_ms: 0,
+            next_sequence: 0,
+            tasks: vec![],
+        }
+    }
+}
+
+impl<F: Clone> Scheduler<F> {
+    pub fn now(&self) -> u64 {
+        self.now_ms
+    }
+
+    pub fn name(&self, id: TaskId) -> &str {
+        &self.tasks[id].name
+    }
+
+    pub fn interval(&self, id: TaskId) -> usize {
+        self.tasks[id].interval_ms
+    }
+
+    /// Adds a task whose first tick is due now, or after one interval when
+    /// it is not `immediate`. Each tick runs a copy of `fiber`.
+    pub fn spawn(&mut self, name: &str, fiber: F, interval_ms: usize, immediate: bool) -> TaskId {
+        self.tasks.push(Task {
+            name: name.to_string(),
+            detached: false,
+            fiber,
+            interval_ms,
+            state: TaskState::Stopped,
+        });
+
+        let id = self.tasks.len() - 1;
+        self.restart(id, None, immediate);
+        id
+    }
+
+    /// Adds a task that runs `fiber` once, now, and that the program has no
+    /// handle to, such as a `when` handler.
+    pub fn spawn_detached(&mut self, name: &str, fiber: F) {
+        let reusable = self
+            .tasks
+            .iter()
+            .position(|task| task.detached && matches!(task.state, TaskState::Stopped));
+        let task = Task {
+            name: name.to_string(),
+            detached: true,
+            fiber,
+            interval_ms: 0,
+            state: TaskState::Stopped,
+        };
+
+        let id = match reusable {
+            Some(id) => {
+                self.tasks[id] = task;
+                id
+            }
+            None => {
+                self.tasks.push(task);
+                self.tasks.len() - 1
+            }
+        };
+        self.restart(id, None, true);
+    }
+
+    /// Starts a task over from the beginning of its body, optionally with a
+    /// new interval. A tick in progress is abandoned at its next delay.
+    pub fn restart(&mut self, id: TaskId, interval_ms: Option<usize>, immediate: bool) {
+        let now_ms = self.now_ms;
+        let sequence = self.sequence();
+        let task = &mut self.tasks[id];
+
+        if let Some(interval_ms) = interval_ms {
+            task.interval_ms = interval_ms;
+        }
+        let wake_at = if immediate {
+            now_ms
+        } else {
+            now_ms + task.interval_ms as u64
+        };
+
+        task.state = TaskState::Waiting {
+            wake_at,
+            sequence,
+            tick: None,
+        };
+    }
+
+    /// Stops a task. Stopping a task that is not running does nothing.
+    pub fn stop(&mut self, id: TaskId) {
+        self.tasks[id].state = TaskState::Stopped;
+    }
+
+    /// The virtual time at which the next task is due.
+    pub fn next_wake(&self) -> Option<u64> {
+        self.next_due().map(|(_, wake_at)| wake_at)
+    }
+
+    /// Moves the clock forward to `time` without running anything.
+    pub fn advance_to(&mut self, time: u64) {
+        self.now_ms = self.now_ms.max(time);
+    }
+
+    /// Moves the clock to the next due task and hands out the fiber to
+    /// resume, creating it when a new tick starts, along with whether it did.
+    pub fn take_due(&mut self) -> Option<(TaskId, F, bool)> {
+        let (id, wake_at) = self.next_due()?;
+        self.advance_to(wake_at);
+
+        let now_ms = self.now_ms;
+        let task = &mut self.tasks[id];
+        let (fiber, tick_started, new_tick) =
+            match std::mem::replace(&mut task.state, TaskState::Stopped) {
+                TaskState::Waiting {
+                    tick: Some((fiber, tick_started)),
+                    ..
+                } => (fiber, tick_started, false),
+                _ => (task.fiber.clone(), now_ms, true),
+            };
+        task.state = TaskState::Running { tick_started };
+
+        Some((id, fiber, new_tick))
+    }
+
+    /// Takes back a fiber handed out by `take_due` after it was resumed.
+    pub fn park(&mut self, id: TaskId, fiber: F, step: Step) {
+        let now_ms = self.now_ms;
+        let sequence = self.sequence();
+        let task = &mut self.tasks[id];
+
+        let tick_started = match task.state {
+            TaskState::Running { tick_started } => tick_started,
+            _ => return,
+        };
+
+        task.state = match step {
+            Step::Suspended(delay_ms) => TaskState::Waiting {
+                wake_at: now_ms + delay_ms as u64,
+                sequence,
+                tick: Some((fiber, tick_started)),
+            },
+            Step::Finished(_) if task.interval_ms > 0 => TaskState::Waiting {
+                wake_at: now_ms.max(tick_started + task.interval_ms as u64),
+                sequence,
+                tick: None,
+            },
+            Step::Finished(_) => TaskState::Stopped,
+        };
+    }
+
+    fn next_due(&self) -> Option<(TaskId, u64)> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .filter_map(|(id, task)| match task.state {
+                TaskState::Waiting {
+                    wake_at, sequence, ..
+                } => Some((wake_at, sequence, id)),
+                _ => None,
+            })
+            .min()
+            .map(|(wake_at, _, id)| (id, wake_at))
+    }
+
+    fn sequence(&mut self) -> u64 {
+        self.next_sequence += 1;
+        self.next_sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::value::Value;
+
+    /// A fiber that waits for each of its delays in turn and then finishes.
+    type Delays = Vec<usize>;
+
+    /// Runs everything due up to `until_ms`, returning when each task was
+    /// resumed and whether a new tick started then.
+    fn run(scheduler: &mut Scheduler<Delays>, until_ms: u64) -> Vec<(u64, String, bool)> {
+        let mut resumed = vec![];
+        while scheduler
+            .next_wake()
+            .is_some_and(|wake_at| wake_at <= until_ms)
+        {
+            let (id, mut fiber, new_tick) = scheduler.take_due().unwrap();
+            resumed.push((scheduler.now(), scheduler.name(id).to_string(), new_tick));
+            let step = match fiber.is_empty() {
+                true => Step::Finished(Value::Nil),
+                false => Step::Suspended(fiber.remove(0)),
+            };
+            scheduler.park(id, fiber, step);
+        }
+        resumed
+    }
+
+    fn times(resumed: &[(u64, String, bool)]) -> Vec<u64> {
+        resumed.iter().map(|(time, ..)| *time).collect()
+    }
+
+    #[test]
+    fn ticks_at_every_interval() {
+        let mut scheduler = Scheduler::default();
+        scheduler.spawn("now", vec![], 100, true);
+        scheduler.spawn("later", vec![], 100, false);
+        // "later" was scheduled for 100 before the first tick of "now" ended
+        // and scheduled its next one.
+        let resumed = run(&mut scheduler, 350);
+        let ticks: Vec<_> = resumed
+            .iter()
+            .map(|(time, name, _)| (*time, name.as_str()))
+            .collect();
+        assert_eq!(
+            ticks,
+            [
+                (0, "now"),
+                (100, "later"),
+                (100, "now"),
+                (200, "later"),
+                (200, "now"),
+                (300, "later"),
+                (300, "now")
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_a_late_tick_right_away() {
+        let mut scheduler = Scheduler::default();
+        scheduler.spawn("slow", vec![150], 100, true);
+        let resumed = run(&mut scheduler, 400);
+        assert_eq!(times(&resumed), [0, 150, 150, 300, 300]);
+        let new_ticks: Vec<_> = resumed.iter().map(|(.., new_tick)| *new_tick).collect();
+        assert_eq!(new_ticks, [true, false, true, false, true]);
+    }
+
+    #[test]
+    fn resumes_after_delays() {
+        let mut scheduler = Scheduler::default();
+        scheduler.spawn("once", vec![30, 20], 0, true);
+        assert_eq!(times(&run(&mut scheduler, 1000)), [0, 30, 50]);
+        assert_eq!(scheduler.next_wake(), None);
+        assert_eq!(scheduler.now(), 50);
+    }
+
+    #[test]
+    fn runs_tasks_due_together_in_scheduling_order() {
+        let mut scheduler = Scheduler::default();
+        scheduler.spawn("b", vec![10], 0, true);
+        scheduler.spawn("a", vec![10], 0, true);
+        scheduler.spawn_detached("handler", vec![]);
+        let order: Vec<_> = run(&mut scheduler, 100)
+            .into_iter()
+            .map(|(time, name, _)| (time, name))
+            .collect();
+        let order: Vec<_> = order
+            .iter()
+            .map(|(time, name)| (*time, name.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            [(0, "b"), (0, "a"), (0, "handler"), (10, "b"), (10, "a")]
+        );
+    }
+}
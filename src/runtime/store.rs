@@ -0,0 +1,249 @@
+use crate::runtime::value::Value;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// The `store` variables of one scope: the whole program, for variables
+/// stored at the top level, or a single function, task or `when` handler.
+///
+/// Stored variables start as `nil` and keep their value between runs of the
+/// bodies that share them.
+#[derive(Debug)]
+pub struct Storage {
+    /// Names the scope in a store file, such as `task heatbeat_r`.
+    pub scope: String,
+    pub values: HashMap<String, Value>,
+}
+
+impl Storage {
+    pub fn new(scope: String, names: &[&str]) -> Self {
+        let values = names
+            .iter()
+            .map(|name| (name.to_string(), Value::Nil))
+            .collect();
+        Self { scope, values }
+    }
+}
+
+/// Writes the stored variables to a store file, one `[scope]` section per
+/// storage with a `name = value` line per variable. Task handles and
+/// functions are saved as `nil`, since they do not outlive the run.
+pub fn save<'a>(path: &Path, storages: impl Iterator<Item = &'a Storage>) -> Result<(), String> {
+    let mut storages: Vec<&Storage> = storages.collect();
+    storages.sort_by(|a, b| a.scope.cmp(&b.scope));
+
+    let mut text = String::new();
+    for storage in storages {
+        let mut names: Vec<&String> = storage.values.keys().collect();
+        names.sort();
+
+        writeln!(text, "[{}]", storage.scope).unwrap();
+        for name in names {
+            writeln!(text, "{} = {}", name, encode(&storage.values[name])).unwrap();
+        }
+    }
+
+    fs::write(path, text).map_err(|err| format!("Cannot write {}: {}", path.display(), err))
+}
+
+/// Reads the values of a store file written by `save`, by scope and name.
+/// A missing file holds no values.
+pub fn load(path: &Path) -> Result<HashMap<String, HashMap<String, Value>>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(format!("Cannot read {}: {}", path.display(), err)),
+    };
+
+    let mut scopes: HashMap<String, HashMap<String, Value>> = HashMap::new();
+    let mut scope = None;
+    for (line_number, line) in text.lines().enumerate() {
+        let invalid = || {
+            format!(
+                "Invalid store file {} at line {}",
+                path.display(),
+                line_number + 1
+            )
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            scope = Some(scopes.entry(name.to_string()).or_default());
+            continue;
+        }
+
+        let (name, value) = line.split_once(" = ").ok_or_else(invalid)?;
+        let mut bytes = value.as_bytes();
+        let value = decode(&mut bytes).ok_or_else(invalid)?;
+        if !bytes.is_empty() {
+            return Err(invalid());
+        }
+        scope
+            .as_mut()
+            .ok_or_else(invalid)?
+            .insert(name.to_string(), value);
+    }
+
+    Ok(scopes)
+}
+
+fn encode(value: &Value) -> String {
+    match value {
+        Value::Nil | Value::Task(_) | Value::Function(_) => "nil".to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Integer(value) => value.to_string(),
+        Value::String(data) => encode_string(data),
+        Value::Bytes(data) => format!("b{}", encode_string(data)),
+        Value::List(items) => {
+            let items: Vec<String> = items.iter().map(encode).collect();
+            format!("[{}]", items.join(", "))
+        }
+    }
+}
+
+fn encode_string(data: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for &b in data {
+        match b {
+            0x20..=0x7e if b != b'"' && b != b'\\' => text.push(b as char),
+            _ => write!(text, "\\x{:02x}", b).unwrap(),
+        }
+    }
+    text.push('"');
+    text
+}
+
+/// Decodes the value at the beginning of `text` and moves past it.
+fn decode(text: &mut &[u8]) -> Option<Value> {
+    let word_len = text
+        .iter()
+        .position(|b| !(b.is_ascii_alphanumeric() || *b == b'-'))
+        .unwrap_or(text.len());
+
+    let value = match text.first()? {
+        b'"' => Value::String(decode_string(text)?),
+        b'b' if text.get(1) == Some(&b'"') => {
+            *text = &text[1..];
+            Value::Bytes(decode_string(text)?)
+        }
+        b'[' => {
+            *text = &text[1..];
+            let mut items = vec![];
+            while text.first()? != &b']' {
+                if !items.is_empty() {
+                    *text = text.strip_prefix(b", ")?;
+                }
+                items.push(decode(text)?);
+            }
+            *text = &text[1..];
+            Value::List(items)
+        }
+        _ => {
+            let word = std::str::from_utf8(&text[..word_len]).ok()?;
+            let value = match word {
+                "nil" => Value::Nil,
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                _ => Value::Integer(word.parse().ok()?),
+            };
+            *text = &text[word_len..];
+            value
+        }
+    };
+    Some(value)
+}
+
+fn decode_string(text: &mut &[u8]) -> Option<Vec<u8>> {
+    let mut data = vec![];
+    let mut position = 1;
+    loop {
+        match *text.get(position)? {
+            b'"' => break,
+            b'\\' => {
+                let hex = text.get(position + 1..position + 4)?;
+                let hex = std::str::from_utf8(hex.strip_prefix(b"x")?).ok()?;
+                data.push(u8::from_str_radix(hex, 16).ok()?);
+                position += 4;
+            }
+            b => {
+                data.push(b);
+                position += 1;
+            }
+        }
+    }
+    *text = &text[position + 1..];
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nxc-store-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn round_trips_values() {
+        let value = Value::List(vec![
+            Value::Integer(-42),
+            Value::Integer(i64::MIN),
+            Value::List(vec![
+                Value::Nil,
+                Value::Boolean(true),
+                Value::List(vec![]),
+                Value::String("say \"hi\" \\ é".as_bytes().to_vec()),
+            ]),
+            Value::Bytes(vec![b'"', b'\\', 0x00, 0xff, b'a']),
+            Value::Task(3),
+        ]);
+        assert_eq!(
+            encode(&value),
+            "[-42, -9223372036854775808, [nil, true, [], \"say \\x22hi\\x22 \\x5c \\xc3\\xa9\"], \
+             b\"\\x22\\x5c\\x00\\xffa\", nil]"
+        );
+
+        let path = temp_path("round-trip");
+        let mut storage = Storage::new("task main".to_string(), &["value", "empty"]);
+        storage.values.insert("value".to_string(), value.clone());
+        save(&path, std::iter::once(&storage)).unwrap();
+        let scopes = load(&path);
+        fs::remove_file(path).unwrap();
+
+        let mut expected = value;
+        if let Value::List(items) = &mut expected {
+            items[4] = Value::Nil;
+        }
+        let scope = &scopes.unwrap()["task main"];
+        assert_eq!(scope["value"], expected);
+        assert_eq!(scope["empty"], Value::Nil);
+    }
+
+    #[test]
+    fn reports_the_line_of_malformed_values() {
+        let cases = [
+            ("[task main]\nok = 1\nbad = [1, 2\n", 3),
+            ("[task main]\n\nbad = \"\\x4\"\n", 3),
+            ("[task main]\nbad = 1 2\n", 2),
+            ("bad = 1\n", 1),
+            ("[task main]\nbad: 1\n", 2),
+        ];
+        for (index, (text, line)) in cases.iter().enumerate() {
+            let path = temp_path(&format!("malformed-{}", index));
+            fs::write(&path, text).unwrap();
+            let result = load(&path);
+            fs::remove_file(&path).unwrap();
+            assert_eq!(
+                result.unwrap_err(),
+                format!("Invalid store file {} at line {}", path.display(), line)
+            );
+        }
+    }
+}
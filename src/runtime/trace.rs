@@ -0,0 +1,154 @@
+use crate::runtime::value::TaskId;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Something that happened during a run, as logged by a `Trace`.
+pub enum Event<'a> {
+    /// The seed of `rand` for the run, logged first.
+    Seed {
+        seed: u64,
+    },
+    /// A `when` handler is started with a packet.
+    Packet {
+        interface: &'a str,
+        handler: &'a str,
+        bytes: &'a [u8],
+    },
+    /// Bytes received on an interface that no handler accepted.
+    Garbage {
+        interface: &'a str,
+        bytes: &'a [u8],
+    },
+    Send {
+        interface: &'a str,
+        bytes: &'a [u8],
+    },
+    Print {
+        text: &'a [u8],
+    },
+    /// A task is started, or started over, with its interval. Task events
+    /// carry the id of the task, since tasks started from the same function
+    /// share its name.
+    Start {
+        id: TaskId,
+        task: &'a str,
+        interval_ms: usize,
+    },
+    Stop {
+        id: TaskId,
+        task: &'a str,
+    },
+    /// A task begins a run of its body.
+    Tick {
+        id: TaskId,
+        task: &'a str,
+    },
+    /// A task waits at an `@` delay.
+    Delay {
+        id: TaskId,
+        task: &'a str,
+        delay_ms: usize,
+    },
+}
+
+/// Logs the events of a run as JSON Lines, one object per event stamped with
+/// the virtual time in milliseconds, such as
+/// `{"time":100,"event":"send","interface":"uart","bytes":"3a01"}`.
+///
+/// Keys are always written in the same order and bytes as lowercase hex, so
+/// that the traces of two versions of a program can be diffed.
+pub struct Trace {
+    output: Box<dyn Write>,
+}
+
+impl Trace {
+    pub fn new(output: Box<dyn Write>) -> Self {
+        Self { output }
+    }
+
+    pub fn log(&mut self, time_ms: u64, event: Event) -> Result<(), String> {
+        let mut line = format!("{{\"time\":{}", time_ms);
+        match event {
+            Event::Seed { seed } => {
+                field(&mut line, "event", "seed");
+                write!(line, ",\"seed\":{}", seed).unwrap();
+            }
+            Event::Packet {
+                interface,
+                handler,
+                bytes,
+            } => {
+                field(&mut line, "event", "packet");
+                field(&mut line, "interface", interface);
+                field(&mut line, "handler", handler);
+                field(&mut line, "bytes", &hex(bytes));
+            }
+            Event::Garbage { interface, bytes } => {
+                field(&mut line, "event", "garbage");
+                field(&mut line, "interface", interface);
+                field(&mut line, "bytes", &hex(bytes));
+            }
+            Event::Send { interface, bytes } => {
+                field(&mut line, "event", "send");
+                field(&mut line, "interface", interface);
+                field(&mut line, "bytes", &hex(bytes));
+            }
+            Event::Print { text } => {
+                field(&mut line, "event", "print");
+                field(&mut line, "text", &String::from_utf8_lossy(text));
+            }
+            Event::Start {
+                id,
+                task,
+                interval_ms,
+            } => {
+                field(&mut line, "event", "start");
+                write!(line, ",\"id\":{}", id).unwrap();
+                field(&mut line, "task", task);
+                write!(line, ",\"interval\":{}", interval_ms).unwrap();
+            }
+            Event::Stop { id, task } => {
+                field(&mut line, "event", "stop");
+                write!(line, ",\"id\":{}", id).unwrap();
+                field(&mut line, "task", task);
+            }
+            Event::Tick { id, task } => {
+                field(&mut line, "event", "tick");
+                write!(line, ",\"id\":{}", id).unwrap();
+                field(&mut line, "task", task);
+            }
+            Event::Delay { id, task, delay_ms } => {
+                field(&mut line, "event", "delay");
+                write!(line, ",\"id\":{}", id).unwrap();
+                field(&mut line, "task", task);
+                write!(line, ",\"delay\":{}", delay_ms).unwrap();
+            }
+        }
+        line.push_str("}\n");
+
+        self.output
+            .write_all(line.as_bytes())
+            .and_then(|_| self.output.flush())
+            .map_err(|err| format!("Cannot write the trace: {}", err))
+    }
+}
+
+fn field(line: &mut String, key: &str, value: &str) {
+    write!(line, ",\"{}\":\"", key).unwrap();
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(line, "\\u{:04x}", c as u32).unwrap(),
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,161 @@
+use crate::parser::{Endianness, Guard, Literal};
+use std::fmt::{Display, Formatter};
+
+pub type TaskId = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    /// Text written in the program, such as interface names and formats.
+    String(Vec<u8>),
+    /// Raw bytes: `when` packets and values packed with `::N`.
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Task(TaskId),
+    Function(String),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) => "integer",
+            Value::String(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Task(_) => "task",
+            Value::Function(_) => "function",
+        }
+    }
+
+    pub fn as_integer(&self) -> Result<i64, String> {
+        match self {
+            Value::Integer(value) => Ok(*value),
+            other => Err(format!("Expected an integer, found {}", other.type_name())),
+        }
+    }
+
+    pub fn as_boolean(&self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(value) => Ok(*value),
+            other => Err(format!("Expected a boolean, found {}", other.type_name())),
+        }
+    }
+
+    /// The bytes this value stands for when it is sent or unpacked: integers
+    /// are single bytes, strings and bytes are taken as they are and lists
+    /// are flattened.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![];
+        self.write_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write_bytes(&self, bytes: &mut Vec<u8>) -> Result<(), String> {
+        match self {
+            Value::Integer(value) => {
+                if !(0..=255).contains(value) {
+                    return Err(format!("The integer {} does not fit in a byte", value));
+                }
+                bytes.push(*value as u8);
+            }
+            Value::String(data) | Value::Bytes(data) => bytes.extend(data),
+            Value::List(items) => {
+                for item in items {
+                    item.write_bytes(bytes)?;
+                }
+            }
+            other => return Err(format!("Cannot convert {} to bytes", other.type_name())),
+        }
+        Ok(())
+    }
+
+    /// The items visited by a `for` loop.
+    pub fn items(&self) -> Result<Vec<Value>, String> {
+        match self {
+            Value::String(data) | Value::Bytes(data) => {
+                Ok(data.iter().map(|b| Value::Integer(*b as i64)).collect())
+            }
+            Value::List(items) => Ok(items.clone()),
+            other => Err(format!("Cannot iterate over {}", other.type_name())),
+        }
+    }
+
+    /// Packs an integer into the bytes described by a numeric guard.
+    pub fn pack(&self, guard: &Guard) -> Result<Value, String> {
+        let value = self.as_integer()?;
+        let (width, endianness) = match guard {
+            Guard::Numeric { width, endianness } => (*width, *endianness),
+            _ => return Err(format!("Cannot pack a value with the guard {}", guard)),
+        };
+        if !guard.fits(value as isize) {
+            return Err(format!(
+                "The value {} does not fit in {} byte(s)",
+                value, width
+            ));
+        }
+
+        let mut bytes = value.to_le_bytes()[..width].to_vec();
+        if endianness == Endianness::Big {
+            bytes.reverse();
+        }
+        Ok(Value::Bytes(bytes))
+    }
+
+    /// Reads the unsigned integer stored in `bytes` with the given byte order.
+    pub fn unpack(bytes: &[u8], endianness: Endianness) -> Value {
+        let fold = |acc: i64, b: &u8| (acc << 8) | *b as i64;
+        Value::Integer(match endianness {
+            Endianness::Little => bytes.iter().rev().fold(0, fold),
+            Endianness::Big => bytes.iter().fold(0, fold),
+        })
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Integer(value) => Value::Integer(*value as i64),
+            Literal::String(data) => Value::String(data.clone()),
+            Literal::Bytes(data) => Value::Bytes(data.clone()),
+            Literal::Boolean(value) => Value::Boolean(*value),
+            Literal::Nil => Value::Nil,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::String(data) => write!(f, "{}", String::from_utf8_lossy(data)),
+            Value::Bytes(data) => {
+                write!(f, "[")?;
+                for (idx, b) in data.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "0x{:02x}", b)?;
+                }
+                write!(f, "]")
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Task(id) => write!(f, "<task {}>", id),
+            Value::Function(name) => write!(f, "<function {}>", name),
+        }
+    }
+}
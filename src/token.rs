@@ -13,12 +13,26 @@ pub enum TokenKind {
     GuardOperator,
     TimeOperator,
     PipeOperator,
+    RangeOperator,
+    AlternativeOperator,
     RightArrow,
     MatchDefaultOperator,
     BooleanLiteral,
     NilLiteral,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 pub struct Token {
     kind: TokenKind,
     value: String,
@@ -48,6 +62,21 @@ impl Token {
         self.value.len()
     }
 
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
     pub fn set_line(&mut self, line: usize) {
         self.line = line;
     }